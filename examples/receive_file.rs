@@ -4,7 +4,7 @@ mod common;
 use common::format_bytes;
 
 use anyhow::Result;
-use quicsock::QuicSocket;
+use quicsock::{pairing, QuicSocket};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use tokio::{fs::File, io::AsyncWriteExt};
@@ -19,9 +19,9 @@ struct Args {
     /// Path where the received file should be saved.
     #[arg(short = 's', long = "save", help = "Path where the received file should be saved.", required = true)]
     save_path: PathBuf,
-    /// Unique ID to identify the file to be received.
-    #[arg(short = 't', long = "token", help = "Unique ID to identify the file to be received.", required = true)]
-    token: String,
+    /// Pairing code shared by the sender.
+    #[arg(short = 'c', long = "code", help = "Pairing code shared by the sender.", required = true)]
+    code: String,
     /// Server address to connect to.
     //#[clap(default_value = "127.0.0.1:5000")]
     #[arg(short = 'a', long = "addr", help = "Server address to connect to.", default_value = "127.0.0.1:5000")]
@@ -78,10 +78,8 @@ async fn main() -> Result<()> {
         client_socket.connect(args.server_addr, "localhost").await?
     };
     
-    let stream_id = connection.open_bi_stream().await?;
-
-    // Send the unique ID to the server
-    client_socket.send(&connection, stream_id, args.token.as_bytes()).await?;
+    // Pair with the sender using the code
+    pairing::initiate(&connection, &args.code).await?;
 
     // Receive the file data
     info!("Receiving file...");
@@ -4,12 +4,11 @@ mod common;
 use common::format_bytes;
 
 use anyhow::Result;
-use quicsock::QuicSocket;
+use quicsock::{pairing, QuicSocket};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use tokio::{fs::File, io::AsyncReadExt};
 use clap::Parser;
-use uuid::Uuid;
 
 use tracing::{info, error, Level};
 use tracing_subscriber::FmtSubscriber;
@@ -62,18 +61,16 @@ async fn main() -> Result<()> {
         },
     };
 
-    // Create a unique ID for the file
-    let unique_id = Uuid::new_v4().to_string();
-    println!("Share this ID with the receiver: {}", unique_id);
+    // Create a pairing code for the file
+    let code = pairing::generate_code()?;
+    println!("Share this code with the receiver: {}", code);
 
     // Accept incoming connections
     if let Some(connection) = server_socket.accept(&mut incoming_connections).await {
-        // Accept the bi-directional stream and receive the request ID
-        let stream_id = connection.accept_bi_stream().await?;
-        let request_data = server_socket.receive(&connection, stream_id).await?;
-        let token = String::from_utf8(request_data).unwrap();
-        info!("Received request token: {}", token);
-        if token == unique_id {
+        // Pair with the receiver. The code itself is never sent.
+        if let Err(e) = pairing::respond(&connection, &code).await {
+            error!("Pairing failed: {}", e);
+        } else {
             // Read the file data
             let mut file = File::open(&args.file_path).await?;
             let mut buffer = Vec::new();
@@ -91,8 +88,6 @@ async fn main() -> Result<()> {
             // Calculate bps
             let bps = buffer.len() as f64 / elapsed_time.as_secs_f64();
             println!("Speed: {}ps", format_bytes(bps as usize));
-        } else {
-            error!("Received request ID does not match.");
         }
     } else {
         error!("No connection received.");
@@ -0,0 +1,63 @@
+//! This example demonstrates how a server can push streams to a client using QUIC.
+//!
+//! The server opens streams toward the client to push notifications, and the client receives them
+//! from its incoming-stream receiver. Both sides run in this process for simplicity.
+
+use anyhow::Result;
+use quicsock::QuicSocket;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tracing::{info, Level};
+use tracing_subscriber::FmtSubscriber;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // a builder for `FmtSubscriber`.
+    let subscriber = FmtSubscriber::builder()
+        // all spans/events with a level higher than INFO (e.g, warn, error, etc.)
+        // will be written to stdout.
+        .with_max_level(Level::INFO)
+        // completes the builder.
+        .finish();
+
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("setting default subscriber failed");
+
+    let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+    // Create a server socket with a self-signed certificate
+    let (server_socket, mut incoming_connections) = QuicSocket::new_server(server_addr, None, None).await
+        .map_err(anyhow::Error::msg)?;
+
+    // Push a few notifications to every client
+    let server = tokio::spawn(async move {
+        if let Some(connection) = server_socket.accept(&mut incoming_connections).await {
+            for i in 1..=3 {
+                let stream_id = connection.open_bi_stream().await?;
+                let notification = format!("notification #{}", i);
+                info!("Server pushing: {}", notification);
+                connection.send(stream_id, notification.as_bytes()).await?;
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            connection.close().await;
+        }
+        anyhow::Ok(())
+    });
+
+    // Connect as a client, skipping verification of the self-signed certificate
+    let client_socket = QuicSocket::new_insecure_client("0.0.0.0:0".parse().unwrap()).await
+        .map_err(anyhow::Error::msg)?;
+    let connection = client_socket.connect(server_addr, "localhost").await?;
+
+    // Receive the streams pushed by the server
+    let mut incoming_streams = connection.incoming_streams();
+    while let Some(stream_id) = incoming_streams.recv().await {
+        let data = connection.receive(stream_id).await?;
+        info!("Client received: {}", String::from_utf8_lossy(&data));
+    }
+    info!("Server closed the connection.");
+
+    server.await??;
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Feeds arbitrary bytes to the frame decoder in arbitrarily sized chunks.
+//!
+//! The first byte picks the chunk size and the next two the maximum frame size, so that both
+//! truncated frames and oversized length prefixes are explored.
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use quicsock::framing::{FrameDecoder, FRAME_HEADER_SIZE, MAX_RESERVE};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    let chunk_size = usize::from(data[0]).max(1);
+    let max_frame_size = usize::from(u16::from_be_bytes([data[1], data[2]]));
+    let input = &data[3..];
+
+    let mut decoder = FrameDecoder::new(max_frame_size);
+    let mut buffer = BytesMut::new();
+    let mut decoded = 0;
+    for chunk in input.chunks(chunk_size) {
+        buffer.extend_from_slice(chunk);
+        loop {
+            match decoder.decode(&mut buffer) {
+                Ok(Some(frame)) => {
+                    assert!(frame.len() <= max_frame_size);
+                    decoded += FRAME_HEADER_SIZE + frame.len();
+                },
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+        // The buffer never grows far beyond the input received so far, whatever the length prefixes say.
+        assert!(buffer.capacity() <= 2 * (input.len() + MAX_RESERVE));
+    }
+    assert!(decoded <= input.len());
+});
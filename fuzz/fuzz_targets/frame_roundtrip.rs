@@ -0,0 +1,21 @@
+//! Encodes arbitrary payloads and checks that decoding returns them unchanged.
+
+#![no_main]
+
+use bytes::{Bytes, BytesMut};
+use libfuzzer_sys::fuzz_target;
+use quicsock::framing::FrameCodec;
+use tokio_util::codec::{Decoder, Encoder};
+
+fuzz_target!(|payloads: Vec<Vec<u8>>| {
+    let mut codec = FrameCodec::new();
+    let mut buffer = BytesMut::new();
+    for payload in &payloads {
+        codec.encode(Bytes::copy_from_slice(payload), &mut buffer).unwrap();
+    }
+    for payload in &payloads {
+        let frame = codec.decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(&frame[..], &payload[..]);
+    }
+    assert!(codec.decode_eof(&mut buffer).unwrap().is_none());
+});
@@ -0,0 +1,184 @@
+//! Connection audit log.
+//!
+//! An `AuditLog` set with `QuicSocket::set_audit_log` records when connections of the socket open and
+//! close, the identities they authenticate as, how many bytes they transferred and why they closed.
+//! Records are written as JSON lines or passed to a callback, to be kept wherever compliance requires.
+//!
+//! quicsock does not authenticate peers itself beyond TLS: applications report the identity a connection
+//! authenticated as, such as a user name or share token, with `AuditLog::record_identity`.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::QuicConnection;
+
+/// The kind of an audit record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A connection completed its handshake.
+    Open,
+    /// The application reported the identity a connection authenticated as.
+    Authenticated,
+    /// A connection closed.
+    Close,
+}
+
+/// Whether a connection was accepted or initiated by the socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Accepted from a peer.
+    Inbound,
+    /// Initiated by the socket.
+    Outbound,
+}
+
+/// A record of the audit log, serialized as a JSON object. Fields that do not apply are omitted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    /// When the record was made, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// What happened.
+    pub event: AuditEvent,
+    /// The identifier of the connection, unique among the open connections of the socket.
+    pub connection_id: usize,
+    /// The address of the peer.
+    pub remote_address: SocketAddr,
+    /// Whether the connection was accepted or initiated.
+    pub direction: Direction,
+    /// The server name (SNI) requested by the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// The negotiated ALPN protocol.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alpn_protocol: Option<String>,
+    /// The SHA-256 fingerprint of the peer's certificate, in hex, if it presented one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_certificate_sha256: Option<String>,
+    /// The identity the connection authenticated as, as reported by the application.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// The number of UDP payload bytes sent on the connection, on close.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_sent: Option<u64>,
+    /// The number of UDP payload bytes received on the connection, on close.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_received: Option<u64>,
+    /// How long the connection was open, in milliseconds, on close.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Why the connection closed, on close.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub close_reason: Option<String>,
+}
+
+enum Sink {
+    Writer(Mutex<Box<dyn Write + Send>>),
+    Callback(Box<dyn Fn(&AuditRecord) + Send + Sync>),
+}
+
+/// What is remembered about an open connection until its close is recorded.
+struct OpenConnection {
+    record: AuditRecord,
+    opened: Instant,
+}
+
+/// A structured log of connection activity.
+pub struct AuditLog {
+    sink: Sink,
+    open: Mutex<HashMap<usize, OpenConnection>>,
+}
+
+impl AuditLog {
+    /// Creates a log writing each record to `writer` as a line of JSON.
+    ///
+    /// Records are written from the tasks watching connections, so the writer should not block for long.
+    pub fn to_writer(writer: impl Write + Send + 'static) -> Self {
+        Self::new(Sink::Writer(Mutex::new(Box::new(writer))))
+    }
+    /// Creates a log passing each record to `callback`.
+    pub fn with_callback(callback: impl Fn(&AuditRecord) + Send + Sync + 'static) -> Self {
+        Self::new(Sink::Callback(Box::new(callback)))
+    }
+    fn new(sink: Sink) -> Self {
+        Self { sink, open: Mutex::new(HashMap::new()) }
+    }
+    /// Records the identity a connection authenticated as. It is also included in the record of its close.
+    ///
+    /// Has no effect for connections opened before the log was set on the socket.
+    pub fn record_identity(&self, connection: &QuicConnection, identity: &str) {
+        let record = {
+            let mut open = self.open.lock().unwrap();
+            let Some(entry) = open.get_mut(&connection.connection.stable_id()) else { return };
+            entry.record.identity = Some(identity.to_string());
+            AuditRecord { timestamp_ms: now_ms(), event: AuditEvent::Authenticated, ..entry.record.clone() }
+        };
+        self.write(&record);
+    }
+    /// Records a connection that completed its handshake.
+    pub(crate) fn record_open(&self, connection: &QuicConnection, direction: Direction) {
+        let record = AuditRecord {
+            timestamp_ms: now_ms(),
+            event: AuditEvent::Open,
+            connection_id: connection.connection.stable_id(),
+            remote_address: connection.connection.remote_address(),
+            direction,
+            server_name: connection.server_name().map(str::to_string),
+            alpn_protocol: connection.alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+            peer_certificate_sha256: connection.peer_certificates().and_then(|certs| certs.first().map(|cert| sha256_hex(cert))),
+            identity: None,
+            bytes_sent: None,
+            bytes_received: None,
+            duration_ms: None,
+            close_reason: None,
+        };
+        self.open.lock().unwrap().insert(record.connection_id, OpenConnection { record: record.clone(), opened: Instant::now() });
+        self.write(&record);
+    }
+    /// Records the close of a connection whose open was recorded.
+    pub(crate) fn record_close(&self, connection: &quinn::Connection, reason: &quinn::ConnectionError) {
+        let Some(entry) = self.open.lock().unwrap().remove(&connection.stable_id()) else { return };
+        let stats = connection.stats();
+        let record = AuditRecord {
+            timestamp_ms: now_ms(),
+            event: AuditEvent::Close,
+            bytes_sent: Some(stats.udp_tx.bytes),
+            bytes_received: Some(stats.udp_rx.bytes),
+            duration_ms: Some(entry.opened.elapsed().as_millis() as u64),
+            close_reason: Some(reason.to_string()),
+            ..entry.record
+        };
+        self.write(&record);
+    }
+    fn write(&self, record: &AuditRecord) {
+        match &self.sink {
+            Sink::Writer(writer) => {
+                let mut line = match serde_json::to_vec(record) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        tracing::warn!("Failed to serialize audit record: {}", e);
+                        return;
+                    },
+                };
+                line.push(b'\n');
+                let mut writer = writer.lock().unwrap();
+                if let Err(e) = writer.write_all(&line).and_then(|_| writer.flush()) {
+                    tracing::warn!("Failed to write audit record: {}", e);
+                }
+            },
+            Sink::Callback(callback) => callback(record),
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or_default()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, data).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
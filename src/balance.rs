@@ -0,0 +1,115 @@
+//! Client-side load balancing across multiple server addresses.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The default period during which an address that failed to connect is deprioritized.
+pub const DEFAULT_FAILURE_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Strategy used to pick a server address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Try addresses in order and use the first one that connects.
+    FirstSuccess,
+    /// Start from the next address on each attempt, spreading connections evenly.
+    RoundRobin,
+    /// Dial all addresses concurrently and keep the first handshake to complete.
+    LowestRtt,
+}
+
+/// Health information tracked for a server address.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    /// The number of consecutive failed connection attempts.
+    pub consecutive_failures: u32,
+    /// The time of the last failed connection attempt.
+    pub last_failure: Option<Instant>,
+    /// The round-trip time measured on the last successful connection.
+    pub rtt: Option<Duration>,
+}
+
+/// A set of server addresses with a selection strategy and health tracking.
+///
+/// Addresses that failed within the cooldown period are tried after healthy ones.
+#[derive(Debug)]
+pub struct LoadBalancer {
+    addrs: Vec<SocketAddr>,
+    strategy: Strategy,
+    cooldown: Duration,
+    next: AtomicUsize,
+    health: Mutex<HashMap<SocketAddr, Health>>,
+}
+
+impl LoadBalancer {
+    /// Creates a new load balancer over the given addresses.
+    pub fn new(addrs: Vec<SocketAddr>, strategy: Strategy) -> Self {
+        Self {
+            addrs,
+            strategy,
+            cooldown: DEFAULT_FAILURE_COOLDOWN,
+            next: AtomicUsize::new(0),
+            health: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Sets the period during which a failed address is deprioritized.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+    /// Returns the selection strategy.
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+    /// Returns the addresses managed by the load balancer.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+    /// Returns the health information for an address.
+    pub fn health(&self, addr: &SocketAddr) -> Health {
+        self.health.lock().unwrap().get(addr).cloned().unwrap_or_default()
+    }
+    /// Returns whether an address is currently considered healthy.
+    pub fn is_healthy(&self, addr: &SocketAddr) -> bool {
+        match self.health(addr).last_failure {
+            Some(last_failure) => last_failure.elapsed() >= self.cooldown,
+            None => true,
+        }
+    }
+    /// Records a successful connection to an address.
+    pub fn report_success(&self, addr: SocketAddr, rtt: Duration) {
+        let mut health = self.health.lock().unwrap();
+        let entry = health.entry(addr).or_default();
+        entry.consecutive_failures = 0;
+        entry.last_failure = None;
+        entry.rtt = Some(rtt);
+    }
+    /// Records a failed connection attempt to an address.
+    pub fn report_failure(&self, addr: SocketAddr) {
+        let mut health = self.health.lock().unwrap();
+        let entry = health.entry(addr).or_default();
+        entry.consecutive_failures += 1;
+        entry.last_failure = Some(Instant::now());
+    }
+    /// Returns the addresses in the order they should be tried.
+    pub fn candidates(&self) -> Vec<SocketAddr> {
+        let mut addrs = self.addrs.clone();
+        match self.strategy {
+            Strategy::FirstSuccess => {},
+            Strategy::RoundRobin => {
+                if !addrs.is_empty() {
+                    let start = self.next.fetch_add(1, Ordering::Relaxed) % addrs.len();
+                    addrs.rotate_left(start);
+                }
+            },
+            Strategy::LowestRtt => {
+                addrs.sort_by_key(|addr| self.health(addr).rtt.unwrap_or(Duration::MAX));
+            },
+        }
+        // Stable sort keeps the strategy's order within healthy and unhealthy addresses.
+        addrs.sort_by_key(|addr| !self.is_healthy(addr));
+        addrs
+    }
+}
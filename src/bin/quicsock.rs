@@ -0,0 +1,312 @@
+//! The `quicsock` command line tool, for transferring files without writing code.
+//!
+//! Built with the `cli` feature: `cargo install quicsock --features cli`.
+//!
+//! Every transfer is authenticated with a pairing code, which is bound to the TLS session, so the
+//! self-signed certificates generated by default need no verification.
+
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+use quicsock::transfer::{self, Filter, TransferOptions, TransferSummary};
+use quicsock::{pairing, QuicConnection, QuicSocket};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::Level;
+use tracing_subscriber::FmtSubscriber;
+
+/// The interval between progress updates.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Transfer files and directories over QUIC.
+#[derive(Parser, Debug)]
+#[command(name = "quicsock", version, about)]
+struct Cli {
+    /// Print debug logs.
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Receive files pushed by `quicsock send --to`, from any number of senders.
+    Serve {
+        /// Address to listen on. Use `0.0.0.0:5000` to accept senders on other hosts.
+        #[arg(short = 'a', long = "addr", default_value = "127.0.0.1:5000")]
+        addr: SocketAddr,
+        /// Directory to save received files in.
+        #[arg(short = 'd', long = "dir", default_value = ".")]
+        dir: PathBuf,
+        /// Pairing code senders must know. Generated if not set.
+        #[arg(short = 't', long = "token")]
+        token: Option<String>,
+        #[command(flatten)]
+        cert: CertArgs,
+    },
+    /// Send a file or directory, either to a `quicsock serve` instance or to a `quicsock recv` receiver.
+    Send {
+        /// File or directory to send.
+        path: PathBuf,
+        /// Address of a `quicsock serve` instance to push to. If not set, waits for a receiver.
+        #[arg(long = "to", requires = "token")]
+        to: Option<SocketAddr>,
+        /// Address to listen on when waiting for a receiver. Use `0.0.0.0:5000` to accept receivers on other hosts.
+        #[arg(short = 'a', long = "addr", default_value = "127.0.0.1:5000")]
+        addr: SocketAddr,
+        /// Pairing code. Required with `--to`, generated when waiting for a receiver and not set.
+        #[arg(short = 't', long = "token")]
+        token: Option<String>,
+        /// Exclude pattern for directories, such as `target` or `*.tmp`. May be repeated.
+        #[arg(short = 'e', long = "exclude")]
+        exclude: Vec<String>,
+        /// Maximum throughput, in bytes per second.
+        #[arg(long = "rate-limit")]
+        rate_limit: Option<u64>,
+        /// Size of the chunks files are read and sent in, in bytes.
+        #[arg(long = "chunk-size", default_value_t = transfer::CHUNK_SIZE)]
+        chunk_size: usize,
+        /// Number of chunks read from disk ahead of the stream.
+        #[arg(long = "read-ahead", default_value_t = transfer::DEFAULT_READ_AHEAD)]
+        read_ahead: usize,
+        #[command(flatten)]
+        cert: CertArgs,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Receive a file or directory from a `quicsock send` sender waiting for a receiver.
+    Recv {
+        /// Pairing code shown by the sender.
+        #[arg(short = 't', long = "token", required = true)]
+        token: String,
+        /// Address of the sender.
+        #[arg(short = 'f', long = "from", default_value = "127.0.0.1:5000")]
+        from: SocketAddr,
+        /// Directory to save received files in.
+        #[arg(short = 'd', long = "dir", default_value = ".")]
+        dir: PathBuf,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+}
+
+/// Certificate options for listening.
+#[derive(Args, Debug)]
+struct CertArgs {
+    /// Path to the certificate file (PEM or DER format). A self-signed certificate is generated if not set.
+    #[arg(short = 'c', long = "cert", requires = "key_path")]
+    cert_path: Option<PathBuf>,
+    /// Path to the private key file (PEM or DER format).
+    #[arg(short = 'k', long = "key", requires = "cert_path")]
+    key_path: Option<PathBuf>,
+}
+
+/// Options for connecting.
+#[derive(Args, Debug)]
+struct ClientArgs {
+    /// Server name to validate the certificate against, with `--verify`.
+    #[arg(short = 'n', long = "name", default_value = "localhost")]
+    server_name: String,
+    /// Also verify the certificate against the native roots, for peers listening with `--cert`.
+    /// Otherwise the peer is only authenticated by the pairing code.
+    #[arg(long)]
+    verify: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(if cli.verbose { Level::DEBUG } else { Level::WARN })
+        .with_writer(std::io::stderr)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+
+    match cli.command {
+        Command::Serve { addr, dir, token, cert } => serve(addr, &dir, token, &cert).await,
+        Command::Send { path, to, addr, token, exclude, rate_limit, chunk_size, read_ahead, cert, client } => {
+            let filter = exclude.iter().fold(Filter::new(), |filter, pattern| filter.exclude(pattern));
+            let options = TransferOptions { rate_limit, chunk_size, read_ahead };
+            match to {
+                Some(to) => push(&path, to, token.as_deref().unwrap_or_default(), &filter, &options, &client).await,
+                None => offer(&path, addr, token, &filter, &options, &cert).await,
+            }
+        },
+        Command::Recv { token, from, dir, client } => recv(&token, from, &dir, &client).await,
+    }
+}
+
+/// Receives transfers from any number of senders until interrupted.
+async fn serve(addr: SocketAddr, dir: &Path, token: Option<String>, cert: &CertArgs) -> Result<()> {
+    let token = match token {
+        Some(token) => token,
+        None => pairing::generate_code()?,
+    };
+    let (socket, mut incoming) = QuicSocket::new_server(addr, cert.cert_path.as_deref(), cert.key_path.as_deref()).await.map_err(anyhow::Error::msg)?;
+    println!("Listening on {}, saving to {}", addr, dir.display());
+    println!("Send with: quicsock send --to {} --token {} <path>", addr, token);
+    let socket = Arc::new(socket);
+    let shutdown = socket.shutdown_token();
+    let drain = {
+        let socket = socket.clone();
+        tokio::spawn(async move { socket.shutdown_on(quicsock::shutdown::signal(), quicsock::shutdown::DEFAULT_DRAIN_TIMEOUT).await })
+    };
+    loop {
+        let connection = tokio::select! {
+            connection = socket.accept(&mut incoming) => connection,
+            _ = shutdown.cancelled() => None,
+        };
+        let Some(connection) = connection else {
+            break;
+        };
+        let dir = dir.to_path_buf();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let remote = connection.inner().remote_address();
+            let result = async {
+                pairing::respond(&connection, &token).await?;
+                transfer::receive(&connection, &dir).await
+            }.await;
+            match result {
+                Ok(summary) => println!("{}: received {}", remote, describe(&summary)),
+                Err(e) => eprintln!("{}: transfer failed: {:#}", remote, e),
+            }
+            connection.close().await;
+        });
+    }
+    // Let transfers in progress complete.
+    drain.await?;
+    Ok(())
+}
+
+/// Pushes a file or directory to a `quicsock serve` instance.
+async fn push(path: &Path, to: SocketAddr, token: &str, filter: &Filter, options: &TransferOptions, client: &ClientArgs) -> Result<()> {
+    let (socket, connection) = connect(to, client).await?;
+    let result = async {
+        pairing::initiate(&connection, token).await?;
+        with_progress(&connection, send(&connection, path, filter, options)).await
+    }.await;
+    close(&socket, &connection).await;
+    println!("Sent {}", describe(&result?));
+    Ok(())
+}
+
+/// Waits for a receiver and sends it a file or directory.
+async fn offer(path: &Path, addr: SocketAddr, token: Option<String>, filter: &Filter, options: &TransferOptions, cert: &CertArgs) -> Result<()> {
+    let token = match token {
+        Some(token) => token,
+        None => pairing::generate_code()?,
+    };
+    let (socket, mut incoming) = QuicSocket::new_server(addr, cert.cert_path.as_deref(), cert.key_path.as_deref()).await.map_err(anyhow::Error::msg)?;
+    println!("Waiting for a receiver on {}", addr);
+    println!("Receive with: quicsock recv --token {}", token);
+    loop {
+        let Some(connection) = socket.accept(&mut incoming).await else {
+            anyhow::bail!("socket closed before a receiver connected");
+        };
+        // Keep waiting when a receiver has the wrong code.
+        if let Err(e) = pairing::respond(&connection, &token).await {
+            eprintln!("{}: {:#}", connection.inner().remote_address(), e);
+            connection.close().await;
+            continue;
+        }
+        let result = with_progress(&connection, send(&connection, path, filter, options)).await;
+        close(&socket, &connection).await;
+        println!("Sent {}", describe(&result?));
+        return Ok(());
+    }
+}
+
+/// Receives a file or directory from a waiting sender.
+async fn recv(token: &str, from: SocketAddr, dir: &Path, client: &ClientArgs) -> Result<()> {
+    let (socket, connection) = connect(from, client).await?;
+    let result = async {
+        pairing::initiate(&connection, token).await?;
+        with_progress(&connection, transfer::receive(&connection, dir)).await
+    }.await;
+    close(&socket, &connection).await;
+    println!("Received {} into {}", describe(&result?), dir.display());
+    Ok(())
+}
+
+/// Connects to a server, to be authenticated by pairing.
+async fn connect(addr: SocketAddr, client: &ClientArgs) -> Result<(QuicSocket, Arc<QuicConnection>)> {
+    let bind_addr: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0".parse()? } else { "[::]:0".parse()? };
+    let socket = if client.verify {
+        QuicSocket::new_native_client(bind_addr).await
+    } else {
+        QuicSocket::new_insecure_client(bind_addr).await
+    }.map_err(anyhow::Error::msg)?;
+    let connection = socket.connect(addr, &client.server_name).await?;
+    Ok((socket, connection))
+}
+
+/// Closes the connection and waits for the peer to be notified before the process exits.
+/// Otherwise the peer only notices after its idle timeout, even on failures.
+async fn close(socket: &QuicSocket, connection: &QuicConnection) {
+    connection.close().await;
+    socket.shutdown(Duration::ZERO).await;
+}
+
+/// Sends a file or a directory, depending on what `path` is.
+async fn send(connection: &QuicConnection, path: &Path, filter: &Filter, options: &TransferOptions) -> Result<TransferSummary> {
+    if tokio::fs::metadata(path).await?.is_dir() {
+        transfer::send_directory_with_options(connection, path, filter, options).await
+    } else {
+        transfer::send_file_with_options(connection, path, options).await
+    }
+}
+
+/// Runs a transfer while printing the bytes moved over the connection and the current speed.
+async fn with_progress<T>(connection: &QuicConnection, transfer: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+    let start = Instant::now();
+    let moved = || {
+        let stats = connection.inner().stats();
+        stats.udp_tx.bytes + stats.udp_rx.bytes
+    };
+    let initial = moved();
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + PROGRESS_INTERVAL, PROGRESS_INTERVAL);
+    let mut last = initial;
+    tokio::pin!(transfer);
+    let result = loop {
+        tokio::select! {
+            result = &mut transfer => break result,
+            _ = interval.tick() => {
+                let total = moved();
+                let speed = (total - last) as f64 / PROGRESS_INTERVAL.as_secs_f64();
+                last = total;
+                eprint!("\r{} transferred, {}/s        ", format_bytes(total - initial), format_bytes(speed as u64));
+                let _ = std::io::stderr().flush();
+            },
+        }
+    };
+    let elapsed = start.elapsed();
+    let total = moved() - initial;
+    eprintln!("\r{} transferred in {:.2?} ({}/s)        ", format_bytes(total), elapsed, format_bytes((total as f64 / elapsed.as_secs_f64()) as u64));
+    result
+}
+
+/// Describes what was transferred.
+fn describe(summary: &TransferSummary) -> String {
+    format!("{} files, {} directories, {} (blake3 {})", summary.files, summary.directories, format_bytes(summary.bytes), summary.digest_hex())
+}
+
+/// Formats a byte count into a human-readable string.
+fn format_bytes(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+
+    if bytes >= GB {
+        format!("{:.2} GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.2} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.2} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
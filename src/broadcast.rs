@@ -0,0 +1,273 @@
+//! Fair broadcasting of messages to many connections.
+//!
+//! A `Broadcaster` sends each message to every registered peer on its own bi-directional stream,
+//! like `SendScheduler`. Peers are served independently: each has a limit of messages in flight, and
+//! sends that take longer than the timeout are abandoned, so a slow peer neither delays the others
+//! nor buffers without bound. What happens to the messages of a peer that falls behind is set by a
+//! `LagPolicy`. Peers receive messages with `QuicConnection::accept_bi_stream` and `receive`.
+
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::cancel::STREAM_CANCELLED_CODE;
+use crate::QuicConnection;
+
+pub use crate::codes::LAGGING_CODE;
+
+/// The default maximum number of messages in flight to a peer.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 4;
+/// The default time allowed to send a message to a peer.
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to do with messages for a peer that has `max_in_flight` messages in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Skip the message for the peer.
+    Skip,
+    /// Queue up to the given number of messages for the peer, skipping messages beyond it.
+    Queue(usize),
+    /// Queue up to the given number of messages for the peer, and disconnect it when more are needed.
+    ///
+    /// Peers whose sends time out are also disconnected.
+    Disconnect(usize),
+}
+
+/// Options for a `Broadcaster`.
+#[derive(Debug, Clone)]
+pub struct BroadcastOptions {
+    /// The maximum number of messages in flight to a peer. A message is in flight until the peer
+    /// acknowledges all of it.
+    pub max_in_flight: usize,
+    /// The time allowed to send a message to a peer, after which the send is abandoned.
+    pub send_timeout: Duration,
+    /// What to do with messages for a peer that falls behind.
+    pub lag_policy: LagPolicy,
+}
+
+impl Default for BroadcastOptions {
+    fn default() -> Self {
+        Self {
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+            lag_policy: LagPolicy::Queue(64),
+        }
+    }
+}
+
+/// The identifier of a peer registered with a `Broadcaster`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(u64);
+
+/// Delivery counters of a peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// The number of messages delivered.
+    pub delivered: u64,
+    /// The number of messages skipped because the peer was lagging.
+    pub skipped: u64,
+    /// The number of messages whose send failed or timed out.
+    pub failed: u64,
+    /// The number of messages queued for the peer.
+    pub queued: usize,
+}
+
+/// The outcome of a `Broadcaster::broadcast` call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastReport {
+    /// The number of peers the message is being sent to.
+    pub sending: usize,
+    /// The number of peers the message was queued for.
+    pub queued: usize,
+    /// The number of peers the message was skipped for.
+    pub skipped: usize,
+    /// The number of peers disconnected for lagging.
+    pub disconnected: usize,
+}
+
+struct PeerState {
+    queue: VecDeque<Bytes>,
+    in_flight: usize,
+    stats: PeerStats,
+}
+
+struct Peer {
+    connection: Arc<QuicConnection>,
+    state: Mutex<PeerState>,
+}
+
+struct Inner {
+    peers: Mutex<HashMap<PeerId, Arc<Peer>>>,
+    options: BroadcastOptions,
+    next_id: AtomicU64,
+}
+
+impl Inner {
+    fn remove(&self, id: PeerId) -> Option<Arc<Peer>> {
+        self.peers.lock().unwrap().remove(&id)
+    }
+    /// Removes a peer and closes its connection with `LAGGING_CODE`.
+    fn disconnect(&self, id: PeerId, peer: &Peer) {
+        tracing::debug!("Disconnecting lagging peer {}", peer.connection.connection.remote_address());
+        self.remove(id);
+        peer.connection.close_with(LAGGING_CODE, b"lagging");
+    }
+}
+
+/// Sends messages to a set of connections, serving each independently.
+pub struct Broadcaster {
+    inner: Arc<Inner>,
+}
+
+impl Broadcaster {
+    /// Creates a broadcaster with no peers.
+    pub fn new(options: BroadcastOptions) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                peers: Mutex::new(HashMap::new()),
+                options,
+                next_id: AtomicU64::new(0),
+            }),
+        }
+    }
+    /// Registers a peer. It is removed once its connection closes.
+    pub fn add(&self, connection: Arc<QuicConnection>) -> PeerId {
+        let id = PeerId(self.inner.next_id.fetch_add(1, Ordering::Relaxed));
+        // Only the quinn connection is held by the watcher, so it does not keep the `QuicConnection` alive.
+        let closed = connection.connection.clone();
+        let peer = Arc::new(Peer {
+            connection,
+            state: Mutex::new(PeerState { queue: VecDeque::new(), in_flight: 0, stats: PeerStats::default() }),
+        });
+        self.inner.peers.lock().unwrap().insert(id, peer);
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            closed.closed().await;
+            if let Some(inner) = inner.upgrade() {
+                inner.remove(id);
+            }
+        });
+        id
+    }
+    /// Unregisters a peer. Messages in flight are still sent, queued ones are dropped.
+    /// Returns whether the peer was registered.
+    pub fn remove(&self, id: PeerId) -> bool {
+        self.inner.remove(id).is_some()
+    }
+    /// Returns the number of peers.
+    pub fn len(&self) -> usize {
+        self.inner.peers.lock().unwrap().len()
+    }
+    /// Returns whether there are no peers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the delivery counters of a peer.
+    pub fn stats(&self, id: PeerId) -> Option<PeerStats> {
+        let peer = self.inner.peers.lock().unwrap().get(&id).cloned()?;
+        let state = peer.state.lock().unwrap();
+        Some(PeerStats { queued: state.queue.len(), ..state.stats })
+    }
+    /// Sends a message to every peer, without waiting for the sends to complete.
+    pub fn broadcast(&self, message: Bytes) -> BroadcastReport {
+        let peers: Vec<(PeerId, Arc<Peer>)> = self.inner.peers.lock().unwrap().iter().map(|(id, peer)| (*id, Arc::clone(peer))).collect();
+        let options = &self.inner.options;
+        let mut report = BroadcastReport::default();
+        for (id, peer) in peers {
+            let mut state = peer.state.lock().unwrap();
+            if state.in_flight < options.max_in_flight.max(1) {
+                state.in_flight += 1;
+                report.sending += 1;
+                drop(state);
+                tokio::spawn(send_loop(Arc::clone(&self.inner), id, peer, message.clone()));
+                continue;
+            }
+            match options.lag_policy {
+                LagPolicy::Queue(capacity) | LagPolicy::Disconnect(capacity) if state.queue.len() < capacity => {
+                    state.queue.push_back(message.clone());
+                    report.queued += 1;
+                },
+                LagPolicy::Disconnect(_) => {
+                    drop(state);
+                    self.inner.disconnect(id, &peer);
+                    report.disconnected += 1;
+                },
+                _ => {
+                    state.stats.skipped += 1;
+                    report.skipped += 1;
+                },
+            }
+        }
+        report
+    }
+}
+
+/// Sends a message to a peer, then its queued messages until the queue is empty.
+async fn send_loop(inner: Arc<Inner>, id: PeerId, peer: Arc<Peer>, mut message: Bytes) {
+    loop {
+        let outcome = send_message(&peer.connection, message, inner.options.send_timeout).await;
+        let mut state = peer.state.lock().unwrap();
+        match outcome {
+            Outcome::Delivered => state.stats.delivered += 1,
+            Outcome::Failed(e) => {
+                tracing::debug!("Failed to broadcast to {}: {}", peer.connection.connection.remote_address(), e);
+                state.stats.failed += 1;
+            },
+            Outcome::TimedOut => {
+                tracing::debug!("Broadcast to {} timed out", peer.connection.connection.remote_address());
+                state.stats.failed += 1;
+                if matches!(inner.options.lag_policy, LagPolicy::Disconnect(_)) {
+                    state.queue.clear();
+                    state.in_flight -= 1;
+                    drop(state);
+                    inner.disconnect(id, &peer);
+                    return;
+                }
+            },
+        }
+        if peer.connection.connection.close_reason().is_some() {
+            state.queue.clear();
+        }
+        match state.queue.pop_front() {
+            Some(next) => message = next,
+            None => {
+                state.in_flight -= 1;
+                return;
+            },
+        }
+    }
+}
+
+enum Outcome {
+    Delivered,
+    Failed(anyhow::Error),
+    TimedOut,
+}
+
+/// Sends a message on a new stream, waiting until the peer acknowledges all of it.
+///
+/// The stream is reset with `STREAM_CANCELLED_CODE` if the send times out, so its data stops being buffered.
+async fn send_message(connection: &QuicConnection, message: Bytes, timeout: Duration) -> Outcome {
+    let deadline = Instant::now() + timeout;
+    let (mut send_stream, _recv_stream) = match tokio::time::timeout_at(deadline, connection.connection.open_bi()).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Outcome::Failed(e.into()),
+        Err(_) => return Outcome::TimedOut,
+    };
+    let result = tokio::time::timeout_at(deadline, async {
+        send_stream.write_chunk(message).await?;
+        send_stream.finish()?;
+        _ = send_stream.stopped().await;
+        anyhow::Ok(())
+    }).await;
+    match result {
+        Ok(Ok(())) => Outcome::Delivered,
+        Ok(Err(e)) => Outcome::Failed(e),
+        Err(_) => {
+            let _ = send_stream.reset(STREAM_CANCELLED_CODE.into());
+            Outcome::TimedOut
+        },
+    }
+}
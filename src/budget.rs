@@ -0,0 +1,97 @@
+//! Socket-wide budget on the memory buffered by receives.
+//!
+//! Every `receive()` in progress buffers the data read so far until the peer finishes the stream. Each
+//! chunk read is charged to the budget of the socket, and released once the receive returns or fails.
+//! While the budget is used up, receives stop reading, so QUIC flow control slows their peers down
+//! instead of the buffered data growing without bound when many peers send at once.
+//!
+//! The oldest receive in progress is always allowed to read on, so receives waiting on each other
+//! cannot stall forever, and a message larger than the whole budget still completes. The memory
+//! buffered can therefore exceed the budget by that receive, plus the last chunk read by each other one.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+struct State {
+    limit: Option<usize>,
+    used: usize,
+    next_ticket: u64,
+    /// The tickets of the receives in progress, in the order they started.
+    active: BTreeSet<u64>,
+}
+
+/// A budget on the bytes buffered by the receives of a socket.
+pub(crate) struct ReceiveBudget {
+    state: Mutex<State>,
+    released: Notify,
+}
+
+impl ReceiveBudget {
+    /// Creates a budget of `limit` bytes, or an unlimited one with `None`.
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            state: Mutex::new(State { limit, used: 0, next_ticket: 0, active: BTreeSet::new() }),
+            released: Notify::new(),
+        }
+    }
+    /// Sets the limit, or removes it with `None`. Applies to receives in progress.
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        self.state.lock().unwrap().limit = limit;
+        self.released.notify_waiters();
+    }
+    /// Returns the number of bytes currently buffered by receives.
+    pub(crate) fn used(&self) -> usize {
+        self.state.lock().unwrap().used
+    }
+    /// Starts accounting a receive.
+    pub(crate) fn start(self: &Arc<Self>) -> Reservation {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.active.insert(ticket);
+        Reservation { budget: Arc::clone(self), ticket, held: 0 }
+    }
+}
+
+/// The bytes buffered by a receive in progress, released when dropped.
+pub(crate) struct Reservation {
+    budget: Arc<ReceiveBudget>,
+    ticket: u64,
+    held: usize,
+}
+
+impl Reservation {
+    /// Charges a chunk that was read.
+    pub(crate) fn charge(&mut self, bytes: usize) {
+        self.budget.state.lock().unwrap().used += bytes;
+        self.held += bytes;
+    }
+    /// Waits until the budget allows reading more, or this is the oldest receive in progress.
+    pub(crate) async fn wait(&self) {
+        loop {
+            let released = self.budget.released.notified();
+            tokio::pin!(released);
+            // Register for notifications before checking, so a release in between is not missed.
+            released.as_mut().enable();
+            {
+                let state = self.budget.state.lock().unwrap();
+                let within = state.limit.is_none_or(|limit| state.used < limit);
+                if within || state.active.first() == Some(&self.ticket) {
+                    return;
+                }
+            }
+            released.await;
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut state = self.budget.state.lock().unwrap();
+        state.used -= self.held;
+        state.active.remove(&self.ticket);
+        drop(state);
+        self.budget.released.notify_waiters();
+    }
+}
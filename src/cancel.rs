@@ -0,0 +1,18 @@
+//! Cancellation support for long-running operations.
+
+use std::fmt;
+
+pub use tokio_util::sync::CancellationToken;
+pub use crate::codes::STREAM_CANCELLED_CODE;
+
+/// Error returned when an operation is aborted through its `CancellationToken`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
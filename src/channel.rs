@@ -0,0 +1,321 @@
+//! Message channels with per-channel reliability, like WebRTC data channels.
+//!
+//! A `ChannelHost` takes over the streams and datagrams a peer opens on a connection and multiplexes
+//! channels over them. `ChannelHost::open` negotiates a channel with the peer, which receives it from
+//! `ChannelHost::accept`. Each channel is labelled and has a `Reliability` chosen by the side opening it:
+//!
+//! - `ReliableOrdered` messages are framed on the bi-directional stream the channel was negotiated on.
+//! - `ReliableUnordered` messages are each sent on their own stream.
+//! - `MaxLifetime` messages are sent like unordered ones, but their stream is reset with
+//!   `MESSAGE_EXPIRED_CODE` if they are not acknowledged within the lifetime.
+//! - `MaxRetransmits` messages are sent as datagrams if no retransmission is allowed. Otherwise, as quinn
+//!   does not expose retransmissions, they are sent like `MaxLifetime` messages with a lifetime of one
+//!   probe timeout per transmission, estimated as three round-trip times.
+//!
+//! Both peers must use a `ChannelHost` on the connection, which should not carry other streams opened by
+//! the peer or datagrams. Messages of a channel arriving after the peer closed it are dropped.
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::SinkExt;
+use quinn::{Connection, RecvStream, SendDatagramError, SendStream, VarInt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::AbortHandle;
+use crate::error::Error;
+use crate::framing::DEFAULT_MAX_FRAME_SIZE;
+use crate::message::MessageStream;
+use crate::QuicConnection;
+
+pub use crate::codes::MESSAGE_EXPIRED_CODE;
+
+/// The number of received messages buffered for each channel until they are received.
+pub const CHANNEL_BACKLOG: usize = 64;
+/// The number of channels opened by the peer buffered until they are accepted.
+pub const INCOMING_CHANNEL_BACKLOG: usize = 16;
+/// The maximum size of a message, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = DEFAULT_MAX_FRAME_SIZE;
+/// The size of the channel ID at the start of unordered messages and datagrams.
+const CHANNEL_ID_SIZE: usize = 8;
+/// The first byte of a stream negotiating a channel.
+const STREAM_OPEN: u8 = 0x00;
+/// The first byte of a stream carrying an unordered message.
+const STREAM_MESSAGE: u8 = 0x01;
+/// The number of round-trip times a transmission is assumed to take before it is retransmitted.
+const RTTS_PER_TRANSMISSION: u32 = 3;
+
+/// The delivery guarantees of the messages of a channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reliability {
+    /// Every message is delivered, in order.
+    #[default]
+    ReliableOrdered,
+    /// Every message is delivered, in any order.
+    ReliableUnordered,
+    /// Messages are retransmitted at most this many times, and delivered in any order.
+    MaxRetransmits(u16),
+    /// Messages are retransmitted until this long after they were sent, and delivered in any order.
+    MaxLifetime(Duration),
+}
+
+impl Reliability {
+    /// Encodes the reliability as a kind and a parameter.
+    fn encode(&self, buf: &mut BytesMut) {
+        let (kind, parameter) = match *self {
+            Reliability::ReliableOrdered => (0, 0),
+            Reliability::ReliableUnordered => (1, 0),
+            Reliability::MaxRetransmits(retransmits) => (2, u32::from(retransmits)),
+            Reliability::MaxLifetime(lifetime) => (3, lifetime.as_millis().min(u32::MAX as u128) as u32),
+        };
+        buf.put_u8(kind);
+        buf.put_u32(parameter);
+    }
+    /// Decodes a reliability encoded by `encode`.
+    fn decode(buf: &mut Bytes) -> Result<Self> {
+        if buf.len() < 5 {
+            anyhow::bail!("truncated channel reliability");
+        }
+        let kind = buf.get_u8();
+        let parameter = buf.get_u32();
+        match kind {
+            0 => Ok(Reliability::ReliableOrdered),
+            1 => Ok(Reliability::ReliableUnordered),
+            2 => Ok(Reliability::MaxRetransmits(parameter.min(u16::MAX as u32) as u16)),
+            3 => Ok(Reliability::MaxLifetime(Duration::from_millis(u64::from(parameter)))),
+            _ => anyhow::bail!("unknown channel reliability {}", kind),
+        }
+    }
+}
+
+type Channels = Arc<std::sync::Mutex<HashMap<u64, mpsc::Sender<Bytes>>>>;
+
+/// Multiplexes channels over a connection.
+pub struct ChannelHost {
+    connection: Connection,
+    channels: Channels,
+    incoming: Mutex<mpsc::Receiver<Channel>>,
+    tasks: Vec<AbortHandle>,
+}
+
+impl ChannelHost {
+    /// Starts multiplexing channels over `connection`.
+    pub fn new(connection: &QuicConnection) -> Self {
+        let connection = connection.connection.clone();
+        let channels = Channels::default();
+        let (incoming_tx, incoming) = mpsc::channel(INCOMING_CHANNEL_BACKLOG);
+        let tasks = vec![
+            tokio::spawn(accept_streams(connection.clone(), Arc::clone(&channels), incoming_tx)).abort_handle(),
+            tokio::spawn(accept_datagrams(connection.clone(), Arc::clone(&channels))).abort_handle(),
+        ];
+        Self { connection, channels, incoming: Mutex::new(incoming), tasks }
+    }
+    /// Opens a channel, returning once the peer has registered it.
+    pub async fn open(&self, label: &str, reliability: Reliability) -> Result<Channel> {
+        let (mut send, recv) = self.connection.open_bi().await.map_err(Error::from)?;
+        let id = u64::from(send.id());
+        send.write_all(&[STREAM_OPEN]).await.map_err(Error::from)?;
+        let mut stream = MessageStream::new(send, recv);
+        let mut request = BytesMut::new();
+        reliability.encode(&mut request);
+        request.put_slice(label.as_bytes());
+        stream.send_message(request.freeze()).await?;
+        if stream.receive_message().await?.is_none() {
+            anyhow::bail!("the peer refused channel {:?}", label);
+        }
+        Ok(Channel::new(id, label.to_string(), reliability, self.connection.clone(), stream, &self.channels))
+    }
+    /// Accepts the next channel opened by the peer.
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn accept(&self) -> Option<Channel> {
+        self.incoming.lock().await.recv().await
+    }
+}
+
+impl Drop for ChannelHost {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// A message channel negotiated with the peer.
+pub struct Channel {
+    id: u64,
+    label: String,
+    reliability: Reliability,
+    connection: Connection,
+    stream: MessageStream,
+    messages: mpsc::Receiver<Bytes>,
+    channels: Channels,
+}
+
+impl Channel {
+    /// Creates a channel negotiated on `stream` and registers it to receive messages.
+    fn new(id: u64, label: String, reliability: Reliability, connection: Connection, stream: MessageStream, channels: &Channels) -> Self {
+        let (messages_tx, messages) = mpsc::channel(CHANNEL_BACKLOG);
+        channels.lock().unwrap().insert(id, messages_tx);
+        Self { id, label, reliability, connection, stream, messages, channels: Arc::clone(channels) }
+    }
+    /// Returns the label of the channel.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+    /// Returns the delivery guarantees of the channel.
+    pub fn reliability(&self) -> Reliability {
+        self.reliability
+    }
+    /// Sends a message.
+    ///
+    /// Returns once the message is queued, so only `ReliableOrdered` channels apply backpressure.
+    pub async fn send(&mut self, message: Bytes) -> Result<()> {
+        if message.len() > MAX_MESSAGE_SIZE {
+            anyhow::bail!("message of {} bytes exceeds the maximum of {} bytes", message.len(), MAX_MESSAGE_SIZE);
+        }
+        let lifetime = match self.reliability {
+            Reliability::ReliableOrdered => return self.stream.send(message).await,
+            Reliability::ReliableUnordered => None,
+            Reliability::MaxRetransmits(0) => return self.send_datagram(message),
+            Reliability::MaxRetransmits(retransmits) => {
+                let round_trips = (u32::from(retransmits) + 1) * RTTS_PER_TRANSMISSION;
+                Some(self.connection.rtt() * round_trips)
+            },
+            Reliability::MaxLifetime(lifetime) => Some(lifetime),
+        };
+        let (send, _) = self.connection.open_bi().await.map_err(Error::from)?;
+        tokio::spawn(send_unordered(send, self.id, message, lifetime));
+        Ok(())
+    }
+    /// Sends a message as a datagram.
+    fn send_datagram(&self, message: Bytes) -> Result<()> {
+        let mut datagram = BytesMut::with_capacity(CHANNEL_ID_SIZE + message.len());
+        datagram.put_u64(self.id);
+        datagram.put_slice(&message);
+        match self.connection.send_datagram(datagram.freeze()) {
+            Ok(()) => Ok(()),
+            Err(SendDatagramError::ConnectionLost(e)) => Err(Error::from(e).into()),
+            Err(e) => Err(anyhow::anyhow!("failed to send datagram: {}", e)),
+        }
+    }
+    /// Receives the next message.
+    ///
+    /// Returns `None` once the peer has closed the channel.
+    pub async fn receive(&mut self) -> Result<Option<Bytes>> {
+        tokio::select! {
+            biased;
+            Some(message) = self.messages.recv() => Ok(Some(message)),
+            message = self.stream.receive_message() => message,
+        }
+    }
+    /// Closes the channel, after the messages sent on `ReliableOrdered` channels are delivered.
+    pub async fn close(mut self) -> Result<()> {
+        self.stream.finish().await
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.channels.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Sends an unordered message on its own stream, resetting it if it is not acknowledged within `lifetime`.
+async fn send_unordered(mut send: SendStream, id: u64, message: Bytes, lifetime: Option<Duration>) {
+    let transfer = async {
+        send.write_all(&[STREAM_MESSAGE]).await?;
+        send.write_all(&id.to_be_bytes()).await?;
+        send.write_chunk(message).await?;
+        send.finish()?;
+        send.stopped().await?;
+        anyhow::Ok(())
+    };
+    let result = match lifetime {
+        Some(lifetime) => match tokio::time::timeout(lifetime, transfer).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::trace!("Message on channel {} expired", id);
+                let _ = send.reset(VarInt::from_u32(MESSAGE_EXPIRED_CODE));
+                return;
+            },
+        },
+        None => transfer.await,
+    };
+    if let Err(e) = result {
+        tracing::debug!("Failed to send message on channel {}: {}", id, e);
+    }
+}
+
+/// Dispatches the streams opened by the peer, negotiating channels or carrying unordered messages.
+async fn accept_streams(connection: Connection, channels: Channels, incoming: mpsc::Sender<Channel>) {
+    while let Ok((send, mut recv)) = connection.accept_bi().await {
+        let connection = connection.clone();
+        let channels = Arc::clone(&channels);
+        let incoming = incoming.clone();
+        tokio::spawn(async move {
+            let mut kind = [0; 1];
+            let result = match recv.read_exact(&mut kind).await.map_err(Error::from) {
+                Ok(()) if kind[0] == STREAM_OPEN => accept_channel(connection, &channels, incoming, send, recv).await,
+                Ok(()) if kind[0] == STREAM_MESSAGE => receive_message(&channels, recv).await,
+                Ok(()) => Err(anyhow::anyhow!("unknown stream type {}", kind[0])),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                tracing::debug!("Failed to handle channel stream: {}", e);
+            }
+        });
+    }
+}
+
+/// Reads the request of a channel opened by the peer and confirms it.
+async fn accept_channel(connection: Connection, channels: &Channels, incoming: mpsc::Sender<Channel>, send: SendStream, recv: RecvStream) -> Result<()> {
+    let id = u64::from(send.id());
+    let mut stream = MessageStream::new(send, recv);
+    let Some(mut request) = stream.receive_message().await? else {
+        return Ok(());
+    };
+    let reliability = Reliability::decode(&mut request)?;
+    let label = String::from_utf8(request.to_vec())?;
+    let mut channel = Channel::new(id, label, reliability, connection, stream, channels);
+    channel.stream.send_message(Bytes::new()).await?;
+    if incoming.send(channel).await.is_err() {
+        anyhow::bail!("channel host dropped before accepting a channel");
+    }
+    Ok(())
+}
+
+/// Reads an unordered message and delivers it to its channel.
+async fn receive_message(channels: &Channels, mut recv: RecvStream) -> Result<()> {
+    let message = recv.read_to_end(CHANNEL_ID_SIZE + MAX_MESSAGE_SIZE).await.map_err(Error::from)?;
+    deliver(channels, Bytes::from(message), true).await;
+    Ok(())
+}
+
+/// Delivers the messages sent as datagrams to their channels.
+async fn accept_datagrams(connection: Connection, channels: Channels) {
+    while let Ok(datagram) = connection.read_datagram().await {
+        deliver(&channels, datagram, false).await;
+    }
+}
+
+/// Delivers a message prefixed with its channel ID, dropping it if the channel is gone. Reliable messages
+/// wait for room in the backlog of the channel, unreliable ones are dropped if it is full.
+async fn deliver(channels: &Channels, mut message: Bytes, reliable: bool) {
+    if message.len() < CHANNEL_ID_SIZE {
+        tracing::debug!("Dropping message of {} bytes without channel ID", message.len());
+        return;
+    }
+    let id = message.get_u64();
+    let channel = channels.lock().unwrap().get(&id).cloned();
+    match channel {
+        Some(channel) if reliable => {
+            let _ = channel.send(message).await;
+        },
+        Some(channel) => {
+            let _ = channel.try_send(message);
+        },
+        None => tracing::trace!("Dropping message for unknown channel {}", id),
+    }
+}
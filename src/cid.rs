@@ -0,0 +1,174 @@
+//! Connection ID generation.
+//!
+//! Every connection ID an endpoint issues is chosen by its generator, and the peer puts it on the
+//! packets it sends to the endpoint. quinn generates random 8-byte IDs by default. Load balancers
+//! routing QUIC by connection ID, such as those following the QUIC-LB draft, need every ID to tell which
+//! server it belongs to instead: `RoutableConnectionIds` embeds a server ID in them. Other schemes can be
+//! plugged in by implementing `ConnectionIdGenerator`. Clients that never migrate can do without
+//! connection IDs, see `SocketConfig::with_zero_length_connection_ids`.
+//!
+//! The generator is set with `SocketConfig::with_connection_ids`, or with `SocketConfig::behind_load_balancer`
+//! along with the other settings servers behind a load balancer need.
+
+use anyhow::Result;
+use quinn_proto::InvalidCid;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use quinn::{ConnectionId, ConnectionIdGenerator};
+
+/// The maximum length of a connection ID, in bytes.
+pub const MAX_CONNECTION_ID_LENGTH: usize = 20;
+/// The default length of the random nonce of `RoutableConnectionIds`, in bytes.
+pub const DEFAULT_NONCE_LENGTH: usize = 8;
+/// The minimum length of the random nonce of `RoutableConnectionIds`, in bytes.
+pub const MIN_NONCE_LENGTH: usize = 4;
+/// The maximum length of the server ID of `RoutableConnectionIds`, in bytes.
+pub const MAX_SERVER_ID_LENGTH: usize = 15;
+/// The highest config rotation ID of `RoutableConnectionIds`. The next one is reserved by QUIC-LB.
+pub const MAX_CONFIG_ID: u8 = 6;
+
+/// Creates a connection ID generator.
+type Factory = Arc<dyn Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync>;
+
+/// How an endpoint generates its connection IDs.
+#[derive(Clone)]
+pub struct ConnectionIds {
+    factory: Factory,
+    id_length: usize,
+}
+
+impl ConnectionIds {
+    /// Generates IDs with generators created by `factory`.
+    ///
+    /// quinn creates a generator per endpoint. All generators must produce IDs of the same length.
+    pub fn new<F, G>(factory: F) -> Self
+    where
+        F: Fn() -> G + Send + Sync + 'static,
+        G: ConnectionIdGenerator + 'static,
+    {
+        let id_length = factory().cid_len();
+        Self { factory: Arc::new(move || Box::new(factory())), id_length }
+    }
+    /// Generates random IDs of `length` bytes, at most `MAX_CONNECTION_ID_LENGTH`.
+    pub fn random(length: usize) -> Self {
+        Self::new(move || quinn_proto::RandomConnectionIdGenerator::new(length.min(MAX_CONNECTION_ID_LENGTH)))
+    }
+    /// Generates IDs carrying a server ID, see `RoutableConnectionIds`.
+    ///
+    /// Fails if the settings do not fit in a connection ID.
+    pub fn routable(ids: RoutableConnectionIds) -> Result<Self> {
+        ids.validate()?;
+        Ok(Self::new(move || ids.clone()))
+    }
+    /// Returns the length of the generated IDs, in bytes.
+    pub fn id_length(&self) -> usize {
+        self.id_length
+    }
+    /// Creates a generator.
+    pub(crate) fn generator(&self) -> Box<dyn ConnectionIdGenerator> {
+        (self.factory)()
+    }
+}
+
+impl fmt::Debug for ConnectionIds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionIds").field("id_length", &self.id_length).finish_non_exhaustive()
+    }
+}
+
+/// Generates connection IDs carrying a server ID in the clear, as the plaintext algorithm of QUIC-LB.
+///
+/// The first byte of an ID holds the config rotation ID in its 3 high bits and the length of the rest
+/// of the ID in its 5 low bits. The server ID follows, then a random nonce. A load balancer configured
+/// with the same config ID and server ID length routes every packet to the server whose ID it carries,
+/// see `server_id`. Each server behind the load balancer needs a distinct server ID.
+///
+/// The server ID is visible to anyone on the path, so it should not carry information of its own.
+#[derive(Debug, Clone)]
+pub struct RoutableConnectionIds {
+    config_id: u8,
+    server_id: Vec<u8>,
+    nonce_length: usize,
+    lifetime: Option<Duration>,
+}
+
+impl RoutableConnectionIds {
+    /// Creates IDs with the given config rotation ID, at most `MAX_CONFIG_ID`, and server ID, of 1 to
+    /// `MAX_SERVER_ID_LENGTH` bytes, followed by a nonce of `DEFAULT_NONCE_LENGTH` bytes.
+    pub fn new(config_id: u8, server_id: &[u8]) -> Self {
+        Self { config_id, server_id: server_id.to_vec(), nonce_length: DEFAULT_NONCE_LENGTH, lifetime: None }
+    }
+    /// Sets the length of the random nonce, at least `MIN_NONCE_LENGTH` bytes. Longer nonces make IDs
+    /// harder to link for observers, at the cost of a few bytes per packet.
+    pub fn with_nonce_length(mut self, length: usize) -> Self {
+        self.nonce_length = length;
+        self
+    }
+    /// Retires IDs after `lifetime`, replacing them with new ones. By default IDs are kept for the
+    /// lifetime of the connection.
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = Some(lifetime);
+        self
+    }
+    /// Returns the length of the generated IDs, in bytes.
+    pub fn id_length(&self) -> usize {
+        1 + self.server_id.len() + self.nonce_length
+    }
+    /// Checks that the settings fit in a connection ID.
+    fn validate(&self) -> Result<()> {
+        if self.config_id > MAX_CONFIG_ID {
+            anyhow::bail!("config ID {} exceeds the maximum of {}", self.config_id, MAX_CONFIG_ID);
+        }
+        if self.server_id.is_empty() || self.server_id.len() > MAX_SERVER_ID_LENGTH {
+            anyhow::bail!("server ID must be 1 to {} bytes long", MAX_SERVER_ID_LENGTH);
+        }
+        if self.nonce_length < MIN_NONCE_LENGTH {
+            anyhow::bail!("nonce must be at least {} bytes long", MIN_NONCE_LENGTH);
+        }
+        if self.id_length() > MAX_CONNECTION_ID_LENGTH {
+            anyhow::bail!("connection ID of {} bytes exceeds the maximum of {} bytes", self.id_length(), MAX_CONNECTION_ID_LENGTH);
+        }
+        Ok(())
+    }
+    /// Returns the first byte of the generated IDs.
+    fn first_byte(&self) -> u8 {
+        (self.config_id << 5) | (self.id_length() - 1) as u8
+    }
+}
+
+impl ConnectionIdGenerator for RoutableConnectionIds {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut id = [0u8; MAX_CONNECTION_ID_LENGTH];
+        id[0] = self.first_byte();
+        id[1..1 + self.server_id.len()].copy_from_slice(&self.server_id);
+        SystemRandom::new().fill(&mut id[1 + self.server_id.len()..self.id_length()]).expect("failed to generate connection ID nonce");
+        ConnectionId::new(&id[..self.id_length()])
+    }
+
+    fn validate(&self, id: &ConnectionId) -> Result<(), InvalidCid> {
+        if id.len() == self.id_length() && id[0] == self.first_byte() && id[1..1 + self.server_id.len()] == self.server_id[..] {
+            Ok(())
+        } else {
+            Err(InvalidCid)
+        }
+    }
+
+    fn cid_len(&self) -> usize {
+        self.id_length()
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+}
+
+/// Returns the config rotation ID and server ID carried by a connection ID generated by
+/// `RoutableConnectionIds` with server IDs of `server_id_length` bytes, as a load balancer reads them.
+pub fn server_id(id: &[u8], server_id_length: usize) -> Option<(u8, &[u8])> {
+    let first = *id.first()?;
+    let server_id = id.get(1..1 + server_id_length)?;
+    (usize::from(first & 0x1f) + 1 == id.len()).then_some((first >> 5, server_id))
+}
@@ -0,0 +1,112 @@
+//! Why a connection was closed.
+//!
+//! quinn reports the end of a connection as a `quinn::ConnectionError`, which does not tell which code a
+//! connection closed locally was closed with. `CloseReason` sorts the cases an application usually reacts
+//! to differently, and carries the code and reason of local closes made through `QuicConnection`.
+//! It is returned by `QuicConnection::close_reason` and carried by `SocketEvent::ConnectionClosed`.
+
+use bytes::Bytes;
+use quinn::ConnectionError;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Why a connection was closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CloseReason {
+    /// No packet was received from the peer for the idle timeout.
+    IdleTimeout,
+    /// The connection was closed by this endpoint.
+    ///
+    /// The code and reason are known for closes through `QuicConnection`, such as `close_with`, and for
+    /// the closes of quicsock itself. They are `None` and empty for closes through the quinn connection.
+    LocallyClosed {
+        /// The application error code.
+        code: Option<u64>,
+        /// The reason sent to the peer.
+        reason: Bytes,
+    },
+    /// The peer closed the connection with an application error code.
+    PeerClosed {
+        /// The application error code.
+        code: u64,
+        /// The reason sent by the peer.
+        reason: Bytes,
+    },
+    /// The connection was closed with a QUIC transport error, detected by either endpoint.
+    TransportError {
+        /// The transport error code.
+        code: u64,
+    },
+    /// The peer sent a stateless reset, e.g. because it restarted and lost the connection's state.
+    Reset,
+    /// The peer does not support any of the QUIC versions offered.
+    VersionMismatch,
+}
+
+impl CloseReason {
+    /// Sorts a quinn connection error, using the recorded local close, if any.
+    pub(crate) fn new(error: &ConnectionError, local: &LocalClose) -> Self {
+        match error {
+            ConnectionError::TimedOut => CloseReason::IdleTimeout,
+            ConnectionError::LocallyClosed | ConnectionError::CidsExhausted => {
+                let (code, reason) = local.get().map_or((None, Bytes::new()), |(code, reason)| (Some(code), reason));
+                CloseReason::LocallyClosed { code, reason }
+            },
+            ConnectionError::ApplicationClosed(close) => CloseReason::PeerClosed {
+                code: close.error_code.into_inner(),
+                reason: close.reason.clone(),
+            },
+            ConnectionError::ConnectionClosed(close) => CloseReason::TransportError { code: u64::from(close.error_code) },
+            ConnectionError::TransportError(error) => CloseReason::TransportError { code: u64::from(error.code) },
+            ConnectionError::Reset => CloseReason::Reset,
+            ConnectionError::VersionMismatch => CloseReason::VersionMismatch,
+        }
+    }
+    /// Returns the application or transport error code, if any.
+    pub fn code(&self) -> Option<u64> {
+        match self {
+            CloseReason::LocallyClosed { code, .. } => *code,
+            CloseReason::PeerClosed { code, .. } | CloseReason::TransportError { code } => Some(*code),
+            _ => None,
+        }
+    }
+    /// Returns the application error code of a close by either peer, if known.
+    ///
+    /// Unlike transport error codes, it can be interpreted with `codes::describe`.
+    pub fn application_code(&self) -> Option<u64> {
+        match self {
+            CloseReason::LocallyClosed { code, .. } => *code,
+            CloseReason::PeerClosed { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::IdleTimeout => write!(f, "idle timeout"),
+            CloseReason::LocallyClosed { code: Some(code), reason } => write!(f, "closed locally: error {} ({})", code, String::from_utf8_lossy(reason)),
+            CloseReason::LocallyClosed { code: None, .. } => write!(f, "closed locally"),
+            CloseReason::PeerClosed { code, reason } => write!(f, "closed by peer: error {} ({})", code, String::from_utf8_lossy(reason)),
+            CloseReason::TransportError { code } => write!(f, "transport error {:#x}", code),
+            CloseReason::Reset => write!(f, "reset by peer"),
+            CloseReason::VersionMismatch => write!(f, "version mismatch"),
+        }
+    }
+}
+
+/// The code and reason a connection was first closed with locally, shared with the tasks watching it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LocalClose(Arc<Mutex<Option<(u64, Bytes)>>>);
+
+impl LocalClose {
+    /// Records a close, unless the connection was closed already, as quinn only sends the first one.
+    pub(crate) fn record(&self, code: u64, reason: &[u8]) {
+        self.0.lock().unwrap().get_or_insert_with(|| (code, Bytes::copy_from_slice(reason)));
+    }
+    fn get(&self) -> Option<(u64, Bytes)> {
+        self.0.lock().unwrap().clone()
+    }
+}
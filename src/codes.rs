@@ -0,0 +1,59 @@
+//! Application error codes used by quicsock.
+//!
+//! quicsock resets and stops streams and closes connections with its own application error codes, e.g.
+//! when a stream exceeds its quota or a connection outlives a shutdown. They are all in `RESERVED`, so a
+//! peer can tell them from the codes of the application and interpret them with `describe`.
+//! Applications should pick their own codes outside of this range. `QuicConnection::close` uses
+//! `NO_ERROR_CODE`.
+//!
+//! The codes are also exported by the modules that use them, e.g. `cancel::STREAM_CANCELLED_CODE`.
+
+use std::ops::RangeInclusive;
+
+/// The application error codes reserved for quicsock.
+pub const RESERVED: RangeInclusive<u32> = 0x10..=0xff;
+
+/// The application error code of a connection closed normally, without an error.
+pub const NO_ERROR_CODE: u32 = 0x00;
+/// The application error code used to reset or stop a stream whose operation was cancelled.
+pub const STREAM_CANCELLED_CODE: u32 = 0x10;
+/// The application error code used to reject a stream exceeding the peer's stream quota.
+pub const STREAM_QUOTA_EXCEEDED_CODE: u32 = 0x11;
+/// The application error code used to close connections that outlive the drain timeout of a shutdown.
+pub const SHUTDOWN_CODE: u32 = 0x12;
+/// The application error code used to close the connections of peers disconnected for lagging.
+pub const LAGGING_CODE: u32 = 0x13;
+/// The application error code a connection is closed with when its last `QuicConnection` handle is dropped.
+pub const DROPPED_CODE: u32 = 0x14;
+/// The application error code used to reset or stop a stream whose deadline passed.
+pub const STREAM_DEADLINE_CODE: u32 = 0x15;
+/// The application error code used to reset or stop a stream that was idle for too long.
+pub const STREAM_IDLE_CODE: u32 = 0x16;
+/// The application error code used to close a connection whose peer failed to authenticate, e.g. by
+/// pairing with the wrong code.
+pub const AUTH_FAILED_CODE: u32 = 0x17;
+/// The application error code used to reset the stream of a channel message whose lifetime passed.
+pub const MESSAGE_EXPIRED_CODE: u32 = 0x18;
+
+/// Returns whether an application error code is reserved for quicsock.
+pub fn is_reserved(code: u64) -> bool {
+    u32::try_from(code).is_ok_and(|code| RESERVED.contains(&code))
+}
+
+/// Returns a short description of an application error code used by quicsock, or `None` for other codes.
+pub fn describe(code: u64) -> Option<&'static str> {
+    let description = match u32::try_from(code).ok()? {
+        NO_ERROR_CODE => "no error",
+        STREAM_CANCELLED_CODE => "stream cancelled",
+        STREAM_QUOTA_EXCEEDED_CODE => "stream quota exceeded",
+        SHUTDOWN_CODE => "shutting down",
+        LAGGING_CODE => "peer lagging",
+        DROPPED_CODE => "connection dropped",
+        STREAM_DEADLINE_CODE => "stream deadline exceeded",
+        STREAM_IDLE_CODE => "stream idle",
+        AUTH_FAILED_CODE => "authentication failed",
+        MESSAGE_EXPIRED_CODE => "message expired",
+        _ => return None,
+    };
+    Some(description)
+}
@@ -0,0 +1,355 @@
+//! Socket configuration.
+
+use rustls::client::ClientSessionStore;
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::cid::ConnectionIds;
+use crate::connection::DEFAULT_PATH_CHECK_INTERVAL;
+use crate::endpoint::{ServerVerification, UdpBackend};
+use crate::flood::FloodProtection;
+use crate::liveness::LivenessThresholds;
+use crate::offload::UdpOffload;
+use crate::pacing::Pacing;
+use crate::reset::StatelessResetKey;
+use crate::socket::RegistryMode;
+use crate::retry::RetryTokenKey;
+use crate::tls::crypto::{CipherSuite, KeyExchangeGroup};
+use crate::tls::ct::CertificateTransparency;
+use crate::transport::{Profile, TransportOptions};
+use crate::version::QuicVersion;
+use crate::zerortt::ZeroRttPolicy;
+
+/// Configuration for sockets created with a config, such as `QuicSocket::new_peer`.
+///
+/// Built by chaining the `with_*` methods on `SocketConfig::new()`.
+#[derive(Debug, Clone)]
+pub struct SocketConfig {
+    pub(crate) cert_path: Option<PathBuf>,
+    pub(crate) key_path: Option<PathBuf>,
+    pub(crate) verification: ServerVerification,
+    pub(crate) alpn_protocols: Vec<Vec<u8>>,
+    pub(crate) session_store: Option<Arc<dyn ClientSessionStore>>,
+    pub(crate) transport: TransportOptions,
+    pub(crate) reset_key: Option<Arc<StatelessResetKey>>,
+    pub(crate) connection_ids: Option<ConnectionIds>,
+    pub(crate) migration: Option<bool>,
+    pub(crate) preferred_address_v4: Option<SocketAddrV4>,
+    pub(crate) preferred_address_v6: Option<SocketAddrV6>,
+    pub(crate) retry_token_key: Option<Arc<dyn RetryTokenKey>>,
+    pub(crate) cipher_suites: Vec<CipherSuite>,
+    pub(crate) kx_groups: Vec<KeyExchangeGroup>,
+    pub(crate) transparency: Option<CertificateTransparency>,
+    pub(crate) crl_paths: Vec<PathBuf>,
+    pub(crate) crls: Vec<Vec<u8>>,
+    pub(crate) offload: Option<UdpOffload>,
+    pub(crate) pacing: Option<Pacing>,
+    pub(crate) udp_backend: UdpBackend,
+    pub(crate) flood_protection: Option<FloodProtection>,
+    pub(crate) quic_versions: Option<Vec<QuicVersion>>,
+    pub(crate) zero_rtt: Option<ZeroRttPolicy>,
+    pub(crate) receive_budget: Option<usize>,
+    pub(crate) idle_stream_timeout: Option<Duration>,
+    pub(crate) path_check_interval: Option<Duration>,
+    pub(crate) liveness_thresholds: Option<LivenessThresholds>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    pub(crate) registry_mode: RegistryMode,
+    #[cfg(feature = "sim")]
+    pub(crate) network_conditions: Option<crate::sim::NetworkConditions>,
+}
+
+impl SocketConfig {
+    /// Creates a new configuration.
+    ///
+    /// By default a self-signed certificate is generated, and remote certificates are verified
+    /// against the platform's native root certificates (see `ServerVerification::NativeRoots`).
+    /// Without the `native-certs` feature no remote certificate is trusted until trusted certificates
+    /// are set with `with_verification`.
+    pub fn new() -> Self {
+        Self {
+            cert_path: None,
+            key_path: None,
+            #[cfg(feature = "native-certs")]
+            verification: ServerVerification::NativeRoots,
+            #[cfg(not(feature = "native-certs"))]
+            verification: ServerVerification::Certificates(Vec::new()),
+            alpn_protocols: Vec::new(),
+            session_store: None,
+            transport: TransportOptions::new(),
+            reset_key: None,
+            connection_ids: None,
+            migration: None,
+            preferred_address_v4: None,
+            preferred_address_v6: None,
+            retry_token_key: None,
+            cipher_suites: Vec::new(),
+            kx_groups: Vec::new(),
+            transparency: None,
+            crl_paths: Vec::new(),
+            crls: Vec::new(),
+            offload: None,
+            pacing: None,
+            udp_backend: UdpBackend::Tokio,
+            flood_protection: None,
+            quic_versions: None,
+            zero_rtt: None,
+            receive_budget: None,
+            idle_stream_timeout: None,
+            path_check_interval: None,
+            liveness_thresholds: None,
+            handshake_timeout: None,
+            registry_mode: RegistryMode::Strong,
+            #[cfg(feature = "sim")]
+            network_conditions: None,
+        }
+    }
+    /// Uses the certificate and private key at the given paths (PEM or DER format)
+    /// instead of a self-signed certificate.
+    pub fn with_cert(mut self, cert_path: &Path, key_path: &Path) -> Self {
+        self.cert_path = Some(cert_path.to_path_buf());
+        self.key_path = Some(key_path.to_path_buf());
+        self
+    }
+    /// Sets how the certificates of remote servers are verified when connecting.
+    pub fn with_verification(mut self, verification: ServerVerification) -> Self {
+        self.verification = verification;
+        self
+    }
+    /// Sets the ALPN protocols offered and accepted during the handshake, in order of preference.
+    pub fn with_alpn_protocols(mut self, alpn_protocols: &[&[u8]]) -> Self {
+        self.alpn_protocols = alpn_protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+    /// Sets the store used to cache TLS sessions for resumption when connecting.
+    ///
+    /// Sessions are kept for the life of the process: rustls does not expose their contents, so they
+    /// cannot be saved across process restarts.
+    pub fn with_session_store(mut self, session_store: Arc<dyn ClientSessionStore>) -> Self {
+        self.session_store = Some(session_store);
+        self
+    }
+    /// Sets the transport options applied to every connection of the socket.
+    pub fn with_transport(mut self, transport: TransportOptions) -> Self {
+        self.transport = transport;
+        self
+    }
+    /// Configures the transport of every connection of the socket with a profile.
+    ///
+    /// Same as setting the profile of the options passed to `with_transport`, see `TransportOptions::with_profile`.
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.transport.profile = Some(profile);
+        self
+    }
+    /// Sets the key used to derive stateless reset tokens.
+    ///
+    /// By default a random key is generated for every endpoint. See `reset::StatelessResetKey`.
+    pub fn with_reset_key(mut self, reset_key: Arc<StatelessResetKey>) -> Self {
+        self.reset_key = Some(reset_key);
+        self
+    }
+    /// Sets how the socket generates its connection IDs. See `cid`.
+    ///
+    /// By default connection IDs are 8 random bytes.
+    pub fn with_connection_ids(mut self, connection_ids: ConnectionIds) -> Self {
+        self.connection_ids = Some(connection_ids);
+        self
+    }
+    /// Uses zero-length connection IDs, for clients on bandwidth-constrained links.
+    ///
+    /// Servers then send packets without a destination connection ID, saving 8 bytes per packet with
+    /// quinn's default IDs. Packets are matched to connections by the server's address instead, so
+    /// the client cannot migrate: a NAT rebinding or network change breaks its connections. Only
+    /// client sockets support it; creating a server or peer socket with it fails.
+    pub fn with_zero_length_connection_ids(self) -> Self {
+        self.with_connection_ids(ConnectionIds::random(0))
+    }
+    /// Sets whether servers let clients migrate to a new address, e.g. after a NAT rebinding or a switch
+    /// from Wi-Fi to cellular. Connections of clients migrating while it is disabled stop working.
+    ///
+    /// By default migration is allowed.
+    pub fn with_migration(mut self, enabled: bool) -> Self {
+        self.migration = Some(enabled);
+        self
+    }
+    /// Configures a server for operation behind a stateless layer 4 load balancer routing QUIC packets
+    /// by connection ID, such as one following the QUIC-LB draft.
+    ///
+    /// - Connection IDs are generated with `connection_ids`, usually `ConnectionIds::routable`, so the
+    ///   load balancer sends every packet of a connection to this server. Each server needs its own
+    ///   server ID, while the config ID and server ID length must match the load balancer's settings.
+    /// - Migration is disabled, as load balancers falling back to hashing the address for packets they
+    ///   cannot route would send a migrated client's packets elsewhere. Call `with_migration(true)`
+    ///   afterwards if the load balancer routes every packet by connection ID.
+    /// - Stateless resets use `reset_key`, which should be the same on every server behind the load
+    ///   balancer and persisted across restarts, see `reset::StatelessResetKey::load_or_generate`.
+    ///   Packets of a connection whose server restarted or went away then get a stateless reset from
+    ///   whichever server they reach, so the client reconnects at once instead of waiting for the idle
+    ///   timeout. With per-server keys, resets from other servers are ignored by the client.
+    ///
+    /// Servers also validating address tokens of one another should share a key set with `with_retry_token_key`.
+    pub fn behind_load_balancer(self, connection_ids: ConnectionIds, reset_key: Arc<StatelessResetKey>) -> Self {
+        self.with_connection_ids(connection_ids).with_migration(false).with_reset_key(reset_key)
+    }
+    /// Advertises a preferred address to clients, one per address family. Later calls replace the address
+    /// of the same family.
+    ///
+    /// Clients may move their connections to it once the handshake completes, so it must reach the same
+    /// endpoint, e.g. by binding the socket to the unspecified address. quinn clients, including
+    /// quicsock's, stay on the address they connected to.
+    pub fn with_preferred_address(mut self, address: SocketAddr) -> Self {
+        match address {
+            SocketAddr::V4(address) => self.preferred_address_v4 = Some(address),
+            SocketAddr::V6(address) => self.preferred_address_v6 = Some(address),
+        }
+        self
+    }
+    /// Configures a server reached through an anycast address.
+    ///
+    /// Anycast routes every packet to the nearest site, which may change mid-connection when routes do.
+    /// The server advertises its own unicast `preferred_addresses` (see `with_preferred_address`) for
+    /// clients to move their connections to after the handshake, and allows migration, so connections
+    /// survive the move. The paths of connections are checked every `DEFAULT_PATH_CHECK_INTERVAL`, and
+    /// a `SocketEvent::PathChanged` whose `local_ip` is a preferred address shows a client has moved
+    /// off the anycast path.
+    pub fn behind_anycast(self, preferred_addresses: &[SocketAddr]) -> Self {
+        let config = preferred_addresses.iter().fold(self, |config, &address| config.with_preferred_address(address));
+        config.with_migration(true).with_path_check_interval(DEFAULT_PATH_CHECK_INTERVAL)
+    }
+    /// Sets the key minting and validating the retry tokens of servers. See `retry`.
+    ///
+    /// By default tokens are sealed with a random key of the endpoint.
+    pub fn with_retry_token_key(mut self, key: Arc<dyn RetryTokenKey>) -> Self {
+        self.retry_token_key = Some(key);
+        self
+    }
+    /// Restricts the TLS 1.3 cipher suites offered and accepted, in order of preference.
+    ///
+    /// By default all cipher suites of the crypto provider are enabled. Both peers must share at least one
+    /// cipher suite for the handshake to succeed. Initial packets are always protected with AES-128-GCM,
+    /// as required by QUIC, regardless of this setting.
+    pub fn with_cipher_suites(mut self, cipher_suites: &[CipherSuite]) -> Self {
+        self.cipher_suites = cipher_suites.to_vec();
+        self
+    }
+    /// Restricts the key exchange groups offered and accepted, in order of preference.
+    ///
+    /// By default all key exchange groups of the crypto provider are enabled. Both peers must share at least
+    /// one group for the handshake to succeed. A client sends a key share for its first group, so putting the
+    /// group the server prefers first avoids an extra round trip.
+    pub fn with_key_exchange_groups(mut self, groups: &[KeyExchangeGroup]) -> Self {
+        self.kx_groups = groups.to_vec();
+        self
+    }
+    /// Requires server certificates to carry valid SCTs from trusted Certificate Transparency logs.
+    ///
+    /// Applies on top of the verification set with `with_verification`, and cannot be combined with
+    /// `ServerVerification::Insecure`. See `tls::ct`.
+    pub fn with_certificate_transparency(mut self, transparency: CertificateTransparency) -> Self {
+        self.transparency = Some(transparency);
+        self
+    }
+    /// Adds the certificate revocation lists in a file (PEM or DER format), consulted when verifying servers.
+    ///
+    /// The file is read when the socket is created. Can be called several times. See `with_crl`.
+    pub fn with_crl_file(mut self, crl_path: &Path) -> Self {
+        self.crl_paths.push(crl_path.to_path_buf());
+        self
+    }
+    /// Adds a DER-encoded certificate revocation list, consulted when verifying servers.
+    ///
+    /// Only the revocation status of server certificates is checked, and it must be known: once any CRL is
+    /// set, a CRL from the issuer of the server certificate is required for the handshake to succeed.
+    /// Has no effect with `ServerVerification::Insecure`.
+    pub fn with_crl(mut self, crl: &[u8]) -> Self {
+        self.crls.push(crl.to_vec());
+        self
+    }
+    /// Sets the segmentation offload settings of the socket.
+    ///
+    /// By default offload is used wherever the platform supports it. See `offload::UdpOffload`.
+    pub fn with_udp_offload(mut self, offload: UdpOffload) -> Self {
+        self.offload = Some(offload);
+        self
+    }
+    /// Paces the datagrams sent by all connections of the socket to a fixed rate, on top of quinn's own
+    /// pacing of each connection. See `pacing`.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = Some(pacing);
+        self
+    }
+    /// Sets the implementation of UDP I/O. See `endpoint::UdpBackend`.
+    pub fn with_udp_backend(mut self, backend: UdpBackend) -> Self {
+        self.udp_backend = backend;
+        self
+    }
+    /// Protects servers against handshake floods with retries, a per-IP handshake rate limit and a cap on
+    /// handshakes in progress. `FloodProtection::default()` suits most public servers. See `flood`.
+    pub fn with_flood_protection(mut self, protection: FloodProtection) -> Self {
+        self.flood_protection = Some(protection);
+        self
+    }
+    /// Restricts the QUIC versions offered, in order of preference. See `version`.
+    ///
+    /// Servers only accept these versions, and clients connect with the first one. Building the socket fails
+    /// if the list is empty or holds a version quinn does not implement.
+    pub fn with_quic_versions(mut self, versions: &[QuicVersion]) -> Self {
+        self.quic_versions = Some(versions.to_vec());
+        self
+    }
+    /// Enables 0-RTT on servers, with the given policy deciding which connections' early data is
+    /// processed before the handshake completes. See `zerortt`.
+    ///
+    /// By default servers refuse early data, so resuming clients wait for the handshake.
+    pub fn with_zero_rtt(mut self, policy: ZeroRttPolicy) -> Self {
+        self.zero_rtt = Some(policy);
+        self
+    }
+    /// Sets the maximum number of bytes buffered by `receive()` calls in progress across all connections.
+    /// See `QuicSocket::set_receive_budget`.
+    pub fn with_receive_budget(mut self, bytes: usize) -> Self {
+        self.receive_budget = Some(bytes);
+        self
+    }
+    /// Resets and removes streams without read or write activity for the given time.
+    /// See `QuicConnection::set_idle_stream_timeout`.
+    pub fn with_idle_stream_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_stream_timeout = Some(timeout);
+        self
+    }
+    /// Checks the paths of connections at the given interval, emitting a `SocketEvent::PathChanged` when
+    /// one changed. See `QuicConnection::set_path_check_interval`.
+    pub fn with_path_check_interval(mut self, interval: Duration) -> Self {
+        self.path_check_interval = Some(interval);
+        self
+    }
+    /// Watches connections for silent peers, emitting `SocketEvent::PeerSuspect` and `SocketEvent::PeerDead`.
+    /// See `QuicConnection::set_liveness_thresholds`.
+    pub fn with_liveness_thresholds(mut self, thresholds: LivenessThresholds) -> Self {
+        self.liveness_thresholds = Some(thresholds);
+        self
+    }
+    /// Sets the time allowed for the handshakes of `connect()` and of incoming connections to complete.
+    /// See `QuicSocket::set_handshake_timeout`.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+    /// Sets how the socket's registry holds its connections. See `socket::RegistryMode`.
+    pub fn with_registry_mode(mut self, mode: RegistryMode) -> Self {
+        self.registry_mode = mode;
+        self
+    }
+    /// Simulates the given network conditions on datagrams sent by the socket. For tests.
+    #[cfg(feature = "sim")]
+    pub fn with_network_conditions(mut self, conditions: crate::sim::NetworkConditions) -> Self {
+        self.network_conditions = Some(conditions);
+        self
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,15 +3,166 @@
 use anyhow::Result;
 use quinn::{Connection, RecvStream, SendStream};
 use tokio::io::AsyncWriteExt;
-use std::collections::HashMap;
+use tracing::Instrument;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, Mutex, OwnedSemaphorePermit, RwLock, Semaphore};
+use crate::budget::ReceiveBudget;
+use crate::cancel::{CancellationToken, Cancelled, STREAM_CANCELLED_CODE};
+use crate::close::{CloseReason, LocalClose};
+use crate::codes::NO_ERROR_CODE;
+use crate::deadline::{Timeout, STREAM_DEADLINE_CODE, STREAM_IDLE_CODE};
+use crate::error::{self, Error};
+use crate::event::{SocketEvent, EVENT_CHANNEL_CAPACITY};
+use crate::interceptor::Interceptor;
+use crate::liveness::LivenessThresholds;
+use crate::logging::stream_trace;
+use crate::framing::FrameCodec;
+use crate::message::MessageStream;
+use crate::pool::StreamPool;
+use crate::reader::StreamReader;
+use crate::registry::ShardedMap;
+use crate::stats::{IoCounters, IoStats};
+use crate::telemetry;
+use crate::quota::{QuotaExceeded, StreamQuota, STREAM_QUOTA_EXCEEDED_CODE};
+use crate::transport::WindowAutoTune;
+use crate::version::QuicVersion;
+use tokio::task::AbortHandle;
+use tokio_util::codec::Decoder;
+
+pub use crate::codes::DROPPED_CODE;
 
 /// The size of the default send buffer, in bytes.
 pub const DEFAULT_SEND_BUFFER_SIZE: usize = 16384;
 /// The size of the default receive buffer, in bytes.
 pub const DEFAULT_RECEIVE_BUFFER_SIZE: usize = 16384;
 
+/// The number of streams opened by the peer buffered by `incoming_streams` until they are received.
+pub const INCOMING_STREAM_BACKLOG: usize = 32;
+
+/// The default maximum number of handlers of `on_incoming_stream` running at once.
+pub const DEFAULT_STREAM_HANDLER_CONCURRENCY: usize = 64;
+
+/// The number of chunks buffered by `subscribe` until they are received.
+pub const SUBSCRIBE_BACKLOG: usize = 16;
+
+/// The default interval at which the path of a connection is checked for changes.
+pub const DEFAULT_PATH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The type tag of the control stream reporting the observed address of a peer.
+pub const CONTROL_OBSERVED_ADDRESS: u8 = 0x01;
+/// The maximum size of a control stream message, in bytes.
+const MAX_CONTROL_MESSAGE_SIZE: usize = 1024;
+
+/// Data negotiated during the TLS handshake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    /// The server name (SNI) requested by the client, if any.
+    pub server_name: Option<String>,
+    /// The negotiated ALPN protocol, if any.
+    pub alpn_protocol: Option<Vec<u8>>,
+}
+
+impl HandshakeInfo {
+    /// Extracts the handshake data of a quinn connection.
+    pub fn from_connection(connection: &Connection) -> Self {
+        connection
+            .handshake_data()
+            .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+            .map(|data| Self { server_name: data.server_name, alpn_protocol: data.protocol })
+            .unwrap_or_default()
+    }
+}
+
+/// The streams of a connection registered by ID.
+///
+/// Shared with the tasks expiring streams at their deadline or when idle, which may outlive the connection.
+struct Streams {
+    send: ShardedMap<u64, Arc<Mutex<SendStream>>>,
+    recv: ShardedMap<u64, Arc<Mutex<RecvStream>>>,
+    quota_permits: ShardedMap<u64, OwnedSemaphorePermit>,
+    io: ShardedMap<u64, Arc<IoCounters>>,
+    deadlines: ShardedMap<u64, Instant>,
+    /// When data was last read or written on each stream.
+    activity: ShardedMap<u64, Instant>,
+    /// Cancelled when a stream expires, which aborts the operations pending on it.
+    expired: ShardedMap<u64, CancellationToken>,
+}
+
+impl Streams {
+    fn new() -> Self {
+        Self {
+            send: ShardedMap::new(),
+            recv: ShardedMap::new(),
+            quota_permits: ShardedMap::new(),
+            io: ShardedMap::new(),
+            deadlines: ShardedMap::new(),
+            activity: ShardedMap::new(),
+            expired: ShardedMap::new(),
+        }
+    }
+    /// Records that data was read or written on a certain stream.
+    fn touch(&self, stream_id: u64) {
+        self.activity.update(&stream_id, |activity| *activity = Instant::now());
+    }
+    /// Releases the quota slot held by a certain stream, if any.
+    fn release_slot(&self, stream_id: u64) {
+        self.quota_permits.remove(&stream_id);
+    }
+    /// Removes the sending side of a stream that is done, releasing its quota slot.
+    fn remove_send(&self, stream_id: u64) -> Option<Arc<Mutex<SendStream>>> {
+        let send_stream = self.send.remove(&stream_id);
+        self.release_slot(stream_id);
+        self.forget(stream_id);
+        send_stream
+    }
+    /// Removes the receiving side of a stream that is done.
+    fn remove_recv(&self, stream_id: u64) -> Option<Arc<Mutex<RecvStream>>> {
+        let recv_stream = self.recv.remove(&stream_id);
+        self.forget(stream_id);
+        recv_stream
+    }
+    /// Removes the counters, deadline and activity of a stream once both of its sides are removed.
+    fn forget(&self, stream_id: u64) {
+        // Each side is removed before checking the other, so the last one to go always sees both gone.
+        if !self.send.contains_key(&stream_id) && !self.recv.contains_key(&stream_id) {
+            self.io.remove(&stream_id);
+            self.deadlines.remove(&stream_id);
+            self.activity.remove(&stream_id);
+            self.expired.remove(&stream_id);
+        }
+    }
+    /// Removes both sides of an expired stream, resetting and stopping them with the given code.
+    ///
+    /// Returns whether the stream was still registered.
+    async fn expire(&self, stream_id: u64, code: u32) -> bool {
+        // Operations pending on the stream are aborted first, which releases the sides they hold.
+        if let Some(expired) = self.expired.get(&stream_id) {
+            expired.cancel();
+        }
+        let send_stream = self.remove_send(stream_id);
+        let recv_stream = self.remove_recv(stream_id);
+        if send_stream.is_none() && recv_stream.is_none() {
+            return false;
+        }
+        if let Some(send_stream) = send_stream {
+            let _ = send_stream.lock().await.reset(code.into());
+        }
+        if let Some(recv_stream) = recv_stream {
+            let _ = recv_stream.lock().await.stop(code.into());
+        }
+        true
+    }
+    /// Returns the streams without activity since the given instant.
+    fn idle_since(&self, since: Instant) -> Vec<u64> {
+        self.activity.keys_where(|activity| *activity < since)
+    }
+}
+
 /// A QUIC connection that can be used to send and receive data.
 /// 
 /// This struct wraps a `quinn::Connection` and provides a higher-level API for sending and receiving data.
@@ -19,9 +170,24 @@ pub const DEFAULT_RECEIVE_BUFFER_SIZE: usize = 16384;
 /// This is used to manage the state of a QUIC connection, including the state of the send and receive streams.
 pub struct QuicConnection {
     pub connection: Connection,
-    send_streams: Arc<Mutex<HashMap<u64, SendStream>>>,
-    recv_streams: Arc<Mutex<HashMap<u64, RecvStream>>>,
-    stream_id_counter: Arc<Mutex<u64>>,
+    streams: Arc<Streams>,
+    stream_id_counter: AtomicU64,
+    interceptors: RwLock<Vec<Arc<dyn Interceptor>>>,
+    observed_address: std::sync::Mutex<Option<SocketAddr>>,
+    tags: Arc<std::sync::Mutex<BTreeSet<String>>>,
+    stream_quota: std::sync::Mutex<Option<(Arc<Semaphore>, StreamQuota)>>,
+    events: broadcast::Sender<SocketEvent>,
+    stream_pool: StreamPool,
+    io: Arc<IoCounters>,
+    handshake: HandshakeInfo,
+    quic_version: Option<QuicVersion>,
+    receive_budget: Option<Arc<ReceiveBudget>>,
+    idle_reaper: std::sync::Mutex<Option<AbortHandle>>,
+    path_watcher: std::sync::Mutex<Option<AbortHandle>>,
+    liveness_watcher: std::sync::Mutex<Option<AbortHandle>>,
+    stream_handler: std::sync::Mutex<Option<AbortHandle>>,
+    local_close: LocalClose,
+    close_on_drop: bool,
     pub send_buffer_size: usize,
     pub receive_buffer_size: usize,
 }
@@ -30,87 +196,986 @@ impl QuicConnection {
     /// Creates a new QUIC connection with the given `quinn::Connection`.
     pub async fn new(connection: Connection) -> Result<Self> {
         Ok(Self {
+            stream_pool: StreamPool::new(connection.clone()),
+            handshake: HandshakeInfo::from_connection(&connection),
+            quic_version: None,
+            receive_budget: None,
+            idle_reaper: std::sync::Mutex::new(None),
+            path_watcher: std::sync::Mutex::new(None),
+            liveness_watcher: std::sync::Mutex::new(None),
+            stream_handler: std::sync::Mutex::new(None),
+            local_close: LocalClose::default(),
+            close_on_drop: true,
             connection,
-            send_streams: Arc::new(Mutex::new(HashMap::new())),
-            recv_streams: Arc::new(Mutex::new(HashMap::new())),
-            stream_id_counter: Arc::new(Mutex::new(0)),
+            streams: Arc::new(Streams::new()),
+            stream_id_counter: AtomicU64::new(0),
+            interceptors: RwLock::new(Vec::new()),
+            observed_address: std::sync::Mutex::new(None),
+            tags: Arc::new(std::sync::Mutex::new(BTreeSet::new())),
+            stream_quota: std::sync::Mutex::new(None),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            io: Arc::new(IoCounters::default()),
             send_buffer_size: DEFAULT_SEND_BUFFER_SIZE,
             receive_buffer_size: DEFAULT_RECEIVE_BUFFER_SIZE,
         })
     }
+    /// Emits the connection's events on the given channel instead of its own.
+    pub(crate) fn with_events(mut self, events: broadcast::Sender<SocketEvent>) -> Self {
+        self.events = events;
+        self
+    }
+    /// Charges the data buffered by `receive()` to the given budget.
+    pub(crate) fn with_receive_budget(mut self, budget: Arc<ReceiveBudget>) -> Self {
+        self.receive_budget = Some(budget);
+        self
+    }
+    /// Sets the QUIC version the connection uses, if known.
+    pub(crate) fn with_quic_version(mut self, version: Option<QuicVersion>) -> Self {
+        self.quic_version = version;
+        self
+    }
+    /// Also accounts the connection's I/O to the given counters.
+    pub(crate) fn with_parent_io(mut self, parent: Arc<IoCounters>) -> Self {
+        self.io = Arc::new(IoCounters::with_parent(Some(parent)));
+        self
+    }
+    /// Returns the I/O counters of this connection.
+    pub fn io_stats(&self) -> IoStats {
+        self.io.snapshot()
+    }
+    /// Returns the I/O counters of a certain stream, until both of its sides are done.
+    pub fn stream_io_stats(&self, stream_id: u64) -> Option<IoStats> {
+        self.streams.io.get(&stream_id).map(|io| io.snapshot())
+    }
+    /// Returns the counters to account I/O on a certain stream to.
+    fn stream_counters(&self, stream_id: u64) -> Arc<IoCounters> {
+        self.streams.io.get(&stream_id).unwrap_or_else(|| Arc::clone(&self.io))
+    }
+    /// Accounts messages sent on a certain stream.
+    fn account_sent(&self, stream_id: u64, messages: u64, bytes: u64) {
+        self.stream_counters(stream_id).record_sent(messages, bytes);
+        telemetry::record_bytes("sent", self.connection.remote_address(), bytes);
+    }
+    /// Accounts messages received on a certain stream.
+    fn account_received(&self, stream_id: u64, messages: u64, bytes: u64) {
+        self.stream_counters(stream_id).record_received(messages, bytes);
+        telemetry::record_bytes("received", self.connection.remote_address(), bytes);
+    }
+    /// Returns a receiver for the events of this connection.
+    ///
+    /// Connections created by a `QuicSocket` share the socket's event channel.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SocketEvent> {
+        self.events.subscribe()
+    }
+    /// Sets the quota on streams accepted from the peer, or removes it with `None`.
+    ///
+    /// A stream counts against the quota from `accept_bi_stream()` until its sending side is finished,
+    /// it is cancelled or taken from the connection. Message streams count until they are dropped.
+    /// Streams already accepted are not affected.
+    pub fn set_stream_quota(&self, quota: Option<StreamQuota>) {
+        *self.stream_quota.lock().unwrap() = quota.map(|quota| (Arc::new(Semaphore::new(quota.max_bi_streams as usize)), quota));
+    }
+    /// Reserves a quota slot for a stream accepted from the peer.
+    ///
+    /// If the quota is exhausted, the stream is rejected and an event is emitted.
+    fn admit_stream(&self, send_stream: &mut SendStream, recv_stream: &mut RecvStream) -> std::result::Result<Option<OwnedSemaphorePermit>, QuotaExceeded> {
+        let Some((slots, quota)) = self.stream_quota.lock().unwrap().clone() else {
+            return Ok(None);
+        };
+        match slots.try_acquire_owned() {
+            Ok(permit) => Ok(Some(permit)),
+            Err(_) => {
+                let remote_address = self.connection.remote_address();
+                tracing::warn!("Stream quota of {} exceeded by {}, rejecting stream", quota.max_bi_streams, remote_address);
+                let _ = send_stream.reset(STREAM_QUOTA_EXCEEDED_CODE.into());
+                let _ = recv_stream.stop(STREAM_QUOTA_EXCEEDED_CODE.into());
+                let _ = self.events.send(SocketEvent::StreamQuotaExceeded { remote_address, limit: quota.max_bi_streams });
+                Err(QuotaExceeded)
+            },
+        }
+    }
+    /// Returns the underlying `quinn::Connection`.
+    pub fn inner(&self) -> &Connection {
+        &self.connection
+    }
+    /// Consumes the `QuicConnection`, returning the underlying `quinn::Connection`.
+    ///
+    /// Streams opened through this connection that are still registered are dropped, and background tasks
+    /// such as the `on_incoming_stream` handler stop, but the connection stays open.
+    pub fn into_inner(mut self) -> Connection {
+        self.close_on_drop = false;
+        self.connection.clone()
+    }
+    /// Removes the streams with the given ID from the connection and returns them.
+    ///
+    /// This can be used to operate on the underlying quinn streams directly.
+    /// Waits for operations in progress on the stream to complete.
+    ///
+    /// Returns `None`, leaving the stream registered, unless both of its sides are still registered.
+    pub async fn take_stream(&self, stream_id: u64) -> Option<(SendStream, RecvStream)> {
+        let send_stream = self.streams.send.remove(&stream_id)?;
+        let Some(recv_stream) = self.streams.recv.remove(&stream_id) else {
+            self.streams.send.insert(stream_id, send_stream);
+            return None;
+        };
+        self.streams.release_slot(stream_id);
+        self.streams.forget(stream_id);
+        Some((unwrap_stream(send_stream).await, unwrap_stream(recv_stream).await))
+    }
+    /// Removes the receiving side of a stream from the connection and returns it as a `StreamReader`,
+    /// e.g. to parse a line-based protocol. The sending side stays registered.
+    ///
+    /// Waits for operations in progress on the receiving side to complete.
+    pub async fn stream_reader(&self, stream_id: u64) -> Option<StreamReader> {
+        let recv_stream = self.streams.remove_recv(stream_id)?;
+        Some(StreamReader::new(unwrap_stream(recv_stream).await))
+    }
+    /// Returns the number of streams registered by ID that are not done yet.
+    ///
+    /// The sending side of a stream is done once it is finished, stopped by the peer, reset or failed,
+    /// and its receiving side once it has been read to the end or failed. A stream is removed from the
+    /// connection once both sides are done.
+    pub fn open_stream_count(&self) -> usize {
+        self.streams.io.len()
+    }
+    /// Registers a pair of streams under a new stream ID.
+    fn register_stream(&self, send_stream: SendStream, recv_stream: RecvStream) -> u64 {
+        let stream_id = self.stream_id_counter.fetch_add(1, Ordering::Relaxed);
+        self.streams.send.insert(stream_id, Arc::new(Mutex::new(send_stream)));
+        self.streams.recv.insert(stream_id, Arc::new(Mutex::new(recv_stream)));
+        self.streams.io.insert(stream_id, Arc::new(IoCounters::with_parent(Some(Arc::clone(&self.io)))));
+        self.streams.activity.insert(stream_id, Instant::now());
+        self.streams.expired.insert(stream_id, CancellationToken::new());
+        stream_id
+    }
     /// Opens a new bi-directional stream on the connection.
     pub async fn open_bi_stream(&self) -> Result<u64> {
-        let (send_stream, recv_stream) = self.connection.open_bi().await?;
-        let mut send_streams = self.send_streams.lock().await;
-        let mut recv_streams = self.recv_streams.lock().await;
-        let mut stream_id_counter = self.stream_id_counter.lock().await;
-        let stream_id = *stream_id_counter;
-        *stream_id_counter += 1;
-        send_streams.insert(stream_id, send_stream);
-        recv_streams.insert(stream_id, recv_stream);
-        tracing::info!("Opened bi-directional stream with ID: {}", stream_id);
+        let (send_stream, recv_stream) = self.connection.open_bi().await.map_err(Error::from)?;
+        let stream_id = self.register_stream(send_stream, recv_stream);
+        self.io.record_opened();
+        stream_trace!("Opened bi-directional stream with ID: {}", stream_id);
         Ok(stream_id)
     }
     /// Accepts a new bi-directional stream on the connection.
+    ///
+    /// Streams exceeding the stream quota (see `set_stream_quota()`) are rejected and skipped.
     pub async fn accept_bi_stream(&self) -> Result<u64> {
-        let (send_stream, recv_stream) = self.connection.accept_bi().await?;
-        let mut send_streams = self.send_streams.lock().await;
-        let mut recv_streams = self.recv_streams.lock().await;
-        let mut stream_id_counter = self.stream_id_counter.lock().await;
-        let stream_id = *stream_id_counter;
-        *stream_id_counter += 1;
-        send_streams.insert(stream_id, send_stream);
-        recv_streams.insert(stream_id, recv_stream);
-        tracing::info!("Accepted bi-directional stream with ID: {}", stream_id);
+        loop {
+            let (send_stream, recv_stream) = self.connection.accept_bi().await.map_err(Error::from)?;
+            if let Some(stream_id) = self.register_accepted(send_stream, recv_stream) {
+                return Ok(stream_id);
+            }
+        }
+    }
+    /// Registers a bi-directional stream accepted from the peer, or rejects it if it exceeds the stream quota.
+    fn register_accepted(&self, mut send_stream: SendStream, mut recv_stream: RecvStream) -> Option<u64> {
+        let permit = self.admit_stream(&mut send_stream, &mut recv_stream).ok()?;
+        let stream_id = self.register_stream(send_stream, recv_stream);
+        self.io.record_accepted();
+        if let Some(permit) = permit {
+            self.streams.quota_permits.insert(stream_id, permit);
+        }
+        stream_trace!("Accepted bi-directional stream with ID: {}", stream_id);
+        Some(stream_id)
+    }
+    /// Opens a new bi-directional stream like `open_bi_stream()`, which expires at the given deadline.
+    ///
+    /// See `set_stream_deadline()`.
+    pub async fn open_bi_stream_with_deadline(&self, deadline: Instant) -> Result<u64> {
+        let stream_id = self.open_bi_stream().await?;
+        self.set_stream_deadline(stream_id, deadline)?;
+        Ok(stream_id)
+    }
+    /// Accepts a new bi-directional stream like `accept_bi_stream()`, which expires at the given deadline.
+    ///
+    /// See `set_stream_deadline()`.
+    pub async fn accept_bi_stream_with_deadline(&self, deadline: Instant) -> Result<u64> {
+        let stream_id = self.accept_bi_stream().await?;
+        self.set_stream_deadline(stream_id, deadline)?;
         Ok(stream_id)
     }
+    /// Sets the deadline of a certain stream, replacing any previous one.
+    ///
+    /// Once the deadline passes, both sides of the stream are closed with `STREAM_DEADLINE_CODE` and removed
+    /// from the connection, and operations pending on it fail with a `Timeout` error. Sides taken from the
+    /// connection, e.g. with `stream_reader()`, are no longer subject to the deadline.
+    pub fn set_stream_deadline(&self, stream_id: u64, deadline: Instant) -> Result<()> {
+        if !self.streams.send.contains_key(&stream_id) && !self.streams.recv.contains_key(&stream_id) {
+            anyhow::bail!("unknown stream ID: {}", stream_id);
+        }
+        self.streams.deadlines.insert(stream_id, deadline);
+        let streams = Arc::downgrade(&self.streams);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(deadline.into()).await;
+            let Some(streams) = streams.upgrade() else { return };
+            if streams.deadlines.get(&stream_id) == Some(deadline) && streams.expire(stream_id, STREAM_DEADLINE_CODE).await {
+                stream_trace!("Deadline of stream ID {} expired", stream_id);
+            }
+        });
+        Ok(())
+    }
+    /// Runs an operation on a certain stream, failing with a `Timeout` error if the stream expires first.
+    async fn until_expired<T>(&self, stream_id: u64, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(expired) = self.streams.expired.get(&stream_id) else { return operation.await };
+        tokio::select! {
+            // A stream that already expired fails with `Timeout` rather than as an unknown stream.
+            biased;
+            _ = expired.cancelled() => Err(Timeout.into()),
+            result = operation => result,
+        }
+    }
+    /// Resets and removes the streams registered by ID without read or write activity for the given time,
+    /// or stops doing so with `None`.
+    ///
+    /// Idle streams are closed with `STREAM_IDLE_CODE`, operations pending on them fail with a `Timeout`
+    /// error, and a `SocketEvent::StreamIdle` is emitted for each. A background task checks the streams
+    /// every half timeout, so a stream is removed after being idle for up to 1.5 times the timeout.
+    /// An operation waiting for the peer does not count as activity.
+    pub fn set_idle_stream_timeout(&self, timeout: Option<Duration>) {
+        let mut reaper = self.idle_reaper.lock().unwrap();
+        if let Some(reaper) = reaper.take() {
+            reaper.abort();
+        }
+        let Some(timeout) = timeout else { return };
+        let streams = Arc::downgrade(&self.streams);
+        let events = self.events.clone();
+        let remote_address = self.connection.remote_address();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval((timeout / 2).max(Duration::from_millis(1)));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(streams) = streams.upgrade() else { return };
+                let Some(since) = Instant::now().checked_sub(timeout) else { continue };
+                for stream_id in streams.idle_since(since) {
+                    let streams = Arc::clone(&streams);
+                    let events = events.clone();
+                    // Expired in their own tasks, as locking a side may wait for an operation to abort.
+                    tokio::spawn(async move {
+                        if streams.expire(stream_id, STREAM_IDLE_CODE).await {
+                            tracing::debug!("Reset stream ID {} of {}, idle for {:?}", stream_id, remote_address, timeout);
+                            let _ = events.send(SocketEvent::StreamIdle { remote_address, stream_id });
+                        }
+                    });
+                }
+            }
+        });
+        *reaper = Some(task.abort_handle());
+    }
+    /// Checks the network path of the connection at the given interval, emitting a
+    /// `SocketEvent::PathChanged` whenever it changed, or stops doing so with `None`.
+    ///
+    /// quinn does not report path changes, so they are detected by comparing the peer's address and the
+    /// local IP address it sends to between checks. A change reverted within an interval goes unnoticed.
+    /// The local IP address is only known where the platform reports it, usually on servers.
+    pub fn set_path_check_interval(&self, interval: Option<Duration>) {
+        let mut watcher = self.path_watcher.lock().unwrap();
+        if let Some(watcher) = watcher.take() {
+            watcher.abort();
+        }
+        let Some(interval) = interval else { return };
+        let connection = self.connection.clone();
+        let events = self.events.clone();
+        let task = tokio::spawn(async move {
+            let mut path = (connection.remote_address(), connection.local_ip());
+            let mut interval = tokio::time::interval(interval.max(Duration::from_millis(1)));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = connection.closed() => return,
+                }
+                let current = (connection.remote_address(), connection.local_ip());
+                if current == path {
+                    continue;
+                }
+                tracing::debug!("Path of connection to {} changed to {} via {:?}", path.0, current.0, current.1);
+                let _ = events.send(SocketEvent::PathChanged {
+                    remote_address: current.0,
+                    previous_remote_address: path.0,
+                    local_ip: current.1,
+                    previous_local_ip: path.1,
+                });
+                path = current;
+            }
+        });
+        *watcher = Some(task.abort_handle());
+    }
+    /// Watches for the peer going silent, emitting `SocketEvent::PeerSuspect`, `SocketEvent::PeerDead` and
+    /// `SocketEvent::PeerRecovered` as it crosses the thresholds, or stops doing so with `None`. See `liveness`.
+    ///
+    /// The time since the last packet from the peer is sampled at a quarter of the shorter threshold, so
+    /// events may be late by as much.
+    pub fn set_liveness_thresholds(&self, thresholds: Option<LivenessThresholds>) {
+        let mut watcher = self.liveness_watcher.lock().unwrap();
+        if let Some(watcher) = watcher.take() {
+            watcher.abort();
+        }
+        let Some(thresholds) = thresholds else { return };
+        let connection = self.connection.clone();
+        let events = self.events.clone();
+        let task = tokio::spawn(async move {
+            let mut received = connection.stats().udp_rx.datagrams;
+            let mut last_received = Instant::now();
+            let (mut suspect, mut dead) = (false, false);
+            let mut interval = tokio::time::interval(thresholds.check_interval());
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = connection.closed() => return,
+                }
+                let remote_address = connection.remote_address();
+                let current = connection.stats().udp_rx.datagrams;
+                if current != received {
+                    received = current;
+                    last_received = Instant::now();
+                    if suspect || dead {
+                        tracing::debug!("Peer {} is responsive again", remote_address);
+                        let _ = events.send(SocketEvent::PeerRecovered { remote_address });
+                    }
+                    (suspect, dead) = (false, false);
+                    continue;
+                }
+                let silent_for = last_received.elapsed();
+                if !suspect && silent_for >= thresholds.suspect_after {
+                    suspect = true;
+                    tracing::debug!("Peer {} suspected dead, silent for {:?}", remote_address, silent_for);
+                    let _ = events.send(SocketEvent::PeerSuspect { remote_address, silent_for });
+                }
+                if !dead && silent_for >= thresholds.dead_after {
+                    dead = true;
+                    tracing::debug!("Peer {} considered dead, silent for {:?}", remote_address, silent_for);
+                    let _ = events.send(SocketEvent::PeerDead { remote_address, silent_for });
+                }
+            }
+        });
+        *watcher = Some(task.abort_handle());
+    }
+    /// Opens a new bi-directional stream in message mode.
+    ///
+    /// The returned stream carries length-delimited messages and is not tracked by stream ID.
+    pub async fn open_message_stream(&self) -> Result<MessageStream> {
+        let (send_stream, recv_stream) = self.connection.open_bi().await.map_err(Error::from)?;
+        self.io.record_opened();
+        Ok(MessageStream::new(send_stream, recv_stream))
+    }
+    /// Accepts a new bi-directional stream in message mode.
+    ///
+    /// Streams exceeding the stream quota (see `set_stream_quota()`) are rejected and skipped.
+    pub async fn accept_message_stream(&self) -> Result<MessageStream> {
+        loop {
+            let (mut send_stream, mut recv_stream) = self.connection.accept_bi().await.map_err(Error::from)?;
+            if let Ok(permit) = self.admit_stream(&mut send_stream, &mut recv_stream) {
+                self.io.record_accepted();
+                return Ok(MessageStream::new(send_stream, recv_stream).with_quota_permit(permit));
+            }
+        }
+    }
+    /// Returns a receiver of the bi-directional streams opened by the peer, by stream ID.
+    ///
+    /// Works the same on both sides, so a server can push streams to a client: the server opens a stream with
+    /// `open_bi_stream()` and sends on it, and the client receives it here. A peer's stream is only noticed
+    /// once data is sent on it. Streams are accepted in the background until the connection closes or the
+    /// receiver is dropped, so `accept_bi_stream()` and `accept_message_stream()` should not be used at the
+    /// same time. At most `INCOMING_STREAM_BACKLOG` streams are buffered before accepting pauses.
+    pub fn incoming_streams(self: &Arc<Self>) -> mpsc::Receiver<u64> {
+        let (tx, rx) = mpsc::channel(INCOMING_STREAM_BACKLOG);
+        let connection = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                let stream_id = tokio::select! {
+                    stream_id = connection.accept_bi_stream() => match stream_id {
+                        Ok(stream_id) => stream_id,
+                        Err(e) => {
+                            tracing::debug!("Stopped accepting incoming streams: {}", e);
+                            break;
+                        },
+                    },
+                    _ = tx.closed() => break,
+                };
+                if tx.send(stream_id).await.is_err() {
+                    // Nobody will use the stream, drop it.
+                    let _ = connection.take_stream(stream_id).await;
+                    break;
+                }
+            }
+        });
+        rx
+    }
+    /// Returns a receiver of the bi-directional streams opened by the peer, in message mode.
+    ///
+    /// See `incoming_streams()`.
+    pub fn incoming_message_streams(self: &Arc<Self>) -> mpsc::Receiver<MessageStream> {
+        let (tx, rx) = mpsc::channel(INCOMING_STREAM_BACKLOG);
+        let connection = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    stream = connection.accept_message_stream() => match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            tracing::debug!("Stopped accepting incoming message streams: {}", e);
+                            break;
+                        },
+                    },
+                    _ = tx.closed() => break,
+                };
+                if tx.send(stream).await.is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+    /// Calls the handler for each bi-directional stream opened by the peer, with the connection and the
+    /// stream ID, in its own task.
+    ///
+    /// At most `DEFAULT_STREAM_HANDLER_CONCURRENCY` handlers run at once, see
+    /// `on_incoming_stream_with_concurrency()`.
+    pub fn on_incoming_stream<F, Fut>(self: &Arc<Self>, handler: F)
+    where
+        F: Fn(Arc<QuicConnection>, u64) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.on_incoming_stream_with_concurrency(DEFAULT_STREAM_HANDLER_CONCURRENCY, handler)
+    }
+    /// Calls the handler for each bi-directional stream opened by the peer, with at most `concurrency`
+    /// handlers running at once.
+    ///
+    /// Streams are accepted in the background until the connection closes, replacing the handler of a
+    /// previous call. While `concurrency` handlers run, accepting pauses, so further streams wait in the
+    /// peer's stream limit. Handler errors are logged. `accept_bi_stream()` and `incoming_streams()` should
+    /// not be used at the same time.
+    pub fn on_incoming_stream_with_concurrency<F, Fut>(self: &Arc<Self>, concurrency: usize, handler: F)
+    where
+        F: Fn(Arc<QuicConnection>, u64) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        // The task only holds the connection while registering a stream, so it does not keep it alive.
+        // It is aborted when the connection is dropped.
+        let inner = self.connection.clone();
+        let weak = Arc::downgrade(self);
+        let slots = Arc::new(Semaphore::new(concurrency.max(1)));
+        let handler = Arc::new(handler);
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok(slot) = Arc::clone(&slots).acquire_owned().await else { break };
+                let (send_stream, recv_stream) = match inner.accept_bi().await {
+                    Ok(streams) => streams,
+                    Err(e) => {
+                        tracing::debug!("Stopped accepting incoming streams: {}", e);
+                        break;
+                    },
+                };
+                let Some(connection) = weak.upgrade() else { break };
+                let Some(stream_id) = connection.register_accepted(send_stream, recv_stream) else { continue };
+                let handled = handler(connection, stream_id);
+                tokio::spawn(async move {
+                    if let Err(e) = handled.await {
+                        tracing::debug!("Stream handler for stream ID {} failed: {}", stream_id, e);
+                    }
+                    drop(slot);
+                });
+            }
+        });
+        if let Some(previous) = self.stream_handler.lock().unwrap().replace(task.abort_handle()) {
+            previous.abort();
+        }
+    }
+    /// Sends a request and waits for the response, reusing streams from the connection's pool.
+    ///
+    /// This avoids opening a stream per message. The peer answers with `pool::serve_requests`.
+    /// Interceptors are not applied.
+    pub async fn request(&self, message: bytes::Bytes) -> Result<bytes::Bytes> {
+        let sent = message.len() as u64;
+        let response = self.stream_pool.request(message).await?;
+        self.io.record_sent(1, sent);
+        self.io.record_received(1, response.len() as u64);
+        Ok(response)
+    }
+    /// Returns the connection's stream pool.
+    pub fn stream_pool(&self) -> &StreamPool {
+        &self.stream_pool
+    }
+    /// Adds an interceptor to the connection.
+    ///
+    /// Interceptors transform the data passed to `send()` and returned from `receive()`.
+    pub async fn add_interceptor(&self, interceptor: Arc<dyn Interceptor>) {
+        self.interceptors.write().await.push(interceptor);
+    }
     /// Sends data on a certain stream.
+    ///
+    /// Fails if the stream ID is unknown or its sending side is already done, or with `Timeout` if the stream expired.
     pub async fn send(&self, stream_id: u64, data: &[u8]) -> Result<()> {
-        let mut send_streams = self.send_streams.lock().await;
-        if let Some(send_stream) = send_streams.get_mut(&stream_id) {
-            tracing::info!("Sending data on stream ID: {}", stream_id);
-            let mut offset = 0;
-            while offset < data.len() {
-                let end = std::cmp::min(offset + self.send_buffer_size, data.len());
-                send_stream.write_chunk(bytes::Bytes::copy_from_slice(&data[offset..end])).await?;
-                offset = end;
-            }
-            send_stream.flush().await?;
-            send_stream.finish()?;
-            // Wait for stream to close
-            _ = send_stream.stopped().await;
-            tracing::info!("Finished sending data on stream ID: {}", stream_id);
+        let intercepted;
+        let data = {
+            let interceptors = self.interceptors.read().await;
+            if interceptors.is_empty() {
+                data
+            } else {
+                let mut payload = bytes::Bytes::copy_from_slice(data);
+                for interceptor in interceptors.iter() {
+                    payload = interceptor.on_send(stream_id, payload)?;
+                }
+                intercepted = payload;
+                &intercepted[..]
+            }
+        };
+        let span = telemetry::stream_span("send", self.connection.remote_address(), stream_id);
+        self.until_expired(stream_id, self.write_stream(stream_id, data)).instrument(span).await
+    }
+    /// Writes data to a certain stream and finishes it, waiting until the peer has received everything.
+    ///
+    /// The sending side is removed afterwards, whether the write succeeded or not.
+    async fn write_stream(&self, stream_id: u64, data: &[u8]) -> Result<()> {
+        let send_stream = self.streams.send.get(&stream_id).ok_or_else(|| anyhow::anyhow!("unknown stream ID: {}", stream_id))?;
+        let result = self.write_to_end(&mut *send_stream.lock().await, stream_id, data).await;
+        self.streams.remove_send(stream_id);
+        result
+    }
+    /// Writes data to a send stream and finishes it, waiting until the peer has received everything.
+    async fn write_to_end(&self, send_stream: &mut SendStream, stream_id: u64, data: &[u8]) -> Result<()> {
+        stream_trace!("Sending data on stream ID: {}", stream_id);
+        let mut offset = 0;
+        while offset < data.len() {
+            let end = std::cmp::min(offset + self.send_buffer_size, data.len());
+            send_stream.write_chunk(bytes::Bytes::copy_from_slice(&data[offset..end])).await.map_err(write_error)?;
+            self.streams.touch(stream_id);
+            offset = end;
+        }
+        send_stream.flush().await.map_err(error::from_io)?;
+        send_stream.finish().map_err(Error::from)?;
+        self.account_sent(stream_id, 1, data.len() as u64);
+        // Wait for stream to close. A peer stopping it before acknowledging everything did not receive the data.
+        if let Ok(Some(code)) = send_stream.stopped().await {
+            if code == STREAM_QUOTA_EXCEEDED_CODE.into() {
+                return Err(QuotaExceeded.into());
+            }
+            return Err(Error::Stopped(code.into_inner()).into());
         }
+        stream_trace!("Finished sending data on stream ID: {}", stream_id);
         Ok(())
     }
+    /// Sends a batch of messages on a certain stream.
+    ///
+    /// Each message is passed through the interceptors and length-prefixed (see `framing`), and the whole batch is written with a single
+    /// vectored write, so small messages are coalesced into as few packets as possible.
+    /// Unlike `send()`, the stream is not finished; call `finish_stream()` after the last batch.
+    /// The peer can read the messages with `receive_messages()`.
+    pub async fn send_batch(&self, stream_id: u64, messages: impl IntoIterator<Item = bytes::Bytes>) -> Result<()> {
+        let interceptors = self.interceptors.read().await.clone();
+        let mut chunks = Vec::new();
+        for mut message in messages {
+            for interceptor in interceptors.iter() {
+                message = interceptor.on_send(stream_id, message)?;
+            }
+            if message.len() > u32::MAX as usize {
+                anyhow::bail!("message of {} bytes is too large to frame", message.len());
+            }
+            chunks.push(bytes::Bytes::copy_from_slice(&(message.len() as u32).to_be_bytes()));
+            chunks.push(message);
+        }
+        self.until_expired(stream_id, self.write_batch(stream_id, chunks)).await
+    }
+    /// Writes the chunks of a batch to a certain stream.
+    async fn write_batch(&self, stream_id: u64, mut chunks: Vec<bytes::Bytes>) -> Result<()> {
+        let send_stream = self.streams.send.get(&stream_id).ok_or_else(|| anyhow::anyhow!("unknown stream ID: {}", stream_id))?;
+        let mut send_stream = send_stream.lock().await;
+        stream_trace!("Sending batch of {} messages on stream ID: {}", chunks.len() / 2, stream_id);
+        // Chunks alternate between length prefixes and messages, and only the messages are counted.
+        let bytes = chunks.iter().skip(1).step_by(2).map(|chunk| chunk.len() as u64).sum();
+        let span = telemetry::stream_span("send_batch", self.connection.remote_address(), stream_id);
+        if let Err(e) = send_stream.write_all_chunks(&mut chunks).instrument(span.clone()).await {
+            drop(send_stream);
+            self.streams.remove_send(stream_id);
+            return Err(write_error(e));
+        }
+        self.streams.touch(stream_id);
+        let _entered = span.enter();
+        self.account_sent(stream_id, chunks.len() as u64 / 2, bytes);
+        Ok(())
+    }
+    /// Finishes the sending side of a certain stream.
+    pub async fn finish_stream(&self, stream_id: u64) -> Result<()> {
+        let send_stream = self.streams.send.get(&stream_id).ok_or_else(|| anyhow::anyhow!("unknown stream ID: {}", stream_id))?;
+        let result = self.until_expired(stream_id, async { Ok(send_stream.lock().await.finish().map_err(Error::from)) }).await?;
+        self.streams.remove_send(stream_id);
+        Ok(result?)
+    }
+    /// Receives length-prefixed messages on a certain stream until the peer finishes it.
+    ///
+    /// This is the receiving counterpart of `send_batch()`.
+    pub async fn receive_messages(&self, stream_id: u64) -> Result<Vec<bytes::Bytes>> {
+        let data = self.read_to_end(stream_id).await?;
+        let mut buffer = bytes::BytesMut::from(&data[..]);
+        let mut codec = FrameCodec::with_max_frame_size(u32::MAX as usize);
+        let interceptors = self.interceptors.read().await;
+        let mut messages = Vec::new();
+        let mut bytes = 0;
+        // `decode_eof` fails on a truncated message instead of waiting for more data.
+        while let Some(mut message) = codec.decode_eof(&mut buffer)? {
+            // Payloads are counted as received, without their length prefixes, like `send_batch()` counts them.
+            bytes += message.len() as u64;
+            for interceptor in interceptors.iter().rev() {
+                message = interceptor.on_receive(stream_id, message)?;
+            }
+            messages.push(message);
+        }
+        self.account_received(stream_id, messages.len() as u64, bytes);
+        Ok(messages)
+    }
     /// Receives data on a certain stream.
+    ///
+    /// Fails if the stream ID is unknown or its receiving side is already done, or with `Timeout` if the stream expired.
     pub async fn receive(&self, stream_id: u64) -> Result<Vec<u8>> {
-        let mut recv_streams = self.recv_streams.lock().await;
-        if let Some(recv_stream) = recv_streams.get_mut(&stream_id) {
-            tracing::info!("Receiving data on stream ID: {}", stream_id);
-            let mut buffer = Vec::new();
+        let buffer = self.read_to_end(stream_id).await?;
+        self.account_received(stream_id, 1, buffer.len() as u64);
+        let interceptors = self.interceptors.read().await;
+        if !interceptors.is_empty() {
+            let mut payload = bytes::Bytes::from(buffer);
+            for interceptor in interceptors.iter().rev() {
+                payload = interceptor.on_receive(stream_id, payload)?;
+            }
+            return Ok(Vec::from(payload));
+        }
+        Ok(buffer)
+    }
+    /// Returns a receiver of the data of a certain stream, chunk by chunk as it arrives.
+    ///
+    /// The stream is read in the background until the peer finishes it, then the receiver is closed. Reading
+    /// pauses while `SUBSCRIBE_BACKLOG` chunks wait to be received, so a slow consumer slows the peer down
+    /// through flow control instead of buffering. If the receiver is dropped early, the peer is asked to stop
+    /// sending with `STREAM_CANCELLED_CODE` and the stream is removed. A read error, or `Timeout` if the
+    /// stream expires, is received last before the receiver closes, so a truncated stream is never mistaken
+    /// for a finished one.
+    ///
+    /// Fails if the stream ID is unknown or its receiving side is already done.
+    ///
+    /// Chunks are at most `receive_buffer_size` bytes. Interceptors are not applied, as they work on whole messages.
+    pub fn subscribe(self: &Arc<Self>, stream_id: u64) -> Result<mpsc::Receiver<Result<bytes::Bytes>>> {
+        let recv_stream = self.streams.recv.get(&stream_id).ok_or_else(|| anyhow::anyhow!("unknown stream ID: {}", stream_id))?;
+        let (tx, rx) = mpsc::channel(SUBSCRIBE_BACKLOG);
+        let expired = self.streams.expired.get(&stream_id).unwrap_or_default();
+        let connection = Arc::clone(self);
+        let span = telemetry::stream_span("receive", self.connection.remote_address(), stream_id);
+        tokio::spawn(async move {
+            let mut recv_stream = recv_stream.lock().await;
+            stream_trace!("Subscribed to stream ID: {}", stream_id);
+            let mut received = 0;
             loop {
-                match recv_stream.read_chunk(self.receive_buffer_size, true).await {
+                let chunk = tokio::select! {
+                    chunk = recv_stream.read_chunk(connection.receive_buffer_size, true) => chunk,
+                    _ = tx.closed() => break,
+                    // The stream is reset and removed by whoever expired it.
+                    _ = expired.cancelled() => {
+                        let _ = tx.try_send(Err(Timeout.into()));
+                        return;
+                    },
+                };
+                match chunk {
                     Ok(Some(chunk)) => {
-                        buffer.extend_from_slice(&chunk.bytes);
+                        connection.streams.touch(stream_id);
+                        received += chunk.bytes.len() as u64;
+                        if tx.send(Ok(chunk.bytes)).await.is_err() {
+                            break;
+                        }
                     },
                     Ok(None) => {
-                        tracing::debug!("stream end detected");
+                        stream_trace!("End of stream ID: {}", stream_id);
                         break;
                     },
                     Err(e) => {
-                        tracing::error!("failed to read chunk: {}", e);
-                        return Err(e.into());
+                        stream_trace!("Failed to read chunk on stream ID {}: {}", stream_id, e);
+                        let _ = tx.send(Err(read_error(e))).await;
+                        break;
                     },
                 }
             }
-            tracing::info!("Finished receiving data on stream ID: {}", stream_id);
-            return Ok(buffer);
+            if tx.is_closed() {
+                let _ = recv_stream.stop(STREAM_CANCELLED_CODE.into());
+                connection.streams.release_slot(stream_id);
+                stream_trace!("Unsubscribed from stream ID: {}", stream_id);
+            }
+            tracing::Span::current().record(telemetry::BYTES, received);
+            connection.account_received(stream_id, 1, received);
+            drop(recv_stream);
+            connection.streams.remove_recv(stream_id);
+        }.instrument(span));
+        Ok(rx)
+    }
+    /// Reads a certain stream until the peer finishes it, without applying interceptors.
+    async fn read_to_end(&self, stream_id: u64) -> Result<Vec<u8>> {
+        let span = telemetry::stream_span("receive", self.connection.remote_address(), stream_id);
+        let buffer = self.until_expired(stream_id, self.read_stream(stream_id)).instrument(span.clone()).await?;
+        span.record(telemetry::BYTES, buffer.len() as u64);
+        Ok(buffer)
+    }
+    /// Reads a certain stream until the peer finishes it.
+    async fn read_stream(&self, stream_id: u64) -> Result<Vec<u8>> {
+        let recv_stream = self.streams.recv.get(&stream_id).ok_or_else(|| anyhow::anyhow!("unknown stream ID: {}", stream_id))?;
+        let result = self.read_to_end_of(&mut *recv_stream.lock().await, stream_id).await;
+        // Read to the end or failed, the receiving side is done either way.
+        self.streams.remove_recv(stream_id);
+        result
+    }
+    /// Reads a receive stream until the peer finishes it.
+    ///
+    /// Reading pauses while the receive budget of the socket is used up, if any.
+    async fn read_to_end_of(&self, recv_stream: &mut RecvStream, stream_id: u64) -> Result<Vec<u8>> {
+        stream_trace!("Receiving data on stream ID: {}", stream_id);
+        let mut reservation = self.receive_budget.as_ref().map(ReceiveBudget::start);
+        let mut buffer = Vec::new();
+        loop {
+            if let Some(reservation) = &reservation {
+                reservation.wait().await;
+            }
+            match recv_stream.read_chunk(self.receive_buffer_size, true).await {
+                Ok(Some(chunk)) => {
+                    self.streams.touch(stream_id);
+                    if let Some(reservation) = reservation.as_mut() {
+                        reservation.charge(chunk.bytes.len());
+                    }
+                    buffer.extend_from_slice(&chunk.bytes);
+                },
+                Ok(None) => {
+                    stream_trace!("End of stream ID: {}", stream_id);
+                    break;
+                },
+                Err(e) => {
+                    stream_trace!("Failed to read chunk on stream ID {}: {}", stream_id, e);
+                    return Err(read_error(e));
+                },
+            }
+        }
+        stream_trace!("Finished receiving data on stream ID: {}", stream_id);
+        Ok(buffer)
+    }
+    /// Sends data on a certain stream, aborting if the token is cancelled.
+    ///
+    /// On cancellation the send stream is reset and removed from the connection, and a `Cancelled` error is returned.
+    pub async fn send_with_cancel(&self, stream_id: u64, data: &[u8], token: &CancellationToken) -> Result<()> {
+        tokio::select! {
+            res = self.send(stream_id, data) => res,
+            _ = token.cancelled() => {
+                if let Some(send_stream) = self.streams.remove_send(stream_id) {
+                    let _ = send_stream.lock().await.reset(STREAM_CANCELLED_CODE.into());
+                }
+                stream_trace!("Cancelled sending on stream ID: {}", stream_id);
+                Err(Cancelled.into())
+            },
+        }
+    }
+    /// Receives data on a certain stream, aborting if the token is cancelled.
+    ///
+    /// On cancellation the peer is asked to stop sending, the receive stream is removed from the connection,
+    /// and a `Cancelled` error is returned.
+    pub async fn receive_with_cancel(&self, stream_id: u64, token: &CancellationToken) -> Result<Vec<u8>> {
+        tokio::select! {
+            res = self.receive(stream_id) => res,
+            _ = token.cancelled() => {
+                if let Some(recv_stream) = self.streams.remove_recv(stream_id) {
+                    let _ = recv_stream.lock().await.stop(STREAM_CANCELLED_CODE.into());
+                }
+                self.streams.release_slot(stream_id);
+                stream_trace!("Cancelled receiving on stream ID: {}", stream_id);
+                Err(Cancelled.into())
+            },
+        }
+    }
+    /// Reports the remote address observed on this connection to the peer.
+    ///
+    /// The address is sent on a uni-directional control stream.
+    pub async fn report_observed_address(&self) -> Result<()> {
+        let mut send_stream = self.connection.open_uni().await.map_err(Error::from)?;
+        let mut message = vec![CONTROL_OBSERVED_ADDRESS];
+        message.extend_from_slice(self.connection.remote_address().to_string().as_bytes());
+        send_stream.write_all(&message).await.map_err(Error::from)?;
+        send_stream.finish().map_err(Error::from)?;
+        Ok(())
+    }
+    /// Waits for the peer to report the address it observes for this endpoint.
+    ///
+    /// This accepts the next uni-directional stream from the peer, which must be an observed address
+    /// control stream (see `QuicSocket::set_report_observed_address`).
+    /// The address is also cached and returned by `observed_address()`.
+    pub async fn receive_observed_address(&self) -> Result<SocketAddr> {
+        let mut recv_stream = self.connection.accept_uni().await.map_err(Error::from)?;
+        let message = recv_stream.read_to_end(MAX_CONTROL_MESSAGE_SIZE).await.map_err(Error::from)?;
+        match message.split_first() {
+            Some((&CONTROL_OBSERVED_ADDRESS, addr)) => {
+                let addr: SocketAddr = std::str::from_utf8(addr)?.parse()?;
+                *self.observed_address.lock().unwrap() = Some(addr);
+                tracing::debug!("Peer observed our address as: {}", addr);
+                Ok(addr)
+            },
+            _ => anyhow::bail!("unexpected control stream"),
         }
-        Ok(Vec::new())
     }
-    /// Closes the connection.
+    /// Returns the address the peer observes for this endpoint, if it has been reported.
+    ///
+    /// This is the public address and port as seen from the peer, e.g. after NAT.
+    pub fn observed_address(&self) -> Option<SocketAddr> {
+        *self.observed_address.lock().unwrap()
+    }
+    /// Returns the tags of the connection, in order. See `QuicSocket::tag`.
+    pub fn tags(&self) -> Vec<String> {
+        self.tags.lock().unwrap().iter().cloned().collect()
+    }
+    /// Returns whether the connection has a certain tag.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.lock().unwrap().contains(tag)
+    }
+    /// Adds a tag, returning whether it was not present yet.
+    pub(crate) fn insert_tag(&self, tag: &str) -> bool {
+        self.tags.lock().unwrap().insert(tag.to_string())
+    }
+    /// Removes a tag, returning whether it was present.
+    pub(crate) fn remove_tag(&self, tag: &str) -> bool {
+        self.tags.lock().unwrap().remove(tag)
+    }
+    /// Returns the set of tags, which outlives the connection.
+    pub(crate) fn tag_set(&self) -> Arc<std::sync::Mutex<BTreeSet<String>>> {
+        Arc::clone(&self.tags)
+    }
+    /// Returns the QUIC version of the connection, if known.
+    ///
+    /// Known for connections made by the socket, and for accepted connections if the socket accepts a
+    /// single version (see `SocketConfig::with_quic_versions`), as quinn does not report the version
+    /// a client used.
+    pub fn quic_version(&self) -> Option<QuicVersion> {
+        self.quic_version
+    }
+    /// Returns the data negotiated during the handshake.
+    pub fn handshake_info(&self) -> &HandshakeInfo {
+        &self.handshake
+    }
+    /// Returns the server name (SNI) the client requested.
+    ///
+    /// On servers this can be used to route connections by hostname.
+    pub fn server_name(&self) -> Option<&str> {
+        self.handshake.server_name.as_deref()
+    }
+    /// Returns the negotiated ALPN protocol.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.handshake.alpn_protocol.as_deref()
+    }
+    /// Returns the DER-encoded certificate chain presented by the peer, leaf first.
+    ///
+    /// On clients this is the server's chain. On servers it is the client's chain if client
+    /// authentication is enabled, and `None` otherwise.
+    pub fn peer_certificates(&self) -> Option<Vec<Vec<u8>>> {
+        let identity = self.connection.peer_identity()?;
+        let certs = identity.downcast::<Vec<rustls::pki_types::CertificateDer<'static>>>().ok()?;
+        Some(certs.iter().map(|cert| cert.to_vec()).collect())
+    }
+    /// Sets the maximum number of bytes the peer may send across all streams of this connection.
+    ///
+    /// This overrides the socket's transport options for this connection only, e.g. for bulk transfers.
+    pub fn set_receive_window(&self, bytes: u64) -> Result<()> {
+        self.connection.set_receive_window(quinn::VarInt::from_u64(bytes)?);
+        Ok(())
+    }
+    /// Starts auto-tuning the receive window of this connection.
+    ///
+    /// A background task samples the delivery rate and RTT at the configured interval and grows the
+    /// connection receive window to keep high bandwidth-delay product paths full. The per-stream window
+    /// is not tuned, see `WindowAutoTune`. The task stops when the connection is closed.
+    pub fn autotune_receive_window(&self, options: WindowAutoTune) {
+        let connection = self.connection.clone();
+        tokio::spawn(async move {
+            let mut window = options.min_window;
+            let mut last_bytes = connection.stats().udp_rx.bytes;
+            let mut interval = tokio::time::interval(options.interval);
+            interval.tick().await;
+            while connection.close_reason().is_none() {
+                interval.tick().await;
+                let bytes = connection.stats().udp_rx.bytes;
+                let rate = (bytes - last_bytes) as f64 / options.interval.as_secs_f64();
+                last_bytes = bytes;
+                let target = ((2.0 * rate * connection.rtt().as_secs_f64()) as u64).clamp(options.min_window, options.max_window);
+                if target > window {
+                    window = target;
+                    if let Ok(window) = quinn::VarInt::from_u64(window) {
+                        connection.set_receive_window(window);
+                    }
+                    tracing::debug!("Auto-tuned receive window of {} to {} bytes", connection.remote_address(), window);
+                }
+            }
+        });
+    }
+    /// Closes the connection with `NO_ERROR_CODE`.
     pub async fn close(&self) {
-        self.connection.close(0u32.into(), b"done");
+        self.close_with(NO_ERROR_CODE, b"done");
+    }
+    /// Closes the connection with an application error code and a reason sent to the peer.
+    ///
+    /// Only the first close of a connection takes effect. Applications should use codes outside of
+    /// `codes::RESERVED`, which are reserved for quicsock's own closes.
+    pub fn close_with(&self, code: u32, reason: &[u8]) {
+        self.local_close.record(code.into(), reason);
+        self.connection.close(code.into(), reason);
+    }
+    /// Returns why the connection was closed, or `None` while it is open.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.connection.close_reason().map(|error| CloseReason::new(&error, &self.local_close))
+    }
+    /// Returns the record of the local close, which outlives the connection.
+    pub(crate) fn local_close(&self) -> LocalClose {
+        self.local_close.clone()
+    }
+}
+
+impl Drop for QuicConnection {
+    /// Closes the connection with `DROPPED_CODE`, unless it was taken with `into_inner()`.
+    ///
+    /// Connections of a `QuicSocket` are also held by the socket until they close, so they are closed
+    /// when the socket is dropped.
+    fn drop(&mut self) {
+        if let Some(reaper) = self.idle_reaper.get_mut().unwrap().take() {
+            reaper.abort();
+        }
+        if let Some(watcher) = self.path_watcher.get_mut().unwrap().take() {
+            watcher.abort();
+        }
+        if let Some(watcher) = self.liveness_watcher.get_mut().unwrap().take() {
+            watcher.abort();
+        }
+        if let Some(handler) = self.stream_handler.get_mut().unwrap().take() {
+            handler.abort();
+        }
+        if self.close_on_drop {
+            self.close_with(DROPPED_CODE, b"dropped");
+        }
+    }
+}
+
+/// Converts a stream write error, surfacing rejections by the peer's stream quota as `QuotaExceeded`.
+fn write_error(e: quinn::WriteError) -> anyhow::Error {
+    match e {
+        quinn::WriteError::Stopped(code) if code == STREAM_QUOTA_EXCEEDED_CODE.into() => QuotaExceeded.into(),
+        e => Error::from(e).into(),
+    }
+}
+
+/// Converts a stream read error, surfacing rejections by the peer's stream quota as `QuotaExceeded`.
+fn read_error(e: quinn::ReadError) -> anyhow::Error {
+    match e {
+        quinn::ReadError::Reset(code) if code == STREAM_QUOTA_EXCEEDED_CODE.into() => QuotaExceeded.into(),
+        e => Error::from(e).into(),
+    }
+}
+
+/// Takes ownership of a stream removed from a registry, waiting for operations still holding it.
+async fn unwrap_stream<T>(mut stream: Arc<Mutex<T>>) -> T {
+    loop {
+        match Arc::try_unwrap(stream) {
+            Ok(stream) => return stream.into_inner(),
+            Err(shared) => {
+                drop(shared.lock().await);
+                tokio::task::yield_now().await;
+                stream = shared;
+            },
+        }
     }
 }
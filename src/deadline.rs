@@ -0,0 +1,27 @@
+//! Per-stream deadlines and idle timeouts.
+//!
+//! A deadline can be attached to a stream registered by ID when it is opened or accepted (see
+//! `QuicConnection::open_bi_stream_with_deadline`), e.g. for request streams that must complete within
+//! an SLA. Once it passes, both halves of the stream are closed with `STREAM_DEADLINE_CODE` and the stream
+//! is removed from the connection, so its ID is no longer known. Operations pending on it fail with a
+//! `Timeout` error. The peer sees the code as a `Reset` or `Stopped` error, see `Error::is_deadline_exceeded`.
+//!
+//! An idle stream timeout (see `QuicConnection::set_idle_stream_timeout`) expires streams the same way once
+//! no data was read or written on them for a while, with `STREAM_IDLE_CODE`, so streams abandoned by buggy
+//! or malicious peers do not accumulate.
+
+use std::fmt;
+
+pub use crate::codes::{STREAM_DEADLINE_CODE, STREAM_IDLE_CODE};
+
+/// Error returned when an operation is aborted because its stream expired, at its deadline or when idle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stream timed out")
+    }
+}
+
+impl std::error::Error for Timeout {}
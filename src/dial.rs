@@ -0,0 +1,145 @@
+//! Dialing many servers with bounded concurrency and backoff.
+//!
+//! A `DialQueue` connects to the servers enqueued with `DialQueue::enqueue`, running at most
+//! `DialOptions::max_concurrent` handshakes at once. A server that fails to connect is retried after a
+//! backoff, doubling from `initial_backoff` up to `max_backoff`, until `max_attempts` is reached. The
+//! outcome of each server, its connection or the last error, is sent on the receiver returned by
+//! `DialQueue::new`. Dropping the queue cancels the pending dials.
+
+use anyhow::Result;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
+use crate::{QuicConnection, QuicSocket};
+
+/// The default number of handshakes run at once.
+pub const DEFAULT_MAX_CONCURRENT_DIALS: usize = 16;
+/// The default delay before the first retry of a server that failed to connect.
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The default maximum delay between retries.
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// The number of outcomes buffered until they are received.
+pub const DIAL_BACKLOG: usize = 64;
+
+/// Options for a `DialQueue`.
+#[derive(Debug, Clone)]
+pub struct DialOptions {
+    /// The number of handshakes run at once.
+    pub max_concurrent: usize,
+    /// The delay before the first retry of a server, doubled after each failure.
+    pub initial_backoff: Duration,
+    /// The maximum delay between retries.
+    pub max_backoff: Duration,
+    /// The number of attempts after which a server is given up, or `None` to retry until it connects.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for DialOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrent: DEFAULT_MAX_CONCURRENT_DIALS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            max_attempts: None,
+        }
+    }
+}
+
+impl DialOptions {
+    /// Creates options running `DEFAULT_MAX_CONCURRENT_DIALS` handshakes at once and retrying until connected.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the number of handshakes run at once.
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent;
+        self
+    }
+    /// Sets the delays between retries.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+    /// Sets the number of attempts after which a server is given up.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+}
+
+/// The outcome of dialing a server: its connection, or the error of the last attempt.
+pub type Dialed = (SocketAddr, Result<Arc<QuicConnection>>);
+
+/// Connects to enqueued servers in the background. See the module documentation.
+pub struct DialQueue {
+    socket: Arc<QuicSocket>,
+    options: DialOptions,
+    permits: Arc<Semaphore>,
+    pending: Arc<Mutex<HashSet<SocketAddr>>>,
+    tasks: Mutex<JoinSet<()>>,
+    outcomes: mpsc::Sender<Dialed>,
+}
+
+impl DialQueue {
+    /// Creates a queue dialing from `socket`, returning it with the receiver of the outcomes.
+    pub fn new(socket: Arc<QuicSocket>, options: DialOptions) -> (Self, mpsc::Receiver<Dialed>) {
+        let (outcomes, receiver) = mpsc::channel(DIAL_BACKLOG);
+        let queue = Self {
+            socket,
+            permits: Arc::new(Semaphore::new(options.max_concurrent.max(1))),
+            options,
+            pending: Arc::default(),
+            tasks: Mutex::new(JoinSet::new()),
+            outcomes,
+        };
+        (queue, receiver)
+    }
+    /// Enqueues a server, returning whether it was not pending already.
+    pub fn enqueue(&self, server_addr: SocketAddr, server_name: &str) -> bool {
+        if !self.pending.lock().unwrap().insert(server_addr) {
+            return false;
+        }
+        let socket = Arc::clone(&self.socket);
+        let options = self.options.clone();
+        let permits = Arc::clone(&self.permits);
+        let pending = Arc::clone(&self.pending);
+        let outcomes = self.outcomes.clone();
+        let server_name = server_name.to_string();
+        let mut tasks = self.tasks.lock().unwrap();
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move {
+            let result = dial(&socket, &options, &permits, server_addr, &server_name).await;
+            pending.lock().unwrap().remove(&server_addr);
+            let _ = outcomes.send((server_addr, result)).await;
+        });
+        true
+    }
+    /// Returns the number of servers being dialed or waiting to be.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+}
+
+/// Connects to a server, retrying with backoff until it connects or the attempts run out.
+async fn dial(socket: &QuicSocket, options: &DialOptions, permits: &Semaphore, server_addr: SocketAddr, server_name: &str) -> Result<Arc<QuicConnection>> {
+    let mut backoff = options.initial_backoff;
+    let mut attempts = 0;
+    loop {
+        let result = {
+            let _permit = permits.acquire().await?;
+            socket.connect(server_addr, server_name).await
+        };
+        attempts += 1;
+        match result {
+            Ok(connection) => return Ok(connection),
+            Err(e) if options.max_attempts.is_some_and(|max| attempts >= max) => return Err(e),
+            Err(e) => tracing::debug!("Dial {} to {} failed, retrying in {:?}: {}", attempts, server_addr, backoff, e),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(options.max_backoff);
+    }
+}
@@ -0,0 +1,157 @@
+//! DNS based server endpoint discovery.
+//!
+//! Server endpoints are discovered from SRV or HTTPS/SVCB records and can be fed into
+//! `QuicSocket::connect_balanced` through a `LoadBalancer`.
+
+use anyhow::Result;
+use hickory_resolver::proto::rr::rdata::svcb::SvcParamValue;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::TokioAsyncResolver;
+use std::net::{IpAddr, SocketAddr};
+use crate::balance::{LoadBalancer, Strategy};
+
+/// A server endpoint discovered from DNS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredEndpoint {
+    /// The socket address of the endpoint.
+    pub addr: SocketAddr,
+    /// The target host name the address was resolved from.
+    pub target: String,
+    /// The record priority. Lower values are preferred.
+    pub priority: u16,
+    /// The record weight (SRV only).
+    pub weight: u16,
+    /// The ALPN protocols advertised for the endpoint (HTTPS/SVCB only).
+    pub alpn: Vec<String>,
+}
+
+/// Resolver for discovering server endpoints.
+pub struct Resolver {
+    inner: TokioAsyncResolver,
+}
+
+impl Resolver {
+    /// Creates a resolver using the system's DNS configuration.
+    pub fn from_system_conf() -> Result<Self> {
+        Ok(Self { inner: TokioAsyncResolver::tokio_from_system_conf()? })
+    }
+    /// Creates a resolver from an existing hickory resolver.
+    pub fn from_resolver(inner: TokioAsyncResolver) -> Self {
+        Self { inner }
+    }
+    /// Discovers endpoints from the SRV records of a service name, e.g. `_myapp._udp.example.com`.
+    ///
+    /// The returned endpoints are sorted by priority, then by descending weight. Targets whose addresses
+    /// cannot be resolved are skipped, and the lookup only fails if none of them can be.
+    pub async fn lookup_srv(&self, service_name: &str) -> Result<Vec<DiscoveredEndpoint>> {
+        let lookup = self.inner.srv_lookup(service_name).await?;
+        let mut endpoints = Vec::new();
+        let mut last_error = None;
+        for srv in lookup.iter() {
+            let target = srv.target().to_utf8();
+            let ips = match self.resolve_host(&target).await {
+                Ok(ips) => ips,
+                Err(e) => {
+                    tracing::debug!("Skipping SRV target {} of {}: {}", target, service_name, e);
+                    last_error = Some(e);
+                    continue;
+                },
+            };
+            for ip in ips {
+                endpoints.push(DiscoveredEndpoint {
+                    addr: SocketAddr::new(ip, srv.port()),
+                    target: trim_root(&target),
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    alpn: Vec::new(),
+                });
+            }
+        }
+        if endpoints.is_empty() {
+            if let Some(e) = last_error {
+                return Err(e.context(format!("no SRV target of {} could be resolved", service_name)));
+            }
+        }
+        endpoints.sort_by_key(|e| (e.priority, std::cmp::Reverse(e.weight)));
+        Ok(endpoints)
+    }
+    /// Discovers endpoints from the HTTPS records of a host name.
+    ///
+    /// `default_port` is used for records without a `port` parameter. Address hints are used
+    /// when present; otherwise the record's target name is resolved.
+    /// The returned endpoints are sorted by priority.
+    pub async fn lookup_https(&self, host: &str, default_port: u16) -> Result<Vec<DiscoveredEndpoint>> {
+        let lookup = self.inner.lookup(host, RecordType::HTTPS).await?;
+        let mut endpoints = Vec::new();
+        for rdata in lookup.iter() {
+            let svcb = match rdata {
+                RData::HTTPS(https) => &https.0,
+                RData::SVCB(svcb) => svcb,
+                _ => continue,
+            };
+            // Alias mode records only redirect to another name.
+            if svcb.svc_priority() == 0 {
+                continue;
+            }
+            let target = match svcb.target_name().to_utf8().as_str() {
+                "." => host.to_string(),
+                name => trim_root(name),
+            };
+            let mut port = default_port;
+            let mut alpn = Vec::new();
+            let mut hints: Vec<IpAddr> = Vec::new();
+            for (_, value) in svcb.svc_params() {
+                match value {
+                    SvcParamValue::Port(p) => port = *p,
+                    SvcParamValue::Alpn(protocols) => alpn = protocols.0.clone(),
+                    SvcParamValue::Ipv4Hint(ips) => hints.extend(ips.0.iter().map(|a| IpAddr::V4(a.0))),
+                    SvcParamValue::Ipv6Hint(ips) => hints.extend(ips.0.iter().map(|a| IpAddr::V6(a.0))),
+                    _ => {},
+                }
+            }
+            let ips = if hints.is_empty() { self.resolve_host(&target).await? } else { hints };
+            for ip in ips {
+                endpoints.push(DiscoveredEndpoint {
+                    addr: SocketAddr::new(ip, port),
+                    target: target.clone(),
+                    priority: svcb.svc_priority(),
+                    weight: 0,
+                    alpn: alpn.clone(),
+                });
+            }
+        }
+        endpoints.sort_by_key(|e| e.priority);
+        Ok(endpoints)
+    }
+    /// Discovers endpoints for a host, preferring HTTPS records and falling back to the host's
+    /// A/AAAA records on `default_port`.
+    pub async fn discover(&self, host: &str, default_port: u16) -> Result<Vec<DiscoveredEndpoint>> {
+        match self.lookup_https(host, default_port).await {
+            Ok(endpoints) if !endpoints.is_empty() => return Ok(endpoints),
+            Ok(_) => {},
+            Err(e) => tracing::debug!("HTTPS lookup for {} failed: {}", host, e),
+        }
+        let endpoints = self.resolve_host(host).await?.into_iter().map(|ip| DiscoveredEndpoint {
+            addr: SocketAddr::new(ip, default_port),
+            target: host.to_string(),
+            priority: 0,
+            weight: 0,
+            alpn: Vec::new(),
+        }).collect();
+        Ok(endpoints)
+    }
+    /// Resolves the IP addresses of a host.
+    async fn resolve_host(&self, host: &str) -> Result<Vec<IpAddr>> {
+        Ok(self.inner.lookup_ip(host).await?.iter().collect())
+    }
+}
+
+/// Builds a load balancer over discovered endpoints, preserving their order.
+pub fn load_balancer(endpoints: &[DiscoveredEndpoint], strategy: Strategy) -> LoadBalancer {
+    LoadBalancer::new(endpoints.iter().map(|e| e.addr).collect(), strategy)
+}
+
+/// Removes the trailing root label from a fully qualified name.
+fn trim_root(name: &str) -> String {
+    name.trim_end_matches('.').to_string()
+}
@@ -0,0 +1,209 @@
+//! Routing of tagged messages to handlers.
+//!
+//! A message of a `MessageRouter` carries a string tag naming its handler, such as `"upload"`, in front
+//! of its payload (see `encode` and `decode`). The router accepts the peer's bi-directional streams,
+//! reads tagged messages from them and runs the handler registered for each tag in its own task, up to
+//! a limit of handlers running at once per connection. A handler may answer with a reply, sent back on
+//! the same stream under the same tag.
+//!
+//! Messages on a stream are handled concurrently, so replies may arrive in a different order than the
+//! messages they answer. Peers that need to match replies to messages send each message on its own stream.
+
+use anyhow::{bail, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::future::BoxFuture;
+use futures::stream::SplitSink;
+use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Semaphore};
+use crate::{MessageStream, QuicConnection};
+
+/// The maximum length of a tag, in bytes.
+pub const MAX_TAG_LENGTH: usize = 255;
+/// The default maximum number of handlers running at once for a connection.
+pub const DEFAULT_MAX_CONCURRENCY: usize = 64;
+
+/// Encodes a tagged message: the length of the tag as one byte, the tag, then the payload.
+pub fn encode(tag: &str, payload: &[u8]) -> Result<Bytes> {
+    if tag.len() > MAX_TAG_LENGTH {
+        bail!("tag of {} bytes exceeds the maximum of {}", tag.len(), MAX_TAG_LENGTH);
+    }
+    let mut message = BytesMut::with_capacity(1 + tag.len() + payload.len());
+    message.put_u8(tag.len() as u8);
+    message.put_slice(tag.as_bytes());
+    message.put_slice(payload);
+    Ok(message.freeze())
+}
+
+/// Decodes a tagged message into its tag and payload.
+pub fn decode(mut message: Bytes) -> Result<(String, Bytes)> {
+    let Some(&length) = message.first() else { bail!("empty tagged message") };
+    let length = length as usize;
+    if message.len() < 1 + length {
+        bail!("tagged message truncated: tag of {} bytes in {} bytes", length, message.len() - 1);
+    }
+    let tag = message.split_to(1 + length).split_off(1);
+    let tag = String::from_utf8(tag.to_vec())?;
+    Ok((tag, message))
+}
+
+/// A tagged message received from a peer, passed to its handler.
+#[derive(Clone)]
+pub struct Request {
+    /// The connection the message was received on.
+    pub connection: Arc<QuicConnection>,
+    /// The tag of the message.
+    pub tag: String,
+    /// The payload of the message.
+    pub payload: Bytes,
+}
+
+impl Request {
+    /// Returns the address of the peer that sent the message.
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connection.connection.remote_address()
+    }
+}
+
+/// A boxed message handler.
+type Handler = Arc<dyn Fn(Request) -> BoxFuture<'static, Result<Option<Bytes>>> + Send + Sync>;
+
+/// Routes tagged messages to registered handlers.
+#[derive(Clone)]
+pub struct MessageRouter {
+    handlers: HashMap<String, Handler>,
+    fallback: Option<Handler>,
+    max_concurrency: usize,
+}
+
+impl Default for MessageRouter {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            fallback: None,
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+        }
+    }
+}
+
+impl MessageRouter {
+    /// Creates a router without handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers a handler for the messages with the given tag, replacing any previous one.
+    ///
+    /// The handler's reply, if any, is sent back on the stream the message came from. If the handler
+    /// fails, the error is logged and nothing is sent.
+    pub fn on<F, Fut>(mut self, tag: &str, handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Option<Bytes>>> + Send + 'static,
+    {
+        self.handlers.insert(tag.to_string(), boxed(handler));
+        self
+    }
+    /// Registers a handler for the messages whose tag has no handler.
+    ///
+    /// Without one, such messages are logged and dropped.
+    pub fn fallback<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Option<Bytes>>> + Send + 'static,
+    {
+        self.fallback = Some(boxed(handler));
+        self
+    }
+    /// Sets the maximum number of handlers running at once for a connection. Defaults to `DEFAULT_MAX_CONCURRENCY`.
+    ///
+    /// Once reached, no more messages are read from the connection's streams until a handler finishes.
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+    /// Serves the streams opened by the peer until the connection is closed.
+    pub async fn serve(self, connection: Arc<QuicConnection>) -> Result<()> {
+        let router = Arc::new(self);
+        let permits = Arc::new(Semaphore::new(router.max_concurrency));
+        loop {
+            let stream = match connection.accept_message_stream().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::debug!("Stopped accepting streams from {}: {}", connection.connection.remote_address(), e);
+                    return Ok(());
+                },
+            };
+            tokio::spawn(serve_stream(Arc::clone(&router), Arc::clone(&connection), Arc::clone(&permits), stream));
+        }
+    }
+    fn handler(&self, tag: &str) -> Option<&Handler> {
+        self.handlers.get(tag).or(self.fallback.as_ref())
+    }
+}
+
+fn boxed<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Option<Bytes>>> + Send + 'static,
+{
+    Arc::new(move |request| Box::pin(handler(request)))
+}
+
+/// Reads the tagged messages of a stream, handling each in its own task.
+async fn serve_stream(router: Arc<MessageRouter>, connection: Arc<QuicConnection>, permits: Arc<Semaphore>, stream: MessageStream) {
+    let remote_address = connection.connection.remote_address();
+    let (sink, mut messages) = stream.split();
+    let sink: Arc<Mutex<SplitSink<MessageStream, Bytes>>> = Arc::new(Mutex::new(sink));
+    loop {
+        // Wait for a slot before reading, so a busy connection is slowed down by flow control.
+        let Ok(permit) = Arc::clone(&permits).acquire_owned().await else { return };
+        let message = match messages.next().await {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                tracing::debug!("Failed to read message from {}: {}", remote_address, e);
+                return;
+            },
+            None => return,
+        };
+        let (tag, payload) = match decode(message) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                tracing::debug!("Invalid tagged message from {}: {}", remote_address, e);
+                continue;
+            },
+        };
+        let Some(handler) = router.handler(&tag).cloned() else {
+            tracing::debug!("No handler for tag {:?} from {}", tag, remote_address);
+            continue;
+        };
+        let request = Request { connection: Arc::clone(&connection), tag, payload };
+        let sink = Arc::clone(&sink);
+        tokio::spawn(async move {
+            // The permit is held until the reply is sent, so a peer that does not read replies is bounded too.
+            let _permit = permit;
+            let tag = request.tag.clone();
+            let reply = handler(request).await;
+            let reply = match reply {
+                Ok(Some(reply)) => reply,
+                Ok(None) => return,
+                Err(e) => {
+                    tracing::warn!("Handler for tag {:?} failed for {}: {}", tag, remote_address, e);
+                    return;
+                },
+            };
+            let reply = match encode(&tag, &reply) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    tracing::warn!("Failed to encode reply to {}: {}", remote_address, e);
+                    return;
+                },
+            };
+            if let Err(e) = sink.lock().await.send(reply).await {
+                tracing::debug!("Failed to send reply to {}: {}", remote_address, e);
+            }
+        });
+    }
+}
@@ -1,13 +1,50 @@
 //! Module for creating QUIC endpoints.
 
-use quinn::{ClientConfig, Endpoint, ServerConfig};
-use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use quinn::{ClientConfig, Endpoint, EndpointConfig, ServerConfig, TransportConfig};
+use rustls::pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName, UnixTime};
 use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
 use std::path::Path;
 use std::sync::Arc;
 use std::{error::Error, net::SocketAddr};
-use quinn_proto::crypto::rustls::QuicClientConfig;
+use quinn_proto::crypto::rustls::{QuicClientConfig, QuicServerConfig};
 use rustls::ClientConfig as RustlsClientConfig;
+use rustls::ServerConfig as RustlsServerConfig;
+use rustls::crypto::CryptoProvider;
+use crate::config::SocketConfig;
+use crate::transport::TransportOptions;
+use crate::version::QuicVersion;
+use crate::tls::crypto::{crypto_provider, initial_suite};
+use crate::tls::ct::{CertificateTransparency, CtVerifier};
+
+/// How a client endpoint verifies the server's certificate.
+#[derive(Debug, Clone)]
+pub enum ServerVerification {
+    /// Trust only the given DER-encoded certificates.
+    Certificates(Vec<Vec<u8>>),
+    /// Trust the root certificates found in the platform's native certificate store.
+    ///
+    /// Requires the `native-certs` feature.
+    #[cfg(feature = "native-certs")]
+    NativeRoots,
+    /// Skip server certificate verification.
+    /// NOTE, this is vulnerable to MITM attacks, but convenient for testing.
+    Insecure,
+}
+
+/// The implementation of UDP I/O used by an endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UdpBackend {
+    /// Non-blocking system calls driven by the tokio reactor.
+    #[default]
+    Tokio,
+    /// An io_uring ring driven by a dedicated thread, which cuts the system calls per datagram on
+    /// high-packet-rate servers.
+    ///
+    /// Requires the `io-uring` feature and Linux. Falls back to `Tokio` when unavailable, such as on
+    /// kernels without io_uring support. Segmentation offload is not used with this backend.
+    IoUring,
+}
 
 /// Constructs a QUIC endpoint configured for use a client only.
 ///
@@ -26,6 +63,9 @@ pub fn make_client_endpoint(
 }
 
 /// Constructs a QUIC client endpoint using root certificates found in the platform's native certificate store.
+///
+/// Requires the `native-certs` feature.
+#[cfg(feature = "native-certs")]
 pub fn make_native_client_endpoint(
     bind_addr: SocketAddr,
 ) -> Result<Endpoint, Box<dyn Error + Send + Sync + 'static>> {
@@ -80,6 +120,304 @@ pub fn make_self_signed_server_endpoint(
     Ok(endpoint)
 }
 
+/// Constructs a QUIC client endpoint that offers the given ALPN protocols during the handshake.
+///
+/// ## Args
+///
+/// - bind_addr: the address to bind the client endpoint to.
+///
+/// - verification: how the server's certificate is verified.
+///
+/// - alpn_protocols: the application protocols to offer, in order of preference.
+pub fn make_alpn_client_endpoint(
+    bind_addr: SocketAddr,
+    verification: &ServerVerification,
+    alpn_protocols: &[&[u8]],
+) -> Result<Endpoint, Box<dyn Error + Send + Sync + 'static>> {
+    let mut rustls_client_config = configure_rustls_client(verification, crypto_provider(&[], &[]), Vec::new(), None)?;
+    rustls_client_config.alpn_protocols = alpn_protocols.iter().map(|p| p.to_vec()).collect();
+    let client_config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(rustls_client_config)?));
+    let mut endpoint = Endpoint::client(bind_addr)?;
+    endpoint.set_default_client_config(client_config);
+    Ok(endpoint)
+}
+
+/// Constructs a QUIC server endpoint that negotiates the given ALPN protocols during the handshake.
+/// If `cert_path` and `key_path` are provided, the server will use the certificate and key at those
+/// paths. Otherwise, a self-signed certificate will be generated.
+///
+/// Unlike `make_server_endpoint`, uni-directional streams are left enabled since application
+/// protocols such as HTTP/3 rely on them.
+pub fn make_alpn_server_endpoint(
+    bind_addr: SocketAddr,
+    cert_path: Option<&Path>,
+    key_path: Option<&Path>,
+    alpn_protocols: &[&[u8]],
+) -> Result<Endpoint, Box<dyn Error + Send + Sync + 'static>> {
+    let (cert_chain, key) = crate::tls::load_or_generate_cert(cert_path, key_path)?;
+    let mut rustls_server_config = RustlsServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)?;
+    rustls_server_config.alpn_protocols = alpn_protocols.iter().map(|p| p.to_vec()).collect();
+    let server_config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(rustls_server_config)?));
+    let endpoint = Endpoint::server(server_config, bind_addr)?;
+    Ok(endpoint)
+}
+
+/// Constructs a QUIC endpoint that can both accept incoming connections and connect to other peers.
+///
+/// ## Args
+///
+/// - bind_addr: the address to bind the endpoint to.
+///
+/// - config: the TLS identity, verification and ALPN settings of the endpoint.
+pub fn make_peer_endpoint(
+    bind_addr: SocketAddr,
+    config: &SocketConfig,
+) -> Result<Endpoint, Box<dyn Error + Send + Sync + 'static>> {
+    Ok(make_endpoint_with_config(bind_addr, config, true, true)?.0)
+}
+
+/// Constructs a QUIC endpoint configured for use as a server only, from a socket config.
+///
+/// The server verification settings of the config are ignored.
+pub fn make_server_endpoint_with_config(
+    bind_addr: SocketAddr,
+    config: &SocketConfig,
+) -> Result<Endpoint, Box<dyn Error + Send + Sync + 'static>> {
+    Ok(make_endpoint_with_config(bind_addr, config, true, false)?.0)
+}
+
+/// Constructs a QUIC endpoint configured for use as a client only, from a socket config.
+///
+/// The TLS identity settings of the config are ignored.
+pub fn make_client_endpoint_with_config(
+    bind_addr: SocketAddr,
+    config: &SocketConfig,
+) -> Result<Endpoint, Box<dyn Error + Send + Sync + 'static>> {
+    Ok(make_endpoint_with_config(bind_addr, config, false, true)?.0)
+}
+
+/// The configs an endpoint was made with from a socket config, kept to derive the configs of single
+/// connections from them.
+#[derive(Clone)]
+pub(crate) struct EndpointConfigs {
+    client: Option<ClientConfig>,
+    server: Option<ServerConfig>,
+    transport: TransportOptions,
+    pub(crate) quic_versions: Vec<QuicVersion>,
+}
+
+impl EndpointConfigs {
+    /// Returns the client config with the transport options of `overrides` taking precedence.
+    pub(crate) fn client_with(&self, overrides: &TransportOptions) -> anyhow::Result<ClientConfig> {
+        let mut client_config = self.client.clone().ok_or_else(|| anyhow::anyhow!("the socket cannot connect"))?;
+        client_config.transport_config(Arc::new(self.transport.merged_with(overrides).to_transport_config()?));
+        Ok(client_config)
+    }
+    /// Returns the server config with the transport options of `overrides` taking precedence.
+    pub(crate) fn server_with(&self, overrides: &TransportOptions) -> anyhow::Result<ServerConfig> {
+        let mut server_config = self.server.clone().ok_or_else(|| anyhow::anyhow!("the socket cannot accept"))?;
+        server_config.transport = Arc::new(server_transport_config(&self.transport.merged_with(overrides))?);
+        Ok(server_config)
+    }
+    /// Returns the number of uni-directional streams clients may open, if configured.
+    pub(crate) fn max_concurrent_uni_streams(&self) -> Option<u32> {
+        self.transport.max_concurrent_uni_streams
+    }
+}
+
+/// Constructs a QUIC endpoint from a socket config, accepting connections if `server` is set and
+/// connecting if `client` is set. Also returns the configs it was made with.
+pub(crate) fn make_endpoint_with_config(
+    bind_addr: SocketAddr,
+    config: &SocketConfig,
+    server: bool,
+    client: bool,
+) -> Result<(Endpoint, EndpointConfigs), Box<dyn Error + Send + Sync + 'static>> {
+    if server && config.connection_ids.as_ref().is_some_and(|ids| ids.id_length() == 0) {
+        return Err("zero-length connection IDs are only supported by clients".into());
+    }
+    let server_config = if server { Some(configure_server_with(config)?) } else { None };
+    let client_config = if client { Some(configure_client_with(config)?) } else { None };
+    let mut endpoint = bind_endpoint(bind_addr, server_config.clone(), config)?;
+    if let Some(client_config) = &client_config {
+        endpoint.set_default_client_config(client_config.clone());
+    }
+    let configs = EndpointConfigs {
+        client: client_config,
+        server: server_config,
+        transport: config.transport.clone(),
+        quic_versions: config.quic_versions.clone().unwrap_or_else(QuicVersion::supported),
+    };
+    Ok((endpoint, configs))
+}
+
+/// Binds an endpoint with the endpoint-wide settings of a socket config.
+fn bind_endpoint(
+    bind_addr: SocketAddr,
+    server_config: Option<ServerConfig>,
+    config: &SocketConfig,
+) -> Result<Endpoint, Box<dyn Error + Send + Sync + 'static>> {
+    let mut endpoint_config = EndpointConfig::default();
+    if let Some(reset_key) = &config.reset_key {
+        endpoint_config.reset_key(Arc::clone(reset_key) as Arc<dyn quinn::crypto::HmacKey>);
+    }
+    if let Some(connection_ids) = config.connection_ids.clone() {
+        endpoint_config.cid_generator(move || connection_ids.generator());
+    }
+    if let Some(bytes) = config.transport.max_udp_payload_size {
+        endpoint_config.max_udp_payload_size(bytes)?;
+    }
+    if let Some(versions) = &config.quic_versions {
+        crate::version::validate(versions)?;
+        endpoint_config.supported_versions(versions.iter().map(|version| version.0).collect());
+    }
+    let socket = std::net::UdpSocket::bind(bind_addr)?;
+    let runtime = quinn::default_runtime().ok_or("no async runtime found")?;
+    let socket: Arc<dyn quinn::AsyncUdpSocket> = match config.offload {
+        Some(offload) => {
+            let handle = socket.try_clone()?;
+            crate::offload::OffloadSocket::new(wrap_udp_socket(socket, config.udp_backend, &*runtime)?, &handle, offload)
+        },
+        None => wrap_udp_socket(socket, config.udp_backend, &*runtime)?,
+    };
+    #[cfg(feature = "sim")]
+    let socket: Arc<dyn quinn::AsyncUdpSocket> = match &config.network_conditions {
+        Some(conditions) => crate::sim::SimulatedSocket::new(socket, conditions.clone()),
+        None => socket,
+    };
+    let socket: Arc<dyn quinn::AsyncUdpSocket> = match config.pacing {
+        Some(pacing) => crate::pacing::PacedSocket::new(socket, pacing),
+        None => socket,
+    };
+    Ok(Endpoint::new_with_abstract_socket(endpoint_config, server_config, socket, runtime)?)
+}
+
+/// Wraps a bound socket with the given backend, falling back to the runtime's own when unavailable.
+fn wrap_udp_socket(
+    socket: std::net::UdpSocket,
+    backend: UdpBackend,
+    runtime: &dyn quinn::Runtime,
+) -> std::io::Result<Arc<dyn quinn::AsyncUdpSocket>> {
+    if backend == UdpBackend::IoUring {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        match crate::uring::UringSocket::new(socket.try_clone()?) {
+            Ok(socket) => {
+                tracing::debug!("Using the io_uring UDP backend");
+                return Ok(socket);
+            },
+            Err(e) => tracing::warn!("io_uring is unavailable, falling back to the tokio UDP backend: {}", e),
+        }
+        #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+        tracing::warn!("io_uring support is not enabled, falling back to the tokio UDP backend");
+    }
+    runtime.wrap_udp_socket(socket)
+}
+
+/// Builds quinn server config from a socket config.
+pub(crate) fn configure_server_with(config: &SocketConfig) -> Result<ServerConfig, Box<dyn Error + Send + Sync + 'static>> {
+    let (cert_chain, key) = crate::tls::load_or_generate_cert(config.cert_path.as_deref(), config.key_path.as_deref())?;
+    let mut rustls_server_config = RustlsServerConfig::builder_with_provider(crypto_provider(&config.cipher_suites, &config.kx_groups))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)?;
+    rustls_server_config.alpn_protocols = config.alpn_protocols.clone();
+    if config.zero_rtt.is_some() {
+        // quinn only supports accepting early data without a size limit.
+        rustls_server_config.max_early_data_size = u32::MAX;
+    }
+    let crypto = QuicServerConfig::with_initial(Arc::new(rustls_server_config), initial_suite())?;
+    let mut server_config = ServerConfig::with_crypto(Arc::new(crypto));
+    server_config.transport = Arc::new(server_transport_config(&config.transport)?);
+    if let Some(enabled) = config.migration {
+        server_config.migration(enabled);
+    }
+    server_config.preferred_address_v4(config.preferred_address_v4);
+    server_config.preferred_address_v6(config.preferred_address_v6);
+    if let Some(key) = &config.retry_token_key {
+        server_config.token_key(Arc::new(crate::retry::TokenKey(Arc::clone(key))));
+        server_config.retry_token_lifetime(key.lifetime());
+    }
+    Ok(server_config)
+}
+
+/// Builds the transport config of a server. Uni-directional streams are refused unless the options allow them.
+fn server_transport_config(options: &TransportOptions) -> anyhow::Result<TransportConfig> {
+    let mut transport_config = TransportConfig::default();
+    transport_config.max_concurrent_uni_streams(0_u8.into());
+    options.apply(&mut transport_config)?;
+    Ok(transport_config)
+}
+
+/// Builds quinn client config from a socket config.
+fn configure_client_with(config: &SocketConfig) -> Result<ClientConfig, Box<dyn Error + Send + Sync + 'static>> {
+    let mut crls: Vec<CertificateRevocationListDer<'static>> = config.crls.iter().map(|crl| CertificateRevocationListDer::from(crl.clone())).collect();
+    for crl_path in &config.crl_paths {
+        crls.extend(crate::tls::certificate::load_crls(crl_path)?);
+    }
+    let provider = crypto_provider(&config.cipher_suites, &config.kx_groups);
+    let mut rustls_client_config = configure_rustls_client(&config.verification, provider, crls, config.transparency.as_ref())?;
+    rustls_client_config.alpn_protocols = config.alpn_protocols.clone();
+    if let Some(session_store) = &config.session_store {
+        rustls_client_config.resumption = rustls::client::Resumption::store(Arc::clone(session_store));
+    }
+    let crypto = QuicClientConfig::with_initial(Arc::new(rustls_client_config), initial_suite())?;
+    let mut client_config = ClientConfig::new(Arc::new(crypto));
+    client_config.transport_config(Arc::new(config.transport.to_transport_config()?));
+    if let Some(version) = config.quic_versions.as_ref().and_then(|versions| versions.first()) {
+        client_config.version(version.0);
+    }
+    Ok(client_config)
+}
+
+/// Builds a rustls client config for the given server verification mode and crypto provider.
+///
+/// Server certificates are checked against `crls`, if any. If `transparency` is set, they must also
+/// carry valid SCTs.
+fn configure_rustls_client(
+    verification: &ServerVerification,
+    provider: Arc<CryptoProvider>,
+    crls: Vec<CertificateRevocationListDer<'static>>,
+    transparency: Option<&CertificateTransparency>,
+) -> Result<RustlsClientConfig, Box<dyn Error + Send + Sync + 'static>> {
+    let builder = RustlsClientConfig::builder_with_provider(Arc::clone(&provider)).with_protocol_versions(&[&rustls::version::TLS13])?;
+    let roots = match verification {
+        ServerVerification::Certificates(server_certs) => {
+            let mut certs = rustls::RootCertStore::empty();
+            for cert in server_certs {
+                certs.add(CertificateDer::from(cert.clone()))?;
+            }
+            certs
+        },
+        #[cfg(feature = "native-certs")]
+        ServerVerification::NativeRoots => crate::tls::certificate::get_native_certs()?,
+        ServerVerification::Insecure => {
+            if transparency.is_some() {
+                return Err("certificate transparency cannot be enforced without certificate verification".into());
+            }
+            return Ok(builder
+                .dangerous()
+                .with_custom_certificate_verifier(SkipServerVerification::new())
+                .with_no_client_auth());
+        },
+    };
+    let roots = Arc::new(roots);
+    let mut verifier = WebPkiServerVerifier::builder_with_provider(Arc::clone(&roots), provider);
+    if !crls.is_empty() {
+        verifier = verifier.with_crls(crls).only_check_end_entity_revocation();
+    }
+    let verifier = verifier.build()?;
+    let config = match transparency {
+        Some(transparency) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(CtVerifier::new(verifier, roots, transparency.clone())))
+            .with_no_client_auth(),
+        None => builder.with_webpki_verifier(verifier).with_no_client_auth(),
+    };
+    Ok(config)
+}
+
 /// Builds default quinn client config and trusts given certificates.
 ///
 /// ## Args
@@ -110,7 +448,14 @@ fn configure_server(cert_path: Option<&Path>, key_path: Option<&Path>) -> Result
 /// Returns default server configuration along with its certificate.
 fn configure_self_signed_server() -> Result<ServerConfig, Box<dyn Error + Send + Sync + 'static>> {
     let (cert_chain, key) = crate::tls::generate_self_signed_pair()?;
+    configure_server_with_cert(cert_chain, key)
+}
 
+/// Returns default server configuration using the given certificate chain and key.
+pub(crate) fn configure_server_with_cert(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig, Box<dyn Error + Send + Sync + 'static>> {
     let mut server_config =
         ServerConfig::with_single_cert(cert_chain, key)?;
     let transport_config = Arc::get_mut(&mut server_config.transport).unwrap();
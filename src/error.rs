@@ -0,0 +1,185 @@
+//! Error types for QUIC connection and stream failures.
+//!
+//! Failures reported by quinn are returned as `quicsock::Error` inside `anyhow::Error`,
+//! so they can be inspected with `err.downcast_ref::<quicsock::Error>()`. A peer resetting or stopping
+//! a stream surfaces as `Error::Reset` or `Error::Stopped` with its application error code, both from
+//! `QuicConnection` and from `MessageStream`.
+
+use quinn::{ConnectionError, ReadError, ReadExactError, ReadToEndError, WriteError};
+use std::{fmt, io};
+use crate::codes::{AUTH_FAILED_CODE, STREAM_CANCELLED_CODE, STREAM_DEADLINE_CODE};
+
+/// A connection or stream failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The connection was closed or lost. The quinn error holds the close reason and code.
+    ConnectionLost(ConnectionError),
+    /// The peer stopped the stream with an application error code.
+    Stopped(u64),
+    /// The peer reset the stream with an application error code.
+    Reset(u64),
+    /// The stream was already finished or reset locally.
+    ClosedStream,
+    /// The stream was read in an order that is not allowed, e.g. ordered after unordered reads.
+    IllegalOrderedRead,
+    /// 0-RTT data was rejected by the peer, which requires sending it again.
+    ZeroRttRejected,
+    /// The stream ended before the expected amount of data was read.
+    FinishedEarly(usize),
+    /// The stream carried more data than allowed.
+    TooLong,
+    /// The handshake did not complete within the handshake timeout (see `SocketConfig::with_handshake_timeout`).
+    HandshakeTimedOut,
+}
+
+impl Error {
+    /// Returns the application or transport error code carried by the error, if any.
+    pub fn code(&self) -> Option<u64> {
+        match self {
+            Error::Stopped(code) | Error::Reset(code) => Some(*code),
+            Error::ConnectionLost(ConnectionError::ApplicationClosed(close)) => Some(close.error_code.into_inner()),
+            Error::ConnectionLost(ConnectionError::ConnectionClosed(close)) => Some(u64::from(close.error_code)),
+            Error::ConnectionLost(ConnectionError::TransportError(error)) => Some(u64::from(error.code)),
+            _ => None,
+        }
+    }
+    /// Returns whether the connection is gone, as opposed to a single stream failing.
+    pub fn is_connection_lost(&self) -> bool {
+        matches!(self, Error::ConnectionLost(_))
+    }
+    /// Returns whether the connection timed out, during or after the handshake.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Error::ConnectionLost(ConnectionError::TimedOut) | Error::HandshakeTimedOut)
+    }
+    /// Returns whether the handshake did not complete within the handshake timeout.
+    pub fn is_handshake_timeout(&self) -> bool {
+        matches!(self, Error::HandshakeTimedOut)
+    }
+    /// Returns whether the peer aborted the stream because the operation was cancelled (see `cancel`).
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Error::Stopped(_) | Error::Reset(_)) && self.code() == Some(STREAM_CANCELLED_CODE as u64)
+    }
+    /// Returns whether the peer aborted the stream because its deadline passed (see `deadline`).
+    pub fn is_deadline_exceeded(&self) -> bool {
+        matches!(self, Error::Stopped(_) | Error::Reset(_)) && self.code() == Some(STREAM_DEADLINE_CODE as u64)
+    }
+    /// Returns whether the peer closed the connection because authentication failed, e.g. during pairing.
+    pub fn is_auth_failed(&self) -> bool {
+        matches!(self, Error::ConnectionLost(ConnectionError::ApplicationClosed(_))) && self.code() == Some(AUTH_FAILED_CODE as u64)
+    }
+    /// Returns whether the operation may succeed if retried, possibly on a new connection.
+    ///
+    /// This is the case for rejected 0-RTT data, timeouts and stateless resets.
+    /// Closes initiated by either side and protocol errors are not retryable.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Error::ZeroRttRejected
+                | Error::HandshakeTimedOut
+                | Error::ConnectionLost(ConnectionError::TimedOut)
+                | Error::ConnectionLost(ConnectionError::Reset)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ConnectionLost(e) => write!(f, "connection lost: {}", e),
+            Error::Stopped(code) => write!(f, "stream stopped by peer: error {}", code),
+            Error::Reset(code) => write!(f, "stream reset by peer: error {}", code),
+            Error::ClosedStream => write!(f, "closed stream"),
+            Error::IllegalOrderedRead => write!(f, "ordered read after unordered read"),
+            Error::ZeroRttRejected => write!(f, "0-RTT rejected"),
+            Error::FinishedEarly(read) => write!(f, "stream finished early after {} bytes", read),
+            Error::TooLong => write!(f, "stream too long"),
+            Error::HandshakeTimedOut => write!(f, "handshake timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ConnectionLost(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(e: ConnectionError) -> Self {
+        Error::ConnectionLost(e)
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(e: WriteError) -> Self {
+        match e {
+            WriteError::Stopped(code) => Error::Stopped(code.into_inner()),
+            WriteError::ConnectionLost(e) => Error::ConnectionLost(e),
+            WriteError::ClosedStream => Error::ClosedStream,
+            WriteError::ZeroRttRejected => Error::ZeroRttRejected,
+        }
+    }
+}
+
+impl From<ReadError> for Error {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::Reset(code) => Error::Reset(code.into_inner()),
+            ReadError::ConnectionLost(e) => Error::ConnectionLost(e),
+            ReadError::ClosedStream => Error::ClosedStream,
+            ReadError::IllegalOrderedRead => Error::IllegalOrderedRead,
+            ReadError::ZeroRttRejected => Error::ZeroRttRejected,
+        }
+    }
+}
+
+impl From<ReadExactError> for Error {
+    fn from(e: ReadExactError) -> Self {
+        match e {
+            ReadExactError::FinishedEarly(read) => Error::FinishedEarly(read),
+            ReadExactError::ReadError(e) => e.into(),
+        }
+    }
+}
+
+impl From<ReadToEndError> for Error {
+    fn from(e: ReadToEndError) -> Self {
+        match e {
+            ReadToEndError::Read(e) => e.into(),
+            ReadToEndError::TooLong => Error::TooLong,
+        }
+    }
+}
+
+impl From<quinn::ClosedStream> for Error {
+    fn from(_: quinn::ClosedStream) -> Self {
+        Error::ClosedStream
+    }
+}
+
+/// Converts an I/O error of a quinn stream, e.g. from its `AsyncRead` or `AsyncWrite` implementation, into an
+/// `Error` if it wraps a stream failure, so the peer's error code is not lost.
+pub(crate) fn from_io(e: io::Error) -> anyhow::Error {
+    if let Some(inner) = e.get_ref() {
+        if let Some(read) = inner.downcast_ref::<ReadError>() {
+            return Error::from(read.clone()).into();
+        }
+        if let Some(write) = inner.downcast_ref::<WriteError>() {
+            return Error::from(write.clone()).into();
+        }
+    }
+    e.into()
+}
+
+impl From<quinn::StoppedError> for Error {
+    fn from(e: quinn::StoppedError) -> Self {
+        match e {
+            quinn::StoppedError::ConnectionLost(e) => Error::ConnectionLost(e),
+            quinn::StoppedError::ZeroRttRejected => Error::ZeroRttRejected,
+        }
+    }
+}
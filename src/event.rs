@@ -0,0 +1,82 @@
+//! Socket events.
+//!
+//! Events are broadcast to every receiver returned by `QuicSocket::subscribe_events`
+//! or `QuicConnection::subscribe_events`. Slow receivers miss the oldest events.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use crate::close::CloseReason;
+
+/// The number of events buffered for each receiver.
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// An event emitted by a socket or one of its connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SocketEvent {
+    /// A stream opened by a peer was rejected because the peer exceeded its stream quota.
+    StreamQuotaExceeded {
+        /// The address of the peer.
+        remote_address: SocketAddr,
+        /// The quota that was exceeded.
+        limit: u32,
+    },
+    /// An incoming connection was refused because its source IP address exceeded the connection rate limit.
+    ConnectionRateLimited {
+        /// The address of the peer.
+        remote_address: SocketAddr,
+        /// The number of connections allowed per window.
+        limit: u32,
+    },
+    /// A stream registered by ID was reset and removed because it was idle for longer than the idle
+    /// stream timeout.
+    StreamIdle {
+        /// The address of the peer.
+        remote_address: SocketAddr,
+        /// The ID of the stream on its connection.
+        stream_id: u64,
+    },
+    /// The network path of a connection changed: the peer moved to a new address, or started sending to
+    /// another local address, e.g. the preferred address of a server. Only emitted for connections whose
+    /// path is checked, see `QuicConnection::set_path_check_interval`.
+    PathChanged {
+        /// The new address of the peer.
+        remote_address: SocketAddr,
+        /// The previous address of the peer.
+        previous_remote_address: SocketAddr,
+        /// The local IP address the peer now sends to, if known.
+        local_ip: Option<IpAddr>,
+        /// The local IP address the peer previously sent to, if known.
+        previous_local_ip: Option<IpAddr>,
+    },
+    /// Nothing was received from the peer of a connection for the suspect threshold of its liveness
+    /// thresholds, see `liveness`.
+    PeerSuspect {
+        /// The address of the peer.
+        remote_address: SocketAddr,
+        /// How long nothing was received from the peer.
+        silent_for: Duration,
+    },
+    /// Nothing was received from the peer of a connection for the dead threshold of its liveness
+    /// thresholds, see `liveness`. The connection stays open until it is closed or times out.
+    PeerDead {
+        /// The address of the peer.
+        remote_address: SocketAddr,
+        /// How long nothing was received from the peer.
+        silent_for: Duration,
+    },
+    /// Packets were received again from a peer after a `PeerSuspect` or `PeerDead`.
+    PeerRecovered {
+        /// The address of the peer.
+        remote_address: SocketAddr,
+    },
+    /// A connection registered with the socket was closed and removed from it.
+    ConnectionClosed {
+        /// The address of the peer.
+        remote_address: SocketAddr,
+        /// Why the connection was closed, as reported by quinn.
+        reason: quinn::ConnectionError,
+        /// Why the connection was closed. See `close`.
+        close_reason: CloseReason,
+    },
+}
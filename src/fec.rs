@@ -0,0 +1,326 @@
+//! Forward error correction for datagrams.
+//!
+//! `FecSender` sends datagrams in groups of `FecOptions::group_size`, followed by a parity datagram holding
+//! the XOR of the group. `FecReceiver` delivers datagrams as they arrive and rebuilds a single lost
+//! datagram of a group from the others and the parity, without waiting for a retransmission round trip.
+//! Losing two datagrams of a group, or one and the parity, loses them for good.
+//!
+//! Each datagram carries a 9-byte header, and the parity is as large as the largest datagram of its group
+//! plus 3 bytes, so payloads are limited to `FecSender::max_payload_size`. Both peers must use the layer
+//! on a connection, which should not carry other datagrams, with the same group size: the receiver ignores
+//! parity for groups larger than its own.
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use quinn::{Connection, SendDatagramError};
+use std::collections::{BTreeMap, VecDeque};
+use crate::error::Error;
+use crate::QuicConnection;
+
+/// The default number of datagrams protected by each parity datagram.
+pub const DEFAULT_GROUP_SIZE: usize = 4;
+/// The maximum number of datagrams protected by each parity datagram.
+pub const MAX_GROUP_SIZE: usize = 64;
+/// The default number of recent groups a receiver keeps to recover datagrams.
+pub const DEFAULT_GROUP_WINDOW: usize = 16;
+/// The size of the header of every datagram: the group number and the index in the group.
+const HEADER_SIZE: usize = 9;
+/// The index marking a parity datagram.
+const PARITY_INDEX: u8 = 0xff;
+/// The size added to the largest payload of a group by the parity: its header, group size and length.
+const OVERHEAD: usize = HEADER_SIZE + 1 + 2;
+
+/// Options for `FecSender` and `FecReceiver`.
+#[derive(Debug, Clone)]
+pub struct FecOptions {
+    /// The number of datagrams protected by each parity datagram, up to `MAX_GROUP_SIZE`. Smaller groups
+    /// recover more losses at the cost of more parity traffic.
+    pub group_size: usize,
+    /// The number of recent groups a receiver keeps. Datagrams of older groups are dropped, so this also
+    /// bounds the reordering tolerated.
+    pub group_window: usize,
+}
+
+impl Default for FecOptions {
+    fn default() -> Self {
+        Self {
+            group_size: DEFAULT_GROUP_SIZE,
+            group_window: DEFAULT_GROUP_WINDOW,
+        }
+    }
+}
+
+impl FecOptions {
+    /// Creates options with groups of `DEFAULT_GROUP_SIZE` datagrams and a window of `DEFAULT_GROUP_WINDOW` groups.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the number of datagrams protected by each parity datagram.
+    pub fn with_group_size(mut self, group_size: usize) -> Self {
+        self.group_size = group_size;
+        self
+    }
+    /// Sets the number of recent groups a receiver keeps.
+    pub fn with_group_window(mut self, group_window: usize) -> Self {
+        self.group_window = group_window;
+        self
+    }
+}
+
+/// Sends datagrams protected by parity datagrams.
+pub struct FecSender {
+    connection: Connection,
+    group_size: usize,
+    group: u64,
+    index: usize,
+    parity: BytesMut,
+}
+
+impl FecSender {
+    /// Creates a sender on the datagrams of `connection`.
+    pub fn new(connection: &QuicConnection, options: FecOptions) -> Self {
+        Self {
+            connection: connection.connection.clone(),
+            group_size: options.group_size.clamp(1, MAX_GROUP_SIZE),
+            group: 0,
+            index: 0,
+            parity: BytesMut::new(),
+        }
+    }
+    /// Returns the largest payload that can be sent, or `None` if the peer does not accept datagrams.
+    pub fn max_payload_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size().map(|max| max.saturating_sub(OVERHEAD).min(u16::MAX as usize))
+    }
+    /// Sends a datagram, followed by the parity of its group if it completes one.
+    pub fn send(&mut self, payload: Bytes) -> Result<()> {
+        if let Some(max) = self.max_payload_size() {
+            if payload.len() > max {
+                anyhow::bail!("datagram of {} bytes exceeds the maximum payload size of {} bytes", payload.len(), max);
+            }
+        }
+        let mut datagram = BytesMut::with_capacity(HEADER_SIZE + payload.len());
+        datagram.put_u64(self.group);
+        datagram.put_u8(self.index as u8);
+        datagram.put_slice(&payload);
+        send_datagram(&self.connection, datagram.freeze())?;
+        xor_into(&mut self.parity, &payload);
+        self.index += 1;
+        if self.index == self.group_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+    /// Sends the parity of the current group if it is not empty, starting a new group.
+    ///
+    /// Call this when no datagram will follow for a while, so losses of the last ones can be recovered.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.index == 0 {
+            return Ok(());
+        }
+        let mut datagram = BytesMut::with_capacity(HEADER_SIZE + 1 + self.parity.len());
+        datagram.put_u64(self.group);
+        datagram.put_u8(PARITY_INDEX);
+        datagram.put_u8(self.index as u8);
+        datagram.put_slice(&self.parity);
+        self.group += 1;
+        self.index = 0;
+        self.parity.clear();
+        send_datagram(&self.connection, datagram.freeze())
+    }
+}
+
+/// A group of datagrams being received.
+#[derive(Default)]
+struct Group {
+    datagrams: Vec<Option<Bytes>>,
+    parity: Option<(usize, Bytes)>,
+}
+
+impl Group {
+    /// Rebuilds the only missing datagram of the group, if the parity and all others were received.
+    ///
+    /// Parity claiming more datagrams than `group_size`, or fewer than were received, is ignored.
+    fn recover(&mut self, group_size: usize) -> Option<Bytes> {
+        let (size, parity) = self.parity.as_ref()?;
+        if *size > group_size || *size < self.datagrams.len() {
+            return None;
+        }
+        self.datagrams.resize(*size, None);
+        let mut missing = self.datagrams.iter().enumerate().filter(|(_, datagram)| datagram.is_none()).map(|(index, _)| index);
+        let index = missing.next()?;
+        if missing.next().is_some() {
+            return None;
+        }
+        let mut recovered = BytesMut::from(&parity[..]);
+        for datagram in self.datagrams.iter().flatten() {
+            xor_into(&mut recovered, datagram);
+        }
+        let mut recovered = recovered.freeze();
+        let length = recovered.get_u16() as usize;
+        if length > recovered.len() {
+            return None;
+        }
+        let recovered = recovered.split_to(length);
+        self.datagrams[index] = Some(recovered.clone());
+        Some(recovered)
+    }
+}
+
+/// Receives datagrams protected by parity datagrams, recovering single losses per group.
+pub struct FecReceiver {
+    connection: Connection,
+    group_size: usize,
+    group_window: u64,
+    groups: BTreeMap<u64, Group>,
+    recovered: VecDeque<Bytes>,
+    recovered_count: u64,
+}
+
+impl FecReceiver {
+    /// Creates a receiver on the datagrams of `connection`.
+    pub fn new(connection: &QuicConnection, options: FecOptions) -> Self {
+        Self {
+            connection: connection.connection.clone(),
+            group_size: options.group_size.clamp(1, MAX_GROUP_SIZE),
+            group_window: options.group_window.max(1) as u64,
+            groups: BTreeMap::new(),
+            recovered: VecDeque::new(),
+            recovered_count: 0,
+        }
+    }
+    /// Returns the number of lost datagrams recovered so far.
+    pub fn recovered_count(&self) -> u64 {
+        self.recovered_count
+    }
+    /// Receives the next datagram. Datagrams arrive in any order, recovered ones once their group's parity
+    /// arrives, and each at most once.
+    pub async fn receive(&mut self) -> Result<Bytes> {
+        loop {
+            if let Some(datagram) = self.recovered.pop_front() {
+                return Ok(datagram);
+            }
+            let datagram = self.connection.read_datagram().await.map_err(Error::from)?;
+            if let Some(datagram) = self.process(datagram) {
+                return Ok(datagram);
+            }
+        }
+    }
+    /// Records a received datagram, returning its payload if it is a new data datagram.
+    fn process(&mut self, mut datagram: Bytes) -> Option<Bytes> {
+        if datagram.len() < HEADER_SIZE {
+            tracing::debug!("Dropping datagram of {} bytes without FEC header", datagram.len());
+            return None;
+        }
+        let number = datagram.get_u64();
+        let index = datagram.get_u8();
+        if let Some((&newest, _)) = self.groups.last_key_value() {
+            if number.saturating_add(self.group_window) <= newest {
+                return None;
+            }
+        }
+        let group = self.groups.entry(number).or_default();
+        let payload = if index == PARITY_INDEX {
+            if datagram.is_empty() || group.parity.is_some() {
+                return None;
+            }
+            let size = datagram.get_u8() as usize;
+            group.parity = Some((size, datagram));
+            None
+        } else {
+            let index = index as usize;
+            if group.datagrams.len() <= index {
+                group.datagrams.resize(index + 1, None);
+            }
+            if group.datagrams[index].is_some() {
+                return None;
+            }
+            group.datagrams[index] = Some(datagram.clone());
+            Some(datagram)
+        };
+        if let Some(recovered) = group.recover(self.group_size) {
+            tracing::trace!("Recovered a datagram of group {}", number);
+            self.recovered_count += 1;
+            self.recovered.push_back(recovered);
+        }
+        let newest = self.groups.last_key_value().map_or(number, |(&newest, _)| newest);
+        self.groups.retain(|&number, _| number.saturating_add(self.group_window) > newest);
+        payload
+    }
+}
+
+/// XORs a payload, prefixed with its 16-bit length, into `parity`, growing it as needed.
+fn xor_into(parity: &mut BytesMut, payload: &[u8]) {
+    let length = (payload.len() as u16).to_be_bytes();
+    let size = length.len() + payload.len();
+    if parity.len() < size {
+        parity.resize(size, 0);
+    }
+    for (byte, other) in parity.iter_mut().zip(length.iter().chain(payload)) {
+        *byte ^= other;
+    }
+}
+
+/// Sends a datagram, surfacing a lost connection as `Error::ConnectionLost`.
+fn send_datagram(connection: &Connection, datagram: Bytes) -> Result<()> {
+    match connection.send_datagram(datagram) {
+        Ok(()) => Ok(()),
+        Err(SendDatagramError::ConnectionLost(e)) => Err(Error::from(e).into()),
+        Err(e) => Err(anyhow::anyhow!("failed to send datagram: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a group from the datagrams received, and the parity of all of `payloads`.
+    fn group(payloads: &[&[u8]], received: &[usize]) -> Group {
+        let mut parity = BytesMut::new();
+        for payload in payloads {
+            xor_into(&mut parity, payload);
+        }
+        let mut datagrams = vec![None; payloads.len()];
+        for &index in received {
+            datagrams[index] = Some(Bytes::copy_from_slice(payloads[index]));
+        }
+        Group { datagrams, parity: Some((payloads.len(), parity.freeze())) }
+    }
+
+    #[test]
+    fn recovers_one_loss() {
+        let mut group = group(&[b"first", b"second datagram", b"", b"fourth"], &[0, 2, 3]);
+        assert_eq!(group.recover(DEFAULT_GROUP_SIZE).unwrap(), &b"second datagram"[..]);
+        assert_eq!(group.datagrams[1].as_deref(), Some(&b"second datagram"[..]));
+        // Once recovered, nothing is missing.
+        assert!(group.recover(DEFAULT_GROUP_SIZE).is_none());
+    }
+
+    #[test]
+    fn recovers_a_trailing_loss() {
+        let mut group = group(&[b"first", b"second", b"third"], &[0, 1]);
+        group.datagrams.truncate(2);
+        assert_eq!(group.recover(DEFAULT_GROUP_SIZE).unwrap(), &b"third"[..]);
+    }
+
+    #[test]
+    fn does_not_recover_two_losses() {
+        let mut group = group(&[b"first", b"second", b"third", b"fourth"], &[0, 3]);
+        assert!(group.recover(DEFAULT_GROUP_SIZE).is_none());
+        assert!(group.datagrams[1].is_none() && group.datagrams[2].is_none());
+    }
+
+    #[test]
+    fn ignores_mismatched_parity_size() {
+        // Parity claiming fewer datagrams than were received would drop some of them.
+        let mut group = group(&[b"first", b"second", b"third", b"fourth"], &[0, 1, 3]);
+        let parity = group.parity.take().unwrap().1;
+        group.parity = Some((2, parity.clone()));
+        assert!(group.recover(DEFAULT_GROUP_SIZE).is_none());
+        assert_eq!(group.datagrams.len(), 4);
+        assert_eq!(group.datagrams[3].as_deref(), Some(&b"fourth"[..]));
+        // Parity claiming more datagrams than the group size is ignored too.
+        group.parity = Some((DEFAULT_GROUP_SIZE + 1, parity));
+        assert!(group.recover(DEFAULT_GROUP_SIZE).is_none());
+        assert_eq!(group.datagrams.len(), 4);
+    }
+}
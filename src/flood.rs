@@ -0,0 +1,106 @@
+//! Handshake flood protection.
+//!
+//! `FloodProtection` bundles the defenses a public server needs against handshake floods, applied to
+//! incoming connections before they reach the application:
+//!
+//! - Retry: peers must prove they own their address with a stateless retry before any handshake state
+//!   is kept, which defeats spoofed floods at the cost of one round trip.
+//! - A per-IP handshake rate limit. With retry enabled it applies to validated addresses only, so a
+//!   spoofed flood cannot use up the limit of another address.
+//! - A cap on handshakes in progress, bounding the CPU and memory spent on them at once. A handshake is
+//!   in progress from when the application accepts or inspects the incoming connection until it completes;
+//!   beyond the cap, incoming connections are refused.
+//!
+//! Each rejection is counted in `EndpointStats`. `FloodProtection::default()` holds settings suited
+//! to most servers, enabled with `SocketConfig::with_flood_protection` or `QuicSocket::set_flood_protection`.
+
+use quinn::Incoming;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::ratelimit::{ConnectionRateLimit, ConnectionRateLimiter};
+
+/// The default maximum number of handshakes in progress at once.
+pub const DEFAULT_MAX_CONCURRENT_HANDSHAKES: usize = 256;
+
+/// Handshake flood protection settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodProtection {
+    /// Whether peers must validate their address with a retry before their handshake starts. Defaults to `true`.
+    pub require_retry: bool,
+    /// The number of handshakes allowed per source IP address, if limited. Defaults to 10 per second.
+    pub rate_limit: Option<ConnectionRateLimit>,
+    /// The maximum number of handshakes in progress at once. Defaults to `DEFAULT_MAX_CONCURRENT_HANDSHAKES`.
+    pub max_concurrent_handshakes: usize,
+}
+
+impl Default for FloodProtection {
+    fn default() -> Self {
+        Self {
+            require_retry: true,
+            rate_limit: Some(ConnectionRateLimit::new(10, Duration::from_secs(1))),
+            max_concurrent_handshakes: DEFAULT_MAX_CONCURRENT_HANDSHAKES,
+        }
+    }
+}
+
+/// Why an incoming connection was turned away by the flood protection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rejection {
+    /// The peer was asked to validate its address with a retry.
+    Retry,
+    /// The peer's address exceeded the handshake rate limit.
+    RateLimited(ConnectionRateLimit),
+}
+
+/// The state of the flood protection of a socket.
+pub(crate) struct FloodGuard {
+    protection: FloodProtection,
+    limiter: Option<ConnectionRateLimiter>,
+}
+
+impl FloodGuard {
+    pub(crate) fn new(protection: FloodProtection) -> Self {
+        Self { protection, limiter: protection.rate_limit.map(ConnectionRateLimiter::new) }
+    }
+    /// Checks an incoming connection before it is passed to the application.
+    /// Returns why it has to be turned away, if it does.
+    pub(crate) fn check(&mut self, incoming: &Incoming) -> Option<Rejection> {
+        if self.protection.require_retry && !incoming.remote_address_validated() && incoming.may_retry() {
+            return Some(Rejection::Retry);
+        }
+        let limiter = self.limiter.as_mut()?;
+        (!limiter.check(incoming.remote_address().ip())).then(|| Rejection::RateLimited(limiter.limit()))
+    }
+    /// Returns the maximum number of handshakes in progress at once.
+    pub(crate) fn max_concurrent_handshakes(&self) -> usize {
+        self.protection.max_concurrent_handshakes.max(1)
+    }
+}
+
+/// Counts a handshake in progress until dropped.
+pub(crate) struct HandshakeGuard {
+    handshakes: Arc<AtomicUsize>,
+    /// Whether the connection's early data may be processed before the handshake completes. See `zerortt`.
+    pub(crate) zero_rtt: bool,
+    /// When the handshake times out, if the socket has a handshake timeout.
+    pub(crate) deadline: Option<Instant>,
+}
+
+impl HandshakeGuard {
+    /// Counts a new handshake, unless `max` handshakes are already in progress.
+    pub(crate) fn admit(handshakes: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Self> {
+        let previous = handshakes.fetch_add(1, Ordering::Relaxed);
+        let guard = Self { handshakes: Arc::clone(handshakes), zero_rtt: false, deadline: None };
+        match max {
+            Some(max) if previous >= max => None,
+            _ => Some(guard),
+        }
+    }
+}
+
+impl Drop for HandshakeGuard {
+    fn drop(&mut self) {
+        self.handshakes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,239 @@
+//! Fragmentation of datagrams larger than the path allows.
+//!
+//! `FragmentSender` splits a message larger than a datagram into fragments, each sent as a datagram with
+//! an 8-byte header, and `FragmentReceiver` reassembles them. Fragments are not retransmitted: losing one
+//! loses the whole message, which is dropped once `FragmentOptions::reassembly_timeout` passes or to keep
+//! the fragments buffered within `FragmentOptions::max_buffered_bytes`. Messages are delivered once
+//! complete, in any order.
+//!
+//! Both peers must use the layer on a connection, which should not carry other datagrams.
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use quinn::{Connection, SendDatagramError};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::error::Error;
+use crate::QuicConnection;
+
+/// The default time allowed for all fragments of a message to arrive.
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// The default limit on the fragments buffered for incomplete messages, in bytes.
+pub const DEFAULT_MAX_BUFFERED_BYTES: usize = 4 * 1024 * 1024;
+/// The maximum number of fragments of a message.
+pub const MAX_FRAGMENTS: usize = u16::MAX as usize;
+/// The size of the header of every fragment: the message ID, the fragment index and the fragment count.
+const HEADER_SIZE: usize = 8;
+
+/// Options for `FragmentReceiver`.
+#[derive(Debug, Clone)]
+pub struct FragmentOptions {
+    /// The time allowed for all fragments of a message to arrive, from the first one.
+    pub reassembly_timeout: Duration,
+    /// The limit on the fragments buffered for incomplete messages, in bytes, including the bookkeeping of
+    /// each message. The oldest messages are dropped to stay within it, and fragments of messages too
+    /// large to ever fit are dropped on arrival.
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for FragmentOptions {
+    fn default() -> Self {
+        Self {
+            reassembly_timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            max_buffered_bytes: DEFAULT_MAX_BUFFERED_BYTES,
+        }
+    }
+}
+
+impl FragmentOptions {
+    /// Creates options with `DEFAULT_REASSEMBLY_TIMEOUT` and `DEFAULT_MAX_BUFFERED_BYTES`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the time allowed for all fragments of a message to arrive.
+    pub fn with_reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.reassembly_timeout = timeout;
+        self
+    }
+    /// Sets the limit on the fragments buffered for incomplete messages, in bytes.
+    pub fn with_max_buffered_bytes(mut self, bytes: usize) -> Self {
+        self.max_buffered_bytes = bytes;
+        self
+    }
+}
+
+/// Sends messages of any size up to `max_message_size` as fragmented datagrams.
+pub struct FragmentSender {
+    connection: Connection,
+    next_id: u32,
+}
+
+impl FragmentSender {
+    /// Creates a sender on the datagrams of `connection`.
+    pub fn new(connection: &QuicConnection) -> Self {
+        Self {
+            connection: connection.connection.clone(),
+            next_id: 0,
+        }
+    }
+    /// Returns the largest message that can be sent, or `None` if the peer does not accept datagrams.
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.fragment_size().map(|size| size * MAX_FRAGMENTS)
+    }
+    /// Returns the payload size of each fragment.
+    fn fragment_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size().map(|max| max.saturating_sub(HEADER_SIZE)).filter(|&size| size > 0)
+    }
+    /// Sends a message, split into as many datagrams as needed.
+    ///
+    /// The fragments are queued at once, so quinn may drop some of a large message when its datagram send
+    /// buffer fills up (see `quinn::TransportConfig::datagram_send_buffer_size`).
+    pub fn send(&mut self, message: Bytes) -> Result<()> {
+        let Some(size) = self.fragment_size() else {
+            anyhow::bail!("the peer does not accept datagrams");
+        };
+        let count = message.len().div_ceil(size).max(1);
+        if count > MAX_FRAGMENTS {
+            anyhow::bail!("message of {} bytes exceeds the maximum message size of {} bytes", message.len(), size * MAX_FRAGMENTS);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        for index in 0..count {
+            let fragment = message.slice(index * size..((index + 1) * size).min(message.len()));
+            let mut datagram = BytesMut::with_capacity(HEADER_SIZE + fragment.len());
+            datagram.put_u32(id);
+            datagram.put_u16(index as u16);
+            datagram.put_u16(count as u16);
+            datagram.put_slice(&fragment);
+            match self.connection.send_datagram(datagram.freeze()) {
+                Ok(()) => {},
+                Err(SendDatagramError::ConnectionLost(e)) => return Err(Error::from(e).into()),
+                Err(e) => anyhow::bail!("failed to send datagram: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A message being reassembled.
+struct Partial {
+    fragments: Vec<Option<Bytes>>,
+    missing: usize,
+    /// The bytes charged to the reassembly buffer: the fragments received and the slots for all of them.
+    bytes: usize,
+    started: Instant,
+}
+
+/// Reassembles messages sent by a `FragmentSender`.
+pub struct FragmentReceiver {
+    connection: Connection,
+    options: FragmentOptions,
+    partials: HashMap<u32, Partial>,
+    buffered_bytes: usize,
+    dropped: u64,
+}
+
+impl FragmentReceiver {
+    /// Creates a receiver on the datagrams of `connection`.
+    pub fn new(connection: &QuicConnection, options: FragmentOptions) -> Self {
+        Self {
+            connection: connection.connection.clone(),
+            options,
+            partials: HashMap::new(),
+            buffered_bytes: 0,
+            dropped: 0,
+        }
+    }
+    /// Returns the number of incomplete messages dropped so far, after timing out or to free memory.
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped
+    }
+    /// Returns the number of bytes buffered for incomplete messages.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+    /// Receives the next complete message.
+    pub async fn receive(&mut self) -> Result<Bytes> {
+        loop {
+            let datagram = self.connection.read_datagram().await.map_err(Error::from)?;
+            if let Some(message) = self.process(datagram) {
+                return Ok(message);
+            }
+        }
+    }
+    /// Records a received fragment, returning its message if it completes one.
+    fn process(&mut self, mut datagram: Bytes) -> Option<Bytes> {
+        if datagram.len() < HEADER_SIZE {
+            tracing::debug!("Dropping datagram of {} bytes without fragment header", datagram.len());
+            return None;
+        }
+        let id = datagram.get_u32();
+        let index = datagram.get_u16() as usize;
+        let count = datagram.get_u16() as usize;
+        if index >= count {
+            tracing::debug!("Dropping fragment {} of a message of {} fragments", index, count);
+            return None;
+        }
+        if count == 1 {
+            return Some(datagram);
+        }
+        // Every fragment but the last is full-sized, so this bounds the buffer the message needs.
+        let slots = count * std::mem::size_of::<Option<Bytes>>();
+        let fragment_size = if index + 1 < count { datagram.len() } else { 1 };
+        if slots + (count - 1) * fragment_size > self.options.max_buffered_bytes {
+            tracing::debug!("Dropping fragment of a message of {} fragments that cannot fit in the reassembly buffer", count);
+            return None;
+        }
+        self.expire();
+        let now = Instant::now();
+        let partial = self.partials.entry(id).or_insert_with(|| {
+            self.buffered_bytes += slots;
+            Partial {
+                fragments: vec![None; count],
+                missing: count,
+                bytes: slots,
+                started: now,
+            }
+        });
+        if partial.fragments.len() != count || partial.fragments[index].is_some() {
+            return None;
+        }
+        partial.bytes += datagram.len();
+        partial.missing -= 1;
+        self.buffered_bytes += datagram.len();
+        partial.fragments[index] = Some(datagram);
+        if partial.missing == 0 {
+            let partial = self.partials.remove(&id)?;
+            self.buffered_bytes -= partial.bytes;
+            let mut message = BytesMut::with_capacity(partial.fragments.iter().flatten().map(Bytes::len).sum());
+            for fragment in partial.fragments.into_iter().flatten() {
+                message.put_slice(&fragment);
+            }
+            return Some(message.freeze());
+        }
+        while self.buffered_bytes > self.options.max_buffered_bytes {
+            let Some(oldest) = self.partials.iter().min_by_key(|(_, partial)| partial.started).map(|(&id, _)| id) else {
+                break;
+            };
+            tracing::debug!("Dropping incomplete message {} to stay within the reassembly buffer", oldest);
+            self.drop_partial(oldest);
+        }
+        None
+    }
+    /// Drops the messages whose reassembly timed out.
+    fn expire(&mut self) {
+        let timeout = self.options.reassembly_timeout;
+        let expired: Vec<u32> = self.partials.iter().filter(|(_, partial)| partial.started.elapsed() > timeout).map(|(&id, _)| id).collect();
+        for id in expired {
+            tracing::debug!("Dropping incomplete message {} after the reassembly timeout", id);
+            self.drop_partial(id);
+        }
+    }
+    /// Drops an incomplete message.
+    fn drop_partial(&mut self, id: u32) {
+        if let Some(partial) = self.partials.remove(&id) {
+            self.buffered_bytes -= partial.bytes;
+            self.dropped += 1;
+        }
+    }
+}
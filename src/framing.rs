@@ -0,0 +1,241 @@
+//! Length-delimited message framing.
+//!
+//! Each frame is encoded as a 4-byte big-endian length prefix followed by the payload.
+//! Decoding is done by `FrameDecoder`, which is fuzzed by the targets in the `fuzz` directory.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// The size of the frame header (length prefix), in bytes.
+pub const FRAME_HEADER_SIZE: usize = 4;
+/// The default maximum frame payload size, in bytes.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Codec for length-delimited frames.
+///
+/// Frames larger than `max_frame_size` are rejected on both encode and decode.
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+    decoder: FrameDecoder,
+}
+
+impl FrameCodec {
+    /// Creates a new codec with the default maximum frame size.
+    pub fn new() -> Self {
+        Self::with_max_frame_size(DEFAULT_MAX_FRAME_SIZE)
+    }
+    /// Creates a new codec with the given maximum frame size.
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        let max_frame_size = max_frame_size.min(u32::MAX as usize);
+        Self { max_frame_size, decoder: FrameDecoder::new(max_frame_size) }
+    }
+    /// Returns the maximum frame payload size.
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if item.len() > self.max_frame_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("frame of {} bytes exceeds maximum of {} bytes", item.len(), self.max_frame_size)));
+        }
+        dst.reserve(FRAME_HEADER_SIZE + item.len());
+        dst.put_u32(item.len() as u32);
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decoder.decode(src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decoder.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() && self.decoder.is_idle() => Ok(None),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended within a frame")),
+        }
+    }
+}
+
+/// The part of a frame a `FrameDecoder` is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeState {
+    /// The length prefix.
+    Header,
+    /// The payload of the given length, whose prefix has been consumed.
+    Payload(usize),
+}
+
+/// Incremental decoder for length-delimited frames.
+///
+/// The decoder is a plain state machine over a byte buffer, independent of any I/O. It never panics on
+/// malformed input, rejects length prefixes above the maximum frame size, and grows the buffer by at most
+/// `MAX_RESERVE` bytes per call, so a peer can't make it allocate a large frame it never sends.
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    max_frame_size: usize,
+    state: DecodeState,
+}
+
+/// The maximum number of bytes reserved in the buffer at once while waiting for a payload.
+pub const MAX_RESERVE: usize = 64 * 1024;
+
+impl FrameDecoder {
+    /// Creates a decoder rejecting frames larger than `max_frame_size`.
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size, state: DecodeState::Header }
+    }
+    /// Returns whether the decoder is between frames, i.e. no partial frame has been consumed.
+    pub fn is_idle(&self) -> bool {
+        self.state == DecodeState::Header
+    }
+    /// Returns the number of bytes `src` still lacks to complete the part of the frame being decoded: the
+    /// length prefix, or the payload once the prefix has been consumed.
+    ///
+    /// Reading exactly this many bytes before calling `decode` again never consumes input past the frame.
+    pub fn needed(&self, src: &BytesMut) -> usize {
+        match self.state {
+            DecodeState::Header => FRAME_HEADER_SIZE.saturating_sub(src.len()),
+            DecodeState::Payload(len) => len.saturating_sub(src.len()),
+        }
+    }
+    /// Decodes the next frame from `src`, consuming its bytes.
+    ///
+    /// Returns `None` if more bytes are needed. After an error the input is not valid framing,
+    /// and the decoder should not be used further.
+    pub fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        let len = match self.state {
+            DecodeState::Header => {
+                if src.len() < FRAME_HEADER_SIZE {
+                    return Ok(None);
+                }
+                let len = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+                if len > self.max_frame_size {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes exceeds maximum of {} bytes", len, self.max_frame_size)));
+                }
+                src.advance(FRAME_HEADER_SIZE);
+                self.state = DecodeState::Payload(len);
+                len
+            },
+            DecodeState::Payload(len) => len,
+        };
+        if src.len() < len {
+            src.reserve((len - src.len()).min(MAX_RESERVE));
+            return Ok(None);
+        }
+        self.state = DecodeState::Header;
+        Ok(Some(src.split_to(len).freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        FrameCodec::new().encode(Bytes::copy_from_slice(payload), &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn decodes_consecutive_frames() {
+        let mut src = frame(b"hello");
+        src.extend_from_slice(&frame(b""));
+        src.extend_from_slice(&frame(b"world"));
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        assert_eq!(decoder.decode(&mut src).unwrap().unwrap(), "hello");
+        assert_eq!(decoder.decode(&mut src).unwrap().unwrap(), "");
+        assert_eq!(decoder.decode(&mut src).unwrap().unwrap(), "world");
+        assert_eq!(decoder.decode(&mut src).unwrap(), None);
+        assert!(decoder.is_idle());
+    }
+
+    #[test]
+    fn decodes_frame_split_byte_by_byte() {
+        let encoded = frame(b"split");
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut src = BytesMut::new();
+        for (i, &byte) in encoded.iter().enumerate() {
+            assert_eq!(decoder.decode(&mut src).unwrap(), None);
+            assert_eq!(decoder.is_idle(), i < FRAME_HEADER_SIZE);
+            src.extend_from_slice(&[byte]);
+        }
+        assert_eq!(decoder.decode(&mut src).unwrap().unwrap(), "split");
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn needed_covers_header_then_payload() {
+        let encoded = frame(b"payload");
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut src = BytesMut::from(&encoded[..2]);
+        assert_eq!(decoder.needed(&src), 2);
+        src.extend_from_slice(&encoded[2..FRAME_HEADER_SIZE]);
+        assert_eq!(decoder.needed(&src), 0);
+        assert_eq!(decoder.decode(&mut src).unwrap(), None);
+        assert_eq!(decoder.needed(&src), 7);
+        src.extend_from_slice(&encoded[FRAME_HEADER_SIZE..]);
+        assert_eq!(decoder.needed(&src), 0);
+        assert_eq!(decoder.decode(&mut src).unwrap().unwrap(), "payload");
+    }
+
+    #[test]
+    fn rejects_oversized_frame() {
+        let mut src = frame(&[0; 9]);
+        let mut decoder = FrameDecoder::new(8);
+        let e = decoder.decode(&mut src).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let e = FrameCodec::with_max_frame_size(8).encode(Bytes::from_static(&[0; 9]), &mut BytesMut::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rejects_oversized_header_before_payload_arrives() {
+        let mut src = BytesMut::from(&u32::MAX.to_be_bytes()[..]);
+        assert!(FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE).decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn reserves_at_most_max_reserve() {
+        let mut src = BytesMut::from(&(DEFAULT_MAX_FRAME_SIZE as u32).to_be_bytes()[..]);
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        assert_eq!(decoder.decode(&mut src).unwrap(), None);
+        assert!(src.capacity() <= FRAME_HEADER_SIZE + MAX_RESERVE);
+    }
+
+    #[test]
+    fn fails_on_eof_mid_frame() {
+        let encoded = frame(b"truncated");
+        for end in [2, FRAME_HEADER_SIZE, encoded.len() - 1] {
+            let mut src = BytesMut::from(&encoded[..end]);
+            let e = FrameCodec::new().decode_eof(&mut src).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof, "ended after {} bytes", end);
+        }
+    }
+
+    #[test]
+    fn ends_cleanly_on_eof_between_frames() {
+        let mut src = frame(b"last");
+        let mut codec = FrameCodec::new();
+        assert_eq!(codec.decode_eof(&mut src).unwrap().unwrap(), "last");
+        assert_eq!(codec.decode_eof(&mut src).unwrap(), None);
+    }
+}
@@ -0,0 +1,59 @@
+//! HTTP/3 adapter built on the `h3` crate.
+//!
+//! Sockets created here negotiate the `h3` ALPN protocol, and accepted or connected
+//! `QuicConnection`s can be upgraded to `h3` client or server connections.
+
+use anyhow::Result;
+use bytes::Bytes;
+use quinn::Incoming;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::sync::mpsc;
+use crate::endpoint::{make_alpn_client_endpoint, make_alpn_server_endpoint, ServerVerification};
+use crate::{QuicConnection, QuicSocket};
+
+pub use h3;
+pub use h3_quinn;
+
+/// The ALPN protocol identifier for HTTP/3.
+pub const ALPN_H3: &[u8] = b"h3";
+
+/// An HTTP/3 server connection.
+pub type ServerConnection = h3::server::Connection<h3_quinn::Connection, Bytes>;
+/// An HTTP/3 client connection driver.
+pub type ClientConnection = h3::client::Connection<h3_quinn::Connection, Bytes>;
+/// A handle for sending HTTP/3 requests.
+pub type SendRequest = h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+/// Creates a new QUIC server that negotiates HTTP/3.
+///
+/// If `cert_path` and `key_path` are provided, the server will use the certificate and key at those
+/// paths. Otherwise, a self-signed certificate will be generated.
+pub async fn new_server(addr: SocketAddr, cert_path: Option<&Path>, key_path: Option<&Path>) -> Result<(QuicSocket, mpsc::Receiver<Incoming>), Box<dyn Error + Send + Sync + 'static>> {
+    let endpoint = make_alpn_server_endpoint(addr, cert_path, key_path, &[ALPN_H3])?;
+    tracing::info!("HTTP/3 server listening on: {}", addr);
+    Ok(QuicSocket::from_server_endpoint(endpoint))
+}
+
+/// Creates a new QUIC client that negotiates HTTP/3.
+pub async fn new_client(bind_addr: SocketAddr, verification: ServerVerification) -> Result<QuicSocket, Box<dyn Error + Send + Sync + 'static>> {
+    let endpoint = make_alpn_client_endpoint(bind_addr, &verification, &[ALPN_H3])?;
+    tracing::info!("HTTP/3 client bound to {:?}", endpoint.local_addr());
+    Ok(QuicSocket::from_client_endpoint(endpoint))
+}
+
+/// Upgrades an accepted connection to an HTTP/3 server connection.
+pub async fn server_connection(connection: &QuicConnection) -> Result<ServerConnection> {
+    let h3_connection = h3::server::Connection::new(h3_quinn::Connection::new(connection.connection.clone())).await?;
+    Ok(h3_connection)
+}
+
+/// Upgrades a connected connection to an HTTP/3 client connection.
+///
+/// The returned driver must be polled (e.g. with `poll_close` in a spawned task) for requests
+/// sent through the `SendRequest` handle to make progress.
+pub async fn client_connection(connection: &QuicConnection) -> Result<(ClientConnection, SendRequest)> {
+    let (driver, send_request) = h3::client::new(h3_quinn::Connection::new(connection.connection.clone())).await?;
+    Ok((driver, send_request))
+}
@@ -0,0 +1,76 @@
+//! Incoming connections that have not been handshaken yet.
+//!
+//! `QuicSocket::next_incoming` yields an `IncomingConnection`, which a server can accept, inspect,
+//! or turn away before any handshake work is done, e.g. based on the remote address.
+
+use anyhow::Result;
+use quinn::Incoming;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use crate::routing::PendingConnection;
+use crate::socket::Shared;
+use crate::transport::TransportOptions;
+use crate::{QuicConnection, QuicSocket};
+
+/// An incoming connection attempt, before the handshake has started.
+pub struct IncomingConnection {
+    incoming: Incoming,
+    shared: Arc<Shared>,
+}
+
+impl IncomingConnection {
+    pub(crate) fn new(incoming: Incoming, shared: Arc<Shared>) -> Self {
+        Self { incoming, shared }
+    }
+    /// Returns the address of the peer.
+    pub fn remote_address(&self) -> SocketAddr {
+        self.incoming.remote_address()
+    }
+    /// Returns whether the peer has proven it owns its address, e.g. through a retry.
+    pub fn remote_address_validated(&self) -> bool {
+        self.incoming.remote_address_validated()
+    }
+    /// Completes the handshake and registers the connection with the socket.
+    ///
+    /// Returns `None` if the handshake fails.
+    pub async fn accept(self) -> Option<Arc<QuicConnection>> {
+        QuicSocket::establish(&self.shared, self.incoming).await
+    }
+    /// Completes the handshake with transport options overriding the socket's for this connection only.
+    /// See `QuicSocket::accept_with_transport`.
+    pub async fn accept_with_transport(self, transport: &TransportOptions) -> Result<Arc<QuicConnection>> {
+        QuicSocket::establish_with_transport(&self.shared, self.incoming, transport).await
+    }
+    /// Starts the handshake and waits for the client's hello. See `QuicSocket::inspect`.
+    pub async fn inspect(self) -> Result<PendingConnection> {
+        let (connecting, handshake) = self.shared.start_handshake(self.incoming, None)?;
+        QuicSocket::inspect_connecting(&self.shared, connecting, handshake).await
+    }
+    /// Rejects the connection with a CONNECTION_REFUSED error, so the peer fails fast.
+    pub fn refuse(self) {
+        tracing::debug!("Refused connection from: {}", self.remote_address());
+        self.shared.record_refused();
+        self.incoming.refuse();
+    }
+    /// Drops the connection without telling the peer, which will time out.
+    ///
+    /// This sends nothing, so it is the cheapest way to shed unwanted or abusive peers.
+    pub fn ignore(self) {
+        tracing::debug!("Ignored connection from: {}", self.remote_address());
+        self.shared.record_refused();
+        self.incoming.ignore();
+    }
+    /// Asks the peer to prove it owns its address before the connection is accepted.
+    ///
+    /// The peer reconnects with a retry token, which shows up as a new incoming connection with
+    /// `remote_address_validated()` set. Fails if the address is already validated.
+    pub fn retry(self) -> Result<()> {
+        self.incoming
+            .retry()
+            .map_err(|_| anyhow::anyhow!("remote address is already validated"))
+    }
+    /// Returns the underlying `quinn::Incoming`.
+    pub fn into_inner(self) -> Incoming {
+        self.incoming
+    }
+}
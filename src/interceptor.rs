@@ -0,0 +1,24 @@
+//! Interceptor pipeline for stream data.
+//!
+//! Interceptors are stacked on a `QuicConnection` and transform the payloads passed to
+//! `send()` and returned from `receive()`, e.g. for compression, checksumming or metrics.
+
+use anyhow::Result;
+use bytes::Bytes;
+
+/// A transform applied to stream data.
+///
+/// On send, interceptors run in the order they were added; on receive, they run in reverse order,
+/// so that a stack of symmetric transforms (e.g. compress then checksum) is undone correctly.
+pub trait Interceptor: Send + Sync {
+    /// Transforms data before it is sent on the stream with the given ID.
+    fn on_send(&self, stream_id: u64, data: Bytes) -> Result<Bytes> {
+        let _ = stream_id;
+        Ok(data)
+    }
+    /// Transforms data after it has been received on the stream with the given ID.
+    fn on_receive(&self, stream_id: u64, data: Bytes) -> Result<Bytes> {
+        let _ = stream_id;
+        Ok(data)
+    }
+}
@@ -0,0 +1,270 @@
+//! JSON-RPC 2.0 over QUIC.
+//!
+//! Each request, notification or batch is sent as one message on a pooled stream (see `pool`),
+//! and answered with one message on the same stream. A `Client` calls methods on the peer, and a
+//! `Dispatcher` routes incoming requests to registered handlers.
+//!
+//! Notifications get no JSON-RPC response, as required by the specification. The transport still
+//! answers them with an empty message, so `Client::notify` returns once the peer has processed them.
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use crate::QuicConnection;
+
+/// The protocol version carried in every message.
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal error.
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// A request or notification ID.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Id {
+    /// A numeric ID.
+    Number(i64),
+    /// A string ID.
+    String(String),
+}
+
+/// A request, or a notification if it has no ID.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    /// The protocol version, `"2.0"`.
+    pub jsonrpc: String,
+    /// The name of the method to invoke.
+    pub method: String,
+    /// The parameters, by position or by name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    /// The ID, or `None` for notifications.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Id>,
+}
+
+/// A response to a request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    /// The protocol version, `"2.0"`.
+    pub jsonrpc: String,
+    /// The result, if the call succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// The error, if the call failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    /// The ID of the request, or `None` if it could not be determined.
+    pub id: Option<Id>,
+}
+
+impl Response {
+    fn success(id: Id, result: Value) -> Self {
+        Self { jsonrpc: JSONRPC_VERSION.to_string(), result: Some(result), error: None, id: Some(id) }
+    }
+    fn failure(id: Option<Id>, error: RpcError) -> Self {
+        Self { jsonrpc: JSONRPC_VERSION.to_string(), result: None, error: Some(error), id }
+    }
+}
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// The error code. Codes from -32768 to -32000 are reserved by the specification.
+    pub code: i64,
+    /// A short description of the error.
+    pub message: String,
+    /// Additional information about the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    /// Creates an error with the given code and message.
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+    /// Attaches additional information to the error.
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JSON-RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError::new(INTERNAL_ERROR, e.to_string())
+    }
+}
+
+/// Calls methods on a peer served by a `Dispatcher`.
+pub struct Client {
+    connection: Arc<QuicConnection>,
+    next_id: AtomicI64,
+}
+
+impl Client {
+    /// Creates a client calling methods over the given connection.
+    pub fn new(connection: Arc<QuicConnection>) -> Self {
+        Self { connection, next_id: AtomicI64::new(1) }
+    }
+    /// Calls a method and returns its result.
+    ///
+    /// If the peer answers with an error object, the returned error is an `RpcError`,
+    /// which can be inspected with `err.downcast_ref::<RpcError>()`.
+    pub async fn call<P: Serialize, R: DeserializeOwned>(&self, method: &str, params: P) -> Result<R> {
+        let id = Id::Number(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let request = Request {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params: to_params(params)?,
+            id: Some(id.clone()),
+        };
+        let response = self.connection.request(Bytes::from(serde_json::to_vec(&request)?)).await?;
+        let response: Response = serde_json::from_slice(&response)?;
+        if response.id.as_ref() != Some(&id) {
+            anyhow::bail!("response ID {:?} does not match request ID {:?}", response.id, id);
+        }
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(error.into()),
+            (Some(result), None) => Ok(serde_json::from_value(result)?),
+            (None, None) => anyhow::bail!("response has neither a result nor an error"),
+        }
+    }
+    /// Sends a notification, a call without a result.
+    pub async fn notify<P: Serialize>(&self, method: &str, params: P) -> Result<()> {
+        let request = Request {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params: to_params(params)?,
+            id: None,
+        };
+        self.connection.request(Bytes::from(serde_json::to_vec(&request)?)).await?;
+        Ok(())
+    }
+}
+
+/// Converts parameters to a JSON value, omitting them if they serialize to `null`, e.g. `()`.
+fn to_params<P: Serialize>(params: P) -> Result<Option<Value>> {
+    match serde_json::to_value(params)? {
+        Value::Null => Ok(None),
+        params => Ok(Some(params)),
+    }
+}
+
+/// A boxed method handler.
+type Method = Arc<dyn Fn(Option<Value>) -> BoxFuture<'static, Result<Value, RpcError>> + Send + Sync>;
+
+/// Routes requests to method handlers.
+#[derive(Default, Clone)]
+pub struct Dispatcher {
+    methods: HashMap<String, Method>,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher without methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers a handler for a method.
+    ///
+    /// Parameters are deserialized into `P`, with missing parameters deserialized from `null`,
+    /// so handlers without parameters can take `()`. Parameters that don't fit `P` are answered
+    /// with `INVALID_PARAMS`.
+    pub fn method<P, R, F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + 'static,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, RpcError>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let method: Method = Arc::new(move |params| {
+            let handler = Arc::clone(&handler);
+            Box::pin(async move {
+                let params = serde_json::from_value(params.unwrap_or(Value::Null))
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+                let result = handler(params).await?;
+                serde_json::to_value(result).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+            })
+        });
+        self.methods.insert(name.to_string(), method);
+        self
+    }
+    /// Handles a message holding a request, a notification or a batch, and returns the response message.
+    ///
+    /// The response is empty if there is nothing to answer, i.e. for notifications.
+    pub async fn handle(&self, message: &[u8]) -> Bytes {
+        let response = match serde_json::from_slice::<Value>(message) {
+            Err(e) => serde_json::to_vec(&Response::failure(None, RpcError::new(PARSE_ERROR, e.to_string()))),
+            Ok(Value::Array(batch)) if batch.is_empty() => {
+                serde_json::to_vec(&Response::failure(None, RpcError::new(INVALID_REQUEST, "empty batch")))
+            },
+            Ok(Value::Array(batch)) => {
+                let responses = futures::future::join_all(batch.into_iter().map(|request| self.handle_one(request))).await;
+                let responses = responses.into_iter().flatten().collect::<Vec<_>>();
+                if responses.is_empty() {
+                    return Bytes::new();
+                }
+                serde_json::to_vec(&responses)
+            },
+            Ok(request) => match self.handle_one(request).await {
+                Some(response) => serde_json::to_vec(&response),
+                None => return Bytes::new(),
+            },
+        };
+        // Responses only hold JSON values, which always serialize.
+        Bytes::from(response.unwrap())
+    }
+    /// Handles a single request, returning `None` for notifications.
+    async fn handle_one(&self, request: Value) -> Option<Response> {
+        let request: Request = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(e) => return Some(Response::failure(None, RpcError::new(INVALID_REQUEST, e.to_string()))),
+        };
+        if request.jsonrpc != JSONRPC_VERSION {
+            return Some(Response::failure(request.id, RpcError::new(INVALID_REQUEST, "unsupported JSON-RPC version")));
+        }
+        let result = match self.methods.get(&request.method) {
+            Some(method) => method(request.params).await,
+            None => Err(RpcError::new(METHOD_NOT_FOUND, format!("method not found: {}", request.method))),
+        };
+        let id = request.id?;
+        Some(match result {
+            Ok(result) => Response::success(id, result),
+            Err(error) => Response::failure(Some(id), error),
+        })
+    }
+    /// Serves requests from a peer's `Client` until the connection is closed.
+    pub async fn serve(self, connection: Arc<QuicConnection>) -> Result<()> {
+        let dispatcher = Arc::new(self);
+        crate::pool::serve_requests(connection, move |message| {
+            let dispatcher = Arc::clone(&dispatcher);
+            async move { Ok(dispatcher.handle(&message).await) }
+        }).await
+    }
+}
@@ -1,7 +1,71 @@
 pub mod endpoint;
 pub mod connection;
 pub mod socket;
+pub mod framing;
+pub mod message;
+pub mod reader;
+pub mod codec;
+pub mod interceptor;
+pub mod incoming;
+pub mod cancel;
+pub mod codes;
+pub mod close;
+pub mod deadline;
+pub mod error;
+pub mod balance;
+pub mod shutdown;
+pub mod signaling;
+pub mod relay;
+pub mod routing;
+pub mod scheduler;
+pub mod broadcast;
+pub mod pool;
+pub mod dial;
+pub mod jsonrpc;
+pub mod dispatch;
+pub mod transfer;
+pub mod pairing;
+pub mod share;
+pub mod quota;
+pub mod ratelimit;
+pub mod flood;
+pub mod event;
+pub mod liveness;
+pub mod audit;
+pub mod recording;
+pub mod stats;
+pub mod probe;
+pub mod fec;
+pub mod sequenced;
+pub mod fragment;
+pub mod channel;
+#[cfg(feature = "h3")]
+pub mod http3;
+#[cfg(feature = "tower")]
+pub mod service;
+#[cfg(feature = "dns")]
+pub mod discovery;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod tls;
+pub mod config;
+pub mod transport;
+pub mod offload;
+pub mod pacing;
+pub mod version;
+pub mod zerortt;
+pub mod reset;
+pub mod cid;
+pub mod retry;
+pub mod logging;
+pub mod telemetry;
+pub mod testing;
+mod registry;
+mod budget;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 
 pub use socket::QuicSocket;
 pub use connection::QuicConnection;
+pub use message::MessageStream;
+pub use error::Error;
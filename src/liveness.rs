@@ -0,0 +1,47 @@
+//! Dead-peer detection.
+//!
+//! quinn only reports a silent peer by closing the connection once the idle timeout passes. A connection
+//! with `LivenessThresholds` (see `QuicConnection::set_liveness_thresholds`) emits a
+//! `SocketEvent::PeerSuspect` once nothing was received from the peer for `suspect_after`, and a
+//! `SocketEvent::PeerDead` after `dead_after`, so applications can fail over sooner. A
+//! `SocketEvent::PeerRecovered` follows if packets arrive again. The connection is left open either way.
+//!
+//! Silence only means something if the peer is asked to respond: enable keep-alives with
+//! `TransportOptions::with_keep_alive_interval`, at an interval well below `suspect_after`.
+
+use std::time::Duration;
+
+/// The default time without packets from the peer after which it is suspected to be dead.
+pub const DEFAULT_SUSPECT_AFTER: Duration = Duration::from_secs(5);
+/// The default time without packets from the peer after which it is considered dead.
+pub const DEFAULT_DEAD_AFTER: Duration = Duration::from_secs(15);
+
+/// When a silent peer is suspected and considered to be dead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LivenessThresholds {
+    /// The time without packets from the peer after which a `SocketEvent::PeerSuspect` is emitted.
+    pub suspect_after: Duration,
+    /// The time without packets from the peer after which a `SocketEvent::PeerDead` is emitted. Should be
+    /// below the idle timeout, which closes the connection.
+    pub dead_after: Duration,
+}
+
+impl Default for LivenessThresholds {
+    fn default() -> Self {
+        Self {
+            suspect_after: DEFAULT_SUSPECT_AFTER,
+            dead_after: DEFAULT_DEAD_AFTER,
+        }
+    }
+}
+
+impl LivenessThresholds {
+    /// Creates thresholds suspecting the peer after `suspect_after` and considering it dead after `dead_after`.
+    pub fn new(suspect_after: Duration, dead_after: Duration) -> Self {
+        Self { suspect_after, dead_after }
+    }
+    /// Returns the interval at which the connection is checked, a quarter of the shorter threshold.
+    pub(crate) fn check_interval(&self) -> Duration {
+        (self.suspect_after.min(self.dead_after) / 4).max(Duration::from_millis(10))
+    }
+}
@@ -0,0 +1,34 @@
+//! Logging configuration.
+//!
+//! quicsock logs through `tracing`. Socket and connection lifecycle events are logged under the
+//! `quicsock::socket` and `quicsock::connection` targets, and per-stream operations are logged at
+//! trace level under the `quicsock::stream` target, so they can be filtered with any subscriber.
+//! Per-stream logging can also be disabled entirely, skipping the formatting cost on hot paths.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The tracing target used for per-stream logs.
+pub const STREAM_TARGET: &str = "quicsock::stream";
+
+static STREAM_LOGGING: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables per-stream logging.
+pub fn set_stream_logging(enabled: bool) {
+    STREAM_LOGGING.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether per-stream logging is enabled.
+pub fn stream_logging() -> bool {
+    STREAM_LOGGING.load(Ordering::Relaxed)
+}
+
+/// Logs a per-stream event at trace level, unless per-stream logging is disabled.
+macro_rules! stream_trace {
+    ($($arg:tt)*) => {
+        if $crate::logging::stream_logging() {
+            tracing::trace!(target: $crate::logging::STREAM_TARGET, $($arg)*);
+        }
+    };
+}
+
+pub(crate) use stream_trace;
@@ -0,0 +1,119 @@
+//! Message mode. Framed messages over a bi-directional QUIC stream.
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use quinn::{RecvStream, SendStream};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::OwnedSemaphorePermit;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use crate::codec::{Codec, Envelope, MessageType};
+use crate::error::from_io;
+use crate::framing::FrameCodec;
+
+/// A bi-directional stream carrying length-delimited messages.
+///
+/// Implements `futures::Sink<Bytes>` for sending and `futures::Stream<Item = Result<Bytes>>`
+/// for receiving, so it can be used with combinators such as `forward`, `split` and `select_all`.
+pub struct MessageStream {
+    writer: FramedWrite<SendStream, FrameCodec>,
+    reader: FramedRead<RecvStream, FrameCodec>,
+    quota_permit: Option<OwnedSemaphorePermit>,
+}
+
+impl MessageStream {
+    /// Creates a new message stream from a pair of quinn streams.
+    pub fn new(send_stream: SendStream, recv_stream: RecvStream) -> Self {
+        Self::with_codec(send_stream, recv_stream, FrameCodec::new())
+    }
+    /// Creates a new message stream using the given frame codec.
+    pub fn with_codec(send_stream: SendStream, recv_stream: RecvStream, codec: FrameCodec) -> Self {
+        Self {
+            writer: FramedWrite::new(send_stream, codec.clone()),
+            reader: FramedRead::new(recv_stream, codec),
+            quota_permit: None,
+        }
+    }
+    /// Holds a stream quota slot until the message stream is dropped.
+    pub(crate) fn with_quota_permit(mut self, permit: Option<OwnedSemaphorePermit>) -> Self {
+        self.quota_permit = permit;
+        self
+    }
+    /// Consumes the message stream, returning the underlying quinn streams.
+    ///
+    /// Messages that were received but not yet read are discarded.
+    pub fn into_inner(self) -> (SendStream, RecvStream) {
+        (self.writer.into_inner(), self.reader.into_inner())
+    }
+    /// Sends a single message and flushes it.
+    pub async fn send_message(&mut self, message: Bytes) -> Result<()> {
+        self.send(message).await
+    }
+    /// Receives the next message.
+    ///
+    /// Returns `None` once the peer has finished its side of the stream.
+    pub async fn receive_message(&mut self) -> Result<Option<Bytes>> {
+        self.next().await.transpose()
+    }
+    /// Encodes a value with the given codec and sends it as a single message.
+    pub async fn send_typed<T, C: Codec<T>>(&mut self, codec: &C, value: &T) -> Result<()> {
+        let message = codec.encode(value)?;
+        self.send_message(message).await
+    }
+    /// Receives the next message and decodes it with the given codec.
+    ///
+    /// Returns `None` once the peer has finished its side of the stream.
+    pub async fn receive_typed<T, C: Codec<T>>(&mut self, codec: &C) -> Result<Option<T>> {
+        match self.receive_message().await? {
+            Some(message) => Ok(Some(codec.decode(&message)?)),
+            None => Ok(None),
+        }
+    }
+    /// Encodes a value with the given codec and sends it in an `Envelope` tagged with its type ID.
+    pub async fn send_tagged<T: MessageType, C: Codec<T>>(&mut self, codec: &C, value: &T) -> Result<()> {
+        let envelope = Envelope::pack(codec, value)?;
+        self.send_message(envelope.to_bytes()).await
+    }
+    /// Receives the next message as an `Envelope`, to be dispatched on its type ID.
+    ///
+    /// Returns `None` once the peer has finished its side of the stream.
+    pub async fn receive_envelope(&mut self) -> Result<Option<Envelope>> {
+        match self.receive_message().await? {
+            Some(message) => Ok(Some(Envelope::from_bytes(message)?)),
+            None => Ok(None),
+        }
+    }
+    /// Finishes the sending side of the stream.
+    pub async fn finish(&mut self) -> Result<()> {
+        self.close().await
+    }
+}
+
+impl Sink<Bytes> for MessageStream {
+    type Error = anyhow::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.writer).poll_ready(cx).map_err(from_io)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        Pin::new(&mut self.writer).start_send(item).map_err(from_io)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.writer).poll_flush(cx).map_err(from_io)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.writer).poll_close(cx).map_err(from_io)
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.reader).poll_next(cx).map(|item| item.map(|res| res.map_err(from_io)))
+    }
+}
@@ -0,0 +1,151 @@
+//! UDP segmentation offload controls.
+//!
+//! On platforms that support it, datagrams are sent in batches with a single system call (GSO), and
+//! the kernel may coalesce received datagrams into a single buffer (GRO). This is a large throughput
+//! win on modern kernels and NICs, but offload misbehaves on some older ones. `UdpOffload` disables
+//! it or limits the batch size, as an escape hatch.
+
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, UdpPoller};
+use std::fmt;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Segmentation offload settings of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpOffload {
+    /// Whether datagrams are sent in batches (generic segmentation offload). Defaults to `true`.
+    pub gso: bool,
+    /// Whether the kernel may coalesce received datagrams (generic receive offload). Defaults to `true`.
+    ///
+    /// Only has an effect on Linux.
+    pub gro: bool,
+    /// The maximum number of datagrams sent in a batch. `None` uses the maximum supported by the platform.
+    ///
+    /// All datagrams of a batch have the same size, the path MTU. Smaller batches reduce bursts.
+    pub max_batch_segments: Option<usize>,
+}
+
+impl Default for UdpOffload {
+    fn default() -> Self {
+        Self {
+            gso: true,
+            gro: true,
+            max_batch_segments: None,
+        }
+    }
+}
+
+impl UdpOffload {
+    /// Settings disabling all offload: every datagram is sent and received on its own.
+    pub fn disabled() -> Self {
+        Self {
+            gso: false,
+            gro: false,
+            max_batch_segments: None,
+        }
+    }
+}
+
+/// A UDP socket applying `UdpOffload` settings.
+pub(crate) struct OffloadSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    offload: UdpOffload,
+    gro_disabled: bool,
+}
+
+impl OffloadSocket {
+    /// Wraps a socket created from `socket`, a handle to the same underlying socket.
+    ///
+    /// The GRO setting is applied to the socket here, as it is enabled when the socket is wrapped by quinn.
+    pub(crate) fn new(inner: Arc<dyn AsyncUdpSocket>, socket: &std::net::UdpSocket, offload: UdpOffload) -> Arc<Self> {
+        let gro_disabled = !offload.gro && disable_gro(socket);
+        Arc::new(Self { inner, offload, gro_disabled })
+    }
+}
+
+impl fmt::Debug for OffloadSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OffloadSocket").field("inner", &self.inner).field("offload", &self.offload).finish()
+    }
+}
+
+impl AsyncUdpSocket for OffloadSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Arc::clone(&self.inner).create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.inner.try_send(transmit)
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        if !self.offload.gso {
+            return 1;
+        }
+        let segments = self.inner.max_transmit_segments();
+        self.offload.max_batch_segments.map_or(segments, |max| segments.min(max.max(1)))
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        // Receive buffers are sized for coalesced datagrams, which only stops being needed once GRO is off.
+        if self.gro_disabled {
+            1
+        } else {
+            self.inner.max_receive_segments()
+        }
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Disables GRO on a socket. Returns whether it succeeded.
+#[cfg(target_os = "linux")]
+fn disable_gro(socket: &std::net::UdpSocket) -> bool {
+    match set_socket_option(socket, libc::SOL_UDP, libc::UDP_GRO, 0) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::debug!("Failed to disable GRO: {}", e);
+            false
+        },
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disable_gro(_socket: &std::net::UdpSocket) -> bool {
+    false
+}
+
+/// Sets an integer socket option.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_socket_option(socket: &std::net::UdpSocket, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: the file descriptor is valid for the lifetime of `socket`, and the option value is a
+    // `c_int` living for the duration of the call.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+//! Packet pacing.
+//!
+//! quinn always paces each connection on its own: it spreads a congestion window worth of packets over
+//! the round-trip time, in bursts of about 2ms of data (10 to 256 datagrams), sent in GSO batches where
+//! available. This cannot be disabled or tuned. Smaller GSO batches (see `offload::UdpOffload`) make these
+//! bursts reach the wire more evenly.
+//!
+//! `Pacing` adds a socket-wide pacer on top, set with `SocketConfig::with_pacing`: datagrams sent by
+//! all connections of the socket are held back to a fixed rate, with bursts of at most `Pacing::burst`
+//! bytes. On lossy consumer links, pacing slightly below the link's capacity keeps bursts from
+//! overflowing small buffers or policers along the path. On clean links it only adds delay, so it is
+//! disabled by default.
+
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, UdpPoller};
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+/// The default burst of a pacer, in bytes: 10 full-sized datagrams, as quinn's smallest burst.
+pub const DEFAULT_PACING_BURST: u64 = 10 * 1472;
+/// The smallest datagram size, in bytes, used to derive the largest GSO batch fitting in a burst.
+const MIN_DATAGRAM_SIZE: u64 = 1200;
+
+/// Settings of the socket-wide pacer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    /// The rate datagrams are sent at, in bytes per second, across all connections of the socket.
+    pub rate: u64,
+    /// The number of bytes that may be sent back to back after the socket was idle.
+    pub burst: u64,
+}
+
+impl Pacing {
+    /// Paces to `rate` bytes per second, with bursts of `DEFAULT_PACING_BURST` bytes.
+    pub fn new(rate: u64) -> Self {
+        Self { rate, burst: DEFAULT_PACING_BURST }
+    }
+    /// Sets the number of bytes that may be sent back to back. Lower values smooth traffic further,
+    /// at the cost of more timer wake-ups.
+    pub fn with_burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+}
+
+/// A token bucket holding the bytes that may be sent.
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(pacing: Pacing) -> Self {
+        let capacity = pacing.burst.max(MIN_DATAGRAM_SIZE) as f64;
+        Self { rate: pacing.rate.max(1) as f64, capacity, tokens: capacity, updated: Instant::now() }
+    }
+    /// Returns when the next datagram may be sent, or `None` if it may be sent now.
+    fn ready_at(&mut self, now: Instant) -> Option<Instant> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+        (self.tokens < 0.0).then(|| now + Duration::from_secs_f64(-self.tokens / self.rate))
+    }
+    /// Takes the tokens of sent bytes. The bucket may go into debt for a batch larger than what is left.
+    fn take(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
+
+/// A UDP socket applying `Pacing` to the datagrams sent through it.
+///
+/// quinn waits for `UdpPoller::poll_writable` before every send, which is where sends are held back.
+/// Sends themselves are never refused, so the endpoint's own datagrams, such as stateless resets, are
+/// not lost but only counted.
+pub(crate) struct PacedSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    pacing: Pacing,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl PacedSocket {
+    pub(crate) fn new(inner: Arc<dyn AsyncUdpSocket>, pacing: Pacing) -> Arc<Self> {
+        Arc::new(Self { inner, pacing, bucket: Arc::new(Mutex::new(Bucket::new(pacing))) })
+    }
+}
+
+impl fmt::Debug for PacedSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacedSocket").field("inner", &self.inner).field("pacing", &self.pacing).finish()
+    }
+}
+
+impl AsyncUdpSocket for PacedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(PacedPoller {
+            inner: Arc::clone(&self.inner).create_io_poller(),
+            bucket: Arc::clone(&self.bucket),
+            sleep: Box::pin(tokio::time::sleep(Duration::ZERO)),
+        })
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.inner.try_send(transmit)?;
+        self.bucket.lock().unwrap().take(transmit.contents.len());
+        Ok(())
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        // Keeps GSO batches within a burst, so they do not leave the host back to back beyond it.
+        let segments = (self.pacing.burst / MIN_DATAGRAM_SIZE).max(1);
+        self.inner.max_transmit_segments().min(usize::try_from(segments).unwrap_or(usize::MAX))
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Waits for the pacer, then for the socket to become writable.
+struct PacedPoller {
+    inner: Pin<Box<dyn UdpPoller>>,
+    bucket: Arc<Mutex<Bucket>>,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl fmt::Debug for PacedPoller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacedPoller").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl UdpPoller for PacedPoller {
+    fn poll_writable(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let ready_at = self.bucket.lock().unwrap().ready_at(Instant::now());
+        if let Some(ready_at) = ready_at {
+            self.sleep.as_mut().reset(ready_at);
+            if self.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        self.inner.as_mut().poll_writable(cx)
+    }
+}
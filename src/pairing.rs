@@ -0,0 +1,165 @@
+//! Pairing of peers with a short, human-shareable code.
+//!
+//! One peer generates a code with `generate_code` and shares it out of band. Both peers then run a
+//! SPAKE2 exchange over a bi-directional stream. The code itself never crosses the wire, and a passive
+//! or active attacker gets at most one online guess per pairing attempt.
+//!
+//! The resulting key is bound to the TLS session of the connection through a keying material exporter,
+//! so it is unique per connection even when a code is reused, and an attacker relaying between the
+//! peers ends up with two different keys. Both peers confirm the key before pairing succeeds.
+//!
+//! A peer that finds the codes do not match closes the connection with `AUTH_FAILED_CODE`, so a new
+//! connection is needed for every guess, and the other peer can tell the failure with `Error::is_auth_failed`.
+
+use anyhow::{Context, Result};
+use ring::rand::{SecureRandom, SystemRandom};
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use tokio::io::AsyncReadExt;
+use crate::codes::AUTH_FAILED_CODE;
+use crate::error::Error;
+use crate::QuicConnection;
+
+/// The number of digit groups in a generated code.
+pub const CODE_GROUPS: usize = 3;
+/// The number of digits in each group of a generated code.
+pub const CODE_GROUP_LENGTH: usize = 4;
+/// The label of the TLS keying material exporter the pairing key is bound to.
+pub const EXPORTER_LABEL: &[u8] = b"EXPORTER-quicsock-pairing";
+
+/// The identity of the peer opening the pairing stream.
+const INITIATOR_IDENTITY: &[u8] = b"quicsock-pairing-initiator";
+/// The identity of the peer accepting the pairing stream.
+const RESPONDER_IDENTITY: &[u8] = b"quicsock-pairing-responder";
+/// The maximum size of a SPAKE2 message, in bytes.
+const MAX_MESSAGE_SIZE: usize = 256;
+/// The size of a key confirmation tag, in bytes.
+const CONFIRMATION_SIZE: usize = 32;
+
+/// A key shared by two paired peers.
+#[derive(Clone)]
+pub struct PairingKey {
+    key: [u8; 32],
+}
+
+impl PairingKey {
+    /// Returns the raw key.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.key
+    }
+    /// Derives a confirmation key for a single transfer, identified by `context`.
+    ///
+    /// Both peers derive the same key for the same context, and different keys for different contexts.
+    pub fn confirmation_key(&self, context: &[u8]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_derive_key("quicsock pairing v1 transfer confirmation");
+        hasher.update(&self.key);
+        hasher.update(context);
+        *hasher.finalize().as_bytes()
+    }
+    /// Computes a tag over `data` with the confirmation key for `context`.
+    pub fn tag(&self, context: &[u8], data: &[u8]) -> [u8; 32] {
+        *blake3::keyed_hash(&self.confirmation_key(context), data).as_bytes()
+    }
+    /// Verifies a tag computed with `tag` by the other peer, in constant time.
+    pub fn verify(&self, context: &[u8], data: &[u8], tag: &[u8; 32]) -> bool {
+        blake3::keyed_hash(&self.confirmation_key(context), data) == blake3::Hash::from_bytes(*tag)
+    }
+}
+
+impl std::fmt::Debug for PairingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PairingKey").finish_non_exhaustive()
+    }
+}
+
+/// Generates a random code, such as `4821-0937-5512`.
+pub fn generate_code() -> Result<String> {
+    let random = SystemRandom::new();
+    let mut digits = Vec::with_capacity(CODE_GROUPS * CODE_GROUP_LENGTH);
+    let mut byte = [0u8; 1];
+    while digits.len() < CODE_GROUPS * CODE_GROUP_LENGTH {
+        random.fill(&mut byte).map_err(|_| anyhow::anyhow!("failed to generate pairing code"))?;
+        // Rejects bytes above the largest multiple of ten, so that every digit is equally likely.
+        if byte[0] < 250 {
+            digits.push(char::from(b'0' + byte[0] % 10));
+        }
+    }
+    Ok(digits.chunks(CODE_GROUP_LENGTH).map(|group| group.iter().collect::<String>()).collect::<Vec<_>>().join("-"))
+}
+
+/// Pairs with the peer by opening a stream. The peer must call `respond` with the same code.
+pub async fn initiate(connection: &QuicConnection, code: &str) -> Result<PairingKey> {
+    let (mut send, mut recv) = connection.connection.open_bi().await.map_err(Error::from)?;
+    let (state, message) = Spake2::<Ed25519Group>::start_a(&password(code), &Identity::new(INITIATOR_IDENTITY), &Identity::new(RESPONDER_IDENTITY));
+    write_message(&mut send, &message).await?;
+    let peer_message = read_message(&mut recv).await?;
+    let key = derive_key(connection, &finish(state, &peer_message)?, &message, &peer_message)?;
+    let mut peer_tag = [0u8; CONFIRMATION_SIZE];
+    recv.read_exact(&mut peer_tag).await.map_err(Error::from).context("pairing aborted by the peer")?;
+    if !key.verify(b"confirm", RESPONDER_IDENTITY, &peer_tag) {
+        connection.close_with(AUTH_FAILED_CODE, b"pairing failed");
+        anyhow::bail!("pairing failed, the codes do not match");
+    }
+    send.write_all(&key.tag(b"confirm", INITIATOR_IDENTITY)).await.map_err(Error::from)?;
+    send.finish().map_err(Error::from)?;
+    let _ = send.stopped().await;
+    Ok(key)
+}
+
+/// Pairs with the peer by accepting its stream. The peer must call `initiate` with the same code.
+pub async fn respond(connection: &QuicConnection, code: &str) -> Result<PairingKey> {
+    let (mut send, mut recv) = connection.connection.accept_bi().await.map_err(Error::from)?;
+    let (state, message) = Spake2::<Ed25519Group>::start_b(&password(code), &Identity::new(INITIATOR_IDENTITY), &Identity::new(RESPONDER_IDENTITY));
+    let peer_message = read_message(&mut recv).await?;
+    write_message(&mut send, &message).await?;
+    let key = derive_key(connection, &finish(state, &peer_message)?, &peer_message, &message)?;
+    send.write_all(&key.tag(b"confirm", RESPONDER_IDENTITY)).await.map_err(Error::from)?;
+    send.finish().map_err(Error::from)?;
+    let mut peer_tag = [0u8; CONFIRMATION_SIZE];
+    recv.read_exact(&mut peer_tag).await.map_err(Error::from).context("pairing failed, the codes do not match")?;
+    if !key.verify(b"confirm", INITIATOR_IDENTITY, &peer_tag) {
+        connection.close_with(AUTH_FAILED_CODE, b"pairing failed");
+        anyhow::bail!("pairing failed, the codes do not match");
+    }
+    Ok(key)
+}
+
+/// Normalizes a code, so that it may be typed with or without separators.
+fn password(code: &str) -> Password {
+    let normalized: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect();
+    Password::new(normalized.as_bytes())
+}
+
+/// Finishes the SPAKE2 exchange with the peer's message, returning the shared secret.
+fn finish(state: Spake2<Ed25519Group>, peer_message: &[u8]) -> Result<Vec<u8>> {
+    state.finish(peer_message).map_err(|e| anyhow::anyhow!("invalid pairing message: {:?}", e))
+}
+
+/// Binds the shared secret to the TLS session and the transcript of the exchange.
+fn derive_key(connection: &QuicConnection, secret: &[u8], initiator_message: &[u8], responder_message: &[u8]) -> Result<PairingKey> {
+    let mut exporter = [0u8; 32];
+    connection.connection.export_keying_material(&mut exporter, EXPORTER_LABEL, b"").map_err(|_| anyhow::anyhow!("failed to export keying material"))?;
+    let mut hasher = blake3::Hasher::new_derive_key("quicsock pairing v1 session key");
+    hasher.update(secret);
+    hasher.update(&exporter);
+    hasher.update(initiator_message);
+    hasher.update(responder_message);
+    Ok(PairingKey { key: *hasher.finalize().as_bytes() })
+}
+
+/// Writes a length-prefixed SPAKE2 message.
+async fn write_message(send: &mut quinn::SendStream, message: &[u8]) -> Result<()> {
+    send.write_all(&(message.len() as u16).to_be_bytes()).await.map_err(Error::from)?;
+    send.write_all(message).await.map_err(Error::from)?;
+    Ok(())
+}
+
+/// Reads a length-prefixed SPAKE2 message.
+async fn read_message(recv: &mut quinn::RecvStream) -> Result<Vec<u8>> {
+    let len = recv.read_u16().await.context("pairing aborted by the peer")? as usize;
+    if len > MAX_MESSAGE_SIZE {
+        anyhow::bail!("pairing message of {} bytes exceeds the maximum of {} bytes", len, MAX_MESSAGE_SIZE);
+    }
+    let mut message = vec![0; len];
+    recv.read_exact(&mut message).await.context("pairing aborted by the peer")?;
+    Ok(message)
+}
@@ -0,0 +1,152 @@
+//! Stream pooling for request/response exchanges.
+//!
+//! Opening a stream per message costs a round trip before the peer sees it as a new stream.
+//! A pool keeps bi-directional streams open in message mode and reuses them, one exchange at a time.
+//! Each request is a single framed message, answered by a single framed response on the same stream.
+//! The peer serves pooled streams with `serve_requests`.
+
+use anyhow::Result;
+use bytes::Bytes;
+use quinn::Connection;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::error::Error;
+use crate::{MessageStream, QuicConnection};
+
+/// The default time an idle stream is kept in the pool.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// The default maximum number of idle streams kept in the pool.
+pub const DEFAULT_MAX_IDLE: usize = 16;
+
+/// A pool of reusable message streams on a connection.
+pub struct StreamPool {
+    connection: Connection,
+    idle: Mutex<VecDeque<(MessageStream, Instant)>>,
+    idle_timeout: Duration,
+    max_idle: usize,
+}
+
+impl StreamPool {
+    /// Creates a pool on the connection with the default limits.
+    pub fn new(connection: Connection) -> Self {
+        Self::with_limits(connection, DEFAULT_IDLE_TIMEOUT, DEFAULT_MAX_IDLE)
+    }
+    /// Creates a pool keeping at most `max_idle` streams, each for at most `idle_timeout`.
+    ///
+    /// Idle streams count against the peer's stream limits, so `max_idle` should stay well below them.
+    pub fn with_limits(connection: Connection, idle_timeout: Duration, max_idle: usize) -> Self {
+        Self {
+            connection,
+            idle: Mutex::new(VecDeque::new()),
+            idle_timeout,
+            max_idle,
+        }
+    }
+    /// Checks out a stream, reusing an idle one if available.
+    ///
+    /// Expired idle streams are finished and dropped.
+    pub async fn checkout(&self) -> Result<PooledStream<'_>> {
+        let reused = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.retain(|(_, since)| since.elapsed() < self.idle_timeout);
+            idle.pop_back()
+        };
+        let stream = match reused {
+            Some((stream, _)) => stream,
+            None => {
+                let (send_stream, recv_stream) = self.connection.open_bi().await.map_err(Error::from)?;
+                MessageStream::new(send_stream, recv_stream)
+            },
+        };
+        Ok(PooledStream { stream: Some(stream), pool: self })
+    }
+    /// Sends a request on a pooled stream and waits for its response.
+    pub async fn request(&self, message: Bytes) -> Result<Bytes> {
+        let mut stream = self.checkout().await?;
+        stream.send_message(message).await?;
+        match stream.receive_message().await? {
+            Some(response) => {
+                stream.release();
+                Ok(response)
+            },
+            None => anyhow::bail!("peer finished the stream without responding"),
+        }
+    }
+    /// Returns the number of idle streams in the pool.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+    /// Drops all idle streams.
+    pub fn clear(&self) {
+        self.idle.lock().unwrap().clear();
+    }
+}
+
+/// A stream checked out of a `StreamPool`.
+///
+/// Call `release()` after a complete exchange to return the stream to the pool.
+/// A stream dropped without being released is closed, e.g. after an error left it mid-exchange.
+pub struct PooledStream<'a> {
+    stream: Option<MessageStream>,
+    pool: &'a StreamPool,
+}
+
+impl PooledStream<'_> {
+    /// Returns the stream to the pool for reuse.
+    pub fn release(mut self) {
+        if let Some(stream) = self.stream.take() {
+            let mut idle = self.pool.idle.lock().unwrap();
+            if idle.len() < self.pool.max_idle {
+                idle.push_back((stream, Instant::now()));
+            }
+        }
+    }
+}
+
+impl Deref for PooledStream<'_> {
+    type Target = MessageStream;
+
+    fn deref(&self) -> &MessageStream {
+        self.stream.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledStream<'_> {
+    fn deref_mut(&mut self) -> &mut MessageStream {
+        self.stream.as_mut().unwrap()
+    }
+}
+
+/// Serves pooled streams opened by the peer, answering each request with the handler's response.
+///
+/// Every accepted stream is handled in its own task until the peer finishes it.
+/// Returns when the connection is closed.
+pub async fn serve_requests<F, Fut>(connection: Arc<QuicConnection>, handler: F) -> Result<()>
+where
+    F: Fn(Bytes) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Bytes>> + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let mut stream = connection.accept_message_stream().await?;
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            while let Ok(Some(request)) = stream.receive_message().await {
+                match handler(request).await {
+                    Ok(response) => {
+                        if stream.send_message(response).await.is_err() {
+                            break;
+                        }
+                    },
+                    Err(e) => {
+                        tracing::debug!("Request handler failed: {}", e);
+                        break;
+                    },
+                }
+            }
+        });
+    }
+}
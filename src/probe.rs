@@ -0,0 +1,244 @@
+//! Request-response latency measurement.
+//!
+//! A server enables the echo responder on a connection with `respond`, which can be used directly as a
+//! `QuicSocket::serve` handler. The client then calls `measure`, which sends a number of probes one after
+//! another, waits for each echo and reports the distribution of the round-trip times.
+//!
+//! Probes are sent either on a single bi-directional stream or as datagrams. Stream probes measure what
+//! a request-response protocol on a stream sees, including retransmissions. Datagram probes are not
+//! retransmitted: a probe whose echo does not arrive within `ProbeOptions::timeout` is counted as lost.
+//!
+//! The responder echoes every stream and datagram it receives, so it should run on a connection
+//! dedicated to probing.
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::error::Error;
+use crate::QuicConnection;
+
+/// The default number of probes sent by `measure`.
+pub const DEFAULT_ROUNDS: usize = 100;
+/// The default size of each probe, in bytes.
+pub const DEFAULT_PAYLOAD_SIZE: usize = 32;
+/// The default time to wait for the echo of a datagram probe before counting it as lost.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// The size of the sequence number at the start of each probe. Probes are never smaller.
+const SEQUENCE_SIZE: usize = 8;
+
+/// How probes are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProbeTransport {
+    /// On a single bi-directional stream.
+    #[default]
+    Stream,
+    /// As unreliable datagrams.
+    Datagram,
+}
+
+/// Options for `measure`.
+#[derive(Debug, Clone)]
+pub struct ProbeOptions {
+    /// The number of probes to send.
+    pub rounds: usize,
+    /// The size of each probe, in bytes. Raised to 8 bytes, the size of the sequence number.
+    pub payload_size: usize,
+    /// How probes are sent.
+    pub transport: ProbeTransport,
+    /// The time to wait for the echo of a datagram probe before counting it as lost.
+    pub timeout: Duration,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        Self {
+            rounds: DEFAULT_ROUNDS,
+            payload_size: DEFAULT_PAYLOAD_SIZE,
+            transport: ProbeTransport::default(),
+            timeout: DEFAULT_PROBE_TIMEOUT,
+        }
+    }
+}
+
+impl ProbeOptions {
+    /// Creates options sending `DEFAULT_ROUNDS` probes of `DEFAULT_PAYLOAD_SIZE` bytes on a stream.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the number of probes to send.
+    pub fn with_rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+    /// Sets the size of each probe, in bytes.
+    pub fn with_payload_size(mut self, payload_size: usize) -> Self {
+        self.payload_size = payload_size;
+        self
+    }
+    /// Sets how probes are sent.
+    pub fn with_transport(mut self, transport: ProbeTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+    /// Sets the time to wait for the echo of a datagram probe.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// The round-trip time distribution measured by `measure`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// The number of probes sent.
+    pub sent: usize,
+    /// The number of probes whose echo did not arrive in time. Always 0 for stream probes.
+    pub lost: usize,
+    /// The shortest round-trip time.
+    pub min: Duration,
+    /// The mean round-trip time.
+    pub mean: Duration,
+    /// The median round-trip time.
+    pub p50: Duration,
+    /// The 95th percentile round-trip time.
+    pub p95: Duration,
+    /// The 99th percentile round-trip time.
+    pub p99: Duration,
+    /// The longest round-trip time.
+    pub max: Duration,
+}
+
+impl ProbeReport {
+    /// Summarizes the round-trip times of the probes that were echoed.
+    fn new(sent: usize, mut samples: Vec<Duration>) -> Self {
+        let lost = sent - samples.len();
+        if samples.is_empty() {
+            return Self { sent, lost, ..Self::default() };
+        }
+        samples.sort_unstable();
+        let total: Duration = samples.iter().sum();
+        Self {
+            sent,
+            lost,
+            min: samples[0],
+            mean: total / samples.len() as u32,
+            p50: percentile(&samples, 50),
+            p95: percentile(&samples, 95),
+            p99: percentile(&samples, 99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+/// Returns the nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+/// Echoes the streams and datagrams of a connection until it is closed.
+///
+/// Can be used directly as a `QuicSocket::serve` handler.
+pub async fn respond(connection: Arc<QuicConnection>) -> Result<()> {
+    let datagrams = Arc::clone(&connection);
+    let echo_datagrams = tokio::spawn(async move {
+        while let Ok(datagram) = datagrams.connection.read_datagram().await {
+            if datagrams.connection.send_datagram(datagram).is_err() {
+                break;
+            }
+        }
+    });
+    let result = accept_streams(&connection).await;
+    echo_datagrams.abort();
+    result
+}
+
+/// Echoes every stream opened by the peer, each in its own task.
+async fn accept_streams(connection: &QuicConnection) -> Result<()> {
+    loop {
+        let (mut send, mut recv) = match connection.connection.accept_bi().await {
+            Ok(streams) => streams,
+            Err(quinn::ConnectionError::ApplicationClosed(_) | quinn::ConnectionError::LocallyClosed) => return Ok(()),
+            Err(e) => return Err(Error::from(e).into()),
+        };
+        tokio::spawn(async move {
+            while let Ok(Some(chunk)) = recv.read_chunk(usize::MAX, true).await {
+                if send.write_chunk(chunk.bytes).await.is_err() {
+                    return;
+                }
+            }
+            let _ = send.finish();
+        });
+    }
+}
+
+/// Measures the round-trip time distribution to a peer running `respond`.
+pub async fn measure(connection: &QuicConnection, options: &ProbeOptions) -> Result<ProbeReport> {
+    let size = options.payload_size.max(SEQUENCE_SIZE);
+    let samples = match options.transport {
+        ProbeTransport::Stream => measure_stream(connection, options.rounds, size).await?,
+        ProbeTransport::Datagram => measure_datagrams(connection, options.rounds, size, options.timeout).await?,
+    };
+    Ok(ProbeReport::new(options.rounds, samples))
+}
+
+/// Returns a probe of `size` bytes carrying a sequence number.
+fn probe(sequence: u64, size: usize) -> Bytes {
+    let mut probe = BytesMut::with_capacity(size);
+    probe.put_u64(sequence);
+    probe.resize(size, 0);
+    probe.freeze()
+}
+
+/// Sends probes on a stream, each after the echo of the previous one was read back.
+async fn measure_stream(connection: &QuicConnection, rounds: usize, size: usize) -> Result<Vec<Duration>> {
+    let (mut send, mut recv) = connection.connection.open_bi().await.map_err(Error::from)?;
+    let mut echo = vec![0; size];
+    let mut samples = Vec::with_capacity(rounds);
+    for sequence in 0..rounds as u64 {
+        let start = Instant::now();
+        send.write_chunk(probe(sequence, size)).await.map_err(Error::from)?;
+        recv.read_exact(&mut echo).await.map_err(Error::from)?;
+        samples.push(start.elapsed());
+        if (&echo[..]).get_u64() != sequence {
+            anyhow::bail!("probe echo out of sequence");
+        }
+    }
+    send.finish().map_err(Error::from)?;
+    Ok(samples)
+}
+
+/// Sends datagram probes, each once the echo of the previous one arrived or timed out.
+///
+/// Late echoes of earlier probes are skipped.
+async fn measure_datagrams(connection: &QuicConnection, rounds: usize, size: usize, timeout: Duration) -> Result<Vec<Duration>> {
+    let connection = &connection.connection;
+    match connection.max_datagram_size() {
+        None => anyhow::bail!("the peer does not accept datagrams"),
+        Some(max) if size > max => anyhow::bail!("probe of {} bytes exceeds the maximum datagram size of {} bytes", size, max),
+        Some(_) => {},
+    }
+    let mut samples = Vec::with_capacity(rounds);
+    for sequence in 0..rounds as u64 {
+        let start = Instant::now();
+        connection.send_datagram(probe(sequence, size)).map_err(|e| anyhow::anyhow!("failed to send probe: {}", e))?;
+        let echo = async {
+            loop {
+                let mut echo = connection.read_datagram().await.map_err(Error::from)?;
+                if echo.len() >= SEQUENCE_SIZE && echo.get_u64() == sequence {
+                    return anyhow::Ok(());
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, echo).await {
+            Ok(result) => {
+                result?;
+                samples.push(start.elapsed());
+            },
+            Err(_) => tracing::debug!("Probe {} timed out", sequence),
+        }
+    }
+    Ok(samples)
+}
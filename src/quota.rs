@@ -0,0 +1,36 @@
+//! Per-peer stream quotas.
+//!
+//! A quota limits how many bi-directional streams accepted from a peer can be handled at once.
+//! Streams beyond the quota are rejected: both halves are closed with `STREAM_QUOTA_EXCEEDED_CODE`,
+//! which the peer sees as a `QuotaExceeded` error from `send` or `receive`, and a `SocketEvent::StreamQuotaExceeded` is emitted.
+//! The protocol-level stream limits can be set with `TransportOptions`.
+
+use std::fmt;
+
+pub use crate::codes::STREAM_QUOTA_EXCEEDED_CODE;
+
+/// Limits on the streams a peer may open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamQuota {
+    /// The maximum number of accepted bi-directional streams handled at once.
+    pub max_bi_streams: u32,
+}
+
+impl StreamQuota {
+    /// Creates a quota allowing `max_bi_streams` bi-directional streams at once.
+    pub fn new(max_bi_streams: u32) -> Self {
+        Self { max_bi_streams }
+    }
+}
+
+/// Error returned when the peer rejected a stream because it exceeded the stream quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded;
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stream quota exceeded")
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
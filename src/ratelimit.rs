@@ -0,0 +1,70 @@
+//! Per-source-IP connection rate limiting.
+//!
+//! A limit allows each source IP address a number of new connections per time window. Connections beyond
+//! it are refused before any handshake work is done, and a `SocketEvent::ConnectionRateLimited` is emitted.
+//! This protects public servers from handshake floods coming from a single address.
+//!
+//! Source addresses of incoming connections are not validated before the limit is applied, so a spoofed
+//! flood can use up the limit of another address. Windows are short-lived, which bounds the impact.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Limits on the new connections from a single IP address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionRateLimit {
+    /// The maximum number of new connections from an IP address per window.
+    pub max_connections: u32,
+    /// The length of a window.
+    pub window: Duration,
+}
+
+impl ConnectionRateLimit {
+    /// Creates a limit allowing `max_connections` new connections per IP address every `window`.
+    pub fn new(max_connections: u32, window: Duration) -> Self {
+        Self { max_connections, window }
+    }
+}
+
+/// The state of an IP address in the current window.
+struct Window {
+    started: Instant,
+    connections: u32,
+}
+
+/// Tracks new connections per IP address in fixed windows.
+pub(crate) struct ConnectionRateLimiter {
+    limit: ConnectionRateLimit,
+    windows: HashMap<IpAddr, Window>,
+    last_sweep: Instant,
+}
+
+impl ConnectionRateLimiter {
+    pub(crate) fn new(limit: ConnectionRateLimit) -> Self {
+        Self { limit, windows: HashMap::new(), last_sweep: Instant::now() }
+    }
+    pub(crate) fn limit(&self) -> ConnectionRateLimit {
+        self.limit
+    }
+    /// Counts a new connection from `ip`. Returns whether it is within the limit.
+    pub(crate) fn check(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        // Forget the addresses whose window ended, so the map only holds recently seen addresses.
+        if now.duration_since(self.last_sweep) >= self.limit.window {
+            let window = self.limit.window;
+            self.windows.retain(|_, state| now.duration_since(state.started) < window);
+            self.last_sweep = now;
+        }
+        // IPv4 clients of a dual-stack socket appear as IPv4-mapped IPv6 addresses.
+        let state = self.windows.entry(ip.to_canonical()).or_insert(Window { started: now, connections: 0 });
+        if now.duration_since(state.started) >= self.limit.window {
+            *state = Window { started: now, connections: 0 };
+        }
+        if state.connections >= self.limit.max_connections {
+            return false;
+        }
+        state.connections += 1;
+        true
+    }
+}
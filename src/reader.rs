@@ -0,0 +1,93 @@
+//! Buffered reading of receive streams.
+//!
+//! `StreamReader` buffers the receiving side of a stream and implements `AsyncBufRead`, so text and
+//! line-based protocols can be parsed with `tokio::io::AsyncBufReadExt`, e.g. `read_line`, `read_until`
+//! and `lines`, without a buffering layer of their own.
+//!
+//! Errors are `io::Error`s wrapping the `quinn::ReadError` of the stream, if any, so the peer's error
+//! code can still be recovered with `io::Error::get_ref` and `downcast_ref`.
+
+use anyhow::Result;
+use quinn::RecvStream;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncRead, BufReader, ReadBuf};
+use crate::error::Error;
+use crate::MessageStream;
+
+/// The default size of the buffer of a `StreamReader`.
+pub const DEFAULT_READER_CAPACITY: usize = 8 * 1024;
+
+/// A buffered reader over the receiving side of a QUIC stream.
+///
+/// Created from a `quinn::RecvStream`, a `MessageStream`, or with `QuicConnection::stream_reader` for a
+/// stream registered by ID.
+pub struct StreamReader {
+    reader: BufReader<RecvStream>,
+}
+
+impl StreamReader {
+    /// Creates a reader with a buffer of `DEFAULT_READER_CAPACITY` bytes.
+    pub fn new(recv_stream: RecvStream) -> Self {
+        Self::with_capacity(DEFAULT_READER_CAPACITY, recv_stream)
+    }
+    /// Creates a reader with a buffer of `capacity` bytes.
+    ///
+    /// Lines longer than the buffer are still read whole by `read_line` and `read_until`; the capacity
+    /// only bounds how much is read from the stream at once.
+    pub fn with_capacity(capacity: usize, recv_stream: RecvStream) -> Self {
+        Self { reader: BufReader::with_capacity(capacity, recv_stream) }
+    }
+    /// Returns the data buffered but not yet read.
+    pub fn buffer(&self) -> &[u8] {
+        self.reader.buffer()
+    }
+    /// Returns the underlying stream.
+    ///
+    /// Reading from it directly skips the data that is still buffered.
+    pub fn get_mut(&mut self) -> &mut RecvStream {
+        self.reader.get_mut()
+    }
+    /// Stops the stream, asking the peer to stop sending with the given error code.
+    ///
+    /// Data that is still buffered can be read afterwards.
+    pub fn stop(&mut self, error_code: u32) -> Result<()> {
+        self.reader.get_mut().stop(error_code.into()).map_err(Error::from)?;
+        Ok(())
+    }
+    /// Consumes the reader, returning the underlying stream. Data that is still buffered is discarded.
+    pub fn into_inner(self) -> RecvStream {
+        self.reader.into_inner()
+    }
+}
+
+impl From<RecvStream> for StreamReader {
+    fn from(recv_stream: RecvStream) -> Self {
+        Self::new(recv_stream)
+    }
+}
+
+impl From<MessageStream> for StreamReader {
+    /// Reads the stream of a message stream as raw bytes, dropping its sending side, which finishes it.
+    /// Messages received but not yet read are discarded.
+    fn from(stream: MessageStream) -> Self {
+        Self::new(stream.into_inner().1)
+    }
+}
+
+impl AsyncRead for StreamReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for StreamReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().reader).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.reader).consume(amt)
+    }
+}
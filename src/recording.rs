@@ -0,0 +1,293 @@
+//! Session recording and replay, to reproduce protocol bugs offline.
+//!
+//! A `Recorder` added to a connection with `QuicConnection::add_interceptor` records the messages sent and
+//! received with `send()`, `send_batch()`, `receive()` and `receive_messages()`, the first use of each
+//! stream and, when the application reports them with `Recorder::record_datagram`, datagrams. Each event
+//! is written as a line of JSON with its time since the recorder was created. Add the recorder after other
+//! interceptors, so it records payloads as they are sent and received on the streams.
+//!
+//! `replay` plays the part of the recorded connection's peer on a live connection, typically one side of
+//! `testing::pair` whose other side runs the code under test. It sends what the peer sent, with the
+//! recorded timing, and compares what the code under test sends with the recording. Stream IDs are
+//! mapped by the order in which streams are first used, so the code under test must open and use its
+//! streams in the recorded order. Each message is sent with `QuicConnection::send`, one per stream.
+
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::error::Error;
+use crate::interceptor::Interceptor;
+use crate::QuicConnection;
+
+/// The default time `replay` waits for each message or datagram the recording expects.
+pub const DEFAULT_REPLAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether an event was sent or received by the recorded connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedDirection {
+    /// Sent to the peer.
+    Sent,
+    /// Received from the peer.
+    Received,
+}
+
+/// The kind of a recorded event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedKind {
+    /// A stream was used for the first time. Recorded before its first message.
+    StreamOpened,
+    /// A message on a stream.
+    Message,
+    /// A datagram.
+    Datagram,
+}
+
+/// An event of a recording, serialized as a JSON object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// The time since the recorder was created, in microseconds.
+    pub elapsed_us: u64,
+    /// What happened.
+    pub kind: RecordedKind,
+    /// Whether the event was sent or received.
+    pub direction: RecordedDirection,
+    /// The ID of the stream on the recorded connection, for stream events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_id: Option<u64>,
+    /// The payload of messages and datagrams, in hex.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+}
+
+impl RecordedEvent {
+    /// Returns the decoded payload of the event.
+    pub fn payload(&self) -> Result<Bytes> {
+        decode_hex(&self.data).map(Bytes::from)
+    }
+}
+
+struct RecorderState {
+    writer: Box<dyn Write + Send>,
+    streams: HashSet<u64>,
+}
+
+/// Records the messages of a connection. See the module documentation.
+pub struct Recorder {
+    state: Mutex<RecorderState>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Creates a recorder writing each event to `writer` as a line of JSON.
+    ///
+    /// Events are written as messages pass through the connection, so the writer should not block for long.
+    pub fn to_writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            state: Mutex::new(RecorderState { writer: Box::new(writer), streams: HashSet::new() }),
+            started: Instant::now(),
+        }
+    }
+    /// Creates a recorder writing to a new file at `path`, replacing any existing file.
+    pub fn to_file(path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(Self::to_writer(std::io::BufWriter::new(file)))
+    }
+    /// Records a datagram sent or received by the application.
+    pub fn record_datagram(&self, direction: RecordedDirection, datagram: &[u8]) {
+        self.record(RecordedKind::Datagram, direction, None, datagram);
+    }
+    fn record_message(&self, direction: RecordedDirection, stream_id: u64, data: &[u8]) {
+        let opened = self.state.lock().unwrap().streams.insert(stream_id);
+        if opened {
+            self.record(RecordedKind::StreamOpened, direction, Some(stream_id), &[]);
+        }
+        self.record(RecordedKind::Message, direction, Some(stream_id), data);
+    }
+    fn record(&self, kind: RecordedKind, direction: RecordedDirection, stream_id: Option<u64>, data: &[u8]) {
+        let event = RecordedEvent {
+            elapsed_us: self.started.elapsed().as_micros() as u64,
+            kind,
+            direction,
+            stream_id,
+            data: encode_hex(data),
+        };
+        let mut line = match serde_json::to_vec(&event) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize recorded event: {}", e);
+                return;
+            },
+        };
+        line.push(b'\n');
+        let mut state = self.state.lock().unwrap();
+        if let Err(e) = state.writer.write_all(&line).and_then(|_| state.writer.flush()) {
+            tracing::warn!("Failed to write recorded event: {}", e);
+        }
+    }
+}
+
+impl Interceptor for Recorder {
+    fn on_send(&self, stream_id: u64, data: Bytes) -> Result<Bytes> {
+        self.record_message(RecordedDirection::Sent, stream_id, &data);
+        Ok(data)
+    }
+    fn on_receive(&self, stream_id: u64, data: Bytes) -> Result<Bytes> {
+        self.record_message(RecordedDirection::Received, stream_id, &data);
+        Ok(data)
+    }
+}
+
+/// The events of a recording, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    /// Reads a recording written by a `Recorder`, one JSON event per line.
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        let mut events = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            events.push(serde_json::from_str(&line)?);
+        }
+        Ok(Self { events })
+    }
+    /// Reads a recording from the file at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_reader(BufReader::new(std::fs::File::open(path)?))
+    }
+    /// Returns the events of the recording.
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+}
+
+/// Options for `replay`.
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// How much faster than recorded the events are replayed. 0 replays them without waiting.
+    pub speed: f64,
+    /// The time to wait for each message or datagram the recording expects from the code under test.
+    pub timeout: Duration,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            timeout: DEFAULT_REPLAY_TIMEOUT,
+        }
+    }
+}
+
+impl ReplayOptions {
+    /// Creates options replaying at the recorded speed, waiting up to `DEFAULT_REPLAY_TIMEOUT` for each message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets how much faster than recorded the events are replayed.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+    /// Sets the time to wait for each message or datagram the recording expects.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// The outcome of a `replay`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Messages and datagrams sent to the code under test.
+    pub sent: u64,
+    /// Messages and datagrams received from the code under test.
+    pub received: u64,
+    /// The indices of the events whose payload differed from what the code under test sent.
+    pub mismatches: Vec<usize>,
+}
+
+/// Replays the peer's part of a recording on `connection`. See the module documentation.
+///
+/// Fails if the code under test does not send an expected message or datagram within `ReplayOptions::timeout`.
+pub async fn replay(recording: &Recording, connection: &QuicConnection, options: ReplayOptions) -> Result<ReplayReport> {
+    let started = Instant::now();
+    let mut streams: HashMap<u64, u64> = HashMap::new();
+    let mut report = ReplayReport::default();
+    for (index, event) in recording.events.iter().enumerate() {
+        if options.speed > 0.0 {
+            let at = Duration::from_micros(event.elapsed_us).div_f64(options.speed);
+            tokio::time::sleep_until((started + at).into()).await;
+        }
+        let payload = event.payload()?;
+        let actual = match (event.kind, event.direction) {
+            (RecordedKind::StreamOpened, _) => continue,
+            (RecordedKind::Message, RecordedDirection::Received) => {
+                let stream_id = replay_stream(connection, &mut streams, event, true, options.timeout).await?;
+                connection.send(stream_id, &payload).await?;
+                report.sent += 1;
+                continue;
+            },
+            (RecordedKind::Message, RecordedDirection::Sent) => {
+                let stream_id = replay_stream(connection, &mut streams, event, false, options.timeout).await?;
+                let received = tokio::time::timeout(options.timeout, connection.receive(stream_id)).await;
+                received.map_err(|_| anyhow::anyhow!("timed out waiting for the message of event {}", index))??
+            },
+            (RecordedKind::Datagram, RecordedDirection::Received) => {
+                connection.connection.send_datagram(payload).map_err(|e| anyhow::anyhow!("failed to send datagram: {}", e))?;
+                report.sent += 1;
+                continue;
+            },
+            (RecordedKind::Datagram, RecordedDirection::Sent) => {
+                let received = tokio::time::timeout(options.timeout, connection.connection.read_datagram()).await;
+                received.map_err(|_| anyhow::anyhow!("timed out waiting for the datagram of event {}", index))?.map_err(Error::from)?.to_vec()
+            },
+        };
+        report.received += 1;
+        if actual != payload {
+            tracing::debug!("Event {} expected {} bytes, received {} different bytes", index, payload.len(), actual.len());
+            report.mismatches.push(index);
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the replaying stream of a recorded stream, opening it if the recorded peer used it first, or
+/// accepting it from the code under test otherwise.
+async fn replay_stream(connection: &QuicConnection, streams: &mut HashMap<u64, u64>, event: &RecordedEvent, open: bool, timeout: Duration) -> Result<u64> {
+    let recorded = event.stream_id.ok_or_else(|| anyhow::anyhow!("message event without stream ID"))?;
+    if let Some(&stream_id) = streams.get(&recorded) {
+        return Ok(stream_id);
+    }
+    let stream_id = if open {
+        connection.open_bi_stream().await?
+    } else {
+        let accepted = tokio::time::timeout(timeout, connection.accept_bi_stream()).await;
+        accepted.map_err(|_| anyhow::anyhow!("timed out waiting for recorded stream {}", recorded))??
+    };
+    streams.insert(recorded, stream_id);
+    Ok(stream_id)
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        anyhow::bail!("odd length of hex payload");
+    }
+    hex.as_bytes().chunks(2).map(|pair| Ok(u8::from_str_radix(std::str::from_utf8(pair)?, 16)?)).collect()
+}
@@ -0,0 +1,108 @@
+//! Sharded registry used for the connection and stream maps.
+//!
+//! Entries are spread over independently locked shards, so operations on unrelated keys rarely contend.
+//! Locks are only held for the duration of a map operation and never across an await point;
+//! values that need exclusive async access are stored behind their own lock.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Mutex, MutexGuard};
+
+/// The number of shards in a registry.
+const SHARD_COUNT: usize = 16;
+
+/// A concurrent map split into independently locked shards.
+pub(crate) struct ShardedMap<K, V> {
+    shards: Box<[Mutex<HashMap<K, V>>]>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    /// Creates an empty map.
+    pub(crate) fn new() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+    /// Locks the shard holding the given key.
+    fn shard(&self, key: &K) -> MutexGuard<'_, HashMap<K, V>> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+    /// Inserts a value, returning the previous value for the key.
+    pub(crate) fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).insert(key, value)
+    }
+    /// Removes the value for the key.
+    pub(crate) fn remove(&self, key: &K) -> Option<V> {
+        self.shard(key).remove(key)
+    }
+    /// Returns whether the map holds a value for the key.
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        self.shard(key).contains_key(key)
+    }
+    /// Returns the number of entries.
+    pub(crate) fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+    }
+    /// Removes the value for the key if it matches the predicate.
+    pub(crate) fn remove_if(&self, key: &K, predicate: impl FnOnce(&V) -> bool) -> Option<V> {
+        let mut shard = self.shard(key);
+        if shard.get(key).is_some_and(predicate) {
+            shard.remove(key)
+        } else {
+            None
+        }
+    }
+    /// Updates the value for the key in place, if there is one.
+    pub(crate) fn update(&self, key: &K, f: impl FnOnce(&mut V)) {
+        if let Some(value) = self.shard(key).get_mut(key) {
+            f(value);
+        }
+    }
+    /// Returns the keys whose values match the predicate.
+    pub(crate) fn keys_where(&self, mut predicate: impl FnMut(&V) -> bool) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.shards.iter().flat_map(|shard| {
+            shard.lock().unwrap().iter().filter(|(_, value)| predicate(value)).map(|(key, _)| key.clone()).collect::<Vec<_>>()
+        }).collect()
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> ShardedMap<K, V> {
+    /// Returns a clone of the value for the key.
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        self.shard(key).get(key).cloned()
+    }
+    /// Returns clones of all values.
+    pub(crate) fn values(&self) -> Vec<V> {
+        self.shards.iter().flat_map(|shard| shard.lock().unwrap().values().cloned().collect::<Vec<_>>()).collect()
+    }
+    /// Returns a clone of the value for the key, inserting one created by `default` if there is none.
+    pub(crate) fn get_or_insert_with(&self, key: K, default: impl FnOnce() -> V) -> V {
+        self.shard(&key).entry(key).or_insert_with(default).clone()
+    }
+}
+
+impl<K: Hash + Eq, T> ShardedMap<K, Vec<T>> {
+    /// Appends a value to the list for the key.
+    pub(crate) fn push(&self, key: K, value: T) {
+        self.shard(&key).entry(key).or_default().push(value);
+    }
+    /// Removes the first value matching the predicate from the list for the key.
+    ///
+    /// The key is removed once its list is empty.
+    pub(crate) fn remove_item(&self, key: &K, predicate: impl FnMut(&T) -> bool) -> Option<T> {
+        let mut shard = self.shard(key);
+        let list = shard.get_mut(key)?;
+        let value = list.iter().position(predicate).map(|index| list.remove(index));
+        if list.is_empty() {
+            shard.remove(key);
+        }
+        value
+    }
+}
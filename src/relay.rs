@@ -0,0 +1,304 @@
+//! Relay server subsystem. A TURN-like fallback for peers that cannot reach each other directly.
+//!
+//! Each peer connects to the relay, opens a control message stream and binds a shared token.
+//! Once two connections have bound the same token, the relay pipes every bi-directional stream
+//! and datagram from one connection to the other, so each peer can use its relay connection
+//! as if it were connected to the other peer directly. A token is paired at most once at a time: a
+//! connection binding it while a peer waits is paired with that peer, and the next one waits anew.
+//!
+//! The token is a bearer secret, and the only thing the relay checks. Anyone who knows it can take the
+//! place of either peer, so generate it with enough entropy (e.g. 128 random bits) and share it over a
+//! confidential channel. The relay terminates TLS, so it sees the relayed traffic; peers that do not
+//! trust it should authenticate each other and encrypt end to end on top of the relayed streams.
+//!
+//! `pipe` is the primitive underneath: it copies between two streams in both directions, such as a
+//! `Duplex` QUIC stream and a TCP stream, for proxies and relays built on quicsock.
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use quinn::{Connection, RecvStream, SendStream};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{oneshot, Mutex};
+use crate::cancel::STREAM_CANCELLED_CODE;
+use crate::error::from_io;
+use crate::{MessageStream, QuicConnection, QuicSocket};
+
+/// The size of the buffer of each direction of a `pipe`.
+pub const PIPE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A relay control message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayMessage {
+    /// Binds the connection to a token shared with the other peer. The token is a bearer secret.
+    Bind { token: String },
+    /// Sent by the relay once the other peer has bound the same token.
+    Paired,
+    /// Reports a failed request.
+    Error { message: String },
+}
+
+impl RelayMessage {
+    /// Encodes the message as a frame payload.
+    pub fn encode(&self) -> Result<bytes::Bytes> {
+        Ok(bytes::Bytes::from(serde_json::to_vec(self)?))
+    }
+    /// Decodes a message from a frame payload.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// A connection waiting for its peer to bind the same token.
+struct PendingPeer {
+    connection: Arc<QuicConnection>,
+    control: MessageStream,
+    paired: oneshot::Sender<()>,
+}
+
+/// Relay server pairing connections by token.
+pub struct RelayServer {
+    pending: Mutex<HashMap<String, PendingPeer>>,
+}
+
+impl RelayServer {
+    /// Creates a new relay server.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { pending: Mutex::new(HashMap::new()) })
+    }
+    /// Handles a peer connection until it, or the connection it is paired with, closes.
+    ///
+    /// Can be used directly as a `QuicSocket::serve` handler.
+    pub async fn handle_connection(self: Arc<Self>, connection: Arc<QuicConnection>) -> Result<()> {
+        let mut control = connection.accept_message_stream().await?;
+        let token = match control.next().await {
+            Some(frame) => match RelayMessage::decode(&frame?)? {
+                RelayMessage::Bind { token } => token,
+                _ => {
+                    control.send(RelayMessage::Error { message: "expected bind".to_string() }.encode()?).await?;
+                    return Ok(());
+                },
+            },
+            None => return Ok(()),
+        };
+        // Look up and register under a single guard, so concurrent binds of a token pair with each other.
+        let bound = {
+            let mut pending = self.pending.lock().await;
+            let waiting = match pending.entry(token.clone()) {
+                Entry::Occupied(entry) if entry.get().connection.close_reason().is_none() => Some(entry.remove()),
+                _ => None,
+            };
+            match waiting {
+                Some(peer) => Ok((peer, control)),
+                None => {
+                    // Replaces a peer whose connection closed before it was removed.
+                    let (paired_tx, paired_rx) = oneshot::channel();
+                    pending.insert(token.clone(), PendingPeer { connection: Arc::clone(&connection), control, paired: paired_tx });
+                    Err(paired_rx)
+                },
+            }
+        };
+        match bound {
+            Ok((mut peer, mut control)) => {
+                peer.control.send(RelayMessage::Paired.encode()?).await?;
+                control.send(RelayMessage::Paired.encode()?).await?;
+                let _ = peer.paired.send(());
+                tracing::debug!("Relaying between {} and {}", peer.connection.connection.remote_address(), connection.connection.remote_address());
+                pipe_connections(&peer.connection.connection, &connection.connection).await;
+                peer.connection.close().await;
+                connection.close().await;
+            },
+            Err(paired_rx) => {
+                tokio::select! {
+                    _ = paired_rx => {},
+                    _ = connection.connection.closed() => {
+                        let mut pending = self.pending.lock().await;
+                        if pending.get(&token).is_some_and(|p| Arc::ptr_eq(&p.connection, &connection)) {
+                            pending.remove(&token);
+                        }
+                        return Ok(());
+                    },
+                }
+                connection.connection.closed().await;
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Connects to a peer through a relay.
+///
+/// Binds `token` on the relay at `relay_addr` and waits until the other peer has bound the same token.
+/// Streams opened and datagrams sent on the returned connection are delivered to the other peer.
+pub async fn connect_via_relay(socket: &QuicSocket, relay_addr: SocketAddr, server_name: &str, token: &str) -> Result<Arc<QuicConnection>> {
+    let connection = socket.connect(relay_addr, server_name).await?;
+    let mut control = connection.open_message_stream().await?;
+    control.send(RelayMessage::Bind { token: token.to_string() }.encode()?).await?;
+    match control.next().await {
+        Some(frame) => match RelayMessage::decode(&frame?)? {
+            RelayMessage::Paired => Ok(connection),
+            RelayMessage::Error { message } => anyhow::bail!("relay error: {}", message),
+            message => anyhow::bail!("unexpected relay message: {:?}", message),
+        },
+        None => anyhow::bail!("relay closed the control stream"),
+    }
+}
+
+/// Pipes streams and datagrams between two connections until either one closes.
+async fn pipe_connections(a: &Connection, b: &Connection) {
+    tokio::select! {
+        _ = forward_streams(a, b) => {},
+        _ = forward_streams(b, a) => {},
+        _ = forward_datagrams(a, b) => {},
+        _ = forward_datagrams(b, a) => {},
+    }
+}
+
+/// Opens a stream on `to` for every bi-directional stream accepted from `from`.
+async fn forward_streams(from: &Connection, to: &Connection) -> Result<()> {
+    loop {
+        let (from_send, from_recv) = from.accept_bi().await?;
+        let (to_send, to_recv) = to.open_bi().await?;
+        tokio::spawn(async move {
+            if let Err(e) = pipe(Duplex::new(from_send, from_recv), Duplex::new(to_send, to_recv)).await {
+                tracing::debug!("Relayed stream failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Forwards datagrams received on `from` to `to`.
+async fn forward_datagrams(from: &Connection, to: &Connection) -> Result<()> {
+    loop {
+        let datagram = from.read_datagram().await?;
+        if let Err(e) = to.send_datagram(datagram) {
+            tracing::debug!("Dropped relayed datagram: {}", e);
+        }
+    }
+}
+
+/// Copies data between two streams in both directions until both have ended.
+///
+/// When one side finishes its writing half, the other side's writing half is finished in turn, so the
+/// half-close propagates, and the other direction keeps flowing. Each direction has a buffer of
+/// `PIPE_BUFFER_SIZE` bytes, so a slow side slows the other down through flow control instead of
+/// buffering. Returns the number of bytes copied from `a` to `b` and from `b` to `a`.
+///
+/// On failure, such as a peer resetting a QUIC stream, the error is returned (as a `quicsock::Error`
+/// for QUIC streams) and both streams are dropped, which aborts any `Duplex`.
+pub async fn pipe<A, B>(mut a: A, mut b: B) -> Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::io::copy_bidirectional_with_sizes(&mut a, &mut b, PIPE_BUFFER_SIZE, PIPE_BUFFER_SIZE).await.map_err(from_io)
+}
+
+/// A bi-directional QUIC stream as a single `AsyncRead` and `AsyncWrite` object, e.g. for `pipe`.
+///
+/// Shutting it down finishes the sending half. Dropping it before that resets the sending half with
+/// `STREAM_CANCELLED_CODE`, so the peer does not mistake an aborted stream for a complete one.
+pub struct Duplex {
+    send_stream: Option<SendStream>,
+    recv_stream: RecvStream,
+}
+
+impl Duplex {
+    /// Joins the halves of a bi-directional stream.
+    pub fn new(send_stream: SendStream, recv_stream: RecvStream) -> Self {
+        Self { send_stream: Some(send_stream), recv_stream }
+    }
+}
+
+impl From<(SendStream, RecvStream)> for Duplex {
+    fn from((send_stream, recv_stream): (SendStream, RecvStream)) -> Self {
+        Self::new(send_stream, recv_stream)
+    }
+}
+
+impl From<MessageStream> for Duplex {
+    /// Uses the stream of a message stream as a raw byte stream. Messages received but not yet read are discarded.
+    fn from(stream: MessageStream) -> Self {
+        stream.into_inner().into()
+    }
+}
+
+impl AsyncRead for Duplex {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv_stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Duplex {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.send_stream.as_mut() {
+            Some(send_stream) => AsyncWrite::poll_write(Pin::new(send_stream), cx, buf),
+            None => Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, "stream was shut down"))),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.send_stream.as_mut() {
+            Some(send_stream) => Pin::new(send_stream).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(send_stream) = self.send_stream.as_mut() else { return Poll::Ready(Ok(())) };
+        let result = std::task::ready!(Pin::new(send_stream).poll_shutdown(cx));
+        // Once finished, dropping the sending half no longer aborts the stream.
+        self.send_stream = None;
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for Duplex {
+    fn drop(&mut self) {
+        if let Some(send_stream) = self.send_stream.as_mut() {
+            let _ = send_stream.reset(STREAM_CANCELLED_CODE.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Loopback, LOOPBACK_SERVER_NAME};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn pairs_each_waiting_peer_once() {
+        let Loopback { server, mut incoming, client, .. } = Loopback::new().await.unwrap();
+        let relay_addr = server.endpoint().local_addr().unwrap();
+        let relay = RelayServer::new();
+        tokio::spawn(async move { server.serve(&mut incoming, move |connection| Arc::clone(&relay).handle_connection(connection)).await });
+        let client = Arc::new(client);
+
+        let bind = |client: Arc<QuicSocket>| tokio::spawn(async move { connect_via_relay(&client, relay_addr, LOOPBACK_SERVER_NAME, "token").await });
+        let (first, second) = tokio::join!(bind(Arc::clone(&client)), bind(Arc::clone(&client)));
+        let (first, second) = (first.unwrap().unwrap(), second.unwrap().unwrap());
+
+        // A third peer binding the token waits for a fourth instead of displacing a paired one.
+        let third = bind(Arc::clone(&client));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!third.is_finished());
+        let fourth = bind(Arc::clone(&client)).await.unwrap().unwrap();
+        let third = third.await.unwrap().unwrap();
+
+        for (a, b) in [(&first, &second), (&third, &fourth)] {
+            let stream_id = a.open_bi_stream().await.unwrap();
+            a.send(stream_id, b"relayed").await.unwrap();
+            let accepted = b.accept_bi_stream().await.unwrap();
+            assert_eq!(b.receive(accepted).await.unwrap(), b"relayed");
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Stateless reset key management.
+//!
+//! A QUIC endpoint derives the stateless reset token of every connection ID it issues from its reset key.
+//! When a packet arrives for a connection the endpoint no longer knows, e.g. after a restart, it replies
+//! with a stateless reset so the peer closes the connection immediately instead of waiting for the idle timeout.
+//! This only works if the endpoint still has the key that was used when the connection was established,
+//! so servers that restart behind the same address should persist their key with `save` and `load`.
+//! Servers behind a load balancer should also share it, see `SocketConfig::behind_load_balancer`.
+
+use anyhow::{Context, Result};
+use quinn::crypto::HmacKey;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// The length of a generated reset key secret, in bytes.
+pub const RESET_KEY_LENGTH: usize = 64;
+/// The minimum length of a reset key secret, in bytes.
+pub const MIN_RESET_KEY_LENGTH: usize = 32;
+
+struct KeyState {
+    secret: Vec<u8>,
+    key: hmac::Key,
+}
+
+impl KeyState {
+    fn new(secret: Vec<u8>) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &secret);
+        Self { secret, key }
+    }
+}
+
+/// A stateless reset key that can be persisted and rotated.
+///
+/// Use with `SocketConfig::with_reset_key`. The key is shared with the endpoint, so `rotate`
+/// takes effect immediately.
+pub struct StatelessResetKey {
+    state: RwLock<KeyState>,
+}
+
+impl StatelessResetKey {
+    /// Generates a new random key.
+    pub fn generate() -> Result<Self> {
+        Ok(Self { state: RwLock::new(KeyState::new(random_secret()?)) })
+    }
+    /// Creates a key from a secret of at least `MIN_RESET_KEY_LENGTH` bytes.
+    pub fn from_bytes(secret: &[u8]) -> Result<Self> {
+        check_secret(secret)?;
+        Ok(Self { state: RwLock::new(KeyState::new(secret.to_vec())) })
+    }
+    /// Returns the secret of the current key.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.state.read().unwrap().secret.clone()
+    }
+    /// Loads a key from a file, generating and saving a new one if the file does not exist.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(secret) => Self::from_bytes(&secret),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key = Self::generate()?;
+                key.save(path)?;
+                Ok(key)
+            },
+            Err(e) => Err(e).context("failed to read reset key"),
+        }
+    }
+    /// Saves the secret of the current key to a file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes()).context("failed to write reset key")
+    }
+    /// Replaces the key with a newly generated one.
+    ///
+    /// Connections established before the rotation can no longer be reset statelessly once the endpoint
+    /// has forgotten them, so rotate when few such connections are expected, e.g. at a scheduled restart.
+    pub fn rotate(&self) -> Result<()> {
+        self.rotate_to(&random_secret()?)
+    }
+    /// Replaces the key with one derived from the given secret.
+    pub fn rotate_to(&self, secret: &[u8]) -> Result<()> {
+        check_secret(secret)?;
+        *self.state.write().unwrap() = KeyState::new(secret.to_vec());
+        tracing::debug!("Rotated stateless reset key");
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for StatelessResetKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatelessResetKey").finish_non_exhaustive()
+    }
+}
+
+impl HmacKey for StatelessResetKey {
+    fn sign(&self, data: &[u8], signature_out: &mut [u8]) {
+        let tag = hmac::sign(&self.state.read().unwrap().key, data);
+        signature_out.copy_from_slice(tag.as_ref());
+    }
+
+    fn signature_len(&self) -> usize {
+        hmac::HMAC_SHA256.digest_algorithm().output_len()
+    }
+
+    fn verify(&self, data: &[u8], signature: &[u8]) -> std::result::Result<(), quinn::crypto::CryptoError> {
+        hmac::verify(&self.state.read().unwrap().key, data, signature).map_err(|_| quinn::crypto::CryptoError)
+    }
+}
+
+/// Generates a random secret.
+fn random_secret() -> Result<Vec<u8>> {
+    let mut secret = vec![0; RESET_KEY_LENGTH];
+    SystemRandom::new().fill(&mut secret).map_err(|_| anyhow::anyhow!("failed to generate reset key"))?;
+    Ok(secret)
+}
+
+/// Checks that a secret is long enough.
+fn check_secret(secret: &[u8]) -> Result<()> {
+    if secret.len() < MIN_RESET_KEY_LENGTH {
+        anyhow::bail!("reset key must be at least {} bytes, got {}", MIN_RESET_KEY_LENGTH, secret.len());
+    }
+    Ok(())
+}
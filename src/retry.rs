@@ -0,0 +1,62 @@
+//! Retry token minting and validation.
+//!
+//! A server asking a client to validate its address with a retry (see `flood`) sends it a token, which
+//! the client echoes back in its next Initial packet. By default quinn seals tokens with a random key of
+//! the endpoint, so only that endpoint accepts them. A `RetryTokenKey` replaces this: it seals the
+//! tokens the server mints and opens the tokens clients send back, e.g. with a key shared by every server
+//! behind a load balancer, or by asking an external anti-abuse system whether to honor a token.
+//!
+//! quinn still encodes the token contents, the client's address and when the token was issued, and
+//! checks them once opened, so a key only decides how tokens are protected and which ones are accepted.
+//! The same key protects the address validation tokens servers send in NEW_TOKEN frames.
+
+use quinn::crypto::{AeadKey, CryptoError, HandshakeTokenKey};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The default time a retry token is valid for after it was issued.
+pub const DEFAULT_RETRY_TOKEN_LIFETIME: Duration = Duration::from_secs(15);
+
+/// Mints and validates the tokens of a server. Set with `SocketConfig::with_retry_token_key`.
+pub trait RetryTokenKey: fmt::Debug + Send + Sync {
+    /// Seals the contents of a new token in place.
+    ///
+    /// `nonce` is random, unique to the token and sent along with it in the clear.
+    fn seal(&self, nonce: &[u8], token: &mut Vec<u8>);
+    /// Opens a token sealed by `seal` in place, returning its contents, or `None` to reject it.
+    ///
+    /// A rejected retry token fails the handshake, so this should only reject tokens that are forged,
+    /// tampered with or flagged as abusive.
+    fn open<'a>(&self, nonce: &[u8], token: &'a mut [u8]) -> Option<&'a mut [u8]>;
+    /// Returns how long a retry token is valid for after it was issued. Defaults to `DEFAULT_RETRY_TOKEN_LIFETIME`.
+    fn lifetime(&self) -> Duration {
+        DEFAULT_RETRY_TOKEN_LIFETIME
+    }
+}
+
+/// Adapts a `RetryTokenKey` to the handshake token key of a quinn server config.
+pub(crate) struct TokenKey(pub(crate) Arc<dyn RetryTokenKey>);
+
+impl HandshakeTokenKey for TokenKey {
+    fn aead_from_hkdf(&self, random_bytes: &[u8]) -> Box<dyn AeadKey> {
+        Box::new(TokenSeal { key: Arc::clone(&self.0), nonce: random_bytes.to_vec() })
+    }
+}
+
+/// The key of a single token, bound to its nonce.
+struct TokenSeal {
+    key: Arc<dyn RetryTokenKey>,
+    nonce: Vec<u8>,
+}
+
+impl AeadKey for TokenSeal {
+    fn seal(&self, data: &mut Vec<u8>, _additional_data: &[u8]) -> Result<(), CryptoError> {
+        self.key.seal(&self.nonce, data);
+        Ok(())
+    }
+
+    fn open<'a>(&self, data: &'a mut [u8], _additional_data: &[u8]) -> Result<&'a mut [u8], CryptoError> {
+        self.key.open(&self.nonce, data).ok_or(CryptoError)
+    }
+}
@@ -0,0 +1,143 @@
+//! Early handshake inspection and routing.
+//!
+//! A server can read the server name (SNI) and ALPN protocol of a client before the handshake completes,
+//! with `QuicSocket::inspect`, and accept or reject the connection based on them.
+//! `Router` builds on this to serve several applications on one port with `QuicSocket::serve_routed`.
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use quinn::Connecting;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use crate::flood::HandshakeGuard;
+use crate::socket::Shared;
+use crate::{QuicConnection, QuicSocket};
+
+/// What a client asked for in its hello.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHello {
+    /// The address of the client.
+    pub remote_address: SocketAddr,
+    /// The server name (SNI) requested by the client, if any.
+    pub server_name: Option<String>,
+    /// The ALPN protocol selected from the client's offer, if any.
+    pub alpn_protocol: Option<Vec<u8>>,
+}
+
+impl ClientHello {
+    /// Waits for the client hello of a connection being handshaken and reads it.
+    pub(crate) async fn read(connecting: &mut Connecting) -> Result<Self> {
+        let handshake_data = connecting.handshake_data().await.map_err(crate::error::Error::from)?;
+        let handshake_data = handshake_data
+            .downcast::<quinn::crypto::rustls::HandshakeData>()
+            .map_err(|_| anyhow::anyhow!("unexpected handshake data"))?;
+        Ok(Self {
+            remote_address: connecting.remote_address(),
+            server_name: handshake_data.server_name,
+            alpn_protocol: handshake_data.protocol,
+        })
+    }
+}
+
+/// An incoming connection whose client hello has been read, but whose handshake is not complete.
+pub struct PendingConnection {
+    hello: ClientHello,
+    connecting: Connecting,
+    shared: Arc<Shared>,
+    handshake: HandshakeGuard,
+}
+
+impl PendingConnection {
+    pub(crate) fn new(hello: ClientHello, connecting: Connecting, shared: Arc<Shared>, handshake: HandshakeGuard) -> Self {
+        Self { hello, connecting, shared, handshake }
+    }
+    /// Returns what the client asked for.
+    pub fn client_hello(&self) -> &ClientHello {
+        &self.hello
+    }
+    /// Completes the handshake and registers the connection with the socket.
+    ///
+    /// Returns `None` if the handshake fails.
+    pub async fn accept(self) -> Option<Arc<QuicConnection>> {
+        QuicSocket::complete(&self.shared, self.connecting, self.handshake).await.ok()
+    }
+    /// Aborts the handshake, closing the connection.
+    ///
+    /// QUIC does not carry application error codes before the handshake completes, so the client sees the
+    /// connection closed with the transport error `APPLICATION_ERROR`, possibly just after its side of the
+    /// handshake succeeded.
+    pub fn reject(self) {
+        tracing::debug!("Rejected connection from: {}", self.hello.remote_address);
+        self.shared.record_refused();
+    }
+}
+
+/// A boxed connection handler.
+type Handler = Arc<dyn Fn(Arc<QuicConnection>) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// A route matching a server name and/or ALPN protocol.
+struct Route {
+    server_name: Option<String>,
+    alpn_protocol: Option<Vec<u8>>,
+    handler: Handler,
+}
+
+/// Dispatches connections to handlers by server name and ALPN protocol.
+///
+/// Routes are matched in the order they were added. Server names are compared case-insensitively.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Handler>,
+}
+
+impl Router {
+    /// Creates a router without routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds a route. `None` matches any server name or ALPN protocol.
+    pub fn route<F, Fut>(mut self, server_name: Option<&str>, alpn_protocol: Option<&[u8]>, handler: F) -> Self
+    where
+        F: Fn(Arc<QuicConnection>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.routes.push(Route {
+            server_name: server_name.map(|name| name.to_ascii_lowercase()),
+            alpn_protocol: alpn_protocol.map(|alpn| alpn.to_vec()),
+            handler: boxed(handler),
+        });
+        self
+    }
+    /// Sets the handler for connections no route matches. Without one, they are rejected.
+    pub fn fallback<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Arc<QuicConnection>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.fallback = Some(boxed(handler));
+        self
+    }
+    /// Returns the handler for a client hello.
+    pub(crate) fn find(&self, hello: &ClientHello) -> Option<Handler> {
+        let server_name = hello.server_name.as_ref().map(|name| name.to_ascii_lowercase());
+        self.routes
+            .iter()
+            .find(|route| {
+                route.server_name.as_ref().is_none_or(|name| server_name.as_ref() == Some(name))
+                    && route.alpn_protocol.as_ref().is_none_or(|alpn| hello.alpn_protocol.as_ref() == Some(alpn))
+            })
+            .map(|route| Arc::clone(&route.handler))
+            .or_else(|| self.fallback.clone())
+    }
+}
+
+/// Boxes a connection handler.
+fn boxed<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(Arc<QuicConnection>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Arc::new(move |connection| Box::pin(handler(connection)))
+}
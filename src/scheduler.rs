@@ -0,0 +1,179 @@
+//! Outgoing message scheduler with priorities.
+//!
+//! Messages are enqueued with a priority class and drained by a writer task. Classes are served
+//! by weighted round-robin, so higher classes get most of the bandwidth without starving lower ones.
+//! Each message is sent on its own bi-directional stream whose quinn stream priority matches its
+//! class, so control traffic is also transmitted ahead of bulk data that is already in flight.
+//! The peer receives messages with `QuicConnection::accept_bi_stream` and `receive`.
+
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Notify, Semaphore};
+use crate::QuicConnection;
+
+/// The default maximum number of messages in flight at once.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+/// Priority class of an outgoing message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Control traffic. Served first.
+    Control,
+    /// Latency-sensitive traffic.
+    High,
+    /// Regular traffic.
+    Normal,
+    /// Bulk transfers. Served last.
+    Bulk,
+}
+
+impl Priority {
+    /// All classes, from highest to lowest.
+    const ALL: [Priority; 4] = [Priority::Control, Priority::High, Priority::Normal, Priority::Bulk];
+
+    /// The number of messages served from this class per round.
+    fn weight(self) -> usize {
+        match self {
+            Priority::Control => 8,
+            Priority::High => 4,
+            Priority::Normal => 2,
+            Priority::Bulk => 1,
+        }
+    }
+    /// The quinn stream priority used for messages of this class.
+    fn stream_priority(self) -> i32 {
+        match self {
+            Priority::Control => 3,
+            Priority::High => 2,
+            Priority::Normal => 1,
+            Priority::Bulk => 0,
+        }
+    }
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Queues and round-robin state.
+struct Queues {
+    queues: [VecDeque<Bytes>; 4],
+    class: usize,
+    credits: usize,
+}
+
+impl Queues {
+    /// Pops the next message according to the weighted round-robin order.
+    fn pop(&mut self) -> Option<(Priority, Bytes)> {
+        for _ in 0..Priority::ALL.len() * 2 {
+            let priority = Priority::ALL[self.class];
+            if self.credits > 0 {
+                if let Some(message) = self.queues[self.class].pop_front() {
+                    self.credits -= 1;
+                    return Some((priority, message));
+                }
+            }
+            self.class = (self.class + 1) % Priority::ALL.len();
+            self.credits = Priority::ALL[self.class].weight();
+        }
+        None
+    }
+}
+
+struct Inner {
+    queues: Mutex<Queues>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+/// A per-connection send queue drained by a writer task.
+pub struct SendScheduler {
+    inner: Arc<Inner>,
+}
+
+impl SendScheduler {
+    /// Creates a scheduler for the connection with the default in-flight limit.
+    pub fn new(connection: Arc<QuicConnection>) -> Self {
+        Self::with_max_in_flight(connection, DEFAULT_MAX_IN_FLIGHT)
+    }
+    /// Creates a scheduler for the connection, sending at most `max_in_flight` messages at once.
+    pub fn with_max_in_flight(connection: Arc<QuicConnection>, max_in_flight: usize) -> Self {
+        let inner = Arc::new(Inner {
+            queues: Mutex::new(Queues {
+                queues: Default::default(),
+                class: 0,
+                credits: Priority::Control.weight(),
+            }),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+        tokio::spawn(run_writer(Arc::clone(&inner), connection, max_in_flight));
+        Self { inner }
+    }
+    /// Enqueues a message with the given priority.
+    pub fn enqueue(&self, priority: Priority, message: Bytes) {
+        self.inner.queues.lock().unwrap().queues[priority.index()].push_back(message);
+        self.inner.notify.notify_one();
+    }
+    /// Returns the number of queued messages that have not been sent yet.
+    pub fn queued(&self) -> usize {
+        self.inner.queues.lock().unwrap().queues.iter().map(|q| q.len()).sum()
+    }
+    /// Returns the number of queued messages of the given priority.
+    pub fn queued_with_priority(&self, priority: Priority) -> usize {
+        self.inner.queues.lock().unwrap().queues[priority.index()].len()
+    }
+    /// Stops the writer task once all queued messages have been sent.
+    pub fn close(&self) {
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.notify.notify_one();
+    }
+}
+
+impl Drop for SendScheduler {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Drains the queues, sending each message on its own stream.
+async fn run_writer(inner: Arc<Inner>, connection: Arc<QuicConnection>, max_in_flight: usize) {
+    let in_flight = Arc::new(Semaphore::new(max_in_flight));
+    loop {
+        // The permit is taken before popping, so the message sent is picked by priority when a slot frees
+        // up, not when the previous message was popped.
+        let Ok(permit) = Arc::clone(&in_flight).acquire_owned().await else {
+            return;
+        };
+        let (priority, message) = loop {
+            let next = inner.queues.lock().unwrap().pop();
+            if let Some(next) = next {
+                break next;
+            }
+            if inner.closed.load(Ordering::Acquire) {
+                return;
+            }
+            inner.notify.notified().await;
+        };
+        let connection = Arc::clone(&connection);
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = send_message(&connection, priority, message).await {
+                tracing::debug!("Failed to send scheduled message: {}", e);
+            }
+        });
+    }
+}
+
+/// Sends a single message on a new stream with the class's stream priority.
+async fn send_message(connection: &QuicConnection, priority: Priority, message: Bytes) -> anyhow::Result<()> {
+    let (mut send_stream, _recv_stream) = connection.connection.open_bi().await?;
+    send_stream.set_priority(priority.stream_priority())?;
+    send_stream.write_chunk(message).await?;
+    send_stream.flush().await?;
+    send_stream.finish()?;
+    _ = send_stream.stopped().await;
+    Ok(())
+}
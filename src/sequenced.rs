@@ -0,0 +1,127 @@
+//! Sequenced unreliable channels over datagrams.
+//!
+//! `SequencedSender` numbers the datagrams it sends, and `SequencedReceiver` only delivers a datagram if it
+//! is newer than every datagram delivered before: duplicates and datagrams overtaken by newer ones are
+//! dropped, so the application always sees the latest state, in order. Each delivered datagram reports
+//! the number of datagrams skipped since the previous one, lost or dropped as stale.
+//!
+//! Each datagram carries an 8-byte sequence number. Both peers must use the channel on a connection, which
+//! should not carry other datagrams.
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use quinn::{Connection, SendDatagramError};
+use crate::error::Error;
+use crate::QuicConnection;
+
+/// The size of the sequence number at the start of each datagram.
+const SEQUENCE_SIZE: usize = 8;
+
+/// A datagram delivered by a `SequencedReceiver`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequencedDatagram {
+    /// The sequence number of the datagram, counting from 0.
+    pub sequence: u64,
+    /// The number of datagrams sent between the previously delivered one and this one that were not
+    /// delivered.
+    pub gap: u64,
+    /// The payload of the datagram.
+    pub payload: Bytes,
+}
+
+/// Counters of a `SequencedReceiver`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequencedStats {
+    /// Datagrams delivered.
+    pub delivered: u64,
+    /// Datagrams dropped as duplicates or older than a delivered one.
+    pub stale: u64,
+    /// Datagrams skipped, the sum of the gaps reported.
+    pub skipped: u64,
+}
+
+/// Sends numbered datagrams.
+pub struct SequencedSender {
+    connection: Connection,
+    next: u64,
+}
+
+impl SequencedSender {
+    /// Creates a sender on the datagrams of `connection`.
+    pub fn new(connection: &QuicConnection) -> Self {
+        Self {
+            connection: connection.connection.clone(),
+            next: 0,
+        }
+    }
+    /// Returns the largest payload that can be sent, or `None` if the peer does not accept datagrams.
+    pub fn max_payload_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size().map(|max| max.saturating_sub(SEQUENCE_SIZE))
+    }
+    /// Returns the sequence number of the next datagram.
+    pub fn next_sequence(&self) -> u64 {
+        self.next
+    }
+    /// Sends a datagram, returning its sequence number.
+    pub fn send(&mut self, payload: Bytes) -> Result<u64> {
+        let mut datagram = BytesMut::with_capacity(SEQUENCE_SIZE + payload.len());
+        datagram.put_u64(self.next);
+        datagram.put_slice(&payload);
+        match self.connection.send_datagram(datagram.freeze()) {
+            Ok(()) => {},
+            Err(SendDatagramError::ConnectionLost(e)) => return Err(Error::from(e).into()),
+            Err(e) => anyhow::bail!("failed to send datagram: {}", e),
+        }
+        let sequence = self.next;
+        self.next += 1;
+        Ok(sequence)
+    }
+}
+
+/// Receives numbered datagrams, newest wins.
+pub struct SequencedReceiver {
+    connection: Connection,
+    next: u64,
+    stats: SequencedStats,
+}
+
+impl SequencedReceiver {
+    /// Creates a receiver on the datagrams of `connection`.
+    pub fn new(connection: &QuicConnection) -> Self {
+        Self {
+            connection: connection.connection.clone(),
+            next: 0,
+            stats: SequencedStats::default(),
+        }
+    }
+    /// Returns the counters of the receiver.
+    pub fn stats(&self) -> SequencedStats {
+        self.stats
+    }
+    /// Receives the next datagram newer than every datagram received before.
+    pub async fn receive(&mut self) -> Result<SequencedDatagram> {
+        loop {
+            let datagram = self.connection.read_datagram().await.map_err(Error::from)?;
+            if let Some(datagram) = self.process(datagram) {
+                return Ok(datagram);
+            }
+        }
+    }
+    /// Records a received datagram, returning it unless it is malformed or stale.
+    fn process(&mut self, mut datagram: Bytes) -> Option<SequencedDatagram> {
+        if datagram.len() < SEQUENCE_SIZE {
+            tracing::debug!("Dropping datagram of {} bytes without sequence number", datagram.len());
+            return None;
+        }
+        let sequence = datagram.get_u64();
+        if sequence < self.next {
+            self.stats.stale += 1;
+            return None;
+        }
+        let gap = sequence - self.next;
+        self.next = sequence.saturating_add(1);
+        self.stats.delivered += 1;
+        self.stats.skipped += gap;
+        Some(SequencedDatagram { sequence, gap, payload: datagram })
+    }
+}
@@ -0,0 +1,82 @@
+//! `tower::Service` integration.
+//!
+//! Every framed message received on a connection's bi-directional streams is dispatched to a
+//! `tower::Service`, and the response is written back on the same stream as a framed message.
+//! This allows middleware from the tower ecosystem (timeouts, rate limiting, load shedding)
+//! to be layered on top of quicsock servers.
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::{Service, ServiceExt};
+use crate::{MessageStream, QuicConnection};
+
+/// A framed message received from a peer, dispatched to a service.
+#[derive(Debug, Clone)]
+pub struct MessageRequest {
+    /// The address of the peer that sent the message.
+    pub remote_address: SocketAddr,
+    /// The message payload.
+    pub body: Bytes,
+}
+
+/// Serves a connection with the given service.
+///
+/// Accepts bi-directional streams until the connection is closed, spawning a task per stream.
+/// Messages on a stream are handled in order; if the service fails, the stream is finished
+/// and the error is logged.
+pub async fn serve_connection<S>(connection: Arc<QuicConnection>, service: S) -> Result<()>
+where
+    S: Service<MessageRequest, Response = Bytes> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<Box<dyn Error + Send + Sync>> + Send,
+{
+    let remote_address = connection.connection.remote_address();
+    loop {
+        let stream = match connection.accept_message_stream().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::debug!("stopped accepting streams from {}: {}", remote_address, e);
+                return Ok(());
+            },
+        };
+        tokio::spawn(serve_stream(stream, remote_address, service.clone()));
+    }
+}
+
+/// Serves a single message stream with the given service.
+pub async fn serve_stream<S>(mut stream: MessageStream, remote_address: SocketAddr, mut service: S)
+where
+    S: Service<MessageRequest, Response = Bytes>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    while let Some(message) = stream.next().await {
+        let body = match message {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::debug!("failed to read message from {}: {}", remote_address, e);
+                return;
+            },
+        };
+        let response: Result<Bytes, Box<dyn Error + Send + Sync>> = match service.ready().await {
+            Ok(service) => service.call(MessageRequest { remote_address, body }).await.map_err(Into::into),
+            Err(e) => Err(e.into()),
+        };
+        match response {
+            Ok(response) => {
+                if let Err(e) = stream.send(response).await {
+                    tracing::debug!("failed to send response to {}: {}", remote_address, e);
+                    return;
+                }
+            },
+            Err(e) => {
+                tracing::warn!("service error for {}: {}", remote_address, e);
+                break;
+            },
+        }
+    }
+    let _ = stream.close().await;
+}
@@ -0,0 +1,237 @@
+//! A persistent file server sharing files and directories under tokens.
+//!
+//! Each share gets a token made of a public share id followed by a pairing code, such as
+//! `7310-4821-0937-5512`. A receiver calls `download` with the token: the share id is sent to select the
+//! share, and the pairing code is only used in a PAKE exchange (see `pairing`), so it never crosses the wire.
+//!
+//! The server handles any number of receivers concurrently, and a connection may download several shares
+//! one after another. Shares can expire, and every download is logged and counted in `ShareStats`.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use quinn::Incoming;
+use crate::error::Error;
+use crate::pairing;
+use crate::socket::ServeOptions;
+use crate::transfer::{self, read_frame, write_frame, Filter, TransferOptions, TransferSummary};
+use crate::{QuicConnection, QuicSocket};
+
+/// Download counters of a share.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShareStats {
+    /// The number of completed downloads.
+    pub downloads: u64,
+    /// The number of failed downloads, including pairing failures.
+    pub failures: u64,
+    /// The number of file bytes served by completed downloads.
+    pub bytes: u64,
+}
+
+/// Options for a share.
+#[derive(Debug, Clone, Default)]
+pub struct ShareOptions {
+    /// How long the share is available. `None` means until it is revoked.
+    pub ttl: Option<Duration>,
+    /// The filter applied when sharing a directory.
+    pub filter: Filter,
+    /// The options of each transfer, such as a rate limit.
+    pub transfer: TransferOptions,
+}
+
+/// A registered share.
+struct Share {
+    path: PathBuf,
+    code: String,
+    expires_at: Option<Instant>,
+    options: ShareOptions,
+    stats: ShareStats,
+}
+
+impl Share {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| Instant::now() >= expires_at)
+    }
+}
+
+/// The request selecting a share, sent by the receiver.
+#[derive(Debug, Serialize, Deserialize)]
+struct ShareRequest {
+    id: String,
+}
+
+/// The server's response to a `ShareRequest`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ShareResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A file server sharing files and directories under tokens.
+#[derive(Default)]
+pub struct ShareServer {
+    shares: Mutex<HashMap<String, Share>>,
+}
+
+impl ShareServer {
+    /// Creates a server with no shares.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Shares a file or directory, returning the token receivers download it with.
+    pub fn register(&self, path: &Path, options: ShareOptions) -> Result<String> {
+        if !path.exists() {
+            anyhow::bail!("no such file or directory: {}", path.display());
+        }
+        let code = pairing::generate_code()?;
+        let mut shares = self.shares.lock().unwrap();
+        let id = loop {
+            let id = pairing::generate_code()?.split('-').next().unwrap_or_default().to_string();
+            if !shares.contains_key(&id) {
+                break id;
+            }
+        };
+        let token = format!("{}-{}", id, code);
+        shares.insert(id, Share {
+            path: path.to_path_buf(),
+            code,
+            expires_at: options.ttl.map(|ttl| Instant::now() + ttl),
+            options,
+            stats: ShareStats::default(),
+        });
+        Ok(token)
+    }
+    /// Stops sharing the file or directory registered under `token`. Returns whether it was shared.
+    ///
+    /// Downloads in progress are not interrupted.
+    pub fn revoke(&self, token: &str) -> bool {
+        let Some((id, _)) = token.split_once('-') else { return false };
+        self.shares.lock().unwrap().remove(id).is_some()
+    }
+    /// Removes the expired shares, returning how many were removed.
+    ///
+    /// Expired shares can no longer be downloaded either way, this only frees them.
+    pub fn remove_expired(&self) -> usize {
+        let mut shares = self.shares.lock().unwrap();
+        let before = shares.len();
+        shares.retain(|_, share| !share.is_expired());
+        before - shares.len()
+    }
+    /// Returns the download counters of the share registered under `token`.
+    pub fn stats(&self, token: &str) -> Option<ShareStats> {
+        let (id, _) = token.split_once('-')?;
+        self.shares.lock().unwrap().get(id).map(|share| share.stats)
+    }
+    /// Returns the number of shares, including expired ones not removed yet.
+    pub fn len(&self) -> usize {
+        self.shares.lock().unwrap().len()
+    }
+    /// Returns whether there are no shares.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Serves downloads to incoming connections until the socket shuts down.
+    ///
+    /// Expired shares are removed periodically while serving.
+    pub async fn serve(self: &Arc<Self>, socket: &QuicSocket, incoming: &mut mpsc::Receiver<Incoming>, options: ServeOptions) {
+        let server = Arc::downgrade(self);
+        let sweeper = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let Some(server) = server.upgrade() else { break };
+                let removed = server.remove_expired();
+                if removed > 0 {
+                    tracing::debug!("Removed {} expired shares", removed);
+                }
+            }
+        });
+        let server = Arc::clone(self);
+        socket.serve_with_options(incoming, options, move |connection| {
+            let server = Arc::clone(&server);
+            async move { server.handle(&connection).await }
+        }).await;
+        sweeper.abort();
+    }
+    /// Handles the download requests of a connection until it closes.
+    async fn handle(&self, connection: &QuicConnection) -> Result<()> {
+        let remote_addr = connection.connection.remote_address();
+        loop {
+            let (mut send, mut recv) = match connection.connection.accept_bi().await {
+                Ok(stream) => stream,
+                Err(quinn::ConnectionError::ApplicationClosed(_)) | Err(quinn::ConnectionError::LocallyClosed) => return Ok(()),
+                Err(e) => return Err(Error::from(e).into()),
+            };
+            let request: ShareRequest = serde_json::from_slice(&read_frame(&mut recv).await?)?;
+            let share = {
+                let shares = self.shares.lock().unwrap();
+                match shares.get(&request.id) {
+                    Some(share) if !share.is_expired() => Ok((share.path.clone(), share.code.clone(), share.options.clone())),
+                    Some(_) => Err("share expired"),
+                    None => Err("no such share"),
+                }
+            };
+            let (path, code, options) = match share {
+                Ok(share) => share,
+                Err(error) => {
+                    tracing::info!("Download of share {} by {} refused: {}", request.id, remote_addr, error);
+                    write_frame(&mut send, &serde_json::to_vec(&ShareResponse { error: Some(error.to_string()) })?).await?;
+                    send.finish().map_err(Error::from)?;
+                    continue;
+                },
+            };
+            write_frame(&mut send, &serde_json::to_vec(&ShareResponse { error: None })?).await?;
+            send.finish().map_err(Error::from)?;
+            let started = Instant::now();
+            let result = async {
+                pairing::respond(connection, &code).await?;
+                if tokio::fs::metadata(&path).await?.is_dir() {
+                    transfer::send_directory_with_options(connection, &path, &options.filter, &options.transfer).await
+                } else {
+                    transfer::send_file_with_options(connection, &path, &options.transfer).await
+                }
+            }.await;
+            let elapsed = started.elapsed();
+            let mut shares = self.shares.lock().unwrap();
+            let stats = shares.get_mut(&request.id).map(|share| &mut share.stats);
+            match result {
+                Ok(summary) => {
+                    tracing::info!(
+                        "Share {} downloaded by {}: {} files, {} bytes in {:?} ({:.0} bytes/s)",
+                        request.id, remote_addr, summary.files, summary.bytes, elapsed, summary.bytes as f64 / elapsed.as_secs_f64(),
+                    );
+                    if let Some(stats) = stats {
+                        stats.downloads += 1;
+                        stats.bytes += summary.bytes;
+                    }
+                },
+                Err(e) => {
+                    tracing::warn!("Download of share {} by {} failed after {:?}: {}", request.id, remote_addr, elapsed, e);
+                    if let Some(stats) = stats {
+                        stats.failures += 1;
+                    }
+                    // The connection is in an unknown state after a failure.
+                    return Err(e);
+                },
+            }
+        }
+    }
+}
+
+/// Downloads the share registered under `token` from a `ShareServer`, saving it under `destination`.
+pub async fn download(connection: &QuicConnection, token: &str, destination: &Path) -> Result<TransferSummary> {
+    let (id, code) = token.split_once('-').ok_or_else(|| anyhow::anyhow!("invalid share token"))?;
+    let (mut send, mut recv) = connection.connection.open_bi().await.map_err(Error::from)?;
+    write_frame(&mut send, &serde_json::to_vec(&ShareRequest { id: id.to_string() })?).await?;
+    send.finish().map_err(Error::from)?;
+    let response: ShareResponse = serde_json::from_slice(&read_frame(&mut recv).await?)?;
+    if let Some(error) = response.error {
+        anyhow::bail!("download refused: {}", error);
+    }
+    pairing::initiate(connection, code).await?;
+    transfer::receive(connection, destination).await
+}
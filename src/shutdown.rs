@@ -0,0 +1,36 @@
+//! Graceful shutdown.
+//!
+//! `QuicSocket::shutdown_on` stops accepting, notifies handlers through `QuicSocket::shutdown_token`,
+//! drains connections and waits for the endpoint to become idle once a future such as `signal()` resolves.
+
+use std::time::Duration;
+
+pub use crate::codes::SHUTDOWN_CODE;
+
+/// The default time connections are given to close by themselves during a shutdown.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Resolves when the process receives Ctrl-C, or SIGTERM on Unix.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+            },
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+    tracing::info!("Shutdown signal received");
+}
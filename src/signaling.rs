@@ -0,0 +1,307 @@
+//! Signaling protocol for hole-punch coordination.
+//!
+//! Peers keep a connection to a rendezvous server and register their candidate addresses.
+//! When a peer asks to connect to another, the server sends both peers a `Punch` message with
+//! the other's candidates and a delay, timed so that both sides dial each other simultaneously.
+//! Messages are JSON-encoded frames on a message stream.
+//!
+//! A peer ID belongs to the first live connection registering it, until that connection closes. The
+//! server does not authenticate peers, so IDs should be hard to guess where that matters.
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use quinn::Incoming;
+use crate::balance::{LoadBalancer, Strategy};
+use crate::{MessageStream, QuicConnection, QuicSocket};
+
+/// The margin added to the dial delay computed by the rendezvous server.
+pub const DEFAULT_PUNCH_MARGIN: Duration = Duration::from_millis(50);
+/// The default time a hole punch may take after the coordinated delay.
+pub const DEFAULT_PUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A signaling message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignalMessage {
+    /// Registers the sending peer under an ID with its candidate addresses.
+    Register { peer_id: String, candidates: Vec<SocketAddr> },
+    /// Asks the server to coordinate a hole punch with another peer.
+    Connect { peer_id: String },
+    /// Instructs a peer to dial the candidates of another peer after the given delay.
+    Punch { peer_id: String, candidates: Vec<SocketAddr>, delay_ms: u64 },
+    /// Reports a failed request.
+    Error { message: String },
+}
+
+impl SignalMessage {
+    /// Encodes the message as a frame payload.
+    pub fn encode(&self) -> Result<Bytes> {
+        Ok(Bytes::from(serde_json::to_vec(self)?))
+    }
+    /// Decodes a message from a frame payload.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// A peer registered with the rendezvous server.
+struct RegisteredPeer {
+    connection: Arc<QuicConnection>,
+    candidates: Vec<SocketAddr>,
+    tx: mpsc::Sender<SignalMessage>,
+}
+
+/// Rendezvous server coordinating hole punches between registered peers.
+pub struct RendezvousServer {
+    peers: Mutex<HashMap<String, RegisteredPeer>>,
+    margin: Duration,
+}
+
+impl RendezvousServer {
+    /// Creates a new rendezvous server.
+    pub fn new() -> Arc<Self> {
+        Self::with_margin(DEFAULT_PUNCH_MARGIN)
+    }
+    /// Creates a new rendezvous server with the given dial delay margin.
+    pub fn with_margin(margin: Duration) -> Arc<Self> {
+        Arc::new(Self { peers: Mutex::new(HashMap::new()), margin })
+    }
+    /// Handles a peer connection until it closes.
+    ///
+    /// The peer's observed address is added to its candidates on registration. Registering an ID held by
+    /// another live connection is refused with an `Error` message.
+    /// Can be used directly as a `QuicSocket::serve` handler.
+    pub async fn handle_connection(self: Arc<Self>, connection: Arc<QuicConnection>) -> Result<()> {
+        let stream = connection.accept_message_stream().await?;
+        let (mut sink, mut stream) = stream.split();
+        let (tx, mut rx) = mpsc::channel::<SignalMessage>(16);
+        let writer = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let frame = message.encode()?;
+                sink.send(frame).await?;
+            }
+            anyhow::Ok(())
+        });
+        let mut registered: Option<String> = None;
+        while let Some(frame) = stream.next().await {
+            let message = match SignalMessage::decode(&frame?) {
+                Ok(message) => message,
+                Err(e) => {
+                    let _ = tx.send(SignalMessage::Error { message: e.to_string() }).await;
+                    continue;
+                },
+            };
+            match message {
+                SignalMessage::Register { peer_id, mut candidates } => {
+                    let observed = connection.connection.remote_address();
+                    if !candidates.contains(&observed) {
+                        candidates.insert(0, observed);
+                    }
+                    let mut peers = self.peers.lock().await;
+                    let peer = RegisteredPeer { connection: Arc::clone(&connection), candidates, tx: tx.clone() };
+                    match peers.entry(peer_id.clone()) {
+                        Entry::Occupied(entry) if !Arc::ptr_eq(&entry.get().connection, &connection) && entry.get().connection.connection.close_reason().is_none() => {
+                            drop(peers);
+                            tracing::debug!("Refused to register peer {} held by another connection", peer_id);
+                            let _ = tx.send(SignalMessage::Error { message: format!("peer id already registered: {}", peer_id) }).await;
+                            continue;
+                        },
+                        // Replaces this connection's registration, or one whose connection closed.
+                        Entry::Occupied(mut entry) => {
+                            entry.insert(peer);
+                        },
+                        Entry::Vacant(entry) => {
+                            entry.insert(peer);
+                        },
+                    }
+                    tracing::debug!("Registered peer {} with candidates {:?}", peer_id, peers[&peer_id].candidates);
+                    // A connection holds a single ID, so registering another one releases the previous.
+                    if let Some(previous) = registered.take().filter(|previous| *previous != peer_id) {
+                        peers.remove(&previous);
+                    }
+                    registered = Some(peer_id);
+                },
+                SignalMessage::Connect { peer_id } => {
+                    let Some(own_id) = registered.clone() else {
+                        let _ = tx.send(SignalMessage::Error { message: "not registered".to_string() }).await;
+                        continue;
+                    };
+                    if let Err(e) = self.coordinate(&own_id, &peer_id).await {
+                        let _ = tx.send(SignalMessage::Error { message: e.to_string() }).await;
+                    }
+                },
+                _ => {
+                    let _ = tx.send(SignalMessage::Error { message: "unexpected message".to_string() }).await;
+                },
+            }
+        }
+        if let Some(peer_id) = registered {
+            let mut peers = self.peers.lock().await;
+            if peers.get(&peer_id).is_some_and(|p| Arc::ptr_eq(&p.connection, &connection)) {
+                peers.remove(&peer_id);
+            }
+        }
+        drop(tx);
+        let _ = writer.await;
+        Ok(())
+    }
+    /// Sends `Punch` messages to both peers, timed to make them dial at the same instant.
+    async fn coordinate(&self, a: &str, b: &str) -> Result<()> {
+        let (a_peer, b_peer) = {
+            let peers = self.peers.lock().await;
+            let a_peer = peers.get(a).ok_or_else(|| anyhow::anyhow!("unknown peer: {}", a))?;
+            let b_peer = peers.get(b).ok_or_else(|| anyhow::anyhow!("unknown peer: {}", b))?;
+            (
+                (a_peer.connection.connection.rtt(), a_peer.candidates.clone(), a_peer.tx.clone()),
+                (b_peer.connection.connection.rtt(), b_peer.candidates.clone(), b_peer.tx.clone()),
+            )
+        };
+        // Both messages are sent now and arrive after half of each peer's RTT,
+        // so each peer waits for the remainder of a common deadline.
+        let deadline = a_peer.0.max(b_peer.0) / 2 + self.margin;
+        let a_delay = deadline.saturating_sub(a_peer.0 / 2);
+        let b_delay = deadline.saturating_sub(b_peer.0 / 2);
+        a_peer.2.send(SignalMessage::Punch { peer_id: b.to_string(), candidates: b_peer.1, delay_ms: a_delay.as_millis() as u64 }).await?;
+        b_peer.2.send(SignalMessage::Punch { peer_id: a.to_string(), candidates: a_peer.1, delay_ms: b_delay.as_millis() as u64 }).await?;
+        tracing::debug!("Coordinated hole punch between {} and {}", a, b);
+        Ok(())
+    }
+}
+
+/// Client side of the signaling protocol.
+pub struct SignalingClient {
+    stream: MessageStream,
+}
+
+impl SignalingClient {
+    /// Registers with the rendezvous server over an existing connection.
+    ///
+    /// The server adds the address it observes for this connection to the candidates.
+    pub async fn register(connection: &QuicConnection, peer_id: &str, candidates: Vec<SocketAddr>) -> Result<Self> {
+        let mut stream = connection.open_message_stream().await?;
+        stream.send(SignalMessage::Register { peer_id: peer_id.to_string(), candidates }.encode()?).await?;
+        Ok(Self { stream })
+    }
+    /// Asks the server to coordinate a hole punch with another peer.
+    ///
+    /// Both peers subsequently receive a `Punch` message from `next_message`.
+    pub async fn request_connect(&mut self, peer_id: &str) -> Result<()> {
+        self.stream.send(SignalMessage::Connect { peer_id: peer_id.to_string() }.encode()?).await
+    }
+    /// Waits for the next message from the server.
+    ///
+    /// Returns `None` when the server closes the signaling stream.
+    pub async fn next_message(&mut self) -> Result<Option<SignalMessage>> {
+        match self.stream.next().await {
+            Some(frame) => Ok(Some(SignalMessage::decode(&frame?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Performs a hole punch described by a `Punch` message.
+///
+/// Waits for the coordinated delay and then dials all candidates concurrently from the given
+/// socket, while also accepting the other peer's simultaneous dial from `incoming`; whichever
+/// connection is established first is returned. The socket should be a peer socket
+/// (see `QuicSocket::new_peer`) so that the dial and the registration with the rendezvous server
+/// share the same NAT mapping.
+///
+/// Incoming connections from addresses other than the candidates' IPs are ignored while the
+/// hole punch is in progress; their peers will retransmit and can be accepted afterwards.
+///
+/// If the dial fails, e.g. because the outgoing packets were dropped before the peer's NAT opened, the
+/// peer's dial can still get through, so it is awaited until `DEFAULT_PUNCH_TIMEOUT` passes.
+pub async fn hole_punch(socket: &QuicSocket, incoming: &mut mpsc::Receiver<Incoming>, punch: &SignalMessage, server_name: &str) -> Result<Arc<QuicConnection>> {
+    hole_punch_with_timeout(socket, incoming, punch, server_name, DEFAULT_PUNCH_TIMEOUT).await
+}
+
+/// Performs a hole punch like `hole_punch`, failing once `timeout` passes after the coordinated delay.
+pub async fn hole_punch_with_timeout(socket: &QuicSocket, incoming: &mut mpsc::Receiver<Incoming>, punch: &SignalMessage, server_name: &str, timeout: Duration) -> Result<Arc<QuicConnection>> {
+    let SignalMessage::Punch { candidates, delay_ms, .. } = punch else {
+        anyhow::bail!("not a punch message");
+    };
+    tokio::time::sleep(Duration::from_millis(*delay_ms)).await;
+    let balancer = LoadBalancer::new(candidates.clone(), Strategy::LowestRtt);
+    let accept = async {
+        while let Some(connecting) = incoming.recv().await {
+            if !candidates.iter().any(|c| c.ip() == connecting.remote_address().ip()) {
+                connecting.ignore();
+                continue;
+            }
+            match socket.accept_incoming(connecting).await {
+                Some(connection) => return Ok(connection),
+                None => continue,
+            }
+        }
+        Err(anyhow::anyhow!("incoming connection receiver closed"))
+    };
+    let dial = socket.connect_balanced(&balancer, server_name);
+    tokio::pin!(accept, dial);
+    // Whichever side fails first, the other one may still succeed.
+    let punched = async {
+        tokio::select! {
+            res = &mut dial => match res {
+                Ok(connection) => Ok(connection),
+                Err(e) => {
+                    tracing::debug!("Hole punch dial failed, waiting for the peer's dial: {}", e);
+                    accept.await
+                },
+            },
+            res = &mut accept => match res {
+                Ok(connection) => Ok(connection),
+                Err(e) => {
+                    tracing::debug!("Hole punch accept failed, waiting for the dial: {}", e);
+                    dial.await
+                },
+            },
+        }
+    };
+    tokio::time::timeout(timeout, punched).await.map_err(|_| anyhow::anyhow!("hole punch timed out after {:?}", timeout))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Loopback, LOOPBACK_SERVER_NAME};
+
+    #[tokio::test]
+    async fn refuses_peer_id_held_by_live_connection() {
+        let Loopback { server, mut incoming, client, .. } = Loopback::new().await.unwrap();
+        let server_addr = server.endpoint().local_addr().unwrap();
+        let rendezvous = RendezvousServer::new();
+        let handler = Arc::clone(&rendezvous);
+        tokio::spawn(async move { server.serve(&mut incoming, move |connection| Arc::clone(&handler).handle_connection(connection)).await });
+
+        let holder = client.connect(server_addr, LOOPBACK_SERVER_NAME).await.unwrap();
+        let _holder_client = SignalingClient::register(&holder, "peer", Vec::new()).await.unwrap();
+        let intruder = client.connect(server_addr, LOOPBACK_SERVER_NAME).await.unwrap();
+        let mut intruder_client = SignalingClient::register(&intruder, "peer", Vec::new()).await.unwrap();
+        assert_eq!(intruder_client.next_message().await.unwrap(), Some(SignalMessage::Error { message: "peer id already registered: peer".to_string() }));
+
+        // Once the holder's connection closes, the ID can be registered again.
+        holder.connection.close(0u32.into(), b"done");
+        while rendezvous.peers.lock().await.get("peer").is_some_and(|peer| peer.connection.connection.close_reason().is_none()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let successor = client.connect(server_addr, LOOPBACK_SERVER_NAME).await.unwrap();
+        let mut successor_client = SignalingClient::register(&successor, "peer", Vec::new()).await.unwrap();
+        let other = client.connect(server_addr, LOOPBACK_SERVER_NAME).await.unwrap();
+        let mut other_client = SignalingClient::register(&other, "other", Vec::new()).await.unwrap();
+        let registered = |peers: &HashMap<String, RegisteredPeer>| ["peer", "other"].iter().all(|id| peers.get(*id).is_some_and(|peer| peer.connection.connection.close_reason().is_none()));
+        while !registered(&*rendezvous.peers.lock().await) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        other_client.request_connect("peer").await.unwrap();
+        assert!(matches!(successor_client.next_message().await.unwrap(), Some(SignalMessage::Punch { peer_id, .. }) if peer_id == "other"));
+        assert!(matches!(other_client.next_message().await.unwrap(), Some(SignalMessage::Punch { peer_id, .. }) if peer_id == "peer"));
+    }
+}
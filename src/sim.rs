@@ -0,0 +1,235 @@
+//! Network condition simulation for tests.
+//!
+//! `NetworkConditions` set on a `SocketConfig` with `with_network_conditions` make the socket's endpoint
+//! delay, drop and reorder the UDP datagrams it sends. Only outgoing datagrams are affected, so set
+//! conditions on both sides to impair both directions. Decisions are drawn from a seeded generator,
+//! so the same seed and traffic give the same drops and delays.
+
+use quinn::udp::{EcnCodepoint, RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, UdpPoller};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::io::{self, IoSliceMut};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// The default extra delay of reordered datagrams.
+pub const DEFAULT_REORDER_DELAY: Duration = Duration::from_millis(10);
+
+/// Impairments applied to outgoing datagrams.
+#[derive(Debug, Clone)]
+pub struct NetworkConditions {
+    /// The delay added to every datagram.
+    pub latency: Duration,
+    /// The maximum random delay added on top of `latency`.
+    pub jitter: Duration,
+    /// The probability that a datagram is dropped, between 0 and 1.
+    pub loss: f64,
+    /// The probability that a datagram is held back by `reorder_delay`, letting later ones overtake it.
+    pub reorder: f64,
+    /// The extra delay of reordered datagrams.
+    pub reorder_delay: Duration,
+    /// The seed of the random generator.
+    pub seed: u64,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            reorder: 0.0,
+            reorder_delay: DEFAULT_REORDER_DELAY,
+            seed: 0,
+        }
+    }
+}
+
+impl NetworkConditions {
+    /// Returns whether datagrams may be delayed, as opposed to only dropped.
+    fn delays(&self) -> bool {
+        !self.latency.is_zero() || !self.jitter.is_zero() || self.reorder > 0.0
+    }
+}
+
+/// A small deterministic random generator (SplitMix64).
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    /// Returns a number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A datagram waiting for its delay to elapse.
+struct Delayed {
+    due: Instant,
+    sequence: u64,
+    destination: SocketAddr,
+    ecn: Option<EcnCodepoint>,
+    src_ip: Option<IpAddr>,
+    contents: Vec<u8>,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.sequence).cmp(&(other.due, other.sequence))
+    }
+}
+
+/// A UDP socket applying `NetworkConditions` to the datagrams sent through it.
+pub(crate) struct SimulatedSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    conditions: NetworkConditions,
+    state: Mutex<(Rng, u64)>,
+    queue: mpsc::UnboundedSender<Delayed>,
+}
+
+impl SimulatedSocket {
+    /// Wraps a socket. Delayed datagrams are sent from a background task.
+    pub(crate) fn new(inner: Arc<dyn AsyncUdpSocket>, conditions: NetworkConditions) -> Arc<Self> {
+        let (queue, rx) = mpsc::unbounded_channel();
+        if conditions.delays() {
+            tokio::spawn(send_delayed(Arc::clone(&inner), rx));
+        }
+        let rng = Rng(conditions.seed);
+        Arc::new(Self { inner, conditions, state: Mutex::new((rng, 0)), queue })
+    }
+}
+
+impl fmt::Debug for SimulatedSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimulatedSocket").field("inner", &self.inner).field("conditions", &self.conditions).finish()
+    }
+}
+
+impl AsyncUdpSocket for SimulatedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Arc::clone(&self.inner).create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let delay = {
+            let mut state = self.state.lock().unwrap();
+            let (rng, sequence) = &mut *state;
+            if rng.next_f64() < self.conditions.loss {
+                return Ok(());
+            }
+            if !self.conditions.delays() {
+                None
+            } else {
+                let mut delay = self.conditions.latency + self.conditions.jitter.mul_f64(rng.next_f64());
+                if rng.next_f64() < self.conditions.reorder {
+                    delay += self.conditions.reorder_delay;
+                }
+                *sequence += 1;
+                Some((delay, *sequence))
+            }
+        };
+        let Some((delay, sequence)) = delay else {
+            return self.inner.try_send(transmit);
+        };
+        let _ = self.queue.send(Delayed {
+            due: Instant::now() + delay,
+            sequence,
+            destination: transmit.destination,
+            ecn: transmit.ecn,
+            src_ip: transmit.src_ip,
+            contents: transmit.contents.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Sends queued datagrams once they are due, until the socket is dropped and the queue is empty.
+async fn send_delayed(inner: Arc<dyn AsyncUdpSocket>, mut rx: mpsc::UnboundedReceiver<Delayed>) {
+    let mut poller = Arc::clone(&inner).create_io_poller();
+    let mut pending = BinaryHeap::new();
+    let mut closed = false;
+    loop {
+        if closed && pending.is_empty() {
+            return;
+        }
+        let due = pending.peek().map(|Reverse(delayed): &Reverse<Delayed>| delayed.due).unwrap_or_else(Instant::now);
+        tokio::select! {
+            delayed = rx.recv(), if !closed => match delayed {
+                Some(delayed) => pending.push(Reverse(delayed)),
+                None => closed = true,
+            },
+            _ = tokio::time::sleep_until(due), if !pending.is_empty() => {
+                let Reverse(delayed) = pending.pop().unwrap();
+                send(&*inner, &mut poller, &delayed).await;
+            },
+        }
+    }
+}
+
+/// Sends a datagram, waiting for the socket to become writable if needed.
+async fn send(inner: &dyn AsyncUdpSocket, poller: &mut Pin<Box<dyn UdpPoller>>, delayed: &Delayed) {
+    let transmit = Transmit {
+        destination: delayed.destination,
+        ecn: delayed.ecn,
+        contents: &delayed.contents,
+        segment_size: None,
+        src_ip: delayed.src_ip,
+    };
+    loop {
+        match inner.try_send(&transmit) {
+            Ok(()) => return,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if std::future::poll_fn(|cx| poller.as_mut().poll_writable(cx)).await.is_err() {
+                    return;
+                }
+            },
+            Err(e) => {
+                tracing::debug!("Failed to send simulated datagram to {}: {}", delayed.destination, e);
+                return;
+            },
+        }
+    }
+}
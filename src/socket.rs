@@ -1,18 +1,255 @@
 //! QUIC socket. The main entry point for sending and receiving data over QUIC.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::{error::Error, path::Path};
-use quinn::{Endpoint, Incoming};
+use quinn::{Connecting, Endpoint, Incoming, ServerConfig, ZeroRttAccepted};
 use std::net::SocketAddr;
-use tokio::sync::{mpsc, Mutex};
-use crate::{connection::QuicConnection, endpoint::{make_client_endpoint, make_native_client_endpoint, make_insecure_client_endpoint, make_server_endpoint, make_self_signed_server_endpoint}};
-use std::collections::HashMap;
-use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use crate::audit::{AuditLog, Direction};
+use crate::balance::{LoadBalancer, Strategy};
+use crate::budget::ReceiveBudget;
+use crate::close::CloseReason;
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::{connection::QuicConnection, endpoint::{make_client_endpoint, make_insecure_client_endpoint, make_server_endpoint, make_self_signed_server_endpoint, make_endpoint_with_config, configure_server_with, EndpointConfigs}};
+#[cfg(feature = "native-certs")]
+use crate::endpoint::make_native_client_endpoint;
+use crate::config::SocketConfig;
+use crate::incoming::IncomingConnection;
+use crate::routing::{ClientHello, PendingConnection, Router};
+use crate::event::{SocketEvent, EVENT_CHANNEL_CAPACITY};
+use crate::flood::{FloodGuard, FloodProtection, HandshakeGuard, Rejection};
+use crate::liveness::LivenessThresholds;
+use crate::quota::StreamQuota;
+use crate::transport::TransportOptions;
+use crate::version::QuicVersion;
+use crate::zerortt::{ZeroRttGuard, ZeroRttPolicy};
+use crate::ratelimit::{ConnectionRateLimit, ConnectionRateLimiter};
+use crate::registry::ShardedMap;
+use crate::stats::{EndpointCounters, EndpointStats, IoCounters, IoStats};
+use crate::shutdown::SHUTDOWN_CODE;
+use crate::telemetry;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+use std::future::Future;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use tokio::sync::Semaphore;
+use tokio::task::AbortHandle;
+
+/// The default maximum number of connections served concurrently by `QuicSocket::serve`.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Options for `QuicSocket::serve_with_options`.
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// The maximum number of connections handled concurrently.
+    /// Incoming connections beyond this limit are refused.
+    pub max_connections: usize,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self { max_connections: DEFAULT_MAX_CONNECTIONS }
+    }
+}
+
+/// How the registry of a socket holds its connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RegistryMode {
+    /// The registry keeps connections alive until they close, so they can be found with
+    /// `QuicSocket::connections` even after the application dropped its handles.
+    #[default]
+    Strong,
+    /// The registry only refers to connections. A connection is closed with `connection::DROPPED_CODE`
+    /// and freed once the application drops its last handle, so a long-lived server only keeps the
+    /// connections it still uses.
+    Weak,
+}
+
+/// A connection held by the registry.
+#[derive(Clone)]
+enum Registered {
+    Strong(Arc<QuicConnection>),
+    Weak(Weak<QuicConnection>),
+}
+
+impl Registered {
+    fn new(connection: &Arc<QuicConnection>, mode: RegistryMode) -> Self {
+        match mode {
+            RegistryMode::Strong => Self::Strong(Arc::clone(connection)),
+            RegistryMode::Weak => Self::Weak(Arc::downgrade(connection)),
+        }
+    }
+    /// Returns the connection, unless it was dropped.
+    fn get(&self) -> Option<Arc<QuicConnection>> {
+        match self {
+            Self::Strong(connection) => Some(Arc::clone(connection)),
+            Self::Weak(connection) => connection.upgrade(),
+        }
+    }
+    fn is(&self, connection: *const QuicConnection) -> bool {
+        match self {
+            Self::Strong(registered) => std::ptr::eq(Arc::as_ptr(registered), connection),
+            Self::Weak(registered) => std::ptr::eq(registered.as_ptr(), connection),
+        }
+    }
+}
 
 /// A QUIC socket that can be used to send and receive data.
 pub struct QuicSocket {
     endpoint: Endpoint,
-    connections: Arc<Mutex<HashMap<SocketAddr, Arc<QuicConnection>>>>,
+    shared: Arc<Shared>,
+    /// The task accepting incoming connections, for servers.
+    accept_task: Option<AbortHandle>,
+}
+
+/// State shared between a socket and its background tasks.
+pub(crate) struct Shared {
+    /// Open connections by remote address. There can be several connections to the same address.
+    connections: ShardedMap<SocketAddr, Vec<Registered>>,
+    registry_mode: std::sync::Mutex<RegistryMode>,
+    /// Open connections by tag.
+    tags: ShardedMap<String, Vec<Weak<QuicConnection>>>,
+    /// Locks serializing `connect_or_reuse` calls per address, present while a call is in progress.
+    connect_locks: ShardedMap<SocketAddr, Arc<tokio::sync::Mutex<()>>>,
+    report_observed_address: AtomicBool,
+    accept_paused: AtomicBool,
+    stream_quota: std::sync::Mutex<Option<StreamQuota>>,
+    idle_stream_timeout: std::sync::Mutex<Option<Duration>>,
+    path_check_interval: std::sync::Mutex<Option<Duration>>,
+    liveness_thresholds: std::sync::Mutex<Option<LivenessThresholds>>,
+    handshake_timeout: std::sync::Mutex<Option<Duration>>,
+    connection_rate_limiter: std::sync::Mutex<Option<ConnectionRateLimiter>>,
+    flood_guard: std::sync::Mutex<Option<FloodGuard>>,
+    zero_rtt: std::sync::Mutex<Option<ZeroRttGuard>>,
+    receive_budget: Arc<ReceiveBudget>,
+    /// The number of incoming handshakes in progress.
+    handshakes: Arc<AtomicUsize>,
+    audit_log: std::sync::Mutex<Option<Arc<AuditLog>>>,
+    /// The configs of sockets created from a `SocketConfig`, for per-connection transport overrides.
+    endpoint_configs: std::sync::Mutex<Option<EndpointConfigs>>,
+    events: broadcast::Sender<SocketEvent>,
+    io: Arc<IoCounters>,
+    endpoint_stats: EndpointCounters,
+    shutdown: CancellationToken,
+}
+
+impl Shared {
+    /// Wraps a new quinn connection, applying the socket-wide settings.
+    async fn wrap(&self, connection: quinn::Connection, direction: Direction) -> Result<Arc<QuicConnection>> {
+        let connection = QuicConnection::new(connection).await?
+            .with_events(self.events.clone())
+            .with_parent_io(Arc::clone(&self.io))
+            .with_quic_version(self.quic_version(direction))
+            .with_receive_budget(Arc::clone(&self.receive_budget));
+        connection.set_stream_quota(*self.stream_quota.lock().unwrap());
+        connection.set_idle_stream_timeout(*self.idle_stream_timeout.lock().unwrap());
+        connection.set_path_check_interval(*self.path_check_interval.lock().unwrap());
+        connection.set_liveness_thresholds(*self.liveness_thresholds.lock().unwrap());
+        Ok(Arc::new(connection))
+    }
+    /// Adds a connection to the registry.
+    ///
+    /// A watcher task removes it once the connection is closed, for whatever reason, and emits a
+    /// `SocketEvent::ConnectionClosed`. Both are recorded in the audit log, if any.
+    fn register(self: &Arc<Self>, remote_addr: SocketAddr, connection: &Arc<QuicConnection>, direction: Direction) {
+        let mode = *self.registry_mode.lock().unwrap();
+        self.connections.push(remote_addr, Registered::new(connection, mode));
+        let audit_log = self.audit_log.lock().unwrap().clone();
+        if let Some(audit_log) = &audit_log {
+            audit_log.record_open(connection, direction);
+        }
+        let shared = Arc::downgrade(self);
+        let closed = connection.connection.clone();
+        let tags = connection.tag_set();
+        let local_close = connection.local_close();
+        let connection = Arc::downgrade(connection);
+        tokio::spawn(async move {
+            let reason = closed.closed().await;
+            if let Some(audit_log) = audit_log {
+                audit_log.record_close(&closed, &reason);
+            }
+            let Some(shared) = shared.upgrade() else { return };
+            // The connection itself may be gone already with weak registry entries.
+            let tags = std::mem::take(&mut *tags.lock().unwrap());
+            for tag in tags {
+                shared.tags.remove_item(&tag, |c| Weak::ptr_eq(c, &connection));
+            }
+            let removed = shared.connections.remove_item(&remote_addr, |c| c.is(connection.as_ptr()));
+            // Recorded once the connection is out of the registry, so a snapshot never counts it twice.
+            shared.endpoint_stats.record_closed(&closed.stats());
+            // Connections are only reported while registered, so each close is reported once.
+            if removed.is_some() {
+                tracing::debug!("Connection to {} closed: {}", remote_addr, reason);
+                let close_reason = CloseReason::new(&reason, &local_close);
+                let _ = shared.events.send(SocketEvent::ConnectionClosed { remote_address: remote_addr, reason, close_reason });
+            }
+        });
+    }
+    /// Returns the registered connections that were not dropped.
+    fn open_connections(&self) -> Vec<Arc<QuicConnection>> {
+        self.connections.values().iter().flatten().filter_map(Registered::get).collect()
+    }
+    /// Returns the number of uni-directional streams clients may open, 0 unless configured.
+    fn max_concurrent_uni_streams(&self) -> u32 {
+        self.endpoint_configs.lock().unwrap().as_ref().and_then(EndpointConfigs::max_concurrent_uni_streams).unwrap_or(0)
+    }
+    /// Returns the QUIC versions the endpoint offers, in order of preference.
+    fn quic_versions(&self) -> Vec<QuicVersion> {
+        self.endpoint_configs.lock().unwrap().as_ref()
+            .map_or_else(QuicVersion::supported, |configs| configs.quic_versions.clone())
+    }
+    /// Returns the QUIC version of a new connection, if known.
+    ///
+    /// Clients connect with the preferred version. quinn does not tell which version a client used, so
+    /// the version of an incoming connection is only known if the endpoint accepts a single one.
+    fn quic_version(&self, direction: Direction) -> Option<QuicVersion> {
+        let versions = self.quic_versions();
+        match direction {
+            Direction::Outbound => versions.first().copied(),
+            Direction::Inbound => (versions.len() == 1).then(|| versions[0]),
+        }
+    }
+    /// Returns the configs the socket was created with.
+    fn endpoint_configs(&self) -> Result<EndpointConfigs> {
+        self.endpoint_configs.lock().unwrap().clone()
+            .ok_or_else(|| anyhow::anyhow!("transport overrides require a socket created from a SocketConfig"))
+    }
+    /// Starts the handshake of an incoming connection, with the given server config or the endpoint's.
+    ///
+    /// Refuses the connection if the cap of the flood protection on handshakes in progress is reached.
+    pub(crate) fn start_handshake(&self, incoming: Incoming, server_config: Option<Arc<ServerConfig>>) -> Result<(Connecting, HandshakeGuard)> {
+        let max = self.flood_guard.lock().unwrap().as_ref().map(FloodGuard::max_concurrent_handshakes);
+        let Some(mut handshake) = HandshakeGuard::admit(&self.handshakes, max) else {
+            tracing::debug!("Too many handshakes in progress, refusing connection from: {}", incoming.remote_address());
+            self.endpoint_stats.record_over_capacity();
+            incoming.refuse();
+            anyhow::bail!("too many handshakes in progress");
+        };
+        handshake.zero_rtt = self.zero_rtt.lock().unwrap().as_mut().is_some_and(|guard| guard.check_replay(&incoming));
+        handshake.deadline = self.handshake_deadline();
+        let connecting = match server_config {
+            Some(server_config) => incoming.accept_with(server_config),
+            None => incoming.accept(),
+        };
+        Ok((connecting.map_err(crate::error::Error::from)?, handshake))
+    }
+    /// Returns when a handshake starting now times out, if a handshake timeout is set.
+    fn handshake_deadline(&self) -> Option<Instant> {
+        self.handshake_timeout.lock().unwrap().map(|timeout| Instant::now() + timeout)
+    }
+    /// Records an incoming connection turned away before its handshake completed.
+    pub(crate) fn record_refused(&self) {
+        self.endpoint_stats.record_refused();
+    }
+    /// Counts a new connection from `remote_addr` against the connection rate limit.
+    /// Returns the limit if it is exceeded.
+    fn exceeds_connection_rate_limit(&self, remote_addr: SocketAddr) -> Option<ConnectionRateLimit> {
+        let mut limiter = self.connection_rate_limiter.lock().unwrap();
+        let limiter = limiter.as_mut()?;
+        (!limiter.check(remote_addr.ip())).then(|| limiter.limit())
+    }
 }
 
 impl QuicSocket {
@@ -28,15 +265,8 @@ impl QuicSocket {
                 return Err(e);
             },
         };
-        let (tx, rx) = mpsc::channel(100);
-        let endpoint_clone = endpoint.clone();
-        tokio::spawn(async move {
-            while let Some(incoming) = endpoint_clone.accept().await {
-                let _ = tx.send(incoming).await;
-            }
-        });
         tracing::info!("Server listening on: {}", addr);
-        Ok((Self { endpoint, connections: Arc::new(Mutex::new(HashMap::new())) }, rx))
+        Ok(Self::from_server_endpoint(endpoint))
     }
     /// Creates a new QUIC server bound to a certain address and port.
     /// 
@@ -48,15 +278,24 @@ impl QuicSocket {
                 return Err(e);
             },
         };
-        let (tx, rx) = mpsc::channel(100);
-        let endpoint_clone = endpoint.clone();
-        tokio::spawn(async move {
-            while let Some(incoming) = endpoint_clone.accept().await {
-                let _ = tx.send(incoming).await;
-            }
-        });
         tracing::info!("Server listening on: {}", addr);
-        Ok((Self { endpoint, connections: Arc::new(Mutex::new(HashMap::new())) }, rx))
+        Ok(Self::from_server_endpoint(endpoint))
+    }
+    /// Creates a new QUIC server bound to a certain address and port, using the given configuration.
+    ///
+    /// The server uses the config's certificate, or a self-signed one if none is set.
+    pub async fn new_server_with_config(addr: SocketAddr, config: SocketConfig) -> Result<(Self, mpsc::Receiver<Incoming>), Box<dyn Error + Send + Sync + 'static>> {
+        let (endpoint, configs) = make_endpoint_with_config(addr, &config, true, false)?;
+        tracing::info!("Server listening on: {}", addr);
+        let (socket, incoming) = Self::from_server_endpoint(endpoint);
+        socket.set_flood_protection(config.flood_protection);
+        socket.set_receive_budget(config.receive_budget);
+        socket.set_idle_stream_timeout(config.idle_stream_timeout);
+        socket.set_path_check_interval(config.path_check_interval);
+        socket.set_liveness_thresholds(config.liveness_thresholds);
+        socket.set_handshake_timeout(config.handshake_timeout);
+        socket.set_registry_mode(config.registry_mode);
+        Ok((socket.with_endpoint_configs(configs).with_zero_rtt(config.zero_rtt), incoming))
     }
     /// Creates a new QUIC client bound to a certain address and port.
     /// 
@@ -64,17 +303,20 @@ impl QuicSocket {
     pub async fn new_client(bind_addr: SocketAddr, server_certs: &[&[u8]]) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
         let endpoint = make_client_endpoint(bind_addr, server_certs)?;
         tracing::info!("Client bound to {:?}", endpoint.local_addr());
-        Ok(Self { endpoint, connections: Arc::new(Mutex::new(HashMap::new())) })
+        Ok(Self::from_client_endpoint(endpoint))
     }
     /// Creates a new QUIC client bound to a certain address and port.
     /// 
     /// The client will use the root certificates found in the platform's native certificate store to verify the server's identity.
     /// 
     /// This is useful when connecting to servers that use certificates signed by a trusted CA.
+    ///
+    /// Requires the `native-certs` feature, enabled by default.
+    #[cfg(feature = "native-certs")]
     pub async fn new_native_client(bind_addr: SocketAddr) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
         let endpoint = make_native_client_endpoint(bind_addr)?;
         tracing::info!("Client bound to {:?}", endpoint.local_addr());
-        Ok(Self { endpoint, connections: Arc::new(Mutex::new(HashMap::new())) })
+        Ok(Self::from_client_endpoint(endpoint))
     }
     /// Creates a new QUIC client bound to a certain address and port.
     /// 
@@ -84,35 +326,705 @@ impl QuicSocket {
     pub async fn new_insecure_client(bind_addr: SocketAddr) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
         let endpoint = make_insecure_client_endpoint(bind_addr)?;
         tracing::info!("Client bound to {:?}", endpoint.local_addr());
-        Ok(Self { endpoint, connections: Arc::new(Mutex::new(HashMap::new())) })
+        Ok(Self::from_client_endpoint(endpoint))
+    }
+    /// Creates a new QUIC client bound to a certain address and port, using the given configuration.
+    ///
+    /// The server's identity is verified according to the config's verification mode.
+    pub async fn new_client_with_config(bind_addr: SocketAddr, config: SocketConfig) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        let (endpoint, configs) = make_endpoint_with_config(bind_addr, &config, false, true)?;
+        tracing::info!("Client bound to {:?}", endpoint.local_addr());
+        let socket = Self::from_client_endpoint(endpoint);
+        socket.set_receive_budget(config.receive_budget);
+        socket.set_idle_stream_timeout(config.idle_stream_timeout);
+        socket.set_path_check_interval(config.path_check_interval);
+        socket.set_liveness_thresholds(config.liveness_thresholds);
+        socket.set_handshake_timeout(config.handshake_timeout);
+        socket.set_registry_mode(config.registry_mode);
+        Ok(socket.with_endpoint_configs(configs))
+    }
+    /// Creates a new QUIC peer bound to a certain address and port.
+    ///
+    /// A single endpoint is used to both accept incoming connections and `connect()` to other peers,
+    /// so only one port is needed and NAT mappings are shared between both directions.
+    /// Incoming and outgoing connections are tracked in the same connection registry.
+    pub async fn new_peer(bind_addr: SocketAddr, config: SocketConfig) -> Result<(Self, mpsc::Receiver<Incoming>), Box<dyn Error + Send + Sync + 'static>> {
+        let (endpoint, configs) = make_endpoint_with_config(bind_addr, &config, true, true)?;
+        tracing::info!("Peer bound to {:?}", endpoint.local_addr());
+        let (socket, incoming) = Self::from_server_endpoint(endpoint);
+        socket.set_flood_protection(config.flood_protection);
+        socket.set_receive_budget(config.receive_budget);
+        socket.set_idle_stream_timeout(config.idle_stream_timeout);
+        socket.set_path_check_interval(config.path_check_interval);
+        socket.set_liveness_thresholds(config.liveness_thresholds);
+        socket.set_handshake_timeout(config.handshake_timeout);
+        socket.set_registry_mode(config.registry_mode);
+        Ok((socket.with_endpoint_configs(configs).with_zero_rtt(config.zero_rtt), incoming))
+    }
+    /// Wraps a server endpoint, spawning the background task that accepts incoming connections.
+    ///
+    /// While accepting is paused, the task refuses incoming connections instead of forwarding them.
+    pub(crate) fn from_server_endpoint(endpoint: Endpoint) -> (Self, mpsc::Receiver<Incoming>) {
+        let (tx, rx) = mpsc::channel(100);
+        let mut socket = Self::from_client_endpoint(endpoint);
+        let endpoint = socket.endpoint.clone();
+        let shared = Arc::downgrade(&socket.shared);
+        let accept_task = tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let Some(shared) = shared.upgrade() else { break };
+                if shared.accept_paused.load(Ordering::Relaxed) {
+                    tracing::debug!("Accepting is paused, refusing connection from: {}", incoming.remote_address());
+                    shared.record_refused();
+                    incoming.refuse();
+                    continue;
+                }
+                let rejection = shared.flood_guard.lock().unwrap().as_mut().and_then(|guard| guard.check(&incoming));
+                match rejection {
+                    Some(Rejection::Retry) => {
+                        tracing::trace!("Asking {} to validate its address", incoming.remote_address());
+                        shared.endpoint_stats.record_retry();
+                        if let Err(e) = incoming.retry() {
+                            e.into_incoming().refuse();
+                        }
+                        continue;
+                    },
+                    Some(Rejection::RateLimited(limit)) => {
+                        tracing::debug!("Handshake rate limit exceeded, refusing connection from: {}", incoming.remote_address());
+                        shared.endpoint_stats.record_rate_limited();
+                        let _ = shared.events.send(SocketEvent::ConnectionRateLimited {
+                            remote_address: incoming.remote_address(),
+                            limit: limit.max_connections,
+                        });
+                        incoming.refuse();
+                        continue;
+                    },
+                    None => {},
+                }
+                if let Some(limit) = shared.exceeds_connection_rate_limit(incoming.remote_address()) {
+                    tracing::debug!("Connection rate limit exceeded, refusing connection from: {}", incoming.remote_address());
+                    shared.endpoint_stats.record_rate_limited();
+                    let _ = shared.events.send(SocketEvent::ConnectionRateLimited {
+                        remote_address: incoming.remote_address(),
+                        limit: limit.max_connections,
+                    });
+                    incoming.refuse();
+                    continue;
+                }
+                if let Err(mpsc::error::SendError(incoming)) = tx.send(incoming).await {
+                    tracing::debug!("Incoming receiver closed, dropping connection from: {}", incoming.remote_address());
+                    shared.endpoint_stats.record_dropped();
+                }
+            }
+        });
+        socket.accept_task = Some(accept_task.abort_handle());
+        (socket, rx)
+    }
+    /// Wraps a client endpoint.
+    pub(crate) fn from_client_endpoint(endpoint: Endpoint) -> Self {
+        let shared = Shared {
+            connections: ShardedMap::new(),
+            registry_mode: std::sync::Mutex::new(RegistryMode::default()),
+            tags: ShardedMap::new(),
+            connect_locks: ShardedMap::new(),
+            report_observed_address: AtomicBool::new(false),
+            accept_paused: AtomicBool::new(false),
+            stream_quota: std::sync::Mutex::new(None),
+            idle_stream_timeout: std::sync::Mutex::new(None),
+            path_check_interval: std::sync::Mutex::new(None),
+            liveness_thresholds: std::sync::Mutex::new(None),
+            handshake_timeout: std::sync::Mutex::new(None),
+            connection_rate_limiter: std::sync::Mutex::new(None),
+            flood_guard: std::sync::Mutex::new(None),
+            zero_rtt: std::sync::Mutex::new(None),
+            receive_budget: Arc::new(ReceiveBudget::new(None)),
+            handshakes: Arc::new(AtomicUsize::new(0)),
+            audit_log: std::sync::Mutex::new(None),
+            endpoint_configs: std::sync::Mutex::new(None),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            io: Arc::new(IoCounters::default()),
+            endpoint_stats: EndpointCounters::default(),
+            shutdown: CancellationToken::new(),
+        };
+        Self { endpoint, shared: Arc::new(shared), accept_task: None }
+    }
+    /// Sets the 0-RTT policy of a server whose endpoint was made with early data enabled.
+    fn with_zero_rtt(self, policy: Option<ZeroRttPolicy>) -> Self {
+        *self.shared.zero_rtt.lock().unwrap() = policy.map(ZeroRttGuard::new);
+        self
+    }
+    /// Keeps the configs the endpoint was made with, for per-connection transport overrides.
+    fn with_endpoint_configs(self, configs: EndpointConfigs) -> Self {
+        *self.shared.endpoint_configs.lock().unwrap() = Some(configs);
+        self
+    }
+    /// Returns the underlying `quinn::Endpoint`.
+    ///
+    /// This can be used to access quinn features that are not wrapped by quicsock.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+    /// Enables or disables reporting of observed addresses to peers.
+    ///
+    /// When enabled, every accepted connection is sent the remote address observed by this socket
+    /// over a control stream, which the peer can read with `QuicConnection::receive_observed_address`.
+    ///
+    /// Accepted connections then also allow the peer to open a uni-directional stream, which servers
+    /// otherwise refuse, so the peer can report the address it observes in return with
+    /// `QuicConnection::report_observed_address`.
+    pub fn set_report_observed_address(&self, enabled: bool) {
+        self.shared.report_observed_address.store(enabled, Ordering::Relaxed);
+    }
+    /// Stops taking new connections. Incoming connections are refused until `resume_accept` is called.
+    ///
+    /// Existing connections are not affected, so this can be used to shed load or drain a server.
+    /// Connections already queued in the incoming receiver are still delivered.
+    pub fn pause_accept(&self) {
+        self.shared.accept_paused.store(true, Ordering::Relaxed);
+        tracing::info!("Paused accepting connections on: {:?}", self.endpoint.local_addr());
+    }
+    /// Resumes taking new connections after `pause_accept`.
+    pub fn resume_accept(&self) {
+        self.shared.accept_paused.store(false, Ordering::Relaxed);
+        tracing::info!("Resumed accepting connections on: {:?}", self.endpoint.local_addr());
+    }
+    /// Returns whether accepting new connections is paused.
+    pub fn is_accept_paused(&self) -> bool {
+        self.shared.accept_paused.load(Ordering::Relaxed)
+    }
+    /// Sets the quota on streams each peer may open, or removes it with `None`.
+    ///
+    /// Applies to connections established after the call. See `QuicConnection::set_stream_quota`.
+    pub fn set_stream_quota(&self, quota: Option<StreamQuota>) {
+        *self.shared.stream_quota.lock().unwrap() = quota;
+    }
+    /// Sets the time after which idle streams are reset and removed, or disables it with `None`.
+    ///
+    /// Applies to connections established after the call. See `QuicConnection::set_idle_stream_timeout`.
+    pub fn set_idle_stream_timeout(&self, timeout: Option<Duration>) {
+        *self.shared.idle_stream_timeout.lock().unwrap() = timeout;
+    }
+    /// Sets the interval at which the paths of connections are checked for changes, or disables it with `None`.
+    ///
+    /// Applies to connections established after the call. See `QuicConnection::set_path_check_interval`.
+    pub fn set_path_check_interval(&self, interval: Option<Duration>) {
+        *self.shared.path_check_interval.lock().unwrap() = interval;
+    }
+    /// Sets the thresholds at which silent peers of connections are reported, or disables it with `None`.
+    ///
+    /// Applies to connections established after the call. See `QuicConnection::set_liveness_thresholds`.
+    pub fn set_liveness_thresholds(&self, thresholds: Option<LivenessThresholds>) {
+        *self.shared.liveness_thresholds.lock().unwrap() = thresholds;
+    }
+    /// Sets the time allowed for a handshake to complete, or leaves it to the idle timeout with `None`.
+    ///
+    /// Applies to handshakes started after the call, both of `connect()` and of incoming connections.
+    /// Handshakes that take longer are abandoned and fail with `Error::HandshakeTimedOut`. Without it,
+    /// a handshake with an unresponsive peer, e.g. behind a firewall dropping UDP, only fails once the
+    /// idle timeout of the transport passes.
+    pub fn set_handshake_timeout(&self, timeout: Option<Duration>) {
+        *self.shared.handshake_timeout.lock().unwrap() = timeout;
+    }
+    /// Sets the maximum number of bytes buffered by `receive()` calls in progress across all connections,
+    /// or removes it with `None`.
+    ///
+    /// While the budget is used up, receives pause reading, so flow control slows the peers down. The oldest
+    /// receive in progress always reads on, so a message larger than the budget still completes. Applies to
+    /// receives in progress.
+    pub fn set_receive_budget(&self, bytes: Option<usize>) {
+        self.shared.receive_budget.set_limit(bytes);
+    }
+    /// Returns the number of bytes currently buffered by `receive()` calls in progress.
+    pub fn buffered_receive_bytes(&self) -> usize {
+        self.shared.receive_budget.used()
+    }
+    /// Sets or removes the limit on new connections per source IP address. See `ratelimit`.
+    ///
+    /// Setting a limit resets the counts of the previous one.
+    pub fn set_connection_rate_limit(&self, limit: Option<ConnectionRateLimit>) {
+        *self.shared.connection_rate_limiter.lock().unwrap() = limit.map(ConnectionRateLimiter::new);
+    }
+    /// Enables handshake flood protection, or disables it with `None`. See `flood`.
+    ///
+    /// Applies to incoming connections before the rate limit set with `set_connection_rate_limit`, if any.
+    pub fn set_flood_protection(&self, protection: Option<FloodProtection>) {
+        *self.shared.flood_guard.lock().unwrap() = protection.map(FloodGuard::new);
+    }
+    /// Returns the QUIC versions the socket offers, in order of preference. See `version`.
+    pub fn quic_versions(&self) -> Vec<QuicVersion> {
+        self.shared.quic_versions()
+    }
+    /// Sets or removes the audit log recording the connections of this socket. See `audit`.
+    ///
+    /// Only connections opened afterwards are recorded.
+    pub fn set_audit_log(&self, audit_log: Option<Arc<AuditLog>>) {
+        *self.shared.audit_log.lock().unwrap() = audit_log;
+    }
+    /// Returns the I/O counters aggregated over all connections of this socket, including closed ones.
+    pub fn io_stats(&self) -> IoStats {
+        self.shared.io.snapshot()
+    }
+    /// Returns a snapshot of the endpoint-level statistics of this socket.
+    pub fn stats(&self) -> EndpointStats {
+        let open = self.shared.open_connections();
+        let active_connections = self.endpoint.open_connections() as u64;
+        self.shared.endpoint_stats.snapshot(active_connections, open.iter().map(|connection| &connection.connection))
+    }
+    /// Returns a receiver for the events of this socket and its connections.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SocketEvent> {
+        self.shared.events.subscribe()
     }
     /// Connects to a server at a certain address and port.
     /// 
     /// The returned connection can be used to send and receive data.
     pub async fn connect(&self, server_addr: SocketAddr, server_name: &str) -> Result<Arc<QuicConnection>> {
-        let connection = self.endpoint.connect(server_addr, server_name)?.await?;
-        let quic_connection = Arc::new(QuicConnection::new(connection).await?);
-        self.connections.lock().await.insert(server_addr, Arc::clone(&quic_connection));
-        tracing::info!("Connected to server: {}", server_addr);
+        let connecting = self.endpoint.connect(server_addr, server_name)?;
+        self.establish_outbound(server_addr, connecting).await
+    }
+    /// Connects to a server with transport options overriding the socket's for this connection only,
+    /// e.g. larger windows for a bulk transfer or a short idle timeout for a control connection.
+    ///
+    /// Options not set in `transport` keep the socket's values. Only available on sockets created from
+    /// a `SocketConfig`.
+    pub async fn connect_with_transport(&self, server_addr: SocketAddr, server_name: &str, transport: &TransportOptions) -> Result<Arc<QuicConnection>> {
+        let client_config = self.shared.endpoint_configs()?.client_with(transport)?;
+        let connecting = self.endpoint.connect_with(client_config, server_addr, server_name)?;
+        self.establish_outbound(server_addr, connecting).await
+    }
+    /// Completes the handshake of an outgoing connection and registers it.
+    async fn establish_outbound(&self, server_addr: SocketAddr, connecting: Connecting) -> Result<Arc<QuicConnection>> {
+        let span = telemetry::handshake_span("client", server_addr);
+        let started = Instant::now();
+        let deadline = self.shared.handshake_deadline();
+        let connection = before_deadline(deadline, connecting.instrument(span.clone())).await
+            .and_then(|connection| connection.map_err(crate::error::Error::from));
+        telemetry::record_handshake(&span, "client", connection.as_ref().ok(), started.elapsed());
+        let connection = connection?;
+        let quic_connection = self.shared.wrap(connection, Direction::Outbound).await?;
+        self.shared.register(server_addr, &quic_connection, Direction::Outbound);
+        tracing::debug!("Connected to server: {}", server_addr);
         Ok(quic_connection)
     }
+    /// Returns the open connection to a certain address, connecting to it if there is none.
+    ///
+    /// Concurrent calls for the same address share a single handshake. A registered connection
+    /// is reused as long as it is not closed, regardless of the server name it was opened with.
+    pub async fn connect_or_reuse(&self, server_addr: SocketAddr, server_name: &str) -> Result<Arc<QuicConnection>> {
+        if let Some(connection) = self.open_connection(&server_addr) {
+            return Ok(connection);
+        }
+        let lock = self.shared.connect_locks.get_or_insert_with(server_addr, Default::default);
+        let result = async {
+            let _guard = lock.lock().await;
+            match self.open_connection(&server_addr) {
+                Some(connection) => Ok(connection),
+                None => self.connect(server_addr, server_name).await,
+            }
+        }.await;
+        self.shared.connect_locks.remove_if(&server_addr, |l| Arc::ptr_eq(l, &lock));
+        result
+    }
+    /// Returns the connections to or from a certain address.
+    ///
+    /// Every `connect()` makes a new connection, so there can be several to the same address.
+    pub fn connections(&self, addr: &SocketAddr) -> Vec<Arc<QuicConnection>> {
+        self.shared.connections.get(addr).unwrap_or_default().iter().filter_map(Registered::get).collect()
+    }
+    /// Sets how the registry holds connections established after the call. See `RegistryMode`.
+    pub fn set_registry_mode(&self, mode: RegistryMode) {
+        *self.shared.registry_mode.lock().unwrap() = mode;
+    }
+    /// Tags a connection of this socket, e.g. with `"room:lobby"` or `"role:admin"`, so it can be found with
+    /// `tagged()`. A connection can have any number of tags, and loses them when it closes.
+    ///
+    /// Returns whether the tag was added: `false` if the connection already had it, is closed or does not
+    /// belong to this socket.
+    pub fn tag(&self, connection: &Arc<QuicConnection>, tag: &str) -> bool {
+        let registered = self.connections(&connection.connection.remote_address()).iter().any(|c| Arc::ptr_eq(c, connection));
+        if !registered || !connection.insert_tag(tag) {
+            return false;
+        }
+        self.shared.tags.push(tag.to_string(), Arc::downgrade(connection));
+        // The connection may have closed after its tags were cleared.
+        if connection.connection.close_reason().is_some() {
+            self.untag(connection, tag);
+            return false;
+        }
+        true
+    }
+    /// Removes a tag from a connection. Returns whether the connection had it.
+    pub fn untag(&self, connection: &QuicConnection, tag: &str) -> bool {
+        if !connection.remove_tag(tag) {
+            return false;
+        }
+        self.shared.tags.remove_item(&tag.to_string(), |c| std::ptr::eq(c.as_ptr(), connection));
+        true
+    }
+    /// Returns the open connections with a certain tag.
+    pub fn tagged(&self, tag: &str) -> Vec<Arc<QuicConnection>> {
+        self.shared.tags.get(&tag.to_string()).unwrap_or_default().iter().filter_map(Weak::upgrade).collect()
+    }
+    /// Sends data to every connection with a certain tag, each on a new bi-directional stream.
+    ///
+    /// The sends run concurrently, and each completes once the peer has received everything. Peers receive the data
+    /// with `QuicConnection::accept_bi_stream` and `receive`. Returns the number of connections the data was sent to;
+    /// failed sends are logged.
+    pub async fn broadcast_tagged(&self, tag: &str, data: &[u8]) -> usize {
+        let sends = self.tagged(tag).into_iter().map(|connection| async move {
+            let result = async {
+                let stream_id = connection.open_bi_stream().await?;
+                connection.send(stream_id, data).await
+            }.await;
+            if let Err(e) = &result {
+                tracing::debug!("Failed to send to {} tagged {:?}: {}", connection.connection.remote_address(), tag, e);
+            }
+            result.is_ok()
+        });
+        futures::future::join_all(sends).await.into_iter().filter(|sent| *sent).count()
+    }
+    /// Returns the registered connection to a certain address if it is still open.
+    fn open_connection(&self, addr: &SocketAddr) -> Option<Arc<QuicConnection>> {
+        self.connections(addr).into_iter().find(|connection| connection.connection.close_reason().is_none())
+    }
+    /// Connects to the first reachable server among the given addresses.
+    ///
+    /// Addresses are tried in order.
+    pub async fn connect_any(&self, server_addrs: &[SocketAddr], server_name: &str) -> Result<Arc<QuicConnection>> {
+        let balancer = LoadBalancer::new(server_addrs.to_vec(), Strategy::FirstSuccess);
+        self.connect_balanced(&balancer, server_name).await
+    }
+    /// Connects to each of the given servers, running at most `concurrency` handshakes at once.
+    ///
+    /// Yields each server with the outcome of its connection as soon as it is known, in completion order.
+    /// Failed connections are not retried; see `dial::DialQueue` for that.
+    pub fn connect_many<'a>(
+        &'a self,
+        server_addrs: impl IntoIterator<Item = SocketAddr> + 'a,
+        server_name: &'a str,
+        concurrency: usize,
+    ) -> impl futures::Stream<Item = (SocketAddr, Result<Arc<QuicConnection>>)> + 'a {
+        futures::stream::iter(server_addrs)
+            .map(move |addr| async move { (addr, self.connect(addr, server_name).await) })
+            .buffer_unordered(concurrency.max(1))
+    }
+    /// Connects to one of the servers managed by the load balancer.
+    ///
+    /// The address is picked according to the balancer's strategy, and the outcome of each attempt
+    /// is recorded in the balancer's health information.
+    pub async fn connect_balanced(&self, balancer: &LoadBalancer, server_name: &str) -> Result<Arc<QuicConnection>> {
+        let candidates = balancer.candidates();
+        if candidates.is_empty() {
+            anyhow::bail!("no server addresses to connect to");
+        }
+        if balancer.strategy() == Strategy::LowestRtt {
+            let attempts = candidates.iter().map(|&addr| {
+                Box::pin(async move {
+                    match self.connect(addr, server_name).await {
+                        Ok(connection) => Ok((addr, connection)),
+                        Err(e) => {
+                            balancer.report_failure(addr);
+                            Err(e)
+                        },
+                    }
+                })
+            });
+            let ((addr, connection), _) = futures::future::select_ok(attempts).await?;
+            balancer.report_success(addr, connection.connection.rtt());
+            return Ok(connection);
+        }
+        let mut last_error = None;
+        for addr in candidates {
+            match self.connect(addr, server_name).await {
+                Ok(connection) => {
+                    balancer.report_success(addr, connection.connection.rtt());
+                    return Ok(connection);
+                },
+                Err(e) => {
+                    tracing::debug!("Failed to connect to {}: {}", addr, e);
+                    balancer.report_failure(addr);
+                    last_error = Some(e);
+                },
+            }
+        }
+        Err(last_error.unwrap())
+    }
+    /// Connects to a server at a certain address and port, aborting if the token is cancelled.
+    ///
+    /// On cancellation the pending handshake is abandoned and a `Cancelled` error is returned.
+    pub async fn connect_with_cancel(&self, server_addr: SocketAddr, server_name: &str, token: &CancellationToken) -> Result<Arc<QuicConnection>> {
+        tokio::select! {
+            res = self.connect(server_addr, server_name) => res,
+            _ = token.cancelled() => Err(Cancelled.into()),
+        }
+    }
+    /// Accepts an incoming connection, aborting if the token is cancelled.
+    ///
+    /// Returns `None` on cancellation.
+    pub async fn accept_with_cancel(&self, incoming: &mut mpsc::Receiver<Incoming>, token: &CancellationToken) -> Option<Arc<QuicConnection>> {
+        tokio::select! {
+            res = self.accept(incoming) => res,
+            _ = token.cancelled() => None,
+        }
+    }
     /// Accepts an incoming connection.
     /// 
     /// The returned connection can be used to send and receive data.
     pub async fn accept(&self, incoming: &mut mpsc::Receiver<Incoming>) -> Option<Arc<QuicConnection>> {
         if let Some(connecting) = incoming.recv().await {
-            let connection = match connecting.await {
-                Ok(conn) => Arc::new(QuicConnection::new(conn).await.unwrap()),
-                Err(_) => return None,
-            };
-
-            let remote_addr = connection.connection.remote_address();
-            self.connections.lock().await.insert(remote_addr, Arc::clone(&connection));
-            tracing::info!("Accepted connection from: {}", remote_addr);
-            return Some(connection);
+            return Self::establish(&self.shared, connecting).await;
         }
         None
     }
+    /// Accepts an incoming connection, giving up after `timeout`.
+    ///
+    /// Returns `None` if no connection arrived in time. A connection that arrived in time
+    /// is still fully handshaken, even if that takes longer than `timeout`.
+    pub async fn accept_timeout(&self, incoming: &mut mpsc::Receiver<Incoming>, timeout: Duration) -> Option<Arc<QuicConnection>> {
+        let connecting = tokio::time::timeout(timeout, incoming.recv()).await.ok()??;
+        Self::establish(&self.shared, connecting).await
+    }
+    /// Accepts an incoming connection if one is already waiting, without waiting for new ones.
+    ///
+    /// Returns `None` if none is waiting. A waiting connection is still fully handshaken.
+    pub async fn try_accept(&self, incoming: &mut mpsc::Receiver<Incoming>) -> Option<Arc<QuicConnection>> {
+        let connecting = incoming.try_recv().ok()?;
+        Self::establish(&self.shared, connecting).await
+    }
+    /// Waits for the next incoming connection without starting its handshake.
+    ///
+    /// The connection can then be accepted, or refused or ignored based on its remote address.
+    pub async fn next_incoming(&self, incoming: &mut mpsc::Receiver<Incoming>) -> Option<IncomingConnection> {
+        let incoming = incoming.recv().await?;
+        Some(IncomingConnection::new(incoming, Arc::clone(&self.shared)))
+    }
+    /// Accepts a specific incoming connection, completing its handshake and registering it.
+    ///
+    /// Returns `None` if the handshake fails.
+    pub async fn accept_incoming(&self, incoming: Incoming) -> Option<Arc<QuicConnection>> {
+        Self::establish(&self.shared, incoming).await
+    }
+    /// Accepts a specific incoming connection with transport options overriding the socket's for this
+    /// connection only. See `connect_with_transport`.
+    ///
+    /// The options have to be picked before the handshake, e.g. from `Incoming::remote_address`.
+    pub async fn accept_with_transport(&self, incoming: Incoming, transport: &TransportOptions) -> Result<Arc<QuicConnection>> {
+        Self::establish_with_transport(&self.shared, incoming, transport).await
+    }
+    /// Starts the handshake of an incoming connection and waits for the client's hello, without completing it.
+    ///
+    /// The returned pending connection exposes the requested server name and ALPN protocol,
+    /// and can be accepted or rejected based on them.
+    pub async fn inspect(&self, incoming: Incoming) -> Result<PendingConnection> {
+        let (connecting, handshake) = self.shared.start_handshake(incoming, None)?;
+        Self::inspect_connecting(&self.shared, connecting, handshake).await
+    }
+    /// Like `inspect`, but handshakes with a server configuration built from the given config
+    /// instead of the socket's.
+    ///
+    /// The configuration has to be picked before the client's hello is read, e.g. from `Incoming::remote_address`.
+    /// To pick certificates by server name, use one socket per certificate or a custom rustls certificate resolver.
+    pub async fn inspect_with_config(&self, incoming: Incoming, config: &SocketConfig) -> Result<PendingConnection> {
+        let server_config = configure_server_with(config).map_err(|e| anyhow::anyhow!(e))?;
+        let (connecting, handshake) = self.shared.start_handshake(incoming, Some(Arc::new(server_config)))?;
+        Self::inspect_connecting(&self.shared, connecting, handshake).await
+    }
+    /// Waits for the client's hello on a connection being handshaken.
+    pub(crate) async fn inspect_connecting(shared: &Arc<Shared>, mut connecting: Connecting, handshake: HandshakeGuard) -> Result<PendingConnection> {
+        let hello = before_deadline(handshake.deadline, ClientHello::read(&mut connecting)).await??;
+        Ok(PendingConnection::new(hello, connecting, Arc::clone(shared), handshake))
+    }
+    /// Completes the handshake of an incoming connection and registers it.
+    pub(crate) async fn establish(shared: &Arc<Shared>, incoming: Incoming) -> Option<Arc<QuicConnection>> {
+        let (connecting, handshake) = shared.start_handshake(incoming, None).ok()?;
+        Self::complete(shared, connecting, handshake).await.ok()
+    }
+    /// Completes the handshake of an incoming connection with transport overrides and registers it.
+    pub(crate) async fn establish_with_transport(shared: &Arc<Shared>, incoming: Incoming, transport: &TransportOptions) -> Result<Arc<QuicConnection>> {
+        let remote_address = incoming.remote_address();
+        let server_config = match shared.endpoint_configs().and_then(|configs| configs.server_with(transport)) {
+            Ok(server_config) => server_config,
+            Err(e) => {
+                incoming.refuse();
+                return Err(e);
+            },
+        };
+        let (connecting, handshake) = shared.start_handshake(incoming, Some(Arc::new(server_config)))?;
+        Self::complete(shared, connecting, handshake).await
+            .with_context(|| format!("handshake with {} failed", remote_address))
+    }
+    /// Completes the handshake of a connection being handshaken and registers it.
+    ///
+    /// If the 0-RTT policy allows processing the connection's early data, it is returned right away and
+    /// the handshake completes in the background. The handshake counts as in progress until `handshake`
+    /// is dropped, once it completes. It is abandoned once its deadline, if any, passes.
+    pub(crate) async fn complete(shared: &Arc<Shared>, connecting: Connecting, handshake: HandshakeGuard) -> Result<Arc<QuicConnection>> {
+        let remote_addr = connecting.remote_address();
+        let span = telemetry::handshake_span("server", remote_addr);
+        let started = Instant::now();
+        let deadline = handshake.deadline;
+        let connection = match before_deadline(deadline, Self::accept_early(shared, connecting, &handshake)).await {
+            Ok(Ok((connection, accepted))) => {
+                let (background, early) = (Arc::clone(shared), connection.clone());
+                tokio::spawn(async move {
+                    // Resolves once the handshake completes or fails, to whether early data was received.
+                    accepted.await;
+                    let completed = early.close_reason().is_none();
+                    drop(handshake);
+                    telemetry::record_handshake(&span, "server", completed.then_some(&early), started.elapsed());
+                    background.endpoint_stats.record_handshake(completed);
+                });
+                let connection = shared.wrap(connection, Direction::Inbound).await?;
+                return Ok(Self::register_inbound(shared, connection));
+            },
+            Ok(Err(connecting)) => before_deadline(deadline, connecting.instrument(span.clone())).await
+                .and_then(|connection| connection.map_err(crate::error::Error::from)),
+            Err(e) => Err(e),
+        };
+        drop(handshake);
+        telemetry::record_handshake(&span, "server", connection.as_ref().ok(), started.elapsed());
+        shared.endpoint_stats.record_handshake(connection.is_ok());
+        if let Err(crate::error::Error::HandshakeTimedOut) = connection {
+            tracing::debug!("Handshake with {} timed out", remote_addr);
+        }
+        let connection = shared.wrap(connection?, Direction::Inbound).await?;
+        Ok(Self::register_inbound(shared, connection))
+    }
+    /// Converts a connection being handshaken into a 0.5-RTT connection, whose early data can be read,
+    /// if its client hello passes the 0-RTT policy and it was not detected as a replay.
+    async fn accept_early(shared: &Arc<Shared>, mut connecting: Connecting, handshake: &HandshakeGuard) -> Result<(quinn::Connection, ZeroRttAccepted), Connecting> {
+        if !handshake.zero_rtt {
+            return Err(connecting);
+        }
+        let Ok(hello) = ClientHello::read(&mut connecting).await else { return Err(connecting) };
+        let filter = shared.zero_rtt.lock().unwrap().as_ref().and_then(ZeroRttGuard::filter);
+        if filter.is_some_and(|filter| !filter(&hello)) {
+            tracing::debug!("Deferring early data of {} until the handshake completes", hello.remote_address);
+            return Err(connecting);
+        }
+        connecting.into_0rtt()
+    }
+    /// Registers an accepted connection.
+    fn register_inbound(shared: &Arc<Shared>, connection: Arc<QuicConnection>) -> Arc<QuicConnection> {
+        let remote_addr = connection.connection.remote_address();
+        shared.register(remote_addr, &connection, Direction::Inbound);
+        tracing::debug!("Accepted connection from: {}", remote_addr);
+        if shared.report_observed_address.load(Ordering::Relaxed) {
+            if shared.max_concurrent_uni_streams() == 0 {
+                connection.connection.set_max_concurrent_uni_streams(1_u8.into());
+            }
+            let connection = Arc::clone(&connection);
+            tokio::spawn(async move {
+                if let Err(e) = connection.report_observed_address().await {
+                    tracing::debug!("Failed to report observed address to {}: {}", remote_addr, e);
+                }
+            });
+        }
+        connection
+    }
+    /// Serves incoming connections with the given handler, using the default options.
+    ///
+    /// See `serve_with_options` for details.
+    pub async fn serve<F, Fut>(&self, incoming: &mut mpsc::Receiver<Incoming>, handler: F)
+    where
+        F: Fn(Arc<QuicConnection>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.serve_with_options(incoming, ServeOptions::default(), handler).await
+    }
+    /// Serves incoming connections with the given handler.
+    ///
+    /// Each connection is handshaken and handled in its own task. Once the handler returns (or panics),
+    /// the connection is closed, which removes it from the socket.
+    /// Incoming connections beyond `options.max_connections` are refused.
+    ///
+    /// Returns when the incoming receiver is closed or a shutdown is started.
+    pub async fn serve_with_options<F, Fut>(&self, incoming: &mut mpsc::Receiver<Incoming>, options: ServeOptions, handler: F)
+    where
+        F: Fn(Arc<QuicConnection>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.serve_each(incoming, options, move |shared, connecting| {
+            let handler = Arc::clone(&handler);
+            async move {
+                if let Some(connection) = Self::establish(&shared, connecting).await {
+                    Self::run_handler(connection, |connection| handler(connection)).await;
+                }
+            }
+        }).await
+    }
+    /// Serves incoming connections, dispatching each one to the router's handler for its server name and ALPN protocol.
+    ///
+    /// Connections without a matching route are rejected before their handshake completes.
+    /// Otherwise this behaves like `serve_with_options`.
+    pub async fn serve_routed(&self, incoming: &mut mpsc::Receiver<Incoming>, options: ServeOptions, router: Router) {
+        let router = Arc::new(router);
+        self.serve_each(incoming, options, move |shared, connecting| {
+            let router = Arc::clone(&router);
+            async move {
+                let Ok((connecting, handshake)) = shared.start_handshake(connecting, None) else { return };
+                let pending = match Self::inspect_connecting(&shared, connecting, handshake).await {
+                    Ok(pending) => pending,
+                    Err(e) => {
+                        tracing::debug!("Failed to read client hello: {}", e);
+                        return;
+                    },
+                };
+                let Some(handler) = router.find(pending.client_hello()) else {
+                    tracing::debug!("No route for {:?}, rejecting connection", pending.client_hello());
+                    pending.reject();
+                    return;
+                };
+                if let Some(connection) = pending.accept().await {
+                    Self::run_handler(connection, |connection| handler(connection)).await;
+                }
+            }
+        }).await
+    }
+    /// Runs `task` for every incoming connection in its own task, refusing connections beyond the limit.
+    async fn serve_each<F, Fut>(&self, incoming: &mut mpsc::Receiver<Incoming>, options: ServeOptions, task: F)
+    where
+        F: Fn(Arc<Shared>, Incoming) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let limit = Arc::new(Semaphore::new(options.max_connections));
+        loop {
+            let connecting = tokio::select! {
+                connecting = incoming.recv() => match connecting {
+                    Some(connecting) => connecting,
+                    None => break,
+                },
+                _ = self.shared.shutdown.cancelled() => break,
+            };
+            let permit = match Arc::clone(&limit).try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    tracing::warn!("Connection limit reached, refusing connection from: {}", connecting.remote_address());
+                    self.shared.record_refused();
+                    connecting.refuse();
+                    continue;
+                },
+            };
+            let future = task(Arc::clone(&self.shared), connecting);
+            tokio::spawn(async move {
+                let _permit = permit;
+                future.await;
+            });
+        }
+    }
+    /// Runs a connection handler in its own task, then closes the connection.
+    async fn run_handler<H, Fut>(connection: Arc<QuicConnection>, handler: H)
+    where
+        H: FnOnce(Arc<QuicConnection>) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let remote_addr = connection.connection.remote_address();
+        match tokio::spawn(handler(Arc::clone(&connection))).await {
+            Ok(Ok(())) => {},
+            Ok(Err(e)) => tracing::warn!("Connection handler for {} failed: {}", remote_addr, e),
+            Err(e) => tracing::error!("Connection handler for {} panicked: {}", remote_addr, e),
+        }
+        connection.close().await;
+    }
     /// Sends data to a certain connection.
     /// 
     /// The data will be sent on the stream with the specified ID.
@@ -125,22 +1037,89 @@ impl QuicSocket {
     pub async fn receive(&self, connection: &QuicConnection, stream_id: u64) -> Result<Vec<u8>> {
         connection.receive(stream_id).await
     }
-    /// Closes a certain connection.
+    /// Returns the token cancelled when a shutdown starts.
+    ///
+    /// Connection handlers can watch it to finish their work and close their connection.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shared.shutdown.clone()
+    }
+    /// Shuts down gracefully once `signal` resolves. See `shutdown`.
+    ///
+    /// Use `shutdown::signal()` to shut down on Ctrl-C or SIGTERM.
+    pub async fn shutdown_on<S: Future>(&self, signal: S, drain_timeout: Duration) {
+        tokio::select! {
+            _ = signal => {},
+            _ = self.shared.shutdown.cancelled() => {},
+        }
+        self.shutdown(drain_timeout).await
+    }
+    /// Shuts down gracefully.
+    ///
+    /// New connections are refused, serve loops return and the shutdown token is cancelled to notify handlers.
+    /// Connections are then given `drain_timeout` to close by themselves, after which the remaining ones are
+    /// closed with `SHUTDOWN_CODE`. Returns once the endpoint is idle.
+    pub async fn shutdown(&self, drain_timeout: Duration) {
+        tracing::info!("Shutting down: {:?}", self.endpoint.local_addr());
+        self.shared.accept_paused.store(true, Ordering::Relaxed);
+        self.shared.shutdown.cancel();
+        let deadline = tokio::time::Instant::now() + drain_timeout;
+        // Loop to also drain connections whose handshake completed after the shutdown started.
+        loop {
+            let connections = self.shared.open_connections().into_iter()
+                .filter(|connection| connection.connection.close_reason().is_none())
+                .collect::<Vec<_>>();
+            if connections.is_empty() {
+                break;
+            }
+            let drained = futures::future::join_all(connections.iter().map(|connection| connection.connection.closed()));
+            if tokio::time::timeout_at(deadline, drained).await.is_err() {
+                tracing::warn!("Drain timeout elapsed, closing remaining connections");
+                for connection in self.shared.open_connections() {
+                    connection.close_with(SHUTDOWN_CODE, b"shutdown");
+                }
+                break;
+            }
+        }
+        self.endpoint.wait_idle().await;
+        tracing::info!("Shut down: {:?}", self.endpoint.local_addr());
+    }
+    /// Closes the connections to or from a certain address.
     /// 
-    /// The connection will be gracefully closed.
+    /// The connections will be gracefully closed. Each is removed from the socket, with a
+    /// `SocketEvent::ConnectionClosed`, once closed.
     pub async fn close_connection(&self, addr: &SocketAddr) {
-        if let Some(conn) = self.connections.lock().await.remove(addr) {
+        for conn in self.connections(addr) {
             conn.close().await;
         }
     }
     /// Closes all connections.
     /// 
-    /// All connections will be gracefully closed.
+    /// All connections will be gracefully closed. Each is removed from the socket, with a
+    /// `SocketEvent::ConnectionClosed`, once closed.
     pub async fn close_all(&self) {
-        let mut connections = self.connections.lock().await;
-        for conn in connections.values() {
+        for conn in self.shared.open_connections() {
             conn.close().await;
         }
-        connections.clear();
+    }
+}
+
+/// Waits for a step of a handshake, failing with `Error::HandshakeTimedOut` once the deadline, if any, passes.
+///
+/// The connection being handshaken is dropped with the step, which abandons the handshake.
+async fn before_deadline<F: Future>(deadline: Option<Instant>, step: F) -> Result<F::Output, crate::error::Error> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), step).await.map_err(|_| crate::error::Error::HandshakeTimedOut),
+        None => Ok(step.await),
+    }
+}
+
+impl Drop for QuicSocket {
+    /// Stops accepting incoming connections.
+    ///
+    /// The socket's connections are closed with `connection::DROPPED_CODE` once no other handle to them is left.
+    fn drop(&mut self) {
+        if let Some(accept_task) = &self.accept_task {
+            accept_task.abort();
+        }
     }
 }
@@ -0,0 +1,216 @@
+//! I/O accounting for connections and sockets.
+//!
+//! Counts the application data passed through quicsock's stream APIs, after interceptors on the sending
+//! side and before them on the receiving side. Message streams only count towards stream totals.
+//! For transport-level statistics including packet overhead, see `quinn::Connection::stats`.
+
+use std::ops::{Add, AddAssign};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A snapshot of I/O counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// Bytes written to streams.
+    pub bytes_sent: u64,
+    /// Bytes read from streams.
+    pub bytes_received: u64,
+    /// Messages sent. A `send()` call counts as one message.
+    pub messages_sent: u64,
+    /// Messages received. A `receive()` call counts as one message.
+    pub messages_received: u64,
+    /// Streams opened locally.
+    pub streams_opened: u64,
+    /// Streams accepted from the peer.
+    pub streams_accepted: u64,
+}
+
+impl Add for IoStats {
+    type Output = IoStats;
+
+    fn add(mut self, other: IoStats) -> IoStats {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for IoStats {
+    fn add_assign(&mut self, other: IoStats) {
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.messages_sent += other.messages_sent;
+        self.messages_received += other.messages_received;
+        self.streams_opened += other.streams_opened;
+        self.streams_accepted += other.streams_accepted;
+    }
+}
+
+/// Live I/O counters. Updates are also applied to the parent counters, if any.
+#[derive(Debug, Default)]
+pub(crate) struct IoCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    streams_opened: AtomicU64,
+    streams_accepted: AtomicU64,
+    parent: Option<Arc<IoCounters>>,
+}
+
+impl IoCounters {
+    /// Creates counters that also update `parent`.
+    pub(crate) fn with_parent(parent: Option<Arc<IoCounters>>) -> Self {
+        Self { parent, ..Default::default() }
+    }
+    /// Records sent messages totalling `bytes`.
+    pub(crate) fn record_sent(&self, messages: u64, bytes: u64) {
+        self.messages_sent.fetch_add(messages, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(parent) = &self.parent {
+            parent.record_sent(messages, bytes);
+        }
+    }
+    /// Records received messages totalling `bytes`.
+    pub(crate) fn record_received(&self, messages: u64, bytes: u64) {
+        self.messages_received.fetch_add(messages, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(parent) = &self.parent {
+            parent.record_received(messages, bytes);
+        }
+    }
+    /// Records a stream opened locally.
+    pub(crate) fn record_opened(&self) {
+        self.streams_opened.fetch_add(1, Ordering::Relaxed);
+        if let Some(parent) = &self.parent {
+            parent.record_opened();
+        }
+    }
+    /// Records a stream accepted from the peer.
+    pub(crate) fn record_accepted(&self) {
+        self.streams_accepted.fetch_add(1, Ordering::Relaxed);
+        if let Some(parent) = &self.parent {
+            parent.record_accepted();
+        }
+    }
+    /// Returns a snapshot of the counters.
+    pub(crate) fn snapshot(&self) -> IoStats {
+        IoStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            streams_opened: self.streams_opened.load(Ordering::Relaxed),
+            streams_accepted: self.streams_accepted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A snapshot of endpoint-level counters.
+///
+/// UDP counters cover the connections of the socket, including closed ones,
+/// as reported by quinn's per-connection statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EndpointStats {
+    /// Incoming handshakes that completed.
+    pub handshakes_accepted: u64,
+    /// Incoming handshakes that failed.
+    pub handshakes_failed: u64,
+    /// Incoming connections refused, ignored or rejected before completing their handshake.
+    pub handshakes_refused: u64,
+    /// Incoming connections dropped because the incoming receiver was closed.
+    pub incoming_dropped: u64,
+    /// Incoming connections asked to validate their address with a retry (see `flood`).
+    pub retries_sent: u64,
+    /// Incoming connections refused because their source IP address exceeded a connection or handshake rate limit.
+    pub handshakes_rate_limited: u64,
+    /// Incoming connections refused because too many handshakes were in progress (see `flood`).
+    pub handshakes_over_capacity: u64,
+    /// Connections currently open on the endpoint.
+    pub active_connections: u64,
+    /// UDP datagrams sent.
+    pub udp_datagrams_sent: u64,
+    /// UDP datagrams received.
+    pub udp_datagrams_received: u64,
+    /// UDP payload bytes sent.
+    pub udp_bytes_sent: u64,
+    /// UDP payload bytes received.
+    pub udp_bytes_received: u64,
+}
+
+/// Live endpoint counters.
+#[derive(Debug, Default)]
+pub(crate) struct EndpointCounters {
+    handshakes_accepted: AtomicU64,
+    handshakes_failed: AtomicU64,
+    handshakes_refused: AtomicU64,
+    incoming_dropped: AtomicU64,
+    retries_sent: AtomicU64,
+    handshakes_rate_limited: AtomicU64,
+    handshakes_over_capacity: AtomicU64,
+    udp_datagrams_sent: AtomicU64,
+    udp_datagrams_received: AtomicU64,
+    udp_bytes_sent: AtomicU64,
+    udp_bytes_received: AtomicU64,
+}
+
+impl EndpointCounters {
+    /// Records the outcome of an incoming handshake.
+    pub(crate) fn record_handshake(&self, accepted: bool) {
+        let counter = if accepted { &self.handshakes_accepted } else { &self.handshakes_failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Records an incoming connection turned away before its handshake completed.
+    pub(crate) fn record_refused(&self) {
+        self.handshakes_refused.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Records an incoming connection dropped because the incoming receiver was closed.
+    pub(crate) fn record_dropped(&self) {
+        self.incoming_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Records an incoming connection asked to retry.
+    pub(crate) fn record_retry(&self) {
+        self.retries_sent.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Records an incoming connection refused for exceeding a rate limit. Also counted as refused.
+    pub(crate) fn record_rate_limited(&self) {
+        self.handshakes_rate_limited.fetch_add(1, Ordering::Relaxed);
+        self.record_refused();
+    }
+    /// Records an incoming connection refused for exceeding the handshake cap. Also counted as refused.
+    pub(crate) fn record_over_capacity(&self) {
+        self.handshakes_over_capacity.fetch_add(1, Ordering::Relaxed);
+        self.record_refused();
+    }
+    /// Records the UDP traffic of a closed connection.
+    pub(crate) fn record_closed(&self, stats: &quinn::ConnectionStats) {
+        self.udp_datagrams_sent.fetch_add(stats.udp_tx.datagrams, Ordering::Relaxed);
+        self.udp_datagrams_received.fetch_add(stats.udp_rx.datagrams, Ordering::Relaxed);
+        self.udp_bytes_sent.fetch_add(stats.udp_tx.bytes, Ordering::Relaxed);
+        self.udp_bytes_received.fetch_add(stats.udp_rx.bytes, Ordering::Relaxed);
+    }
+    /// Returns a snapshot of the counters, adding the UDP traffic of the given open connections.
+    pub(crate) fn snapshot<'a>(&self, active_connections: u64, open: impl IntoIterator<Item = &'a quinn::Connection>) -> EndpointStats {
+        let mut stats = EndpointStats {
+            handshakes_accepted: self.handshakes_accepted.load(Ordering::Relaxed),
+            handshakes_failed: self.handshakes_failed.load(Ordering::Relaxed),
+            handshakes_refused: self.handshakes_refused.load(Ordering::Relaxed),
+            incoming_dropped: self.incoming_dropped.load(Ordering::Relaxed),
+            retries_sent: self.retries_sent.load(Ordering::Relaxed),
+            handshakes_rate_limited: self.handshakes_rate_limited.load(Ordering::Relaxed),
+            handshakes_over_capacity: self.handshakes_over_capacity.load(Ordering::Relaxed),
+            active_connections,
+            udp_datagrams_sent: self.udp_datagrams_sent.load(Ordering::Relaxed),
+            udp_datagrams_received: self.udp_datagrams_received.load(Ordering::Relaxed),
+            udp_bytes_sent: self.udp_bytes_sent.load(Ordering::Relaxed),
+            udp_bytes_received: self.udp_bytes_received.load(Ordering::Relaxed),
+        };
+        for connection in open {
+            let connection_stats = connection.stats();
+            stats.udp_datagrams_sent += connection_stats.udp_tx.datagrams;
+            stats.udp_datagrams_received += connection_stats.udp_rx.datagrams;
+            stats.udp_bytes_sent += connection_stats.udp_tx.bytes;
+            stats.udp_bytes_received += connection_stats.udp_rx.bytes;
+        }
+        stats
+    }
+}
@@ -0,0 +1,132 @@
+//! OpenTelemetry integration.
+//!
+//! With the `otel` feature, handshakes and stream operations are wrapped in `tracing` spans carrying
+//! OpenTelemetry semantic attributes, and byte and handshake metrics are recorded with the global
+//! OpenTelemetry meter. Export spans by bridging `tracing` with `tracing-opentelemetry`.
+//! Install the global meter provider before creating sockets, as instruments are created on first use.
+//!
+//! Without the feature, spans are disabled and no metrics are recorded.
+
+use std::net::SocketAddr;
+use tracing::Span;
+
+/// Span attribute holding the peer IP address.
+pub const NETWORK_PEER_ADDRESS: &str = "network.peer.address";
+/// Span attribute holding the peer port.
+pub const NETWORK_PEER_PORT: &str = "network.peer.port";
+/// Span attribute holding the negotiated ALPN protocol.
+pub const TLS_NEXT_PROTOCOL: &str = "tls.next_protocol";
+/// Span attribute holding the quicsock stream ID.
+pub const STREAM_ID: &str = "quicsock.stream.id";
+/// Span attribute holding the number of bytes transferred.
+pub const BYTES: &str = "quicsock.bytes";
+
+/// The name of the meter used for quicsock metrics.
+pub const METER_NAME: &str = "quicsock";
+
+/// Creates the span for a handshake with `peer`. `kind` is `"client"` or `"server"`.
+pub(crate) fn handshake_span(kind: &'static str, peer: SocketAddr) -> Span {
+    #[cfg(feature = "otel")]
+    {
+        tracing::info_span!(
+            "quic.handshake",
+            otel.name = "quic.handshake",
+            otel.kind = kind,
+            otel.status_code = tracing::field::Empty,
+            network.transport = "quic",
+            network.peer.address = %peer.ip(),
+            network.peer.port = peer.port(),
+            tls.next_protocol = tracing::field::Empty,
+        )
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = (kind, peer);
+        Span::none()
+    }
+}
+
+/// Creates the span for a stream operation such as `"send"` or `"receive"`.
+pub(crate) fn stream_span(operation: &'static str, peer: SocketAddr, stream_id: u64) -> Span {
+    #[cfg(feature = "otel")]
+    {
+        tracing::info_span!(
+            "quic.stream",
+            otel.name = operation,
+            otel.kind = "internal",
+            network.transport = "quic",
+            network.peer.address = %peer.ip(),
+            network.peer.port = peer.port(),
+            quicsock.stream.id = stream_id,
+            quicsock.bytes = tracing::field::Empty,
+        )
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = (operation, peer, stream_id);
+        Span::none()
+    }
+}
+
+/// Records the outcome of a handshake on its span and in the handshake metrics.
+pub(crate) fn record_handshake(span: &Span, kind: &'static str, connection: Option<&quinn::Connection>, elapsed: std::time::Duration) {
+    let alpn = connection
+        .and_then(|c| crate::connection::HandshakeInfo::from_connection(c).alpn_protocol)
+        .map(|protocol| String::from_utf8_lossy(&protocol).into_owned());
+    if let Some(alpn) = &alpn {
+        span.record(TLS_NEXT_PROTOCOL, alpn.as_str());
+    }
+    span.record("otel.status_code", if connection.is_some() { "OK" } else { "ERROR" });
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::KeyValue;
+        let attributes = [
+            KeyValue::new("otel.kind", kind),
+            KeyValue::new("outcome", if connection.is_some() { "success" } else { "failure" }),
+        ];
+        metrics().handshakes.add(1, &attributes);
+        metrics().handshake_duration.record(elapsed.as_secs_f64(), &attributes);
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = (kind, elapsed);
+}
+
+/// Records bytes transferred on the current stream span and in the byte metrics.
+/// `direction` is `"sent"` or `"received"`.
+pub(crate) fn record_bytes(direction: &'static str, peer: SocketAddr, bytes: u64) {
+    Span::current().record(BYTES, bytes);
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::KeyValue;
+        let attributes = [KeyValue::new(NETWORK_PEER_ADDRESS, peer.ip().to_string())];
+        match direction {
+            "sent" => metrics().bytes_sent.add(bytes, &attributes),
+            _ => metrics().bytes_received.add(bytes, &attributes),
+        }
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = (direction, peer);
+}
+
+#[cfg(feature = "otel")]
+struct Metrics {
+    handshakes: opentelemetry::metrics::Counter<u64>,
+    handshake_duration: opentelemetry::metrics::Histogram<f64>,
+    bytes_sent: opentelemetry::metrics::Counter<u64>,
+    bytes_received: opentelemetry::metrics::Counter<u64>,
+}
+
+/// Returns the metric instruments, creating them on first use.
+#[cfg(feature = "otel")]
+fn metrics() -> &'static Metrics {
+    static METRICS: std::sync::OnceLock<Metrics> = std::sync::OnceLock::new();
+    METRICS.get_or_init(|| {
+        let meter = opentelemetry::global::meter(METER_NAME);
+        Metrics {
+            handshakes: meter.u64_counter("quicsock.handshakes").with_description("Completed and failed QUIC handshakes").build(),
+            handshake_duration: meter.f64_histogram("quicsock.handshake.duration").with_description("Duration of QUIC handshakes").with_unit("s").build(),
+            bytes_sent: meter.u64_counter("quicsock.bytes.sent").with_description("Application bytes sent on streams").with_unit("By").build(),
+            bytes_received: meter.u64_counter("quicsock.bytes.received").with_description("Application bytes received on streams").with_unit("By").build(),
+        }
+    })
+}
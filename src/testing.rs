@@ -0,0 +1,87 @@
+//! Helpers for testing code built on quicsock.
+//!
+//! `pair()` returns two connected connections over localhost, so integration tests don't have to
+//! set up certificates, endpoints and handshakes by hand.
+
+use anyhow::Result;
+use quinn::{Endpoint, Incoming};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use crate::endpoint::configure_server_with_cert;
+use crate::{QuicConnection, QuicSocket};
+
+/// The server name of the certificate used by loopback servers.
+pub const LOOPBACK_SERVER_NAME: &str = "localhost";
+
+/// A server and a client socket on localhost, with one connection between them.
+pub struct Loopback {
+    /// The server socket, bound to an ephemeral port.
+    pub server: QuicSocket,
+    /// The receiver for further incoming connections to the server.
+    pub incoming: mpsc::Receiver<Incoming>,
+    /// The client socket, which only trusts the server's self-signed certificate.
+    pub client: QuicSocket,
+    /// The server's side of the connection.
+    pub server_connection: Arc<QuicConnection>,
+    /// The client's side of the connection.
+    pub client_connection: Arc<QuicConnection>,
+}
+
+impl Loopback {
+    /// Creates a self-signed server and a client pinned to its certificate, and connects them.
+    pub async fn new() -> Result<Self> {
+        let (cert_chain, key) = crate::tls::generate_self_signed_pair()?;
+        let server_cert = cert_chain[0].to_vec();
+        let server_config = configure_server_with_cert(cert_chain, key).map_err(|e| anyhow::anyhow!(e))?;
+        let endpoint = Endpoint::server(server_config, SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))?;
+        let server_addr = endpoint.local_addr()?;
+        let (server, mut incoming) = QuicSocket::from_server_endpoint(endpoint);
+        let client = QuicSocket::new_client(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), &[&server_cert])
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let (client_connection, server_connection) = tokio::join!(
+            client.connect(server_addr, LOOPBACK_SERVER_NAME),
+            server.accept(&mut incoming),
+        );
+        let client_connection = client_connection?;
+        let server_connection = server_connection.ok_or_else(|| anyhow::anyhow!("loopback handshake failed"))?;
+        Ok(Self { server, incoming, client, server_connection, client_connection })
+    }
+}
+
+/// Returns a connected pair of connections over localhost, as `(client, server)`.
+///
+/// The sockets are kept alive by the connections. Use `Loopback` to access them.
+pub async fn pair() -> Result<(Arc<QuicConnection>, Arc<QuicConnection>)> {
+    let loopback = Loopback::new().await?;
+    Ok((loopback.client_connection, loopback.server_connection))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pair_exchanges_messages() {
+        let (client, server) = pair().await.unwrap();
+        let client_stream = client.open_bi_stream().await.unwrap();
+        client.send(client_stream, b"ping").await.unwrap();
+        let server_stream = server.accept_bi_stream().await.unwrap();
+        assert_eq!(server.receive(server_stream).await.unwrap(), b"ping");
+        server.send(server_stream, b"pong").await.unwrap();
+        assert_eq!(client.receive(client_stream).await.unwrap(), b"pong");
+    }
+
+    #[tokio::test]
+    async fn loopback_accepts_further_connections() {
+        let mut loopback = Loopback::new().await.unwrap();
+        let server_addr = loopback.server.endpoint().local_addr().unwrap();
+        let (connection, accepted) = tokio::join!(
+            loopback.client.connect(server_addr, LOOPBACK_SERVER_NAME),
+            loopback.server.accept(&mut loopback.incoming),
+        );
+        assert_eq!(connection.unwrap().connection.remote_address(), server_addr);
+        assert!(accepted.is_some());
+    }
+}
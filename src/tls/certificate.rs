@@ -1,30 +1,28 @@
 //! Certificate handling utilities
 
-use std::{fs, io, path::Path};
+use std::{fs, path::Path};
 use anyhow::{Context, Result};
-use rustls::pki_types::CertificateDer;
+use rustls::pki_types::{CertificateDer, CertificateRevocationListDer};
 
 /// Get the native certificates from the system. return rustls::RootCertStore
-pub fn get_native_certs() -> io::Result<rustls::RootCertStore> {
+#[cfg(feature = "native-certs")]
+pub fn get_native_certs() -> std::io::Result<rustls::RootCertStore> {
     let mut root_store = rustls::RootCertStore::empty();
     match rustls_native_certs::load_native_certs() {
         Ok(certs) => {
             for cert in certs {
-                match root_store.add(cert) {
-                    Ok(_) => {}
-                    Err(_) => {}
-                }
+                let _ = root_store.add(cert);
             }
             Ok(root_store)
         }
-        Err(e) => return Err(e),
+        Err(e) => Err(e),
     }
 }
 
 /// Load certificate chain from a file
 pub fn load_certs(cert_path: &Path) -> Result<Vec<CertificateDer<'static>>> {
     let cert_chain = fs::read(cert_path).context("failed to read certificate chain")?;
-    let cert_chain = if cert_path.extension().map_or(false, |x| x == "der") {
+    let cert_chain = if cert_path.extension().is_some_and(|x| x == "der") {
         vec![CertificateDer::from(cert_chain)]
     } else {
         rustls_pemfile::certs(&mut &*cert_chain)
@@ -33,3 +31,19 @@ pub fn load_certs(cert_path: &Path) -> Result<Vec<CertificateDer<'static>>> {
     };
     Ok(cert_chain)
 }
+
+/// Load certificate revocation lists from a file
+pub fn load_crls(crl_path: &Path) -> Result<Vec<CertificateRevocationListDer<'static>>> {
+    let crls = fs::read(crl_path).with_context(|| format!("failed to read CRL {}", crl_path.display()))?;
+    let crls = if crl_path.extension().is_some_and(|x| x == "der" || x == "crl") && !crls.starts_with(b"-----") {
+        vec![CertificateRevocationListDer::from(crls)]
+    } else {
+        rustls_pemfile::crls(&mut &*crls)
+            .collect::<Result<Vec<_>, _>>()
+            .context("invalid PEM-encoded CRL")?
+    };
+    if crls.is_empty() {
+        anyhow::bail!("no CRL found in {}", crl_path.display());
+    }
+    Ok(crls)
+}
@@ -0,0 +1,84 @@
+//! Restrictions of the cryptographic algorithms used in TLS handshakes.
+//!
+//! By default all algorithms of the `ring` provider are enabled. Deployments with crypto policy
+//! requirements can restrict them with `SocketConfig::with_cipher_suites` and
+//! `SocketConfig::with_key_exchange_groups`.
+
+use std::sync::Arc;
+use rustls::crypto::CryptoProvider;
+use rustls::quic::Suite;
+use rustls::SupportedCipherSuite;
+use rustls::crypto::ring::{cipher_suite, kx_group};
+use rustls::crypto::SupportedKxGroup;
+
+/// A TLS 1.3 cipher suite. QUIC only supports TLS 1.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    /// `TLS13_AES_128_GCM_SHA256`.
+    Aes128GcmSha256,
+    /// `TLS13_AES_256_GCM_SHA384`.
+    Aes256GcmSha384,
+    /// `TLS13_CHACHA20_POLY1305_SHA256`.
+    Chacha20Poly1305Sha256,
+}
+
+impl CipherSuite {
+    /// All cipher suites, in the default order of preference.
+    pub const ALL: [CipherSuite; 3] = [CipherSuite::Aes256GcmSha384, CipherSuite::Aes128GcmSha256, CipherSuite::Chacha20Poly1305Sha256];
+
+    fn to_rustls(self) -> SupportedCipherSuite {
+        match self {
+            CipherSuite::Aes128GcmSha256 => cipher_suite::TLS13_AES_128_GCM_SHA256,
+            CipherSuite::Aes256GcmSha384 => cipher_suite::TLS13_AES_256_GCM_SHA384,
+            CipherSuite::Chacha20Poly1305Sha256 => cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+        }
+    }
+}
+
+/// A key exchange group used in the TLS 1.3 handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyExchangeGroup {
+    /// X25519 (Curve25519 ECDH).
+    X25519,
+    /// NIST P-256 (`secp256r1`).
+    Secp256r1,
+    /// NIST P-384 (`secp384r1`).
+    Secp384r1,
+}
+
+impl KeyExchangeGroup {
+    /// All key exchange groups, in the default order of preference.
+    pub const ALL: [KeyExchangeGroup; 3] = [KeyExchangeGroup::X25519, KeyExchangeGroup::Secp256r1, KeyExchangeGroup::Secp384r1];
+
+    fn to_rustls(self) -> &'static dyn SupportedKxGroup {
+        match self {
+            KeyExchangeGroup::X25519 => kx_group::X25519,
+            KeyExchangeGroup::Secp256r1 => kx_group::SECP256R1,
+            KeyExchangeGroup::Secp384r1 => kx_group::SECP384R1,
+        }
+    }
+}
+
+/// Returns the crypto provider restricted to the given cipher suites and key exchange groups, in order
+/// of preference. An empty list keeps the provider defaults.
+pub(crate) fn crypto_provider(cipher_suites: &[CipherSuite], kx_groups: &[KeyExchangeGroup]) -> Arc<CryptoProvider> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    if !cipher_suites.is_empty() {
+        provider.cipher_suites = cipher_suites.iter().map(|suite| suite.to_rustls()).collect();
+    }
+    if !kx_groups.is_empty() {
+        provider.kx_groups = kx_groups.iter().map(|group| group.to_rustls()).collect();
+    }
+    Arc::new(provider)
+}
+
+/// Returns the suite protecting Initial packets.
+///
+/// QUIC always uses AES-128-GCM for Initial packets, whose keys are public anyway (RFC 9001, section 5.2),
+/// so it is needed even when the cipher suites negotiated for the handshake are restricted.
+pub(crate) fn initial_suite() -> Suite {
+    cipher_suite::TLS13_AES_128_GCM_SHA256
+        .tls13()
+        .and_then(|suite| suite.quic_suite())
+        .expect("TLS13_AES_128_GCM_SHA256 supports QUIC")
+}
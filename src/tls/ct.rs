@@ -0,0 +1,550 @@
+//! Certificate Transparency (RFC 6962) verification of server certificates.
+//!
+//! When enabled with `SocketConfig::with_certificate_transparency`, clients require the server certificate
+//! to carry valid signed certificate timestamps (SCTs) from a number of distinct trusted logs, on top of the
+//! usual certificate verification. This detects misissued publicly-trusted certificates, which must be
+//! logged to be accepted.
+//!
+//! SCTs embedded in the certificate are supported, which is how publicly-trusted CAs deliver them.
+//! SCTs delivered in the TLS handshake or in OCSP responses are not. The trusted logs must be supplied by
+//! the caller, for example from a browser's log list.
+
+use std::fmt;
+use std::sync::Arc;
+use anyhow::Result;
+use ring::digest::{digest, SHA256};
+use ring::signature::{self, UnparsedPublicKey};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+/// The default number of SCTs from distinct logs required.
+pub const DEFAULT_MIN_SCTS: usize = 2;
+
+/// The OID of the embedded SCT list extension (1.3.6.1.4.1.11129.2.4.2), DER-encoded.
+const SCT_LIST_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+
+/// A trusted Certificate Transparency log.
+#[derive(Clone)]
+pub struct CtLog {
+    id: [u8; 32],
+    key: Vec<u8>,
+}
+
+impl CtLog {
+    /// Creates a log from its DER-encoded public key (`SubjectPublicKeyInfo`), as published in log lists.
+    pub fn from_public_key(spki: &[u8]) -> Result<Self> {
+        let key = public_key_bits(spki).ok_or_else(|| anyhow::anyhow!("invalid log public key"))?;
+        let mut id = [0u8; 32];
+        id.copy_from_slice(digest(&SHA256, spki).as_ref());
+        Ok(Self { id, key })
+    }
+    /// Returns the log ID, the SHA-256 hash of its public key.
+    pub fn id(&self) -> &[u8; 32] {
+        &self.id
+    }
+}
+
+impl fmt::Debug for CtLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id: String = self.id.iter().map(|byte| format!("{:02x}", byte)).collect();
+        f.debug_struct("CtLog").field("id", &id).finish()
+    }
+}
+
+/// The Certificate Transparency requirements of a client.
+#[derive(Debug, Clone)]
+pub struct CertificateTransparency {
+    pub(crate) logs: Vec<CtLog>,
+    pub(crate) min_scts: usize,
+}
+
+impl CertificateTransparency {
+    /// Requires `DEFAULT_MIN_SCTS` valid SCTs from distinct logs among `logs`.
+    pub fn new(logs: Vec<CtLog>) -> Self {
+        Self { logs, min_scts: DEFAULT_MIN_SCTS }
+    }
+    /// Sets the number of valid SCTs from distinct logs required.
+    pub fn with_min_scts(mut self, min_scts: usize) -> Self {
+        self.min_scts = min_scts;
+        self
+    }
+}
+
+/// A verifier checking the SCTs of certificates accepted by an inner verifier.
+#[derive(Debug)]
+pub(crate) struct CtVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    /// The roots trusted by `inner`, to find the issuer of certificates issued directly by a root.
+    roots: Arc<RootCertStore>,
+    policy: CertificateTransparency,
+}
+
+impl CtVerifier {
+    pub(crate) fn new(inner: Arc<dyn ServerCertVerifier>, roots: Arc<RootCertStore>, policy: CertificateTransparency) -> Self {
+        Self { inner, roots, policy }
+    }
+    /// Returns the DER-encoded `SubjectPublicKeyInfo` of the issuer of `end_entity`: the first
+    /// intermediate if there is one, or the root it was issued by otherwise.
+    fn issuer_key(&self, end_entity: &[u8], intermediates: &[CertificateDer<'_>]) -> Result<Vec<u8>, rustls::Error> {
+        if let Some(issuer) = intermediates.first() {
+            return subject_spki(issuer).map(<[u8]>::to_vec).ok_or_else(|| ct_error("malformed issuer certificate"));
+        }
+        let issuer = issuer_name(end_entity).ok_or_else(|| ct_error("malformed certificate"))?;
+        let root = self.roots.roots.iter().find(|root| root.subject.as_ref() == issuer);
+        // Trust anchors hold the content of the SubjectPublicKeyInfo, without its SEQUENCE header.
+        root.map(|root| der_encode(0x30, root.subject_public_key_info.as_ref())).ok_or_else(|| ct_error("no issuer certificate to verify SCTs against"))
+    }
+}
+
+impl ServerCertVerifier for CtVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let issuer_key = self.issuer_key(end_entity, intermediates)?;
+        let valid = verify_embedded_scts(end_entity, &issuer_key, &self.policy.logs, now).map_err(|e| ct_error(&e))?;
+        if valid < self.policy.min_scts {
+            return Err(ct_error(&format!("{} valid SCTs from distinct logs, {} required", valid, self.policy.min_scts)));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn ct_error(message: &str) -> rustls::Error {
+    rustls::Error::General(format!("certificate transparency: {}", message))
+}
+
+/// Verifies the SCTs embedded in a certificate issued by the holder of `issuer_key`, a DER-encoded
+/// `SubjectPublicKeyInfo`, returning the number of distinct logs with a valid SCT.
+fn verify_embedded_scts(cert: &[u8], issuer_key: &[u8], logs: &[CtLog], now: UnixTime) -> Result<usize, String> {
+    let (tbs, sct_list) = precert_tbs(cert).ok_or("malformed certificate")?;
+    let sct_list = sct_list.ok_or("no embedded SCTs")?;
+    let issuer_key_hash = digest(&SHA256, issuer_key);
+    let mut valid_logs: Vec<&[u8; 32]> = Vec::new();
+    for sct in parse_sct_list(sct_list).ok_or("malformed SCT list")? {
+        let Some(log) = logs.iter().find(|log| log.id == sct.log_id) else { continue };
+        if valid_logs.contains(&&log.id) || sct.timestamp > now.as_secs().saturating_mul(1000) {
+            continue;
+        }
+        // The signed data of a precertificate entry (RFC 6962, section 3.2).
+        let mut signed = Vec::with_capacity(tbs.len() + 64);
+        signed.extend_from_slice(&[0, 0]);
+        signed.extend_from_slice(&sct.timestamp.to_be_bytes());
+        signed.extend_from_slice(&[0, 1]);
+        signed.extend_from_slice(issuer_key_hash.as_ref());
+        signed.extend_from_slice(&(tbs.len() as u32).to_be_bytes()[1..]);
+        signed.extend_from_slice(&tbs);
+        signed.extend_from_slice(&(sct.extensions.len() as u16).to_be_bytes());
+        signed.extend_from_slice(sct.extensions);
+        let algorithm: &dyn signature::VerificationAlgorithm = match sct.algorithm {
+            (4, 3) => &signature::ECDSA_P256_SHA256_ASN1,
+            (4, 1) => &signature::RSA_PKCS1_2048_8192_SHA256,
+            _ => continue,
+        };
+        if UnparsedPublicKey::new(algorithm, &log.key).verify(&signed, sct.signature).is_ok() {
+            valid_logs.push(&log.id);
+        } else {
+            tracing::debug!("Invalid SCT signature from log {:?}", log);
+        }
+    }
+    Ok(valid_logs.len())
+}
+
+/// A signed certificate timestamp.
+struct Sct<'a> {
+    log_id: [u8; 32],
+    timestamp: u64,
+    extensions: &'a [u8],
+    /// The hash and signature algorithms.
+    algorithm: (u8, u8),
+    signature: &'a [u8],
+}
+
+/// Parses a TLS-encoded SCT list, skipping SCTs of unknown versions.
+fn parse_sct_list(data: &[u8]) -> Option<Vec<Sct<'_>>> {
+    let mut reader = Reader(data);
+    let mut list = Reader(reader.vector(2)?);
+    let mut scts = Vec::new();
+    while !list.0.is_empty() {
+        let mut sct = Reader(list.vector(2)?);
+        if sct.bytes(1)? != [0] {
+            continue;
+        }
+        let log_id = sct.bytes(32)?.try_into().ok()?;
+        let timestamp = u64::from_be_bytes(sct.bytes(8)?.try_into().ok()?);
+        let extensions = sct.vector(2)?;
+        let algorithm = sct.bytes(2)?;
+        let signature = sct.vector(2)?;
+        scts.push(Sct { log_id, timestamp, extensions, algorithm: (algorithm[0], algorithm[1]), signature });
+    }
+    Some(scts)
+}
+
+/// A reader of TLS-encoded data.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+    /// Reads a vector with a big-endian length prefix of `len_size` bytes.
+    fn vector(&mut self, len_size: usize) -> Option<&'a [u8]> {
+        let len = self.bytes(len_size)?.iter().fold(0usize, |len, &byte| len << 8 | byte as usize);
+        self.bytes(len)
+    }
+}
+
+/// A DER element: its tag, its content and the whole encoding.
+struct Der<'a> {
+    tag: u8,
+    content: &'a [u8],
+    raw: &'a [u8],
+}
+
+/// Splits the first DER element off `data`.
+fn der_element(data: &[u8]) -> Option<(Der<'_>, &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)?;
+    let (len, header) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let size = (first & 0x7f) as usize;
+        if size == 0 || size > 4 {
+            return None;
+        }
+        let len = data.get(2..2 + size)?.iter().fold(0usize, |len, &byte| len << 8 | byte as usize);
+        (len, 2 + size)
+    };
+    let end = header.checked_add(len)?;
+    let raw = data.get(..end)?;
+    Some((Der { tag, content: &raw[header..], raw }, &data[end..]))
+}
+
+/// Splits DER content into its elements.
+fn der_elements(mut data: &[u8]) -> Option<Vec<Der<'_>>> {
+    let mut elements = Vec::new();
+    while !data.is_empty() {
+        let (element, rest) = der_element(data)?;
+        elements.push(element);
+        data = rest;
+    }
+    Some(elements)
+}
+
+/// Encodes a DER element.
+fn der_encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        encoded.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|&&byte| byte == 0).count();
+        encoded.push(0x80 | (4 - skip) as u8);
+        encoded.extend_from_slice(&bytes[skip..]);
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+/// Returns the elements of the `TBSCertificate` of a certificate.
+fn tbs_elements(cert: &[u8]) -> Option<Vec<Der<'_>>> {
+    let (cert, _) = der_element(cert)?;
+    let (tbs, _) = der_element(cert.content)?;
+    der_elements(tbs.content)
+}
+
+/// Returns the `TBSCertificate` of a certificate with the SCT list extension removed, which is what the
+/// logs signed, and the content of the SCT list extension, if any.
+fn precert_tbs(cert: &[u8]) -> Option<(Vec<u8>, Option<&[u8]>)> {
+    let mut content = Vec::new();
+    let mut sct_list = None;
+    for element in tbs_elements(cert)? {
+        // Extensions are the explicitly tagged [3] element.
+        if element.tag != 0xa3 {
+            content.extend_from_slice(element.raw);
+            continue;
+        }
+        let (extensions, _) = der_element(element.content)?;
+        let mut kept = Vec::new();
+        for extension in der_elements(extensions.content)? {
+            let fields = der_elements(extension.content)?;
+            if fields.first()?.content == SCT_LIST_OID {
+                // The extension value is an OCTET STRING holding an OCTET STRING with the TLS-encoded list.
+                let (list, _) = der_element(fields.last()?.content)?;
+                sct_list = Some(list.content);
+            } else {
+                kept.extend_from_slice(extension.raw);
+            }
+        }
+        if !kept.is_empty() {
+            content.extend_from_slice(&der_encode(0xa3, &der_encode(0x30, &kept)));
+        }
+    }
+    Some((der_encode(0x30, &content), sct_list))
+}
+
+/// Returns the element of the `TBSCertificate` of a certificate at `index`, counted from the serial number:
+/// serial, signature, issuer, validity, subject, subjectPublicKeyInfo.
+fn tbs_field(cert: &[u8], index: usize) -> Option<Der<'_>> {
+    let mut elements = tbs_elements(cert)?;
+    // The version is the optional, explicitly tagged [0] element.
+    let offset = if elements.first()?.tag == 0xa0 { 1 } else { 0 };
+    (offset + index < elements.len()).then(|| elements.swap_remove(offset + index))
+}
+
+/// Returns the content of the issuer `Name` of a certificate, as trust anchors hold subjects.
+fn issuer_name(cert: &[u8]) -> Option<&[u8]> {
+    Some(tbs_field(cert, 2)?.content)
+}
+
+/// Returns the DER-encoded `SubjectPublicKeyInfo` of a certificate.
+fn subject_spki(cert: &[u8]) -> Option<&[u8]> {
+    Some(tbs_field(cert, 5)?.raw)
+}
+
+/// Returns the public key bits of a DER-encoded `SubjectPublicKeyInfo`, as ring expects them.
+fn public_key_bits(spki: &[u8]) -> Option<Vec<u8>> {
+    let (spki, _) = der_element(spki)?;
+    let elements = der_elements(spki.content)?;
+    let bits = elements.get(1).filter(|bits| bits.tag == 0x03)?;
+    // Skip the unused bits count of the BIT STRING.
+    Some(bits.content.get(1..)?.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, CustomExtension, DistinguishedName, DnType, IsCa, KeyPair};
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use std::time::Duration;
+
+    /// The certificate of letsencrypt.org, with two embedded SCTs.
+    const LETSENCRYPT_ORG: &[u8] = include_bytes!("testdata/letsencrypt_org.der");
+    /// The certificate of the issuer of `LETSENCRYPT_ORG`.
+    const LETSENCRYPT_YE2: &[u8] = include_bytes!("testdata/letsencrypt_ye2.der");
+    const NOW: Duration = Duration::from_secs(1_800_000_000);
+    const ISSUED_AT: u64 = 1_700_000_000_000;
+
+    /// A log signing SCTs with a P-256 key.
+    struct TestLog {
+        key: EcdsaKeyPair,
+        log: CtLog,
+    }
+
+    impl TestLog {
+        fn new() -> Self {
+            let key_pair = KeyPair::generate().unwrap();
+            let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &key_pair.serialize_der(), &SystemRandom::new()).unwrap();
+            let log = CtLog::from_public_key(&key_pair.public_key_der()).unwrap();
+            Self { key, log }
+        }
+        /// Returns a TLS-encoded SCT for the precertificate `tbs` issued by the holder of `issuer_key`.
+        fn sct(&self, timestamp: u64, issuer_key: &[u8], tbs: &[u8]) -> Vec<u8> {
+            let mut signed = vec![0, 0];
+            signed.extend_from_slice(&timestamp.to_be_bytes());
+            signed.extend_from_slice(&[0, 1]);
+            signed.extend_from_slice(digest(&SHA256, issuer_key).as_ref());
+            signed.extend_from_slice(&(tbs.len() as u32).to_be_bytes()[1..]);
+            signed.extend_from_slice(tbs);
+            signed.extend_from_slice(&[0, 0]);
+            let signature = self.key.sign(&SystemRandom::new(), &signed).unwrap();
+            let mut sct = vec![0];
+            sct.extend_from_slice(&self.log.id);
+            sct.extend_from_slice(&timestamp.to_be_bytes());
+            sct.extend_from_slice(&[0, 0, 4, 3]);
+            sct.extend_from_slice(&(signature.as_ref().len() as u16).to_be_bytes());
+            sct.extend_from_slice(signature.as_ref());
+            sct
+        }
+    }
+
+    fn ca(name: &str, issuer: Option<(&rcgen::Certificate, &KeyPair)>) -> (rcgen::Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let cert = match issuer {
+            Some((issuer, issuer_key)) => params.signed_by(&key, issuer, issuer_key),
+            None => params.self_signed(&key),
+        };
+        (cert.unwrap(), key)
+    }
+
+    fn leaf_params() -> CertificateParams {
+        CertificateParams::new(vec!["localhost".to_string()]).unwrap()
+    }
+
+    /// Issues a certificate from `params` embedding the SCTs `scts` returns for its precertificate.
+    fn leaf(mut params: CertificateParams, issuer: &rcgen::Certificate, issuer_key: &KeyPair, scts: impl Fn(&[u8]) -> Vec<Vec<u8>>) -> Vec<u8> {
+        let key = KeyPair::generate().unwrap();
+        let precert = params.clone().signed_by(&key, issuer, issuer_key).unwrap();
+        let (cert, _) = der_element(precert.der()).unwrap();
+        let (tbs, _) = der_element(cert.content).unwrap();
+        let list: Vec<u8> = scts(tbs.raw).iter().flat_map(|sct| [&(sct.len() as u16).to_be_bytes()[..], sct].concat()).collect();
+        let list = [&(list.len() as u16).to_be_bytes()[..], &list].concat();
+        params.custom_extensions.push(CustomExtension::from_oid_content(&[1, 3, 6, 1, 4, 1, 11129, 2, 4, 2], der_encode(0x04, &list)));
+        params.signed_by(&key, issuer, issuer_key).unwrap().der().to_vec()
+    }
+
+    fn verifier(root: &rcgen::Certificate, logs: &[&TestLog], min_scts: usize) -> CtVerifier {
+        let mut roots = RootCertStore::empty();
+        roots.add(root.der().clone()).unwrap();
+        let roots = Arc::new(roots);
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let inner = rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::clone(&roots), provider).build().unwrap();
+        let logs = logs.iter().map(|log| log.log.clone()).collect();
+        CtVerifier::new(inner, roots, CertificateTransparency::new(logs).with_min_scts(min_scts))
+    }
+
+    fn verify(verifier: &CtVerifier, chain: &[&[u8]]) -> Result<ServerCertVerified, rustls::Error> {
+        let end_entity = CertificateDer::from(chain[0].to_vec());
+        let intermediates: Vec<_> = chain[1..].iter().map(|cert| CertificateDer::from(cert.to_vec())).collect();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        verifier.verify_server_cert(&end_entity, &intermediates, &server_name, &[], UnixTime::since_unix_epoch(NOW))
+    }
+
+    fn assert_rejected(result: Result<ServerCertVerified, rustls::Error>, reason: &str) {
+        match result {
+            Err(rustls::Error::General(message)) => assert!(message.contains(reason), "unexpected error: {}", message),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("certificate accepted"),
+        }
+    }
+
+    #[test]
+    fn parses_embedded_scts_of_real_certificate() {
+        let (tbs, list) = precert_tbs(LETSENCRYPT_ORG).unwrap();
+        let scts = parse_sct_list(list.unwrap()).unwrap();
+        assert_eq!(scts.len(), 2);
+        assert_eq!(scts[0].log_id[..4], [0x94, 0x4e, 0x43, 0x87]);
+        assert_eq!(scts[0].timestamp, 1_783_354_984_810);
+        assert!(scts[0].extensions.is_empty());
+        assert_eq!(scts[1].log_id[..4], [0x1a, 0x8b, 0x9d, 0x6b]);
+        assert_eq!(scts[1].extensions, [0x00, 0x00, 0x05, 0x00, 0x26, 0xd8, 0x5d, 0x88]);
+        assert!(scts.iter().all(|sct| sct.algorithm == (4, 3)));
+        // The precertificate is the TBSCertificate without the SCT list extension.
+        let contains_oid = |der: &[u8]| der.windows(SCT_LIST_OID.len()).any(|window| window == SCT_LIST_OID);
+        let (cert, _) = der_element(LETSENCRYPT_ORG).unwrap();
+        let (original, _) = der_element(cert.content).unwrap();
+        assert!(contains_oid(original.raw));
+        assert!(!contains_oid(&tbs));
+        assert!(der_element(&tbs).is_some_and(|(element, rest)| element.tag == 0x30 && rest.is_empty()));
+    }
+
+    #[test]
+    fn finds_issuer_of_real_certificate() {
+        assert_eq!(issuer_name(LETSENCRYPT_ORG).unwrap(), tbs_field(LETSENCRYPT_YE2, 4).unwrap().content);
+        let issuer_key = subject_spki(LETSENCRYPT_YE2).unwrap();
+        assert!(public_key_bits(issuer_key).is_some());
+        // Neither log is trusted, so no SCT is valid.
+        assert_eq!(verify_embedded_scts(LETSENCRYPT_ORG, issuer_key, &[], UnixTime::since_unix_epoch(NOW)), Ok(0));
+    }
+
+    #[test]
+    fn verifies_embedded_scts_from_distinct_logs() {
+        let (logs, root) = ([TestLog::new(), TestLog::new()], ca("Root", None));
+        let intermediate = ca("Intermediate", Some((&root.0, &root.1)));
+        let issuer_key = intermediate.1.public_key_der();
+        let cert = leaf(leaf_params(), &intermediate.0, &intermediate.1, |tbs| logs.iter().map(|log| log.sct(ISSUED_AT, &issuer_key, tbs)).collect());
+        assert!(verify(&verifier(&root.0, &[&logs[0], &logs[1]], 2), &[&cert, intermediate.0.der()]).is_ok());
+        assert_rejected(verify(&verifier(&root.0, &[&logs[0]], 2), &[&cert, intermediate.0.der()]), "1 valid SCTs");
+    }
+
+    #[test]
+    fn verifies_certificate_issued_by_root() {
+        let (log, root) = (TestLog::new(), ca("Root", None));
+        let issuer_key = root.1.public_key_der();
+        let cert = leaf(leaf_params(), &root.0, &root.1, |tbs| vec![log.sct(ISSUED_AT, &issuer_key, tbs)]);
+        assert!(verify(&verifier(&root.0, &[&log], 1), &[&cert]).is_ok());
+    }
+
+    #[test]
+    fn counts_each_log_once() {
+        let (log, root) = (TestLog::new(), ca("Root", None));
+        let issuer_key = root.1.public_key_der();
+        let cert = leaf(leaf_params(), &root.0, &root.1, |tbs| vec![log.sct(ISSUED_AT, &issuer_key, tbs), log.sct(ISSUED_AT + 1, &issuer_key, tbs)]);
+        assert_rejected(verify(&verifier(&root.0, &[&log], 2), &[&cert]), "1 valid SCTs");
+    }
+
+    #[test]
+    fn ignores_untrusted_logs() {
+        let (log, untrusted, root) = (TestLog::new(), TestLog::new(), ca("Root", None));
+        let issuer_key = root.1.public_key_der();
+        let cert = leaf(leaf_params(), &root.0, &root.1, |tbs| vec![untrusted.sct(ISSUED_AT, &issuer_key, tbs)]);
+        assert_rejected(verify(&verifier(&root.0, &[&log], 1), &[&cert]), "0 valid SCTs");
+    }
+
+    #[test]
+    fn rejects_tampered_sct() {
+        let (log, root) = (TestLog::new(), ca("Root", None));
+        let issuer_key = root.1.public_key_der();
+        let cert = leaf(leaf_params(), &root.0, &root.1, |tbs| {
+            let mut sct = log.sct(ISSUED_AT, &issuer_key, tbs);
+            *sct.last_mut().unwrap() ^= 1;
+            vec![sct]
+        });
+        assert_rejected(verify(&verifier(&root.0, &[&log], 1), &[&cert]), "0 valid SCTs");
+    }
+
+    #[test]
+    fn rejects_sct_for_another_issuer() {
+        let (log, root, other) = (TestLog::new(), ca("Root", None), ca("Other", None));
+        let other_key = other.1.public_key_der();
+        let cert = leaf(leaf_params(), &root.0, &root.1, |tbs| vec![log.sct(ISSUED_AT, &other_key, tbs)]);
+        assert_rejected(verify(&verifier(&root.0, &[&log], 1), &[&cert]), "0 valid SCTs");
+    }
+
+    #[test]
+    fn rejects_sct_from_the_future() {
+        let (log, root) = (TestLog::new(), ca("Root", None));
+        let issuer_key = root.1.public_key_der();
+        let future = NOW.as_millis() as u64 + 1000;
+        let cert = leaf(leaf_params(), &root.0, &root.1, |tbs| vec![log.sct(future, &issuer_key, tbs)]);
+        assert_rejected(verify(&verifier(&root.0, &[&log], 1), &[&cert]), "0 valid SCTs");
+    }
+
+    #[test]
+    fn rejects_expired_certificate() {
+        let (log, root) = (TestLog::new(), ca("Root", None));
+        let issuer_key = root.1.public_key_der();
+        let mut params = leaf_params();
+        params.not_after = rcgen::date_time_ymd(2020, 1, 1);
+        let cert = leaf(params, &root.0, &root.1, |tbs| vec![log.sct(ISSUED_AT, &issuer_key, tbs)]);
+        let result = verify(&verifier(&root.0, &[&log], 1), &[&cert]);
+        assert!(matches!(result, Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ExpiredContext { .. }))));
+    }
+
+    /// rustls does not pass SCTs delivered in the TLS extension to verifiers, so only embedded SCTs count.
+    #[test]
+    fn rejects_certificate_without_embedded_scts() {
+        let (log, root) = (TestLog::new(), ca("Root", None));
+        let cert = leaf_params().signed_by(&KeyPair::generate().unwrap(), &root.0, &root.1).unwrap();
+        assert_rejected(verify(&verifier(&root.0, &[&log], 1), &[cert.der()]), "no embedded SCTs");
+    }
+}
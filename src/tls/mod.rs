@@ -1,6 +1,8 @@
 //! TLS module for managing certificates and private keys.
 
 pub mod certificate;
+pub mod crypto;
+pub mod ct;
 pub mod key;
 
 use std::path::Path;
@@ -0,0 +1,849 @@
+//! File and directory transfer.
+//!
+//! The sender opens a bi-directional stream and writes a sequence of entries, each a length-prefixed
+//! JSON header followed by the file contents, and an end marker. The receiver recreates the entries
+//! under a destination directory and acknowledges the transfer with its own summary.
+//!
+//! Both ends compute a BLAKE3 hash over all entries as they are written and read. The sender puts its
+//! digest in the end marker and the receiver verifies it, so corruption anywhere between the two file
+//! systems is detected. The digest is returned in `TransferSummary::digest`. The receiver writes each
+//! file to a temporary sibling (`.name.part`) and only renames the files into place once the digest
+//! matches, so a failed transfer leaves the destination as it was.
+//!
+//! The sender's throughput can be capped per transfer with `TransferOptions::rate_limit`, independently
+//! of connection-level limits. The send loop is paced to the limit rather than relying on flow control.
+//!
+//! Files are sent through a pipeline: up to `TransferOptions::read_ahead` chunks are read from disk
+//! concurrently, ahead of the chunks being written to the stream, and each chunk is handed to the stream
+//! without another copy. The chunk size and read-ahead can be tuned for fast disks and networks.
+//!
+//! With the `mmap` feature, `send_file_mmap` sends a file from a memory map of it instead of reading it
+//! into a buffer, for large files. It is `unsafe`, since the file must not change while it is sent. The
+//! wire format is the same, so any receiver accepts it.
+//!
+//! Entry paths are relative and `/`-separated. The receiver rejects absolute paths and paths
+//! leaving the destination directory. Symbolic links and special files are skipped by the sender.
+
+use anyhow::{Context, Result};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use quinn::{RecvStream, SendStream};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio_util::codec::Encoder;
+use crate::error::Error;
+use crate::framing::{FrameCodec, FrameDecoder};
+use crate::QuicConnection;
+
+/// The size of the chunks files are received in, and the default size they are sent in, in bytes.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+/// The default number of chunks read from disk ahead of the stream when sending.
+pub const DEFAULT_READ_AHEAD: usize = 8;
+/// The maximum size of an entry header, in bytes.
+pub const MAX_HEADER_SIZE: usize = 64 * 1024;
+/// The Unix permission bits transferred with entries. Setuid, setgid and sticky bits are not.
+const PERMISSION_BITS: u32 = 0o777;
+
+/// The kind of a transferred entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum EntryKind {
+    File,
+    Directory,
+    End,
+}
+
+/// The header preceding each entry on the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EntryHeader {
+    kind: EntryKind,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    size: u64,
+    /// Unix permission bits, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<u32>,
+    /// Modification time in seconds since the Unix epoch, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<u64>,
+    /// The hex-encoded BLAKE3 digest of all preceding entries. Only set on the end marker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+}
+
+/// The receiver's acknowledgement of a transfer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Ack {
+    summary: TransferSummary,
+    /// Why the receiver rejected the transfer, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// What was transferred.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferSummary {
+    /// The number of files.
+    pub files: u64,
+    /// The number of directories.
+    pub directories: u64,
+    /// The total size of the files, in bytes.
+    pub bytes: u64,
+    /// The BLAKE3 digest of the transferred entries, including file contents and metadata.
+    pub digest: [u8; 32],
+}
+
+impl TransferSummary {
+    /// Returns the digest as a hex string.
+    pub fn digest_hex(&self) -> String {
+        blake3::Hash::from_bytes(self.digest).to_hex().to_string()
+    }
+}
+
+/// Options for sending a transfer.
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    /// The maximum throughput of the transfer, in bytes per second. `None` means unlimited.
+    pub rate_limit: Option<u64>,
+    /// The size of the chunks files are read and sent in, in bytes.
+    pub chunk_size: usize,
+    /// The maximum number of chunks read from disk ahead of the stream, which is also the number of
+    /// concurrent disk reads.
+    pub read_ahead: usize,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self { rate_limit: None, chunk_size: CHUNK_SIZE, read_ahead: DEFAULT_READ_AHEAD }
+    }
+}
+
+impl TransferOptions {
+    /// Creates options with no rate limit, `CHUNK_SIZE` chunks and `DEFAULT_READ_AHEAD` chunks of read-ahead.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Caps the throughput of the transfer to `bytes_per_second`.
+    pub fn with_rate_limit(mut self, bytes_per_second: u64) -> Self {
+        self.rate_limit = Some(bytes_per_second);
+        self
+    }
+    /// Sets the size of the chunks files are read and sent in. Larger chunks mean fewer disk reads.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+    /// Sets how many chunks are read from disk ahead of the stream. Memory use is bounded by
+    /// `read_ahead * chunk_size` per transfer.
+    pub fn with_read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+}
+
+/// Paces writes to a rate, by sleeping whenever more was written than the rate allows for the elapsed time.
+struct Pacer {
+    bytes_per_second: u64,
+    start: Instant,
+    written: u64,
+}
+
+impl Pacer {
+    fn new(bytes_per_second: u64) -> Self {
+        Self { bytes_per_second: bytes_per_second.max(1), start: Instant::now(), written: 0 }
+    }
+    async fn pace(&mut self, len: usize) {
+        self.written += len as u64;
+        let due = Duration::from_secs_f64(self.written as f64 / self.bytes_per_second as f64);
+        tokio::time::sleep_until(self.start + due).await;
+    }
+}
+
+/// The sending side of a transfer stream, hashing everything written to it.
+struct EntryWriter {
+    send: SendStream,
+    hasher: blake3::Hasher,
+    pacer: Option<Pacer>,
+    chunk_size: usize,
+    read_ahead: usize,
+}
+
+impl EntryWriter {
+    fn new(send: SendStream, options: &TransferOptions) -> Self {
+        Self {
+            send,
+            hasher: blake3::Hasher::new(),
+            pacer: options.rate_limit.map(Pacer::new),
+            chunk_size: options.chunk_size.max(1),
+            read_ahead: options.read_ahead.max(1),
+        }
+    }
+    /// Writes an entry header as a length-prefixed JSON frame.
+    async fn write_header(&mut self, header: &EntryHeader) -> Result<()> {
+        let frame = encode_frame(&serde_json::to_vec(header)?)?;
+        self.write(&frame).await
+    }
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.hasher.update(data);
+        self.send.write_all(data).await.map_err(Error::from)?;
+        if let Some(pacer) = &mut self.pacer {
+            pacer.pace(data.len()).await;
+        }
+        Ok(())
+    }
+    /// Writes a chunk without copying it.
+    async fn write_chunk(&mut self, chunk: Bytes) -> Result<()> {
+        self.hasher.update(&chunk);
+        let len = chunk.len();
+        self.send.write_chunk(chunk).await.map_err(Error::from)?;
+        if let Some(pacer) = &mut self.pacer {
+            pacer.pace(len).await;
+        }
+        Ok(())
+    }
+}
+
+/// The receiving side of a transfer stream, hashing everything read from it.
+struct EntryReader {
+    recv: RecvStream,
+    hasher: blake3::Hasher,
+}
+
+impl EntryReader {
+    fn new(recv: RecvStream) -> Self {
+        Self { recv, hasher: blake3::Hasher::new() }
+    }
+    /// Reads an entry header. The end marker is not hashed, as it carries the sender's digest.
+    async fn read_header(&mut self) -> Result<EntryHeader> {
+        let frame = read_frame(&mut self.recv).await?;
+        let header: EntryHeader = serde_json::from_slice(&frame)?;
+        if header.kind != EntryKind::End {
+            self.hasher.update(&encode_frame(&frame)?);
+        }
+        Ok(header)
+    }
+    async fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.recv.read_exact(buffer).await.map_err(Error::from)?;
+        self.hasher.update(buffer);
+        Ok(())
+    }
+}
+
+/// Include and exclude patterns selecting the entries of a directory transfer.
+///
+/// Patterns are globs where `*` matches within a path component, `?` matches a single character
+/// and `**` matches across components. Patterns without a `/` are matched against entry names,
+/// others against paths relative to the transferred directory.
+///
+/// Excluded directories are skipped with all their contents. If include patterns are set, only
+/// files matching one of them are sent, and directories are only recreated as parents of those files.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Filter {
+    /// Creates a filter selecting every entry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds an include pattern.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_string());
+        self
+    }
+    /// Adds an exclude pattern.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+    /// Returns whether the entry at `path` is excluded.
+    fn is_excluded(&self, path: &str) -> bool {
+        self.exclude.iter().any(|pattern| matches_pattern(pattern, path))
+    }
+    /// Returns whether the file at `path` is selected.
+    fn includes_file(&self, path: &str) -> bool {
+        !self.is_excluded(path) && (self.include.is_empty() || self.include.iter().any(|pattern| matches_pattern(pattern, path)))
+    }
+}
+
+/// Matches a filter pattern against a relative path.
+fn matches_pattern(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        glob_match(pattern.as_bytes(), path.as_bytes())
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+/// Matches a glob pattern supporting `*`, `**` and `?`.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+            // `**/` also matches no directory at all.
+            let rest = &rest[1..];
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                if glob_match(after_slash, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        },
+        Some((b'*', rest)) => {
+            (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != b'/')
+                .any(|i| glob_match(rest, &text[i..]))
+        },
+        Some((b'?', rest)) => text.first().is_some_and(|&c| c != b'/') && glob_match(rest, &text[1..]),
+        Some((&c, rest)) => text.first() == Some(&c) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// Sends a single file, which the receiver saves under its file name.
+pub async fn send_file(connection: &QuicConnection, path: &Path) -> Result<TransferSummary> {
+    send_file_with_options(connection, path, &TransferOptions::default()).await
+}
+
+/// Sends a single file like `send_file`, with the given options.
+pub async fn send_file_with_options(connection: &QuicConnection, path: &Path, options: &TransferOptions) -> Result<TransferSummary> {
+    let name = file_name(path)?;
+    let (send, mut recv) = connection.connection.open_bi().await.map_err(Error::from)?;
+    let mut writer = EntryWriter::new(send, options);
+    let mut summary = TransferSummary::default();
+    send_file_entry(&mut writer, path, name, &mut summary).await?;
+    finish(writer, &mut recv, summary).await
+}
+
+/// Sends a single file like `send_file`, reading it through a memory map instead of a buffer.
+///
+/// The file is handed to the stream as slices of the map, without being copied into memory first,
+/// which suits multi-gigabyte files.
+///
+/// # Safety
+///
+/// The file must not be modified or truncated, by this or any other process, until the returned future
+/// completes. Modifying it makes the sent contents undefined, and truncating it can crash the process.
+#[cfg(feature = "mmap")]
+pub async unsafe fn send_file_mmap(connection: &QuicConnection, path: &Path) -> Result<TransferSummary> {
+    unsafe { send_file_mmap_with_options(connection, path, &TransferOptions::default()).await }
+}
+
+/// Sends a single file like `send_file_mmap`, with the given options.
+///
+/// # Safety
+///
+/// The file must not be modified or truncated until the returned future completes, as for `send_file_mmap`.
+#[cfg(feature = "mmap")]
+pub async unsafe fn send_file_mmap_with_options(connection: &QuicConnection, path: &Path, options: &TransferOptions) -> Result<TransferSummary> {
+    let name = file_name(path)?;
+    let (send, mut recv) = connection.connection.open_bi().await.map_err(Error::from)?;
+    let mut writer = EntryWriter::new(send, options);
+    let mut summary = TransferSummary::default();
+    unsafe { send_file_entry_mmap(&mut writer, path, name, &mut summary).await? };
+    finish(writer, &mut recv, summary).await
+}
+
+/// Returns the name a single file is saved under by the receiver.
+fn file_name(path: &Path) -> Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("invalid file name: {}", path.display()))
+}
+
+/// Sends a directory and its contents, selected by the filter.
+///
+/// The receiver recreates the directory's contents directly under its destination directory.
+pub async fn send_directory(connection: &QuicConnection, root: &Path, filter: &Filter) -> Result<TransferSummary> {
+    send_directory_with_options(connection, root, filter, &TransferOptions::default()).await
+}
+
+/// Sends a directory like `send_directory`, with the given options.
+pub async fn send_directory_with_options(connection: &QuicConnection, root: &Path, filter: &Filter, options: &TransferOptions) -> Result<TransferSummary> {
+    if !tokio::fs::metadata(root).await?.is_dir() {
+        anyhow::bail!("not a directory: {}", root.display());
+    }
+    let (send, mut recv) = connection.connection.open_bi().await.map_err(Error::from)?;
+    let mut writer = EntryWriter::new(send, options);
+    let mut summary = TransferSummary::default();
+    // Depth-first walk with sorted entries, so transfers are reproducible.
+    let mut pending = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, relative)) = pending.pop() {
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&dir).await.with_context(|| format!("failed to read {}", dir.display()))?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            entries.push(entry);
+        }
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut subdirectories = Vec::new();
+        for entry in entries {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                tracing::warn!("Skipping entry with a non UTF-8 name: {}", entry.path().display());
+                continue;
+            };
+            let path = if relative.is_empty() { name } else { format!("{}/{}", relative, name) };
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                if filter.is_excluded(&path) {
+                    continue;
+                }
+                if filter.include.is_empty() {
+                    let metadata = entry.metadata().await?;
+                    writer.write_header(&EntryHeader {
+                        kind: EntryKind::Directory,
+                        path: path.clone(),
+                        size: 0,
+                        mode: mode(&metadata),
+                        modified: None,
+                        digest: None,
+                    }).await?;
+                    summary.directories += 1;
+                }
+                subdirectories.push((entry.path(), path));
+            } else if file_type.is_file() {
+                if filter.includes_file(&path) {
+                    send_file_entry(&mut writer, &entry.path(), &path, &mut summary).await?;
+                }
+            } else {
+                tracing::debug!("Skipping special file or symbolic link: {}", entry.path().display());
+            }
+        }
+        pending.extend(subdirectories.into_iter().rev());
+    }
+    finish(writer, &mut recv, summary).await
+}
+
+/// Writes a file entry: its header followed by its contents.
+async fn send_file_entry(writer: &mut EntryWriter, path: &Path, relative: &str, summary: &mut TransferSummary) -> Result<()> {
+    let file = File::open(path).await.with_context(|| format!("failed to open {}", path.display()))?;
+    let metadata = file.metadata().await?;
+    let size = metadata.len();
+    writer.write_header(&file_header(relative, &metadata)).await?;
+    // Chunks are read by blocking tasks at their offsets, so several reads are in flight while earlier
+    // chunks are written. `buffered` yields them in order and starts a new read as each one is taken.
+    let file = Arc::new(file.into_std().await);
+    let chunk_size = writer.chunk_size as u64;
+    let mut chunks = futures::stream::iter((0..size).step_by(writer.chunk_size))
+        .map(|offset| {
+            let file = Arc::clone(&file);
+            let len = chunk_size.min(size - offset) as usize;
+            async move { tokio::task::spawn_blocking(move || read_chunk_at(&file, offset, len)).await? }
+        })
+        .buffered(writer.read_ahead);
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.with_context(|| format!("{} changed while being sent", path.display()))?;
+        writer.write_chunk(chunk).await?;
+    }
+    summary.files += 1;
+    summary.bytes += size;
+    Ok(())
+}
+
+/// Writes a file entry like `send_file_entry`, from a memory map of the file.
+///
+/// The contents are written as slices of the map, so they are not copied into a buffer first.
+///
+/// # Safety
+///
+/// The file must not be modified or truncated until the returned future completes.
+#[cfg(feature = "mmap")]
+async unsafe fn send_file_entry_mmap(writer: &mut EntryWriter, path: &Path, relative: &str, summary: &mut TransferSummary) -> Result<()> {
+    let file = std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let metadata = file.metadata()?;
+    let size = metadata.len();
+    writer.write_header(&file_header(relative, &metadata)).await?;
+    if size > 0 {
+        // Safety: the caller guarantees the file is not modified or truncated while the map is in use.
+        let map = unsafe { memmap2::MmapOptions::new().len(size as usize).map(&file) }
+            .with_context(|| format!("failed to map {}", path.display()))?;
+        let _ = map.advise(memmap2::Advice::Sequential);
+        let contents = Bytes::from_owner(map);
+        let mut offset = 0;
+        while offset < contents.len() {
+            let len = (contents.len() - offset).min(writer.chunk_size);
+            writer.write_chunk(contents.slice(offset..offset + len)).await?;
+            offset += len;
+        }
+    }
+    summary.files += 1;
+    summary.bytes += size;
+    Ok(())
+}
+
+/// Reads the chunk of a file at an offset.
+fn read_chunk_at(file: &std::fs::File, offset: u64, len: usize) -> io::Result<Bytes> {
+    let mut chunk = vec![0; len];
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(&mut chunk, offset)?;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        let mut read = 0;
+        while read < len {
+            match file.seek_read(&mut chunk[read..], offset + read as u64)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (file, offset);
+        return Err(io::ErrorKind::Unsupported.into());
+    }
+    Ok(Bytes::from(chunk))
+}
+
+/// Returns the header of a file entry.
+fn file_header(relative: &str, metadata: &std::fs::Metadata) -> EntryHeader {
+    EntryHeader {
+        kind: EntryKind::File,
+        path: relative.to_string(),
+        size: metadata.len(),
+        mode: mode(metadata),
+        modified: metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|time| time.as_secs()),
+        digest: None,
+    }
+}
+
+/// Writes the end marker with the digest and waits for the receiver's acknowledgement.
+async fn finish(writer: EntryWriter, recv: &mut RecvStream, mut summary: TransferSummary) -> Result<TransferSummary> {
+    let EntryWriter { mut send, hasher, .. } = writer;
+    let digest = hasher.finalize();
+    summary.digest = *digest.as_bytes();
+    let end = EntryHeader {
+        kind: EntryKind::End,
+        path: String::new(),
+        size: 0,
+        mode: None,
+        modified: None,
+        digest: Some(digest.to_hex().to_string()),
+    };
+    write_frame(&mut send, &serde_json::to_vec(&end)?).await?;
+    send.finish().map_err(Error::from)?;
+    let ack: Ack = serde_json::from_slice(&read_frame(recv).await?)?;
+    if let Some(error) = ack.error {
+        anyhow::bail!("receiver rejected the transfer: {}", error);
+    }
+    if ack.summary != summary {
+        anyhow::bail!("receiver got {:?}, but {:?} was sent", ack.summary, summary);
+    }
+    Ok(summary)
+}
+
+/// Receives a transfer started by the peer, saving its entries under `destination`.
+///
+/// Existing files are overwritten once the whole transfer is verified. If the transfer fails, including
+/// when the digest does not match, the temporary files and the directories it created are removed
+/// before the error is returned, and existing files are left untouched.
+pub async fn receive(connection: &QuicConnection, destination: &Path) -> Result<TransferSummary> {
+    let (mut send, recv) = connection.connection.accept_bi().await.map_err(Error::from)?;
+    let mut reader = EntryReader::new(recv);
+    tokio::fs::create_dir_all(destination).await?;
+    let mut staging = Staging::default();
+    let result = match receive_entries(&mut reader, &mut send, destination, &mut staging).await {
+        Ok(summary) => staging.commit().await.map(|_| summary),
+        Err(e) => Err(e),
+    };
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(e) => {
+            staging.discard().await;
+            return Err(e);
+        },
+    };
+    write_frame(&mut send, &serde_json::to_vec(&Ack { summary, error: None })?).await?;
+    send.finish().map_err(Error::from)?;
+    // Wait for the sender to read the acknowledgement before the stream is dropped.
+    let _ = send.stopped().await;
+    Ok(summary)
+}
+
+/// The suffix of the temporary files a transfer is received into.
+const PART_SUFFIX: &str = ".part";
+
+/// The changes a transfer in progress made to the destination, to be committed or discarded.
+#[derive(Default)]
+struct Staging {
+    /// The temporary file of each received file, and the path it is renamed to.
+    files: Vec<(PathBuf, PathBuf)>,
+    /// The directories created by the transfer, parents first.
+    directories: Vec<PathBuf>,
+    /// The permissions of received directories, applied last so read-only directories can be filled.
+    directory_modes: Vec<(PathBuf, Option<u32>)>,
+}
+
+impl Staging {
+    /// Creates a directory and its missing parents, recording the ones created.
+    async fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        for ancestor in path.ancestors() {
+            if tokio::fs::try_exists(ancestor).await? {
+                break;
+            }
+            missing.push(ancestor.to_path_buf());
+        }
+        tokio::fs::create_dir_all(path).await?;
+        self.directories.extend(missing.into_iter().rev());
+        Ok(())
+    }
+    /// Returns the temporary file to receive the file at `path` into.
+    fn stage_file(&mut self, path: PathBuf) -> PathBuf {
+        let mut name = std::ffi::OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(PART_SUFFIX);
+        let part = path.with_file_name(name);
+        // A path sent twice is received into the same temporary file.
+        self.files.retain(|(_, staged)| staged != &path);
+        self.files.push((part.clone(), path));
+        part
+    }
+    /// Renames the received files into place and applies the permissions of directories.
+    async fn commit(&mut self) -> Result<()> {
+        while let Some((part, path)) = self.files.pop() {
+            if let Err(e) = tokio::fs::rename(&part, &path).await {
+                self.files.push((part, path.clone()));
+                return Err(e).with_context(|| format!("failed to move {} into place", path.display()));
+            }
+        }
+        for (path, mode) in self.directory_modes.drain(..).rev() {
+            set_mode(&path, mode).await?;
+        }
+        Ok(())
+    }
+    /// Removes the temporary files and the directories created by a failed transfer.
+    async fn discard(self) {
+        for (part, _) in self.files {
+            match tokio::fs::remove_file(&part).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => tracing::warn!("Failed to remove {} of a failed transfer: {}", part.display(), e),
+                _ => {},
+            }
+        }
+        // Directories still holding files, such as ones committed before a failure, are kept.
+        for directory in self.directories.into_iter().rev() {
+            if let Err(e) = tokio::fs::remove_dir(&directory).await {
+                tracing::debug!("Kept directory {} of a failed transfer: {}", directory.display(), e);
+            }
+        }
+    }
+}
+
+/// Reads the entries of a transfer up to the end marker, staging them in `staging`.
+async fn receive_entries(reader: &mut EntryReader, send: &mut SendStream, destination: &Path, staging: &mut Staging) -> Result<TransferSummary> {
+    let mut summary = TransferSummary::default();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let header = reader.read_header().await?;
+        match header.kind {
+            EntryKind::End => {
+                let digest = reader.hasher.finalize();
+                summary.digest = *digest.as_bytes();
+                if header.digest.as_deref() != Some(digest.to_hex().as_str()) {
+                    let error = format!("digest mismatch: sender has {}, receiver has {}", header.digest.as_deref().unwrap_or("none"), digest.to_hex());
+                    write_frame(send, &serde_json::to_vec(&Ack { summary, error: Some(error.clone()) })?).await?;
+                    send.finish().map_err(Error::from)?;
+                    let _ = send.stopped().await;
+                    anyhow::bail!("transfer corrupted, {}", error);
+                }
+                break;
+            },
+            EntryKind::Directory => {
+                let path = destination.join(safe_path(&header.path)?);
+                staging.create_dir_all(&path).await?;
+                staging.directory_modes.push((path, header.mode));
+                summary.directories += 1;
+            },
+            EntryKind::File => {
+                let path = destination.join(safe_path(&header.path)?);
+                if let Some(parent) = path.parent() {
+                    staging.create_dir_all(parent).await?;
+                }
+                let part = staging.stage_file(path);
+                let mut file = File::create(&part).await.with_context(|| format!("failed to create {}", part.display()))?;
+                let mut remaining = header.size;
+                while remaining > 0 {
+                    let len = (remaining as usize).min(CHUNK_SIZE);
+                    reader.read_exact(&mut buffer[..len]).await?;
+                    file.write_all(&buffer[..len]).await?;
+                    remaining -= len as u64;
+                }
+                file.flush().await?;
+                if let Some(modified) = header.modified {
+                    let file = file.into_std().await;
+                    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))?;
+                }
+                set_mode(&part, header.mode).await?;
+                summary.files += 1;
+                summary.bytes += header.size;
+            },
+        }
+    }
+    Ok(summary)
+}
+
+/// Converts a transferred path into a relative path, rejecting paths that could escape the destination.
+fn safe_path(path: &str) -> Result<PathBuf> {
+    let mut safe = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => safe.push(part),
+            Component::CurDir => {},
+            _ => anyhow::bail!("unsafe path in transfer: {}", path),
+        }
+    }
+    if safe.as_os_str().is_empty() {
+        anyhow::bail!("empty path in transfer");
+    }
+    Ok(safe)
+}
+
+/// Returns the Unix permission bits of an entry.
+fn mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & PERMISSION_BITS)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Applies Unix permission bits to an entry, if known.
+///
+/// The setuid, setgid and sticky bits sent by the peer are ignored.
+async fn set_mode(path: &Path, mode: Option<u32>) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & PERMISSION_BITS)).await?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+/// Writes a length-prefixed frame of at most `MAX_HEADER_SIZE` bytes (see `framing`).
+pub(crate) async fn write_frame(send: &mut SendStream, frame: &[u8]) -> Result<()> {
+    send.write_all(&encode_frame(frame)?).await.map_err(Error::from)?;
+    Ok(())
+}
+
+/// Encodes a frame of at most `MAX_HEADER_SIZE` bytes with its length prefix.
+fn encode_frame(frame: &[u8]) -> Result<BytesMut> {
+    let mut buffer = BytesMut::new();
+    FrameCodec::with_max_frame_size(MAX_HEADER_SIZE).encode(Bytes::copy_from_slice(frame), &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Reads a length-prefixed frame of at most `MAX_HEADER_SIZE` bytes (see `framing`).
+///
+/// Only the bytes of the frame are read, so the stream can carry other data after it.
+pub(crate) async fn read_frame(recv: &mut RecvStream) -> Result<Bytes> {
+    let mut decoder = FrameDecoder::new(MAX_HEADER_SIZE);
+    let mut buffer = BytesMut::new();
+    loop {
+        if let Some(frame) = decoder.decode(&mut buffer)? {
+            return Ok(frame);
+        }
+        let filled = buffer.len();
+        buffer.resize(filled + decoder.needed(&buffer), 0);
+        recv.read_exact(&mut buffer[filled..]).await.map_err(Error::from)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("quicsock-transfer-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn safe_path_keeps_relative_paths() {
+        assert_eq!(safe_path("a/./b.txt").unwrap(), Path::new("a").join("b.txt"));
+    }
+
+    #[test]
+    fn safe_path_rejects_escaping_paths() {
+        for path in ["../secret", "a/../../secret", "/etc/passwd", "", "."] {
+            assert!(safe_path(path).is_err(), "{:?} was accepted", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn transfers_file() {
+        let source = temp_dir("file-source");
+        let destination = temp_dir("file-destination");
+        let path = source.join("data.bin");
+        let contents: Vec<u8> = (0..3 * CHUNK_SIZE + 17).map(|i| i as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let (client, server) = crate::testing::pair().await.unwrap();
+        let (sent, received) = tokio::join!(send_file(&client, &path), receive(&server, &destination));
+        let (sent, received) = (sent.unwrap(), received.unwrap());
+        assert_eq!(sent, received);
+        assert_eq!(sent.files, 1);
+        assert_eq!(std::fs::read(destination.join("data.bin")).unwrap(), contents);
+    }
+
+    #[tokio::test]
+    async fn keeps_destination_on_digest_mismatch() {
+        let source = temp_dir("digest-source");
+        let destination = temp_dir("digest-destination");
+        let path = source.join("data.bin");
+        std::fs::write(&path, b"contents").unwrap();
+        std::fs::write(destination.join("data.bin"), b"original").unwrap();
+        let (client, server) = crate::testing::pair().await.unwrap();
+        let corrupted = async {
+            let (send, mut recv) = client.connection.open_bi().await.map_err(Error::from)?;
+            let mut writer = EntryWriter::new(send, &TransferOptions::default());
+            send_file_entry(&mut writer, &path, "data.bin", &mut TransferSummary::default()).await?;
+            send_file_entry(&mut writer, &path, "new/data.bin", &mut TransferSummary::default()).await?;
+            // Simulates corruption in transit by making the sender's digest cover other bytes.
+            writer.hasher.update(b"corrupted");
+            finish(writer, &mut recv, TransferSummary::default()).await
+        };
+        let (sent, received) = tokio::join!(corrupted, receive(&server, &destination));
+        assert!(sent.unwrap_err().to_string().contains("digest mismatch"));
+        assert!(received.unwrap_err().to_string().contains("transfer corrupted"));
+        assert_eq!(std::fs::read(destination.join("data.bin")).unwrap(), b"original");
+        let mut entries: Vec<_> = std::fs::read_dir(&destination).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        entries.sort();
+        assert_eq!(entries, ["data.bin"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn applies_only_permission_bits() {
+        use std::os::unix::fs::PermissionsExt;
+        let source = temp_dir("mode-source");
+        let destination = temp_dir("mode-destination");
+        let path = source.join("tool");
+        std::fs::write(&path, b"#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o4750)).unwrap();
+        let (client, server) = crate::testing::pair().await.unwrap();
+        let (sent, received) = tokio::join!(send_file(&client, &path), receive(&server, &destination));
+        sent.unwrap();
+        received.unwrap();
+        let mode = std::fs::metadata(destination.join("tool")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+    }
+}
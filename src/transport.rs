@@ -0,0 +1,333 @@
+//! Transport configuration.
+//!
+//! `TransportOptions` is a subset of quinn's `TransportConfig` covering the settings quicsock
+//! users commonly need to tune. Options that are not set keep quinn's defaults.
+//!
+//! A `Profile` configures several settings coherently for a latency or throughput trade-off, and
+//! options set explicitly take precedence over it.
+
+use anyhow::Result;
+use quinn::congestion::{BbrConfig, CubicConfig};
+use quinn::{AckFrequencyConfig, IdleTimeout, MtuDiscoveryConfig, TransportConfig, VarInt};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The default interval between receive window auto-tuning samples.
+pub const DEFAULT_AUTOTUNE_INTERVAL: Duration = Duration::from_millis(250);
+/// The default upper bound of an auto-tuned receive window, in bytes.
+pub const DEFAULT_AUTOTUNE_MAX_WINDOW: u64 = 256 * 1024 * 1024;
+/// The UDP payload size, in bytes, every QUIC path must carry, and the one quinn starts connections with.
+const INITIAL_MTU: u16 = 1200;
+
+/// A preset of transport settings for a latency or throughput trade-off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// Favors short response times for small, interactive messages.
+    ///
+    /// Uses a lower initial RTT estimate so that early losses are recovered quickly, acknowledges
+    /// every packet promptly, keeps moderate windows, and uses BBR congestion control, which keeps
+    /// queues along the path short.
+    LowLatency,
+    /// Favors throughput for large transfers.
+    ///
+    /// Uses large flow-control windows, a large initial congestion window, fewer acknowledgements,
+    /// and Cubic congestion control. Streams are sent one after another rather than interleaved.
+    BulkTransfer,
+    /// A middle ground suited to mixed traffic.
+    ///
+    /// Uses windows larger than quinn's defaults and Cubic congestion control, and keeps the default
+    /// acknowledgement behavior.
+    Balanced,
+}
+
+impl Profile {
+    /// Applies the profile to a quinn transport config.
+    fn apply(self, config: &mut TransportConfig) {
+        const MB: u32 = 1024 * 1024;
+        match self {
+            Profile::LowLatency => {
+                config
+                    .initial_rtt(Duration::from_millis(100))
+                    .receive_window(VarInt::from_u32(4 * MB))
+                    .stream_receive_window(VarInt::from_u32(MB))
+                    .send_window(4 * MB as u64)
+                    .send_fairness(true)
+                    .congestion_controller_factory(Arc::new(BbrConfig::default()));
+            },
+            Profile::BulkTransfer => {
+                let mut cubic = CubicConfig::default();
+                cubic.initial_window(64 * 1200);
+                config
+                    .receive_window(VarInt::from_u32(64 * MB))
+                    .stream_receive_window(VarInt::from_u32(32 * MB))
+                    .send_window(64 * MB as u64)
+                    .send_fairness(false)
+                    .congestion_controller_factory(Arc::new(cubic));
+            },
+            Profile::Balanced => {
+                config
+                    .receive_window(VarInt::from_u32(16 * MB))
+                    .stream_receive_window(VarInt::from_u32(4 * MB))
+                    .send_window(16 * MB as u64)
+                    .congestion_controller_factory(Arc::new(CubicConfig::default()));
+            },
+        }
+        if let Some((threshold, max_ack_delay)) = self.ack_frequency() {
+            let mut ack_frequency = AckFrequencyConfig::default();
+            ack_frequency.ack_eliciting_threshold(VarInt::from_u32(threshold)).max_ack_delay(Some(max_ack_delay));
+            config.ack_frequency_config(Some(ack_frequency));
+        }
+    }
+    /// Returns the ack-eliciting threshold and maximum ACK delay the profile requests from peers, if any.
+    fn ack_frequency(self) -> Option<(u32, Duration)> {
+        match self {
+            Profile::LowLatency => Some((0, Duration::from_millis(5))),
+            Profile::BulkTransfer => Some((9, Duration::from_millis(25))),
+            Profile::Balanced => None,
+        }
+    }
+}
+
+/// Transport options applied to connections.
+#[derive(Debug, Clone, Default)]
+pub struct TransportOptions {
+    pub(crate) profile: Option<Profile>,
+    pub(crate) receive_window: Option<u64>,
+    pub(crate) stream_receive_window: Option<u64>,
+    pub(crate) send_window: Option<u64>,
+    pub(crate) max_concurrent_bi_streams: Option<u32>,
+    pub(crate) max_concurrent_uni_streams: Option<u32>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) keep_alive_interval: Option<Duration>,
+    pub(crate) initial_mtu: Option<u16>,
+    pub(crate) max_udp_payload_size: Option<u16>,
+    pub(crate) ack_eliciting_threshold: Option<u32>,
+    pub(crate) max_ack_delay: Option<Duration>,
+    pub(crate) ack_reordering_threshold: Option<u32>,
+}
+
+impl TransportOptions {
+    /// Creates options that keep quinn's defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates options with flow-control windows sized for a path's bandwidth-delay product.
+    ///
+    /// `bandwidth` is the expected throughput in bytes per second. Windows are set to twice the
+    /// bandwidth-delay product so that a single stream can keep the path full.
+    pub fn for_bandwidth_delay(bandwidth: u64, rtt: Duration) -> Self {
+        let bdp = (bandwidth as f64 * rtt.as_secs_f64()) as u64;
+        let window = bdp.saturating_mul(2).max(crate::connection::DEFAULT_RECEIVE_BUFFER_SIZE as u64);
+        Self::new()
+            .with_receive_window(window)
+            .with_stream_receive_window(window)
+            .with_send_window(window)
+    }
+    /// Creates options configured by a profile.
+    pub fn from_profile(profile: Profile) -> Self {
+        Self::new().with_profile(profile)
+    }
+    /// Sets the profile the options start from. Options set explicitly take precedence over it.
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+    /// Sets the maximum number of bytes the peer may send across all streams of a connection.
+    pub fn with_receive_window(mut self, bytes: u64) -> Self {
+        self.receive_window = Some(bytes);
+        self
+    }
+    /// Sets the maximum number of bytes the peer may send on a single stream.
+    pub fn with_stream_receive_window(mut self, bytes: u64) -> Self {
+        self.stream_receive_window = Some(bytes);
+        self
+    }
+    /// Sets the maximum number of bytes buffered for sending across all streams of a connection.
+    pub fn with_send_window(mut self, bytes: u64) -> Self {
+        self.send_window = Some(bytes);
+        self
+    }
+    /// Sets the maximum number of bi-directional streams the peer may have open at once.
+    ///
+    /// This is enforced by the QUIC protocol: peers wait for a stream to close before opening another one.
+    pub fn with_max_concurrent_bi_streams(mut self, streams: u32) -> Self {
+        self.max_concurrent_bi_streams = Some(streams);
+        self
+    }
+    /// Sets the maximum number of uni-directional streams the peer may have open at once.
+    ///
+    /// Servers refuse uni-directional streams from clients unless this is set.
+    pub fn with_max_concurrent_uni_streams(mut self, streams: u32) -> Self {
+        self.max_concurrent_uni_streams = Some(streams);
+        self
+    }
+    /// Sets how long a connection may stay idle before it is closed. The smaller of both peers' timeouts applies.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+    /// Sets the interval at which packets are sent to keep an idle connection alive.
+    pub fn with_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+    /// Sets the UDP payload size, in bytes, a connection starts with before path MTU discovery.
+    ///
+    /// quinn starts with 1200 bytes, the minimum QUIC requires of every path, which values below are
+    /// raised to. Larger values, e.g. 8952 on a LAN with jumbo frames, skip the discovery steps
+    /// up to them; if the path does not carry them, packets are lost until quinn detects the black
+    /// hole and falls back to 1200 bytes.
+    pub fn with_initial_mtu(mut self, bytes: u16) -> Self {
+        self.initial_mtu = Some(bytes);
+        self
+    }
+    /// Sets the largest UDP payload size, in bytes, path MTU discovery probes for. quinn probes up to 1452 bytes.
+    ///
+    /// A smaller value keeps packets within paths with a small MTU that drop larger probes, such as VPNs
+    /// and tunnels, and a larger one lets connections on jumbo-capable LANs grow beyond Ethernet's MTU.
+    /// Must not be below the initial MTU. Path MTU discovery is disabled if both are equal.
+    ///
+    /// For the options of a `SocketConfig`, this is also the largest payload the socket accepts, which
+    /// its peers do not exceed. quinn accepts up to 1472 bytes by default, so both peers have to raise it
+    /// for larger packets. Must be between 1200 and 65527 bytes there.
+    pub fn with_max_udp_payload_size(mut self, bytes: u16) -> Self {
+        self.max_udp_payload_size = Some(bytes);
+        self
+    }
+    /// Sets the number of ack-eliciting packets the peer may receive before it must acknowledge them.
+    ///
+    /// This and the other ACK settings are requested from the peer through the QUIC ACK frequency
+    /// extension, which quinn supports, and only apply to peers supporting it. Fewer acknowledgements
+    /// save CPU on both sides of high-throughput transfers, but make loss detection and congestion
+    /// control react later. quinn requests no change by default, so peers acknowledge every other
+    /// packet (a threshold of 1). A threshold of 0 asks for every packet to be acknowledged.
+    pub fn with_ack_eliciting_threshold(mut self, packets: u32) -> Self {
+        self.ack_eliciting_threshold = Some(packets);
+        self
+    }
+    /// Sets the maximum time the peer may delay an acknowledgement when the ack-eliciting threshold
+    /// is not reached. quinn clamps it to at most the RTT or 25ms, whichever is larger.
+    /// See `with_ack_eliciting_threshold`.
+    pub fn with_max_ack_delay(mut self, delay: Duration) -> Self {
+        self.max_ack_delay = Some(delay);
+        self
+    }
+    /// Sets the number of packets received out of order that make the peer acknowledge right away.
+    ///
+    /// 0 disables immediate acknowledgements of reordered packets, which suits paths that reorder
+    /// a lot. quinn defaults to 2. See `with_ack_eliciting_threshold`.
+    pub fn with_ack_reordering_threshold(mut self, packets: u32) -> Self {
+        self.ack_reordering_threshold = Some(packets);
+        self
+    }
+    /// Returns these options with the ones set in `overrides` taking precedence, e.g. to derive the
+    /// options of a single connection from those of its socket.
+    pub fn merged_with(&self, overrides: &TransportOptions) -> TransportOptions {
+        TransportOptions {
+            profile: overrides.profile.or(self.profile),
+            receive_window: overrides.receive_window.or(self.receive_window),
+            stream_receive_window: overrides.stream_receive_window.or(self.stream_receive_window),
+            send_window: overrides.send_window.or(self.send_window),
+            max_concurrent_bi_streams: overrides.max_concurrent_bi_streams.or(self.max_concurrent_bi_streams),
+            max_concurrent_uni_streams: overrides.max_concurrent_uni_streams.or(self.max_concurrent_uni_streams),
+            idle_timeout: overrides.idle_timeout.or(self.idle_timeout),
+            keep_alive_interval: overrides.keep_alive_interval.or(self.keep_alive_interval),
+            initial_mtu: overrides.initial_mtu.or(self.initial_mtu),
+            max_udp_payload_size: overrides.max_udp_payload_size.or(self.max_udp_payload_size),
+            ack_eliciting_threshold: overrides.ack_eliciting_threshold.or(self.ack_eliciting_threshold),
+            max_ack_delay: overrides.max_ack_delay.or(self.max_ack_delay),
+            ack_reordering_threshold: overrides.ack_reordering_threshold.or(self.ack_reordering_threshold),
+        }
+    }
+    /// Applies the options to a quinn transport config.
+    pub fn apply(&self, config: &mut TransportConfig) -> Result<()> {
+        if let Some(profile) = self.profile {
+            profile.apply(config);
+        }
+        if let Some(receive_window) = self.receive_window {
+            config.receive_window(VarInt::from_u64(receive_window)?);
+        }
+        if let Some(stream_receive_window) = self.stream_receive_window {
+            config.stream_receive_window(VarInt::from_u64(stream_receive_window)?);
+        }
+        if let Some(send_window) = self.send_window {
+            config.send_window(send_window);
+        }
+        if let Some(streams) = self.max_concurrent_bi_streams {
+            config.max_concurrent_bidi_streams(streams.into());
+        }
+        if let Some(streams) = self.max_concurrent_uni_streams {
+            config.max_concurrent_uni_streams(streams.into());
+        }
+        if let Some(timeout) = self.idle_timeout {
+            config.max_idle_timeout(Some(IdleTimeout::try_from(timeout)?));
+        }
+        if let Some(interval) = self.keep_alive_interval {
+            config.keep_alive_interval(Some(interval));
+        }
+        if self.ack_eliciting_threshold.is_some() || self.max_ack_delay.is_some() || self.ack_reordering_threshold.is_some() {
+            // Settings not set explicitly keep the profile's.
+            let (threshold, max_ack_delay) = self.profile.and_then(Profile::ack_frequency).unzip();
+            let mut ack_frequency = AckFrequencyConfig::default();
+            if let Some(threshold) = self.ack_eliciting_threshold.or(threshold) {
+                ack_frequency.ack_eliciting_threshold(VarInt::from_u32(threshold));
+            }
+            ack_frequency.max_ack_delay(self.max_ack_delay.or(max_ack_delay));
+            if let Some(threshold) = self.ack_reordering_threshold {
+                ack_frequency.reordering_threshold(VarInt::from_u32(threshold));
+            }
+            config.ack_frequency_config(Some(ack_frequency));
+        }
+        if let Some(bytes) = self.initial_mtu {
+            config.initial_mtu(bytes);
+        }
+        if let Some(bytes) = self.max_udp_payload_size {
+            let initial_mtu = self.initial_mtu.unwrap_or(INITIAL_MTU).max(INITIAL_MTU);
+            if bytes < initial_mtu {
+                anyhow::bail!("maximum UDP payload size of {} bytes is below the initial MTU of {} bytes", bytes, initial_mtu);
+            }
+            if bytes > initial_mtu {
+                let mut mtu_discovery = MtuDiscoveryConfig::default();
+                mtu_discovery.upper_bound(bytes);
+                config.mtu_discovery_config(Some(mtu_discovery));
+            } else {
+                config.mtu_discovery_config(None);
+            }
+        }
+        Ok(())
+    }
+    /// Builds a quinn transport config from the options.
+    pub fn to_transport_config(&self) -> Result<TransportConfig> {
+        let mut config = TransportConfig::default();
+        self.apply(&mut config)?;
+        Ok(config)
+    }
+}
+
+/// Settings for receive window auto-tuning.
+///
+/// The connection receive window is grown to twice the product of the measured delivery rate and RTT,
+/// bounded by `min_window` and `max_window`. The window is never shrunk.
+///
+/// Only the connection-level window is tuned: quinn cannot change the per-stream window of an open
+/// connection, so a single stream remains limited by `TransportOptions::with_stream_receive_window`.
+/// Raise that up front for transfers over one stream.
+#[derive(Debug, Clone)]
+pub struct WindowAutoTune {
+    /// The interval between samples.
+    pub interval: Duration,
+    /// The lower bound of the window, in bytes.
+    pub min_window: u64,
+    /// The upper bound of the window, in bytes.
+    pub max_window: u64,
+}
+
+impl Default for WindowAutoTune {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_AUTOTUNE_INTERVAL,
+            min_window: crate::connection::DEFAULT_RECEIVE_BUFFER_SIZE as u64,
+            max_window: DEFAULT_AUTOTUNE_MAX_WINDOW,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! QUIC version selection.
+//!
+//! By default an endpoint accepts QUIC v1 and the late drafts that quinn implements, and connects with
+//! v1. `SocketConfig::with_quic_versions` restricts the versions, e.g. when a middlebox only lets some
+//! through: servers only accept the given versions, answering others with a version negotiation packet,
+//! and clients connect with the first one.
+//!
+//! QUIC v2 (RFC 9369) is not implemented by quinn yet; sockets configured to offer it fail to build.
+
+use std::fmt;
+
+/// A QUIC version number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QuicVersion(pub u32);
+
+impl QuicVersion {
+    /// QUIC version 1 (RFC 9000).
+    pub const V1: QuicVersion = QuicVersion(0x0000_0001);
+    /// QUIC version 2 (RFC 9369).
+    pub const V2: QuicVersion = QuicVersion(0x6b33_43cf);
+    /// Draft 29, the last draft widely deployed before v1.
+    pub const DRAFT_29: QuicVersion = QuicVersion(0xff00_001d);
+
+    /// Returns the versions supported by quinn, in order of preference.
+    pub fn supported() -> Vec<QuicVersion> {
+        quinn_proto::DEFAULT_SUPPORTED_VERSIONS.iter().copied().map(QuicVersion).collect()
+    }
+    /// Returns whether quinn implements this version.
+    pub fn is_supported(self) -> bool {
+        quinn_proto::DEFAULT_SUPPORTED_VERSIONS.contains(&self.0)
+    }
+    /// Returns the draft number of a draft version.
+    pub fn draft(self) -> Option<u8> {
+        (self.0 & 0xffff_ff00 == 0xff00_0000).then_some(self.0 as u8)
+    }
+}
+
+impl fmt::Display for QuicVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            QuicVersion::V1 => write!(f, "v1"),
+            QuicVersion::V2 => write!(f, "v2"),
+            version => match version.draft() {
+                Some(draft) => write!(f, "draft-{}", draft),
+                None => write!(f, "{:#010x}", version.0),
+            },
+        }
+    }
+}
+
+/// Checks that a list of versions to offer is not empty and only holds versions quinn implements.
+pub(crate) fn validate(versions: &[QuicVersion]) -> Result<(), String> {
+    if versions.is_empty() {
+        return Err("no QUIC version to offer".to_string());
+    }
+    match versions.iter().find(|version| !version.is_supported()) {
+        Some(version) => Err(format!("unsupported QUIC version: {}", version)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,113 @@
+//! Server-side 0-RTT acceptance.
+//!
+//! A client resuming a TLS session can send early (0-RTT) data along with its first flight, saving a
+//! round trip. Early data can be replayed by an attacker who captured it, so a server should only act
+//! on it for idempotent requests, such as token lookups. `ZeroRttPolicy` enables early data on a server
+//! (see `SocketConfig::with_zero_rtt`) and decides, per connection, whether it is handed to the
+//! application before the handshake completes:
+//!
+//! - The filter sees the `ClientHello` of the connection, e.g. to only allow an ALPN protocol whose
+//!   requests are idempotent.
+//! - The replay window remembers the connection IDs clients used in their first packets for a while,
+//!   and refuses early processing for a connection whose ID was already seen in the window.
+//!
+//! A connection that is refused early processing is not rejected: its early data is delivered once the
+//! handshake completes, which proves the client is live, so it cannot be a replay.
+
+use quinn::{ConnectionId, Incoming};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::routing::ClientHello;
+
+/// The default time connection IDs are remembered for replay detection.
+pub const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(10);
+/// The maximum number of connection IDs remembered for replay detection.
+///
+/// Once reached, early processing is refused until IDs leave the window.
+pub const MAX_REPLAY_ENTRIES: usize = 65536;
+
+/// A filter deciding whether a connection's early data is processed before the handshake completes.
+type Filter = Arc<dyn Fn(&ClientHello) -> bool + Send + Sync>;
+
+/// Server-side 0-RTT acceptance policy.
+#[derive(Clone)]
+pub struct ZeroRttPolicy {
+    replay_window: Duration,
+    filter: Option<Filter>,
+}
+
+impl Default for ZeroRttPolicy {
+    fn default() -> Self {
+        Self { replay_window: DEFAULT_REPLAY_WINDOW, filter: None }
+    }
+}
+
+impl fmt::Debug for ZeroRttPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZeroRttPolicy")
+            .field("replay_window", &self.replay_window)
+            .field("filter", &self.filter.is_some())
+            .finish()
+    }
+}
+
+impl ZeroRttPolicy {
+    /// Creates a policy processing the early data of every connection not detected as a replay.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets how long connection IDs are remembered for replay detection. Defaults to `DEFAULT_REPLAY_WINDOW`.
+    ///
+    /// Should cover the time a client takes to send its first flight, plus the time an attacker may
+    /// hold it back before replaying it.
+    pub fn with_replay_window(mut self, replay_window: Duration) -> Self {
+        self.replay_window = replay_window;
+        self
+    }
+    /// Sets a filter deciding, from its client hello, whether a connection's early data is processed
+    /// before the handshake completes.
+    pub fn with_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&ClientHello) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+}
+
+/// The state of the 0-RTT policy of a socket.
+pub(crate) struct ZeroRttGuard {
+    policy: ZeroRttPolicy,
+    seen: HashSet<ConnectionId>,
+    expiry: VecDeque<(Instant, ConnectionId)>,
+}
+
+impl ZeroRttGuard {
+    pub(crate) fn new(policy: ZeroRttPolicy) -> Self {
+        Self { policy, seen: HashSet::new(), expiry: VecDeque::new() }
+    }
+    /// Records the connection ID of an incoming connection.
+    /// Returns whether it was not seen in the replay window, so early processing may be allowed.
+    pub(crate) fn check_replay(&mut self, incoming: &Incoming) -> bool {
+        let now = Instant::now();
+        while let Some(&(expires, id)) = self.expiry.front() {
+            if expires > now {
+                break;
+            }
+            self.expiry.pop_front();
+            self.seen.remove(&id);
+        }
+        let id = incoming.orig_dst_cid();
+        if self.seen.len() >= MAX_REPLAY_ENTRIES || !self.seen.insert(id) {
+            return false;
+        }
+        self.expiry.push_back((now + self.policy.replay_window, id));
+        true
+    }
+    /// Returns the filter of the policy, if any.
+    pub(crate) fn filter(&self) -> Option<Filter> {
+        self.policy.filter.clone()
+    }
+}